@@ -13,6 +13,12 @@ pub struct Block {
     pub approved_image_count: u32,
     pub annotation_count: u32,
     pub block_created_at: String,
+    /// Set while the row sits in the trash
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<String>,
+    /// Trash entry this row was trashed under; restore clears it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trash_id: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -30,3 +36,23 @@ pub struct UpdateBlockPayload {
     pub block_locked: Option<bool>,
     // pub block_assigned_to: Option<String>,
 }
+
+/// How much of a block a clone copies; labels always come along
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum CloneScope {
+    #[default]
+    Labels,
+    /// Labels and the task breakdown
+    Tasks,
+    /// Labels, tasks, images and annotations
+    All,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct CloneBlockPayload {
+    /// Defaults to the source name with " (copy)" appended
+    pub block_name: Option<String>,
+    #[serde(default)]
+    pub scope: CloneScope,
+}
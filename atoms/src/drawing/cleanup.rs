@@ -0,0 +1,194 @@
+use super::geometry;
+use super::model::{Geometry, Point, PolygonRings};
+use serde::{Deserialize, Serialize};
+
+/// Optional tidy-up of hand-drawn outlines, run before validation. Each
+/// step runs when its threshold is given, in the order listed.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct Cleanup {
+    /// Douglas-Peucker tolerance, in pixels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub simplify: Option<f64>,
+    /// Edges within this many degrees of horizontal or vertical are squared up
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub orthogonal: Option<f64>,
+    /// Vertices within this many pixels of a vertex of another annotation on
+    /// the image move onto it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snap: Option<f64>,
+}
+
+impl Cleanup {
+    pub fn is_empty(&self) -> bool {
+        self.simplify.is_none() && self.orthogonal.is_none() && self.snap.is_none()
+    }
+}
+
+/// Run `cleanup` over polygons, polylines and points; other shapes are
+/// already regular and pass through. `neighbours` are the vertices to snap to.
+pub fn clean(geometry: Geometry, cleanup: &Cleanup, neighbours: &[Point]) -> Geometry {
+    let ring = |points: Vec<Point>| clean_path(points, true, cleanup, neighbours);
+    match geometry {
+        Geometry::Polygon { points, holes } => {
+            Geometry::Polygon { points: ring(points), holes: holes.into_iter().map(ring).collect() }
+        }
+        Geometry::MultiPolygon { polygons } => Geometry::MultiPolygon {
+            polygons: polygons
+                .into_iter()
+                .map(|p| PolygonRings { points: ring(p.points), holes: p.holes.into_iter().map(ring).collect() })
+                .collect(),
+        },
+        Geometry::Polyline { points } => Geometry::Polyline { points: clean_path(points, false, cleanup, neighbours) },
+        Geometry::Point { at } => match cleanup.snap {
+            Some(distance) => Geometry::Point { at: snap_point(at, neighbours, distance) },
+            None => Geometry::Point { at },
+        },
+        other => other,
+    }
+}
+
+/// Vertices of `geometry` that others may snap to
+pub fn vertices(geometry: &Geometry) -> Vec<Point> {
+    match geometry {
+        Geometry::Point { at } => vec![at.clone()],
+        Geometry::Polyline { points } => points.clone(),
+        // Ellipse outlines are only approximated; their vertices mean nothing
+        Geometry::Ellipse { .. } => vec![],
+        _ => geometry::polygons(geometry)
+            .into_iter()
+            .flat_map(|p| p.points.into_iter().chain(p.holes.into_iter().flatten()))
+            .collect(),
+    }
+}
+
+fn clean_path(mut points: Vec<Point>, closed: bool, cleanup: &Cleanup, neighbours: &[Point]) -> Vec<Point> {
+    if let Some(tolerance) = cleanup.simplify {
+        points = if closed { simplify_ring(&points, tolerance) } else { simplify(&points, tolerance) };
+    }
+    if let Some(degrees) = cleanup.orthogonal {
+        orthogonalize(&mut points, closed, degrees);
+    }
+    if let Some(distance) = cleanup.snap {
+        points = points.into_iter().map(|p| snap_point(p, neighbours, distance)).collect();
+    }
+    points
+}
+
+/// Douglas-Peucker over an open path; the ends always stay
+pub fn simplify(points: &[Point], tolerance: f64) -> Vec<Point> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+    let mut spans = vec![(0, points.len() - 1)];
+    while let Some((first, last)) = spans.pop() {
+        let farthest = (first + 1..last)
+            .map(|i| (i, segment_distance(&points[i], &points[first], &points[last])))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((i, _)) = farthest.filter(|(_, d)| *d > tolerance) {
+            keep[i] = true;
+            spans.push((first, i));
+            spans.push((i, last));
+        }
+    }
+    points.iter().zip(keep).filter(|(_, k)| *k).map(|(p, _)| p.clone()).collect()
+}
+
+/// Douglas-Peucker over a closed ring, split at the vertex farthest from
+/// the first so both halves keep their shape. Rings that would collapse
+/// below a triangle are left alone.
+fn simplify_ring(ring: &[Point], tolerance: f64) -> Vec<Point> {
+    if ring.len() < 4 {
+        return ring.to_vec();
+    }
+    let far = (1..ring.len())
+        .max_by(|&a, &b| distance(&ring[0], &ring[a]).total_cmp(&distance(&ring[0], &ring[b])))
+        .unwrap_or(1);
+    let mut out = simplify(&ring[..=far], tolerance);
+    let mut back: Vec<Point> = ring[far..].to_vec();
+    back.push(ring[0].clone());
+    let back = simplify(&back, tolerance);
+    out.extend(back[1..back.len() - 1].iter().cloned());
+    if out.len() < 3 {
+        return ring.to_vec();
+    }
+    out
+}
+
+/// Square up edges within `degrees` of an axis by levelling both ends
+fn orthogonalize(points: &mut [Point], closed: bool, degrees: f64) {
+    let n = points.len();
+    let edges = if closed && n > 2 { n } else { n.saturating_sub(1) };
+    for i in 0..edges {
+        let j = (i + 1) % n;
+        let (dx, dy) = (points[j].x - points[i].x, points[j].y - points[i].y);
+        let angle = dy.atan2(dx).to_degrees().rem_euclid(180.0);
+        if angle.min(180.0 - angle) <= degrees {
+            let y = (points[i].y + points[j].y) / 2.0;
+            points[i].y = y;
+            points[j].y = y;
+        } else if (angle - 90.0).abs() <= degrees {
+            let x = (points[i].x + points[j].x) / 2.0;
+            points[i].x = x;
+            points[j].x = x;
+        }
+    }
+}
+
+fn snap_point(point: Point, neighbours: &[Point], max: f64) -> Point {
+    neighbours
+        .iter()
+        .map(|n| (n, distance(&point, n)))
+        .filter(|(_, d)| *d <= max)
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(n, _)| n.clone())
+        .unwrap_or(point)
+}
+
+fn segment_distance(p: &Point, a: &Point, b: &Point) -> f64 {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    let length = dx * dx + dy * dy;
+    if length == 0.0 {
+        return distance(p, a);
+    }
+    let t = (((p.x - a.x) * dx + (p.y - a.y) * dy) / length).clamp(0.0, 1.0);
+    distance(p, &Point { x: a.x + t * dx, y: a.y + t * dy })
+}
+
+fn distance(a: &Point, b: &Point) -> f64 {
+    (b.x - a.x).hypot(b.y - a.y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn p(x: f64, y: f64) -> Point {
+        Point { x, y }
+    }
+
+    #[test]
+    fn test_simplify_square_up_and_snap_a_jittery_wall() {
+        // A 100x40 wall traced with jitter along the long sides
+        let traced = vec![
+            p(0.0, 0.0),
+            p(25.0, 0.4),
+            p(50.0, -0.3),
+            p(75.0, 0.2),
+            p(100.0, 1.0),
+            p(101.0, 40.0),
+            p(50.0, 40.3),
+            p(0.0, 40.0),
+        ];
+        let cleanup = Cleanup { simplify: Some(1.0), orthogonal: Some(5.0), snap: Some(3.0) };
+        let neighbour = vec![p(102.0, 0.0)];
+        let cleaned = clean(Geometry::Polygon { points: traced, holes: vec![] }, &cleanup, &neighbour);
+
+        // Jitter dropped, sides levelled, and the far corner moved onto the
+        // neighbouring wall's
+        let Geometry::Polygon { points, .. } = cleaned else { panic!("still a polygon") };
+        assert_eq!(points, vec![p(0.0, 0.5), p(102.0, 0.0), p(100.5, 40.0), p(0.0, 40.0)]);
+    }
+}
@@ -0,0 +1,349 @@
+use super::model::{Geometry, Point, PolygonRings};
+use super::validate::signed_area;
+use serde::{Deserialize, Serialize};
+
+/// Segments used to stand in for an ellipse's outline
+const ELLIPSE_SEGMENTS: usize = 64;
+
+/// Axis-aligned box around a geometry
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BoundingBox {
+    pub min: Point,
+    pub max: Point,
+}
+
+/// Derived measurements of a geometry, in pixels. `perimeter` is the length
+/// of every outline and hole, or a polyline's length.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Measurements {
+    pub area: f64,
+    pub perimeter: f64,
+    pub centroid: Point,
+    pub bounding_box: BoundingBox,
+}
+
+pub fn measure(geometry: &Geometry) -> Measurements {
+    Measurements {
+        area: area(geometry),
+        perimeter: perimeter(geometry),
+        centroid: centroid(geometry),
+        bounding_box: bounding_box(geometry),
+    }
+}
+
+/// Area covered; zero for points and polylines
+pub fn area(geometry: &Geometry) -> f64 {
+    match geometry {
+        Geometry::Ellipse { rx, ry, .. } => std::f64::consts::PI * rx * ry,
+        Geometry::Point { .. } | Geometry::Polyline { .. } => 0.0,
+        _ => polygons(geometry).iter().map(rings_area).sum(),
+    }
+}
+
+/// Length of the outlines, holes included, or of the polyline
+pub fn perimeter(geometry: &Geometry) -> f64 {
+    match geometry {
+        Geometry::Point { .. } => 0.0,
+        Geometry::Polyline { points } => path_length(points, false),
+        // Ramanujan's approximation
+        Geometry::Ellipse { rx, ry, .. } => {
+            let h = ((rx - ry) / (rx + ry)).powi(2);
+            std::f64::consts::PI * (rx + ry) * (1.0 + 3.0 * h / (10.0 + (4.0 - 3.0 * h).sqrt()))
+        }
+        _ => polygons(geometry)
+            .iter()
+            .flat_map(|p| std::iter::once(&p.points).chain(&p.holes))
+            .map(|ring| path_length(ring, true))
+            .sum(),
+    }
+}
+
+/// Centre of mass: area-weighted for shapes, length-weighted for
+/// polylines. Shapes with no area fall back to the mean vertex.
+pub fn centroid(geometry: &Geometry) -> Point {
+    match geometry {
+        Geometry::Point { at } => at.clone(),
+        Geometry::RotatedBox { center, .. } | Geometry::Ellipse { center, .. } => center.clone(),
+        Geometry::Polyline { points } => {
+            let mut weighted = (0.0, 0.0, 0.0);
+            for pair in points.windows(2) {
+                let length = distance(&pair[0], &pair[1]);
+                weighted.0 += length * (pair[0].x + pair[1].x) / 2.0;
+                weighted.1 += length * (pair[0].y + pair[1].y) / 2.0;
+                weighted.2 += length;
+            }
+            if weighted.2 > 0.0 {
+                Point { x: weighted.0 / weighted.2, y: weighted.1 / weighted.2 }
+            } else {
+                mean(points)
+            }
+        }
+        _ => {
+            let polygons = polygons(geometry);
+            let mut weighted = (0.0, 0.0, 0.0);
+            for polygon in &polygons {
+                let rings = std::iter::once((&polygon.points, 1.0)).chain(polygon.holes.iter().map(|h| (h, -1.0)));
+                for (ring, sign) in rings {
+                    let (a, c) = ring_centroid(ring);
+                    weighted.0 += sign * a * c.x;
+                    weighted.1 += sign * a * c.y;
+                    weighted.2 += sign * a;
+                }
+            }
+            if weighted.2.abs() > f64::EPSILON {
+                Point { x: weighted.0 / weighted.2, y: weighted.1 / weighted.2 }
+            } else {
+                mean(&polygons.into_iter().flat_map(|p| p.points).collect::<Vec<_>>())
+            }
+        }
+    }
+}
+
+pub fn bounding_box(geometry: &Geometry) -> BoundingBox {
+    let points = match geometry {
+        Geometry::Point { at } => vec![at.clone()],
+        Geometry::Polyline { points } => points.clone(),
+        // Exact extent of the turned ellipse
+        Geometry::Ellipse { center, rx, ry, angle } => {
+            let (sin, cos) = angle.to_radians().sin_cos();
+            let half_w = ((rx * cos).powi(2) + (ry * sin).powi(2)).sqrt();
+            let half_h = ((rx * sin).powi(2) + (ry * cos).powi(2)).sqrt();
+            vec![
+                Point { x: center.x - half_w, y: center.y - half_h },
+                Point { x: center.x + half_w, y: center.y + half_h },
+            ]
+        }
+        _ => polygons(geometry).into_iter().flat_map(|p| p.points).collect(),
+    };
+    let mut bbox = BoundingBox {
+        min: Point { x: f64::INFINITY, y: f64::INFINITY },
+        max: Point { x: f64::NEG_INFINITY, y: f64::NEG_INFINITY },
+    };
+    for p in &points {
+        bbox.min.x = bbox.min.x.min(p.x);
+        bbox.min.y = bbox.min.y.min(p.y);
+        bbox.max.x = bbox.max.x.max(p.x);
+        bbox.max.y = bbox.max.y.max(p.y);
+    }
+    bbox
+}
+
+/// Intersection over union of two shapes; None when either covers no area
+/// (points and polylines). Ellipses are compared as 64-gons.
+pub fn iou(a: &Geometry, b: &Geometry) -> Option<f64> {
+    let (area_a, area_b) = (polygon_area(a), polygon_area(b));
+    if area_a <= 0.0 || area_b <= 0.0 {
+        return None;
+    }
+    let (a, b) = (polygons(a), polygons(b));
+    let inter: f64 = a.iter().flat_map(|pa| b.iter().map(move |pb| intersection_area(pa, pb))).sum();
+    let union = area_a + area_b - inter;
+    Some(if union > 0.0 { (inter / union).clamp(0.0, 1.0) } else { 0.0 })
+}
+
+/// The shape as polygons with holes; empty for points and polylines.
+/// Ellipses become 64-gons.
+pub fn polygons(geometry: &Geometry) -> Vec<PolygonRings> {
+    let ring = |points: Vec<Point>| vec![PolygonRings { points, holes: vec![] }];
+    match geometry {
+        Geometry::Polygon { points, holes } => vec![PolygonRings { points: points.clone(), holes: holes.clone() }],
+        Geometry::MultiPolygon { polygons } => polygons.clone(),
+        Geometry::BBox { start, end } => ring(vec![
+            start.clone(),
+            Point { x: end.x, y: start.y },
+            end.clone(),
+            Point { x: start.x, y: end.y },
+        ]),
+        Geometry::RotatedBox { center, width, height, angle } => {
+            let (w, h) = (width / 2.0, height / 2.0);
+            ring([(-w, -h), (w, -h), (w, h), (-w, h)].iter().map(|&(dx, dy)| turn(center, dx, dy, *angle)).collect())
+        }
+        Geometry::Ellipse { center, rx, ry, angle } => ring(
+            (0..ELLIPSE_SEGMENTS)
+                .map(|i| {
+                    let t = i as f64 * std::f64::consts::TAU / ELLIPSE_SEGMENTS as f64;
+                    turn(center, rx * t.cos(), ry * t.sin(), *angle)
+                })
+                .collect(),
+        ),
+        Geometry::Point { .. } | Geometry::Polyline { .. } => vec![],
+    }
+}
+
+/// Area of the polygon form, so IoU compares like with like for ellipses
+fn polygon_area(geometry: &Geometry) -> f64 {
+    polygons(geometry).iter().map(rings_area).sum()
+}
+
+fn rings_area(polygon: &PolygonRings) -> f64 {
+    signed_area(&polygon.points).abs() - polygon.holes.iter().map(|h| signed_area(h).abs()).sum::<f64>()
+}
+
+/// Area shared by two polygons with holes, assuming holes lie inside their
+/// outline
+fn intersection_area(a: &PolygonRings, b: &PolygonRings) -> f64 {
+    let with_b = |ring: &[Point]| -> f64 {
+        ring_intersection(ring, &b.points) - b.holes.iter().map(|h| ring_intersection(ring, h)).sum::<f64>()
+    };
+    (with_b(&a.points) - a.holes.iter().map(|h| with_b(h)).sum::<f64>()).max(0.0)
+}
+
+/// Area shared by two simple rings: split `a` into triangles and clip `b`
+/// to each. Clipping a concave ring to a convex one can leave zero-width
+/// slivers, which add no area.
+fn ring_intersection(a: &[Point], b: &[Point]) -> f64 {
+    triangulate(a).iter().map(|t| signed_area(&clip(b, t)).abs()).sum()
+}
+
+/// Sutherland-Hodgman: the part of `subject` inside the convex `clip`
+fn clip(subject: &[Point], clip: &[Point]) -> Vec<Point> {
+    let orientation = signed_area(clip).signum();
+    let mut output = subject.to_vec();
+    for i in 0..clip.len() {
+        if output.is_empty() {
+            break;
+        }
+        let (c1, c2) = (&clip[i], &clip[(i + 1) % clip.len()]);
+        let inside = |p: &Point| orientation * ((c2.x - c1.x) * (p.y - c1.y) - (c2.y - c1.y) * (p.x - c1.x)) >= 0.0;
+        let input = std::mem::take(&mut output);
+        for j in 0..input.len() {
+            let (current, previous) = (&input[j], &input[(j + input.len() - 1) % input.len()]);
+            match (inside(current), inside(previous)) {
+                (true, true) => output.push(current.clone()),
+                (true, false) => {
+                    output.push(crossing(previous, current, c1, c2));
+                    output.push(current.clone());
+                }
+                (false, true) => output.push(crossing(previous, current, c1, c2)),
+                (false, false) => {}
+            }
+        }
+    }
+    output
+}
+
+/// Where segment `p`-`q` meets the line through `a` and `b`
+fn crossing(p: &Point, q: &Point, a: &Point, b: &Point) -> Point {
+    let (dx, dy) = (q.x - p.x, q.y - p.y);
+    let (ex, ey) = (b.x - a.x, b.y - a.y);
+    let denom = dx * ey - dy * ex;
+    if denom == 0.0 {
+        return q.clone();
+    }
+    let t = ((a.x - p.x) * ey - (a.y - p.y) * ex) / denom;
+    Point { x: p.x + t * dx, y: p.y + t * dy }
+}
+
+/// Ear-clipping triangulation of a simple ring
+fn triangulate(ring: &[Point]) -> Vec<[Point; 3]> {
+    let orientation = signed_area(ring).signum();
+    let mut remaining: Vec<&Point> = ring.iter().collect();
+    let mut triangles = Vec::new();
+    while remaining.len() > 3 {
+        let n = remaining.len();
+        let ear = (0..n).find(|&i| {
+            let (a, b, c) = (remaining[(i + n - 1) % n], remaining[i], remaining[(i + 1) % n]);
+            let convex = orientation * ((b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x)) > 0.0;
+            convex
+                && !remaining
+                    .iter()
+                    .filter(|p| !std::ptr::eq(**p, a) && !std::ptr::eq(**p, b) && !std::ptr::eq(**p, c))
+                    .any(|p| in_triangle(p, a, b, c))
+        });
+        // Only degenerate rings have no ear; what is left covers no area
+        let Some(i) = ear else { break };
+        let (a, b, c) = (remaining[(i + n - 1) % n], remaining[i], remaining[(i + 1) % n]);
+        triangles.push([a.clone(), b.clone(), c.clone()]);
+        remaining.remove(i);
+    }
+    if remaining.len() == 3 {
+        triangles.push([remaining[0].clone(), remaining[1].clone(), remaining[2].clone()]);
+    }
+    triangles
+}
+
+fn in_triangle(p: &Point, a: &Point, b: &Point, c: &Point) -> bool {
+    let side = |o: &Point, q: &Point| (q.x - o.x) * (p.y - o.y) - (q.y - o.y) * (p.x - o.x);
+    let (d1, d2, d3) = (side(a, b), side(b, c), side(c, a));
+    let negative = d1 < 0.0 || d2 < 0.0 || d3 < 0.0;
+    let positive = d1 > 0.0 || d2 > 0.0 || d3 > 0.0;
+    !(negative && positive)
+}
+
+/// Signed area and centroid of one ring
+fn ring_centroid(ring: &[Point]) -> (f64, Point) {
+    let n = ring.len();
+    let (mut cx, mut cy) = (0.0, 0.0);
+    for i in 0..n {
+        let (p, q) = (&ring[i], &ring[(i + 1) % n]);
+        let cross = p.x * q.y - q.x * p.y;
+        cx += (p.x + q.x) * cross;
+        cy += (p.y + q.y) * cross;
+    }
+    let a = signed_area(ring);
+    if a.abs() <= f64::EPSILON {
+        return (0.0, mean(ring));
+    }
+    // Sign-independent: both the sums and the area flip with orientation
+    (a.abs(), Point { x: cx / (6.0 * a), y: cy / (6.0 * a) })
+}
+
+fn turn(center: &Point, dx: f64, dy: f64, angle: f64) -> Point {
+    let (sin, cos) = angle.to_radians().sin_cos();
+    Point { x: center.x + dx * cos - dy * sin, y: center.y + dx * sin + dy * cos }
+}
+
+fn path_length(points: &[Point], closed: bool) -> f64 {
+    let open: f64 = points.windows(2).map(|pair| distance(&pair[0], &pair[1])).sum();
+    match (closed, points.first(), points.last()) {
+        (true, Some(first), Some(last)) if points.len() > 2 => open + distance(last, first),
+        _ => open,
+    }
+}
+
+fn distance(a: &Point, b: &Point) -> f64 {
+    (b.x - a.x).hypot(b.y - a.y)
+}
+
+fn mean(points: &[Point]) -> Point {
+    let n = points.len().max(1) as f64;
+    Point { x: points.iter().map(|p| p.x).sum::<f64>() / n, y: points.iter().map(|p| p.y).sum::<f64>() / n }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn p(x: f64, y: f64) -> Point {
+        Point { x, y }
+    }
+
+    #[test]
+    fn test_measurements_and_iou() {
+        let square = Geometry::BBox { start: p(0.0, 0.0), end: p(4.0, 4.0) };
+        let m = measure(&square);
+        assert_eq!((m.area, m.perimeter, m.centroid.clone()), (16.0, 16.0, p(2.0, 2.0)));
+        assert_eq!(m.bounding_box, BoundingBox { min: p(0.0, 0.0), max: p(4.0, 4.0) });
+
+        let framed = Geometry::Polygon {
+            points: vec![p(0.0, 0.0), p(4.0, 0.0), p(4.0, 4.0), p(0.0, 4.0)],
+            holes: vec![vec![p(1.0, 1.0), p(1.0, 2.0), p(2.0, 2.0), p(2.0, 1.0)]],
+        };
+        assert_eq!(area(&framed), 15.0);
+        assert_eq!(perimeter(&framed), 20.0);
+
+        // An L shape overlapping half of the square it is compared with
+        let l_shape = Geometry::Polygon {
+            points: vec![p(2.0, 0.0), p(6.0, 0.0), p(6.0, 6.0), p(0.0, 6.0), p(0.0, 4.0), p(2.0, 4.0)],
+            holes: vec![],
+        };
+        let shared = 8.0;
+        let expected = shared / (16.0 + area(&l_shape) - shared);
+        assert!((iou(&square, &l_shape).unwrap() - expected).abs() < 1e-9);
+        assert_eq!(iou(&square, &square), Some(1.0));
+        assert_eq!(iou(&square, &Geometry::Point { at: p(1.0, 1.0) }), None);
+
+        let line = Geometry::Polyline { points: vec![p(0.0, 0.0), p(3.0, 4.0), p(3.0, 8.0)] };
+        assert_eq!(perimeter(&line), 9.0);
+    }
+}
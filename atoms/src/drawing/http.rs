@@ -1,7 +1,12 @@
 use aws_sdk_dynamodb::Client as DynamoClient;
 use lambda_http::{Body, Error, Response, http::StatusCode};
-use super::model::{CreateAnnotationPayload, UpdateAnnotationPayload};
+use super::model::{AnnotationChange, CreateAnnotationPayload, NormalizeGeometryPayload, UpdateAnnotationPayload};
 use super::service;
+use super::spatial::SpatialQuery;
+use super::validate::{issues_from_error, INVALID_GEOMETRY};
+use crate::labels::schema::INVALID_ATTRIBUTES;
+use crate::store::version::etag;
+use crate::store::{AnnotationRepo, DynamoStore, Page, PageRequest, INVALID_CURSOR, VERSION_CONFLICT};
 
 pub async fn create_annotation(
     client: &DynamoClient,
@@ -13,13 +18,22 @@ pub async fn create_annotation(
 ) -> Result<Response<Body>, Error> {
     let payload: CreateAnnotationPayload = serde_json::from_slice(body)?;
     
-    match service::create_annotation(client, table_name, block_id, image_id, user_id, payload).await {
+    let store = DynamoStore::new(client, table_name);
+    match service::create_annotation(&store, block_id, image_id, user_id, payload).await {
         Ok(annotation) => Ok(Response::builder()
             .status(StatusCode::CREATED)
             .header("Content-Type", "application/json")
+            .header("ETag", etag(annotation.version))
             .header("Access-Control-Allow-Origin", "*")
             .body(serde_json::to_string(&annotation)?.into())
             .map_err(Box::new)?),
+        Err(e) if e.starts_with(INVALID_ATTRIBUTES) || e.starts_with(INVALID_GEOMETRY) => unprocessable(&e),
+        Err(e) if e == "Block not found" || e == "Image not found" => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({ "error": e }).to_string().into())
+            .map_err(Box::new)?),
         Err(e) => Ok(Response::builder()
             .status(StatusCode::INTERNAL_SERVER_ERROR)
             .header("Content-Type", "application/json")
@@ -29,17 +43,59 @@ pub async fn create_annotation(
     }
 }
 
+/// `spatial` narrows the list to a viewport or hit-test and is not paged
 pub async fn list_image_annotations(
     client: &DynamoClient,
     table_name: &str,
     image_id: &str,
+    page: &PageRequest,
+    spatial: Option<&SpatialQuery>,
+    measure: bool,
+    block_id: Option<&str>,
 ) -> Result<Response<Body>, Error> {
-    match service::list_annotations(client, table_name, image_id).await {
-        Ok(annotations) => Ok(Response::builder()
+    let store = DynamoStore::new(client, table_name);
+    let scale = if measure {
+        service::image_scale(&store, block_id, image_id).await
+    } else {
+        Ok(None)
+    };
+    let body = match (spatial, page.is_paged(), measure, scale) {
+        (_, _, _, Err(e)) => Err(e),
+        (Some(query), _, false, _) => service::query_annotations(&store, image_id, query)
+            .await
+            .and_then(|a| serde_json::to_string(&a).map_err(|e| e.to_string())),
+        (Some(query), _, true, Ok(scale)) => service::query_annotations(&store, image_id, query)
+            .await
+            .map(|a| service::measure_annotations(a, scale.as_ref()))
+            .and_then(|a| serde_json::to_string(&a).map_err(|e| e.to_string())),
+        (None, true, false, _) => service::list_annotations_page(&store, image_id, page)
+            .await
+            .and_then(|p| serde_json::to_string(&p).map_err(|e| e.to_string())),
+        (None, true, true, Ok(scale)) => service::list_annotations_page(&store, image_id, page)
+            .await
+            .map(|p| Page { items: service::measure_annotations(p.items, scale.as_ref()), next_cursor: p.next_cursor })
+            .and_then(|p| serde_json::to_string(&p).map_err(|e| e.to_string())),
+        (None, false, false, _) => service::list_annotations(&store, image_id)
+            .await
+            .and_then(|a| serde_json::to_string(&a).map_err(|e| e.to_string())),
+        (None, false, true, Ok(scale)) => service::list_annotations(&store, image_id)
+            .await
+            .map(|a| service::measure_annotations(a, scale.as_ref()))
+            .and_then(|a| serde_json::to_string(&a).map_err(|e| e.to_string())),
+    };
+
+    match body {
+        Ok(body) => Ok(Response::builder()
             .status(StatusCode::OK)
             .header("Content-Type", "application/json")
             .header("Access-Control-Allow-Origin", "*")
-            .body(serde_json::to_string(&annotations)?.into())
+            .body(body.into())
+            .map_err(Box::new)?),
+        Err(e) if e == INVALID_CURSOR => Ok(Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({ "error": e }).to_string().into())
             .map_err(Box::new)?),
         Err(e) => Ok(Response::builder()
             .status(StatusCode::INTERNAL_SERVER_ERROR)
@@ -50,19 +106,27 @@ pub async fn list_image_annotations(
     }
 }
 
+/// Moves the annotation to the trash
+/// `expected_version` comes from `If-Match`; a stale one gets a 409
 pub async fn delete_annotation(
     client: &DynamoClient,
     table_name: &str,
+    user_id: &str,
     block_id:&str,
     image_id: &str,
     annotation_id: &str,
+    expected_version: Option<u64>,
 ) -> Result<Response<Body>, Error> {
-    match service::delete_annotation(client, table_name, block_id, image_id, annotation_id).await {
+    let store = DynamoStore::new(client, table_name);
+    match service::delete_annotation(&store, block_id, image_id, annotation_id, expected_version, user_id).await {
         Ok(_) => Ok(Response::builder()
             .status(StatusCode::NO_CONTENT)
             .header("Access-Control-Allow-Origin", "*")
             .body(Body::Empty)
             .map_err(Box::new)?),
+        Err(e) if e == VERSION_CONFLICT || e == "Annotation not found" => {
+            conflict_response(&store, image_id, annotation_id).await
+        }
         Err(e) => Ok(Response::builder()
             .status(StatusCode::INTERNAL_SERVER_ERROR)
             .header("Content-Type", "application/json")
@@ -72,21 +136,33 @@ pub async fn delete_annotation(
     }
 }
 
+/// `expected_version` comes from `If-Match`; a stale one gets a 409
+/// Attributes that don't fit the label's schema or a bad geometry get a 422
+#[allow(clippy::too_many_arguments)]
 pub async fn update_annotation(
     client: &DynamoClient,
     table_name: &str,
+    user_id: &str,
+    block_id: &str,
     image_id: &str,
     annotation_id: &str,
+    expected_version: Option<u64>,
     body: &[u8],
 ) -> Result<Response<Body>, Error> {
     let payload: UpdateAnnotationPayload = serde_json::from_slice(body)?;
 
-    match service::update_annotation(client, table_name, image_id, annotation_id, payload).await {
-        Ok(_) => Ok(Response::builder()
+    let store = DynamoStore::new(client, table_name);
+    match service::update_annotation(&store, block_id, image_id, annotation_id, payload, expected_version, user_id).await {
+        Ok(annotation) => Ok(Response::builder()
             .status(StatusCode::NO_CONTENT)
+            .header("ETag", etag(annotation.version))
             .header("Access-Control-Allow-Origin", "*")
             .body(Body::Empty)
             .map_err(Box::new)?),
+        Err(e) if e == VERSION_CONFLICT || e == "Annotation not found" => {
+            conflict_response(&store, image_id, annotation_id).await
+        }
+        Err(e) if e.starts_with(INVALID_ATTRIBUTES) || e.starts_with(INVALID_GEOMETRY) => unprocessable(&e),
         Err(e) => Ok(Response::builder()
             .status(StatusCode::INTERNAL_SERVER_ERROR)
             .header("Content-Type", "application/json")
@@ -97,14 +173,131 @@ pub async fn update_annotation(
 }
 
 pub async fn get_annotation(
-    _client: &DynamoClient,
-    _table_name: &str,
-    _image_id: &str,
-    _annotation_id: &str,
+    client: &DynamoClient,
+    table_name: &str,
+    image_id: &str,
+    annotation_id: &str,
+) -> Result<Response<Body>, Error> {
+    let store = DynamoStore::new(client, table_name);
+    match store.get_annotation(image_id, annotation_id).await?.filter(|a| a.deleted_at.is_none()) {
+        Some(annotation) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .header("ETag", etag(annotation.version))
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::to_string(&annotation)?.into())
+            .map_err(Box::new)?),
+        None => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({ "error": "Annotation not found" }).to_string().into())
+            .map_err(Box::new)?),
+    }
+}
+
+/// GET /images/{iid}/annotations/{aid}/history - oldest first
+pub async fn get_annotation_history(
+    client: &DynamoClient,
+    table_name: &str,
+    image_id: &str,
+    annotation_id: &str,
+) -> Result<Response<Body>, Error> {
+    let store = DynamoStore::new(client, table_name);
+    history_response(service::annotation_history(&store, image_id, annotation_id).await)
+}
+
+/// GET /images/{iid}/history - every annotation change on the image, oldest first
+pub async fn get_image_history(
+    client: &DynamoClient,
+    table_name: &str,
+    image_id: &str,
+) -> Result<Response<Body>, Error> {
+    let store = DynamoStore::new(client, table_name);
+    history_response(service::image_history(&store, image_id).await)
+}
+
+fn history_response(history: Result<Vec<AnnotationChange>, String>) -> Result<Response<Body>, Error> {
+    match history {
+        Ok(changes) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::to_string(&changes)?.into())
+            .map_err(Box::new)?),
+        Err(e) => Ok(Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({ "error": e }).to_string().into())
+            .map_err(Box::new)?),
+    }
+}
+
+/// 409 carrying the annotation as the server has it, so the canvas can
+/// merge or warn; 404 if it was deleted in the meantime
+async fn conflict_response(
+    store: &DynamoStore,
+    image_id: &str,
+    annotation_id: &str,
 ) -> Result<Response<Body>, Error> {
+    match store.get_annotation(image_id, annotation_id).await?.filter(|a| a.deleted_at.is_none()) {
+        Some(current) => Ok(Response::builder()
+            .status(StatusCode::CONFLICT)
+            .header("Content-Type", "application/json")
+            .header("ETag", etag(current.version))
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({ "error": VERSION_CONFLICT, "current": current }).to_string().into())
+            .map_err(Box::new)?),
+        None => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({ "error": "Annotation not found" }).to_string().into())
+            .map_err(Box::new)?),
+    }
+}
+
+/// Tidy and validate a geometry without saving it; answers with what
+/// create or update would store
+pub async fn normalize_geometry(
+    client: &DynamoClient,
+    table_name: &str,
+    block_id: &str,
+    image_id: &str,
+    body: &[u8],
+) -> Result<Response<Body>, Error> {
+    let payload: NormalizeGeometryPayload = serde_json::from_slice(body)?;
+
+    let store = DynamoStore::new(client, table_name);
+    match service::preview_geometry(&store, block_id, image_id, payload).await {
+        Ok(geometry) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({ "geometry": geometry }).to_string().into())
+            .map_err(Box::new)?),
+        Err(e) if e.starts_with(INVALID_GEOMETRY) => unprocessable(&e),
+        Err(e) => Ok(Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({ "error": e }).to_string().into())
+            .map_err(Box::new)?)
+    }
+}
+
+/// 422 for attributes that don't fit the label's schema or a geometry that
+/// can't be stored; geometry errors list each issue and where it is
+fn unprocessable(error: &str) -> Result<Response<Body>, Error> {
+    let body = match issues_from_error(error) {
+        Some(issues) => serde_json::json!({ "error": INVALID_GEOMETRY, "issues": issues }),
+        None => serde_json::json!({ "error": error }),
+    };
     Ok(Response::builder()
-        .status(StatusCode::NOT_IMPLEMENTED)
-        .body(Body::from("Get not implemented"))
+        .status(StatusCode::UNPROCESSABLE_ENTITY)
+        .header("Content-Type", "application/json")
+        .header("Access-Control-Allow-Origin", "*")
+        .body(body.to_string().into())
         .map_err(Box::new)?)
 }
-
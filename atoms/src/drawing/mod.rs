@@ -1,6 +1,10 @@
 pub mod model;
 pub mod service;
 pub mod http;
+pub mod validate;
+pub mod geometry;
+pub mod cleanup;
+pub mod spatial;
 
-pub use model::{Annotation, CreateAnnotationPayload, UpdateAnnotationPayload};
+pub use model::{Annotation, CreateAnnotationPayload, MeasuredAnnotation, UpdateAnnotationPayload};
 pub use http::*;
@@ -1,18 +1,59 @@
+use super::cleanup::Cleanup;
+use super::geometry::Measurements;
+use crate::labels::schema::Attributes;
+use crate::media::scale::RealMeasurements;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Point {
     pub x: f64,
     pub y: f64,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Shape of an annotation in image pixel coordinates (origin top left, y down)
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 #[serde(tag = "type")]
 pub enum Geometry {
+    /// Closed ring; `holes` are rings cut out of it, e.g. a courtyard
     #[serde(rename = "polygon")]
-    Polygon { points: Vec<Point> },
+    Polygon {
+        points: Vec<Point>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        holes: Vec<Vec<Point>>,
+    },
     #[serde(rename = "bbox")]
     BBox { start: Point, end: Point },
+    /// Open line through `points`, e.g. a wall or dimension line
+    #[serde(rename = "polyline")]
+    Polyline { points: Vec<Point> },
+    /// Single location, e.g. a downlight or GPO
+    #[serde(rename = "point")]
+    Point { at: Point },
+    /// Box of `width` by `height` around `center`, turned `angle` degrees
+    /// clockwise
+    #[serde(rename = "rotated_box")]
+    RotatedBox { center: Point, width: f64, height: f64, angle: f64 },
+    /// Ellipse with radii `rx` and `ry` around `center`, turned `angle`
+    /// degrees clockwise
+    #[serde(rename = "ellipse")]
+    Ellipse {
+        center: Point,
+        rx: f64,
+        ry: f64,
+        #[serde(default)]
+        angle: f64,
+    },
+    /// Several polygons, each with its own holes
+    #[serde(rename = "multipolygon")]
+    MultiPolygon { polygons: Vec<PolygonRings> },
+}
+
+/// One polygon of a `MultiPolygon`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PolygonRings {
+    pub points: Vec<Point>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub holes: Vec<Vec<Point>>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -21,24 +62,191 @@ pub struct Annotation {
     pub image_id: String,
     pub label_id: String,
     pub geometry: Geometry,
+    /// Values for the attributes the label declares
+    #[serde(default, skip_serializing_if = "Attributes::is_empty")]
+    pub attributes: Attributes,
     pub created_by: String,
     pub created_at: String,
     pub updated_at: Option<String>,
+    /// Bumped on every update; sent as the `ETag`
+    #[serde(default)]
+    pub version: u64,
+    /// Set while the row sits in the trash
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<String>,
+    /// Trash entry this row was trashed under; restore clears it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trash_id: Option<String>,
+}
+
+/// An annotation listed with its derived measurements
+#[derive(Debug, Serialize, Clone)]
+pub struct MeasuredAnnotation {
+    #[serde(flatten)]
+    pub annotation: Annotation,
+    pub measurements: Measurements,
+    /// `measurements` in metres, when the image's scale is calibrated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub real_world: Option<RealMeasurements>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct CreateAnnotationPayload {
     pub label_id: String,
     pub geometry: Geometry,
+    #[serde(default)]
+    pub attributes: Attributes,
+    /// Tidy the geometry before it is validated and saved
+    #[serde(default)]
+    pub cleanup: Option<Cleanup>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct UpdateAnnotationPayload {
     pub label_id: Option<String>,
     pub geometry: Option<Geometry>,
+    /// Replaces all of the annotation's attributes
+    #[serde(default)]
+    pub attributes: Option<Attributes>,
+    /// Tidy the new geometry before it is validated and saved
+    #[serde(default)]
+    pub cleanup: Option<Cleanup>,
+}
+
+/// Body of the standalone cleanup: the geometry to tidy and validate
+/// without saving. `annotation_id` keeps an edited annotation from
+/// snapping to itself.
+#[derive(Debug, Deserialize)]
+pub struct NormalizeGeometryPayload {
+    pub geometry: Geometry,
+    #[serde(default)]
+    pub cleanup: Cleanup,
+    #[serde(default)]
+    pub annotation_id: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct CreateBatchAnnotationsPayload {
     pub annotations: Vec<CreateAnnotationPayload>,
 }
+
+/// What an annotation change did
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeAction {
+    Create,
+    Update,
+    Delete,
+    /// Brought back from the trash
+    Restore,
+}
+
+/// Label, geometry and attributes of an annotation at one point in its history
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AnnotationState {
+    pub label_id: String,
+    pub geometry: Geometry,
+    #[serde(default, skip_serializing_if = "Attributes::is_empty")]
+    pub attributes: Attributes,
+}
+
+impl From<&Annotation> for AnnotationState {
+    fn from(annotation: &Annotation) -> Self {
+        AnnotationState {
+            label_id: annotation.label_id.clone(),
+            geometry: annotation.geometry.clone(),
+            attributes: annotation.attributes.clone(),
+        }
+    }
+}
+
+/// One append-only history entry, written in the same transaction as the
+/// change it records. `before` is empty for creates and restores, `after`
+/// for deletes.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AnnotationChange {
+    pub change_id: String,
+    pub image_id: String,
+    pub annotation_id: String,
+    pub action: ChangeAction,
+    pub actor: String,
+    pub at: String,
+    /// Annotation version after the change
+    pub version: u64,
+    pub before: Option<AnnotationState>,
+    pub after: Option<AnnotationState>,
+}
+
+impl AnnotationChange {
+    pub fn created(annotation: &Annotation, actor: &str, at: &str) -> Self {
+        Self::record(ChangeAction::Create, annotation, actor, at, None, Some(annotation))
+    }
+
+    pub fn updated(before: &Annotation, after: &Annotation, actor: &str, at: &str) -> Self {
+        Self::record(ChangeAction::Update, after, actor, at, Some(before), Some(after))
+    }
+
+    pub fn deleted(annotation: &Annotation, actor: &str, at: &str) -> Self {
+        Self::record(ChangeAction::Delete, annotation, actor, at, Some(annotation), None)
+    }
+
+    pub fn restored(annotation: &Annotation, actor: &str, at: &str) -> Self {
+        Self::record(ChangeAction::Restore, annotation, actor, at, None, Some(annotation))
+    }
+
+    fn record(
+        action: ChangeAction,
+        annotation: &Annotation,
+        actor: &str,
+        at: &str,
+        before: Option<&Annotation>,
+        after: Option<&Annotation>,
+    ) -> Self {
+        AnnotationChange {
+            change_id: uuid::Uuid::new_v4().to_string(),
+            image_id: annotation.image_id.clone(),
+            annotation_id: annotation.annotation_id.clone(),
+            action,
+            actor: actor.to_string(),
+            at: at.to_string(),
+            version: annotation.version,
+            before: before.map(AnnotationState::from),
+            after: after.map(AnnotationState::from),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stored_geometries_still_parse_and_round_trip() {
+        // As written before holes and the other types existed
+        let polygon: Geometry =
+            serde_json::from_str(r#"{"type":"polygon","points":[{"x":0,"y":0},{"x":4,"y":0},{"x":4,"y":3}]}"#).unwrap();
+        assert!(matches!(&polygon, Geometry::Polygon { points, holes } if points.len() == 3 && holes.is_empty()));
+        assert!(!serde_json::to_string(&polygon).unwrap().contains("holes"));
+        let bbox: Geometry =
+            serde_json::from_str(r#"{"type":"bbox","start":{"x":0,"y":0},"end":{"x":1,"y":1}}"#).unwrap();
+        assert!(matches!(bbox, Geometry::BBox { .. }));
+
+        let p = |x, y| Point { x, y };
+        let shapes = [
+            Geometry::Polyline { points: vec![p(0.0, 0.0), p(5.0, 0.0)] },
+            Geometry::Point { at: p(2.0, 2.0) },
+            Geometry::RotatedBox { center: p(1.0, 1.0), width: 2.0, height: 1.0, angle: 30.0 },
+            Geometry::Ellipse { center: p(1.0, 1.0), rx: 2.0, ry: 1.0, angle: 0.0 },
+            Geometry::MultiPolygon {
+                polygons: vec![PolygonRings {
+                    points: vec![p(0.0, 0.0), p(9.0, 0.0), p(9.0, 9.0), p(0.0, 9.0)],
+                    holes: vec![vec![p(3.0, 3.0), p(6.0, 3.0), p(6.0, 6.0)]],
+                }],
+            },
+        ];
+        for shape in shapes {
+            let json = serde_json::to_string(&shape).unwrap();
+            assert_eq!(serde_json::from_str::<Geometry>(&json).unwrap(), shape);
+        }
+    }
+}
@@ -1,240 +1,366 @@
-use aws_sdk_dynamodb::Client as DynamoClient;
-use aws_sdk_dynamodb::types::AttributeValue;
-use super::model::{Annotation, Geometry, CreateAnnotationPayload, UpdateAnnotationPayload};
-
-/// Create a new annotation
-pub async fn create_annotation(
-    client: &DynamoClient,
-    table_name: &str,
+use super::cleanup::{self, Cleanup};
+use super::geometry;
+use super::spatial::{self, SpatialQuery};
+use super::model::{
+    Annotation, AnnotationChange, CreateAnnotationPayload, Geometry, MeasuredAnnotation, NormalizeGeometryPayload, Point,
+    UpdateAnnotationPayload,
+};
+use super::validate::{self, Bounds};
+use crate::labels::schema::{AttributeSchema, Attributes};
+use crate::media::scale::{DrawingScale, RealMeasurements};
+use crate::store::version::conflict_or;
+use crate::store::{BlockCounter, Counter, EntityKey, Page, PageRequest, Store, WriteOp, VERSION_CONFLICT};
+use crate::trash::model::{TrashEntry, TrashedEntity};
+
+/// Tries an update without `expected_version` makes before giving up
+const UPDATE_ATTEMPTS: usize = 3;
+
+/// Create a new annotation, recording it in the history and the user's
+/// undo log
+/// Fails with INVALID_ATTRIBUTES if the attributes don't fit the label's schema
+/// and INVALID_GEOMETRY if the geometry can't be normalized, and with "Block
+/// not found" or "Image not found" if either is missing or in the trash
+pub async fn create_annotation<S: Store>(
+    store: &S,
     block_id:&str,
     image_id: &str,
     user_id: &str,
     payload: CreateAnnotationPayload,
 ) -> Result<Annotation, String> {
-    let annotation_id = uuid::Uuid::new_v4().to_string();
+    let geometry = match &payload.cleanup {
+        Some(cleanup) => cleanup_geometry(store, image_id, None, payload.geometry, cleanup).await?,
+        None => payload.geometry,
+    };
+    let geometry = normalize_geometry(store, block_id, image_id, geometry).await?;
+    validate_attributes(store, block_id, &payload.label_id, &payload.attributes).await?;
+
     let now = chrono::Utc::now().to_rfc3339();
-    
-    let pk = format!("IMAGE#{}", image_id);
-    let sk = format!("ANNOTATION#{}", annotation_id);
-    
-    let geometry_json = serde_json::to_string(&payload.geometry)
-        .map_err(|e| format!("Failed to serialize geometry: {}", e))?;
-
-    client
-        .put_item()
-        .table_name(table_name)
-        .item("PK", AttributeValue::S(pk))
-        .item("SK", AttributeValue::S(sk))
-        .item("label_id", AttributeValue::S(payload.label_id.clone()))
-        .item("geometry", AttributeValue::S(geometry_json))
-        .item("created_by", AttributeValue::S(user_id.to_string()))
-        .item("created_at", AttributeValue::S(now.clone()))
-        .send()
-        .await
-        .map_err(|e| format!("DynamoDB put_item error: {}", e))?;
-
-
-    // Increment BLOCK- annotation_count
-    client
-        .update_item()
-        .table_name(table_name)
-        .key("PK", AttributeValue::S("BLOCK".to_string()))
-        .key("SK", AttributeValue::S(format!("BLOCK#{}", block_id)))
-        .update_expression("SET annotation_count = annotation_count + :one")
-        .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
-        .send()
-        .await
-        .map_err(|e| format!("DynamoDB update_item error: {}", e))?;
-
-
-    // Increment IMAGE - annotation_count
-    client
-        .update_item()
-        .table_name(table_name)
-        .key("PK", AttributeValue::S(format!("BLOCK#{}", block_id)))
-        .key("SK", AttributeValue::S(format!("IMAGE#{}", image_id)))
-        .update_expression("SET annotation_count = annotation_count + :one")
-        .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
-        .send()
-        .await
-        .map_err(|e| format!("DynamoDB update_item error: {}", e))?;
-
-
-
-
-
-    Ok(Annotation {
-        annotation_id,
+    let annotation = Annotation {
+        annotation_id: uuid::Uuid::new_v4().to_string(),
         image_id: image_id.to_string(),
         label_id: payload.label_id,
-        geometry: payload.geometry,
+        geometry,
+        attributes: payload.attributes,
         created_by: user_id.to_string(),
-        created_at: now,
+        created_at: now.clone(),
         updated_at: None,
-    })
+        version: 1,
+        deleted_at: None,
+        trash_id: None,
+    };
+
+    // Write the annotation and bump BLOCK + IMAGE annotation_count together;
+    // the counter updates also fail the write if either parent is gone
+    let change = AnnotationChange::created(&annotation, user_id, &now);
+    let log = crate::undo::service::logged(store, &change).await?;
+    let mut ops = vec![
+        WriteOp::PutAnnotation(annotation.clone()),
+        WriteOp::PutAnnotationChange(change),
+        log,
+        WriteOp::Increment {
+            key: EntityKey::block(block_id),
+            deltas: vec![(Counter::Block(BlockCounter::AnnotationCount), 1)],
+        },
+        WriteOp::Increment {
+            key: EntityKey::image(block_id, image_id),
+            deltas: vec![(Counter::ImageAnnotationCount, 1)],
+        },
+    ];
+    ops.extend(label_count_op(store, block_id, &annotation.label_id, 1).await?);
+    if let Err(e) = store.transact(ops).await {
+        if store.get_block(block_id).await?.is_none_or(|b| b.deleted_at.is_some()) {
+            return Err("Block not found".to_string());
+        }
+        if store.get_image(block_id, image_id).await?.is_none_or(|i| i.deleted_at.is_some()) {
+            return Err("Image not found".to_string());
+        }
+        return Err(e);
+    }
+
+    Ok(annotation)
 }
 
 /// List annotations for an image
-pub async fn list_annotations(
-    client: &DynamoClient,
-    table_name: &str,
+pub async fn list_annotations<S: Store>(
+    store: &S,
     image_id: &str,
 ) -> Result<Vec<Annotation>, String> {
-    let pk = format!("IMAGE#{}", image_id);
-    
-    let result = client
-        .query()
-        .table_name(table_name)
-        .key_condition_expression("PK = :pk AND begins_with(SK, :sk_prefix)")
-        .expression_attribute_values(":pk", AttributeValue::S(pk))
-        .expression_attribute_values(":sk_prefix", AttributeValue::S("ANNOTATION#".to_string()))
-        .send()
-        .await
-        .map_err(|e| format!("DynamoDB query error: {}", e))?;
-        
-    let mut annotations = Vec::new();
-    
-    for item in result.items() {
-        if let Some(sk) = item.get("SK").and_then(|v| v.as_s().ok()) {
-            if let Some(annotation_id) = sk.strip_prefix("ANNOTATION#") {
-                let geometry_str = item.get("geometry")
-                    .and_then(|v| v.as_s().ok())
-                    .ok_or("Missing geometry")?;
-                    
-                let geometry: Geometry = serde_json::from_str(geometry_str)
-                    .map_err(|e| format!("Failed to parse geometry: {}", e))?;
-                    
-                annotations.push(Annotation {
-                    annotation_id: annotation_id.to_string(),
-                    image_id: image_id.to_string(),
-                    label_id: item.get("label_id").and_then(|v| v.as_s().ok()).unwrap_or(&"default".to_string()).to_string(),
-                    geometry,
-                    created_by: item.get("created_by").and_then(|v| v.as_s().ok()).unwrap_or(&"".to_string()).to_string(),
-                    created_at: item.get("created_at").and_then(|v| v.as_s().ok()).unwrap_or(&"".to_string()).to_string(),
-                    updated_at: item.get("updated_at").and_then(|v| v.as_s().ok()).map(|s| s.to_string()),
-                });
-            }
-        }
-    }
-    
-    Ok(annotations)
+    store.list_annotations(image_id).await
+}
+
+/// Annotations on an image that meet a viewport or lie under a point,
+/// through an R-tree built for the request
+pub async fn query_annotations<S: Store>(
+    store: &S,
+    image_id: &str,
+    query: &SpatialQuery,
+) -> Result<Vec<Annotation>, String> {
+    let annotations = list_annotations(store, image_id).await?;
+    Ok(spatial::filter(annotations, query))
+}
+
+/// List one page of annotations for an image
+pub async fn list_annotations_page<S: Store>(
+    store: &S,
+    image_id: &str,
+    page: &PageRequest,
+) -> Result<Page<Annotation>, String> {
+    store.list_annotations_page(image_id, page).await
+}
+
+/// Attach area, perimeter, centroid and bounding box to each annotation,
+/// also in metres when `scale` is given
+pub fn measure_annotations(annotations: Vec<Annotation>, scale: Option<&DrawingScale>) -> Vec<MeasuredAnnotation> {
+    annotations
+        .into_iter()
+        .map(|annotation| {
+            let measurements = geometry::measure(&annotation.geometry);
+            let real_world = scale.map(|scale| RealMeasurements::new(&measurements, scale));
+            MeasuredAnnotation { annotation, measurements, real_world }
+        })
+        .collect()
 }
 
-/// Update annotation label
-pub async fn update_annotation(
-    client:&DynamoClient,
-    table_name:&str,
+/// The image's calibrated scale; None when it has none or `block_id` isn't
+/// known, since images are stored under their block
+pub async fn image_scale<S: Store>(
+    store: &S,
+    block_id: Option<&str>,
+    image_id: &str,
+) -> Result<Option<DrawingScale>, String> {
+    let Some(block_id) = block_id else {
+        return Ok(None);
+    };
+    Ok(store.get_image(block_id, image_id).await?.and_then(|image| image.scale))
+}
+
+/// Get a specific annotation
+pub async fn get_annotation<S: Store>(
+    store: &S,
+    image_id: &str,
+    annotation_id: &str,
+) -> Result<Annotation, String> {
+    store
+        .get_annotation(image_id, annotation_id)
+        .await?
+        .filter(|annotation| annotation.deleted_at.is_none())
+        .ok_or_else(|| "Annotation not found".to_string())
+}
+
+/// Update annotation label, geometry and/or attributes, recording the
+/// change in the history and the user's undo log
+/// With `expected_version`, fails with VERSION_CONFLICT if someone saved first
+/// Fails with INVALID_ATTRIBUTES if the resulting attributes don't fit the
+/// resulting label's schema and INVALID_GEOMETRY if a new geometry can't be
+/// normalized
+pub async fn update_annotation<S: Store>(
+    store: &S,
+    block_id: &str,
     image_id:&str,
     annotation_id:&str,
-    payload:UpdateAnnotationPayload
-    ) -> Result <(), String> {
+    mut payload:UpdateAnnotationPayload,
+    expected_version: Option<u64>,
+    user_id: &str,
+    ) -> Result <Annotation, String> {
+    if let Some(mut geometry) = payload.geometry.take() {
+        if let Some(cleanup) = &payload.cleanup {
+            geometry = cleanup_geometry(store, image_id, Some(annotation_id), geometry, cleanup).await?;
+        }
+        payload.geometry = Some(normalize_geometry(store, block_id, image_id, geometry).await?);
+    }
 
-    let pk = format!("IMAGE#{}", image_id);
-    let sk = format!("ANNOTATION#{}", annotation_id);
-    let now = chrono::Utc::now().to_rfc3339();
+    // The history needs the exact row the update replaced, so the write is
+    // pinned to the version read; without `expected_version` a concurrent
+    // save just means reading again
+    for _ in 0..UPDATE_ATTEMPTS {
+        let before = get_annotation(store, image_id, annotation_id).await?;
+        if expected_version.is_some_and(|v| v != before.version) {
+            return Err(VERSION_CONFLICT.to_string());
+        }
+        // Geometry-only edits leave the attributes valid for the label
+        if payload.label_id.is_some() || payload.attributes.is_some() {
+            let label_id = payload.label_id.as_ref().unwrap_or(&before.label_id);
+            let attributes = payload.attributes.as_ref().unwrap_or(&before.attributes);
+            validate_attributes(store, block_id, label_id, attributes).await?;
+        }
 
-    // Build dynamic // Start with just timestamp
-    let mut update_parts:Vec<&str> = vec!["updated_at = :updated_at"];
-    let mut expr_values:Vec<(String, AttributeValue)> = vec![(":updated_at".to_string(), AttributeValue::S(now))];
+        let (after, change, mut ops) = update_ops(&before, &payload, user_id);
+        ops.extend(relabel_ops(store, block_id, &before.label_id, &after.label_id).await?);
+        ops.push(crate::undo::service::logged(store, &change).await?);
+        let result = store.transact(ops).await;
+
+        match result {
+            Ok(()) => return Ok(after),
+            Err(e) => {
+                let current = store.get_annotation(image_id, annotation_id).await?;
+                let e = conflict_or(e, Some(before.version), current.map(|a| a.version));
+                if e != VERSION_CONFLICT || expected_version.is_some() {
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    Err(VERSION_CONFLICT.to_string())
+}
 
-    // If label_id exists, add it
+/// The row `payload` turns `before` into, its history entry, and the ops
+/// that write both. The update is pinned to `before.version`.
+pub fn update_ops(
+    before: &Annotation,
+    payload: &UpdateAnnotationPayload,
+    user_id: &str,
+) -> (Annotation, AnnotationChange, Vec<WriteOp>) {
+    // If nothing to update besides timestamp, that's fine
+    let now = chrono::Utc::now().to_rfc3339();
+    let mut after = before.clone();
+    after.version += 1;
+    after.updated_at = Some(now.clone());
     if let Some(label_id) = &payload.label_id {
-        update_parts.push("label_id = :label_id");
-        expr_values.push((":label_id".to_string(), AttributeValue::S(label_id.clone())));
+        after.label_id = label_id.clone();
     }
-    // Now: update_parts = ["updated_at = :updated_at", "label_id = :label_id"]
-
-    // If geometry exists, add it
-    // expr_values.push(":geometry" → "{\"type\":\"polygon\",\"points\":[...]}");
     if let Some(geometry) = &payload.geometry {
-        let geometry_json = serde_json::to_string(geometry).map_err(|e| format!("Faled to serialize geometry: {}", e))?;
-        update_parts.push("geometry = :geometry");
-        expr_values.push((":geometry".to_string(), AttributeValue::S(geometry_json)));
-
+        after.geometry = geometry.clone();
+    }
+    if let Some(attributes) = &payload.attributes {
+        after.attributes = attributes.clone();
     }
 
-    // If nothing to update besides timestamp, that's fine
-    let update_expression = format!("SET {}", update_parts.join(", "));
-
-
-    // UPDATE SET updated_at = :updated_at, label_id = :label_id, geometry = :geometry
-    // WHERE PK = "IMAGE#123" AND SK = "ANNOTATION#456"
-
-    // -- With values:
-    // -- :updated_at = "2024-01-11T00:51:21Z"
-    // -- :label_id = "cat"
-    // -- :geometry = "{\"type\":\"polygon\",...}"
-    let mut update_builder = client
-                                .update_item()
-                                .table_name(table_name)
-                                .key("PK", AttributeValue::S(pk))
-                                .key("SK", AttributeValue::S(sk))
-                                .update_expression(&update_expression);
-
-                                
-    for (name,value) in expr_values {
-        update_builder = update_builder.expression_attribute_values(name, value);
+    let change = AnnotationChange::updated(before, &after, user_id, &now);
+    let ops = vec![
+        WriteOp::UpdateAnnotation {
+            image_id: before.image_id.clone(),
+            annotation_id: before.annotation_id.clone(),
+            changes: payload.clone(),
+            updated_at: now,
+            expected_version: Some(before.version),
+        },
+        WriteOp::PutAnnotationChange(change.clone()),
+    ];
+    (after, change, ops)
+}
+
+/// The op adding `delta` to a label's annotation count; None once the
+/// label is gone, as annotations can outlive it
+pub(crate) async fn label_count_op<S: Store>(
+    store: &S,
+    block_id: &str,
+    label_id: &str,
+    delta: i64,
+) -> Result<Option<WriteOp>, String> {
+    Ok(store.get_label(block_id, label_id).await?.map(|_| WriteOp::Increment {
+        key: EntityKey::label(block_id, label_id),
+        deltas: vec![(Counter::LabelCount, delta)],
+    }))
+}
+
+/// The ops moving one annotation from label `from`'s count to `to`'s
+pub(crate) async fn relabel_ops<S: Store>(store: &S, block_id: &str, from: &str, to: &str) -> Result<Vec<WriteOp>, String> {
+    if from == to {
+        return Ok(vec![]);
     }
+    let mut ops: Vec<WriteOp> = label_count_op(store, block_id, from, -1).await?.into_iter().collect();
+    ops.extend(label_count_op(store, block_id, to, 1).await?);
+    Ok(ops)
+}
+
+/// Validate and normalize `geometry` for the image it is drawn on, clamping
+/// to the image's size when it is known
+pub async fn normalize_geometry<S: Store>(
+    store: &S,
+    block_id: &str,
+    image_id: &str,
+    geometry: Geometry,
+) -> Result<Geometry, String> {
+    let bounds = store.get_image(block_id, image_id).await?.and_then(|image| {
+        Some(Bounds { width: f64::from(image.width?), height: f64::from(image.height?) })
+    });
+    validate::normalize(geometry, bounds).map_err(|issues| validate::to_error(&issues))
+}
 
-    update_builder
-        .send()
-        .await
-        .map_err(|e| format!("DynamoDB update error: {}", e))?;
+/// What `geometry` would be saved as after cleanup and validation, without
+/// saving it
+pub async fn preview_geometry<S: Store>(
+    store: &S,
+    block_id: &str,
+    image_id: &str,
+    payload: NormalizeGeometryPayload,
+) -> Result<Geometry, String> {
+    let annotation_id = payload.annotation_id.as_deref();
+    let geometry = cleanup_geometry(store, image_id, annotation_id, payload.geometry, &payload.cleanup).await?;
+    normalize_geometry(store, block_id, image_id, geometry).await
+}
 
-    Ok(())
+/// Simplify, square up and snap `geometry`; snapping targets the vertices
+/// of the image's other annotations, leaving out `annotation_id` itself
+pub async fn cleanup_geometry<S: Store>(
+    store: &S,
+    image_id: &str,
+    annotation_id: Option<&str>,
+    geometry: Geometry,
+    cleanup: &Cleanup,
+) -> Result<Geometry, String> {
+    let neighbours: Vec<Point> = match cleanup.snap {
+        Some(_) => store
+            .list_annotations(image_id)
+            .await?
+            .iter()
+            .filter(|a| Some(a.annotation_id.as_str()) != annotation_id)
+            .flat_map(|a| cleanup::vertices(&a.geometry))
+            .collect(),
+        None => vec![],
+    };
+    Ok(cleanup::clean(geometry, cleanup, &neighbours))
 }
 
+/// Check `attributes` against the schema of label `label_id`; a label
+/// that is missing or declares no schema takes no attributes
+pub async fn validate_attributes<S: Store>(
+    store: &S,
+    block_id: &str,
+    label_id: &str,
+    attributes: &Attributes,
+) -> Result<(), String> {
+    let label = store.get_label(block_id, label_id).await?;
+    let schema = AttributeSchema::from_properties(label.as_ref().and_then(|l| l.label_properties.as_ref()))?;
+    schema.validate(attributes)
+}
 
-/// Delete annotation
-pub async fn delete_annotation(
-    client: &DynamoClient,
-    table_name: &str,
-    block_id:&str,
+/// History of one annotation, oldest first
+pub async fn annotation_history<S: Store>(
+    store: &S,
     image_id: &str,
     annotation_id: &str,
-) -> Result<(), String> {
-    let pk = format!("IMAGE#{}", image_id);
-    let sk = format!("ANNOTATION#{}", annotation_id);
-    
-    client
-        .delete_item()
-        .table_name(table_name)
-        .key("PK", AttributeValue::S(pk))
-        .key("SK", AttributeValue::S(sk))
-        .send()
-        .await
-        .map_err(|e| format!("DynamoDB delete_item error: {}", e))?;
-
-    // Decrement BLOCK- annotation_count
-    client
-        .update_item()
-        .table_name(table_name)
-        .key("PK", AttributeValue::S("BLOCK".to_string()))
-        .key("SK", AttributeValue::S(format!("BLOCK#{}", block_id)))
-        .update_expression("SET annotation_count = annotation_count - :one")
-        .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
-        .send()
-        .await
-        .map_err(|e| format!("DynamoDB update_item error: {}", e))?;
-        
-
-    // Decrement image annotation count
-    client
-        .update_item()
-        .table_name(table_name)
-        .key("PK", AttributeValue::S(format!("BLOCK#{}", block_id)))
-        .key("SK", AttributeValue::S(format!("IMAGE#{}", image_id)))
-        .update_expression("SET annotation_count = annotation_count - :one")
-        .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
-        .send()
-        .await
-        .map_err(|e| format!("DynamoDB delete_item error: {}", e))?;
-
-
-
-
-        
-    Ok(())
+) -> Result<Vec<AnnotationChange>, String> {
+    store.list_annotation_history(image_id, annotation_id).await
+}
+
+/// History of every annotation on an image, oldest first
+pub async fn image_history<S: Store>(store: &S, image_id: &str) -> Result<Vec<AnnotationChange>, String> {
+    let mut changes = store.list_image_history(image_id).await?;
+    changes.sort_by(|a, b| a.at.cmp(&b.at));
+    Ok(changes)
 }
 
 
+/// Move an annotation to the trash, recording the delete in the history
+/// and the user's undo log
+/// With `expected_version`, fails with VERSION_CONFLICT if someone saved first
+pub async fn delete_annotation<S: Store>(
+    store: &S,
+    block_id:&str,
+    image_id: &str,
+    annotation_id: &str,
+    expected_version: Option<u64>,
+    deleted_by: &str,
+) -> Result<TrashEntry, String> {
+    let entity = TrashedEntity::Annotation {
+        block_id: block_id.to_string(),
+        image_id: image_id.to_string(),
+        annotation_id: annotation_id.to_string(),
+    };
+    let annotation = get_annotation(store, image_id, annotation_id).await?;
+    let change = AnnotationChange::deleted(&annotation, deleted_by, &chrono::Utc::now().to_rfc3339());
+    let log = crate::undo::service::logged(store, &change).await?;
+    crate::trash::service::trash_with(store, entity, deleted_by, expected_version, Some(change), vec![log]).await
+}
@@ -0,0 +1,188 @@
+use super::geometry::{self, BoundingBox};
+use super::model::{Annotation, Geometry, Point};
+use rstar::primitives::{GeomWithData, Rectangle};
+use rstar::{RTree, AABB};
+
+/// How close, in pixels, a hit-test must land to a point or line by default
+pub const DEFAULT_HIT_TOLERANCE: f64 = 4.0;
+
+/// Which annotations on an image a spatial read wants
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpatialQuery {
+    /// Annotations whose bounding box meets the viewport
+    Within(BoundingBox),
+    /// Annotations under the point: inside a shape, or within `tolerance`
+    /// of a line, point or outline
+    At { point: Point, tolerance: f64 },
+}
+
+impl SpatialQuery {
+    /// Read `bbox=x0,y0,x1,y1` or `point=x,y` (with an optional
+    /// `tolerance`); None when neither is given
+    pub fn from_query(bbox: Option<&str>, point: Option<&str>, tolerance: Option<&str>) -> Result<Option<Self>, String> {
+        match (bbox, point) {
+            (Some(_), Some(_)) => Err("Give either bbox or point, not both".to_string()),
+            (Some(bbox), None) => match numbers(bbox)?.as_slice() {
+                &[x0, y0, x1, y1] => Ok(Some(SpatialQuery::Within(BoundingBox {
+                    min: Point { x: x0.min(x1), y: y0.min(y1) },
+                    max: Point { x: x0.max(x1), y: y0.max(y1) },
+                }))),
+                _ => Err("bbox must be x0,y0,x1,y1".to_string()),
+            },
+            (None, Some(point)) => {
+                let &[x, y] = numbers(point)?.as_slice() else {
+                    return Err("point must be x,y".to_string());
+                };
+                let tolerance = match tolerance {
+                    Some(t) => t.parse().ok().filter(|t: &f64| *t >= 0.0).ok_or("tolerance must be a number of pixels")?,
+                    None => DEFAULT_HIT_TOLERANCE,
+                };
+                Ok(Some(SpatialQuery::At { point: Point { x, y }, tolerance }))
+            }
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+/// R-tree over the bounding boxes of one image's annotations
+pub struct SpatialIndex {
+    tree: RTree<GeomWithData<Rectangle<[f64; 2]>, usize>>,
+}
+
+impl SpatialIndex {
+    /// Index `annotations` by position in the slice
+    pub fn new(annotations: &[Annotation]) -> Self {
+        let entries = annotations
+            .iter()
+            .enumerate()
+            .map(|(i, annotation)| {
+                let bbox = geometry::bounding_box(&annotation.geometry);
+                GeomWithData::new(Rectangle::from_corners([bbox.min.x, bbox.min.y], [bbox.max.x, bbox.max.y]), i)
+            })
+            .collect();
+        Self { tree: RTree::bulk_load(entries) }
+    }
+
+    /// Positions of the annotations matching `query`, ascending
+    pub fn query(&self, annotations: &[Annotation], query: &SpatialQuery) -> Vec<usize> {
+        let mut found: Vec<usize> = match query {
+            SpatialQuery::Within(bbox) => self
+                .tree
+                .locate_in_envelope_intersecting(&AABB::from_corners([bbox.min.x, bbox.min.y], [bbox.max.x, bbox.max.y]))
+                .map(|entry| entry.data)
+                .collect(),
+            SpatialQuery::At { point, tolerance } => {
+                let near = AABB::from_corners(
+                    [point.x - tolerance, point.y - tolerance],
+                    [point.x + tolerance, point.y + tolerance],
+                );
+                self.tree
+                    .locate_in_envelope_intersecting(&near)
+                    .map(|entry| entry.data)
+                    .filter(|&i| hits(&annotations[i].geometry, point, *tolerance))
+                    .collect()
+            }
+        };
+        found.sort_unstable();
+        found
+    }
+}
+
+/// The annotations matching `query`, in their original order
+pub fn filter(annotations: Vec<Annotation>, query: &SpatialQuery) -> Vec<Annotation> {
+    let found = SpatialIndex::new(&annotations).query(&annotations, query);
+    let mut found = found.into_iter().peekable();
+    annotations
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| found.next_if_eq(i).is_some())
+        .map(|(_, annotation)| annotation)
+        .collect()
+}
+
+fn hits(geometry: &Geometry, point: &Point, tolerance: f64) -> bool {
+    match geometry {
+        Geometry::Point { at } => distance(at, point) <= tolerance,
+        Geometry::Polyline { points } => points.windows(2).any(|s| segment_distance(point, &s[0], &s[1]) <= tolerance),
+        _ => geometry::polygons(geometry).iter().any(|polygon| {
+            let rings = || std::iter::once(&polygon.points).chain(&polygon.holes);
+            let inside = contains(&polygon.points, point) && !polygon.holes.iter().any(|h| contains(h, point));
+            inside || rings().any(|ring| ring_distance(ring, point) <= tolerance)
+        }),
+    }
+}
+
+/// Even-odd test
+fn contains(ring: &[Point], point: &Point) -> bool {
+    let mut inside = false;
+    for (i, a) in ring.iter().enumerate() {
+        let b = &ring[(i + 1) % ring.len()];
+        if (a.y > point.y) != (b.y > point.y) && point.x < a.x + (point.y - a.y) * (b.x - a.x) / (b.y - a.y) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+fn ring_distance(ring: &[Point], point: &Point) -> f64 {
+    (0..ring.len())
+        .map(|i| segment_distance(point, &ring[i], &ring[(i + 1) % ring.len()]))
+        .fold(f64::INFINITY, f64::min)
+}
+
+fn segment_distance(p: &Point, a: &Point, b: &Point) -> f64 {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    let length = dx * dx + dy * dy;
+    if length == 0.0 {
+        return distance(p, a);
+    }
+    let t = (((p.x - a.x) * dx + (p.y - a.y) * dy) / length).clamp(0.0, 1.0);
+    distance(p, &Point { x: a.x + t * dx, y: a.y + t * dy })
+}
+
+fn distance(a: &Point, b: &Point) -> f64 {
+    (b.x - a.x).hypot(b.y - a.y)
+}
+
+fn numbers(value: &str) -> Result<Vec<f64>, String> {
+    value
+        .split(',')
+        .map(|n| n.trim().parse::<f64>().ok().filter(|n| n.is_finite()))
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| format!("{} is not a list of numbers", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn annotation(id: &str, geometry: Geometry) -> Annotation {
+        crate::fixtures::test_annotation("i1", id, "l1", geometry)
+    }
+
+    #[test]
+    fn test_viewport_and_hit_test() {
+        let p = |x, y| Point { x, y };
+        let annotations = vec![
+            annotation("room", Geometry::Polygon {
+                points: vec![p(0.0, 0.0), p(100.0, 0.0), p(100.0, 100.0), p(0.0, 100.0)],
+                holes: vec![vec![p(40.0, 40.0), p(60.0, 40.0), p(60.0, 60.0), p(40.0, 60.0)]],
+            }),
+            annotation("wall", Geometry::Polyline { points: vec![p(200.0, 0.0), p(200.0, 100.0)] }),
+            annotation("light", Geometry::Point { at: p(500.0, 500.0) }),
+        ];
+        let ids = |query: &str| {
+            let query = SpatialQuery::from_query(None, Some(query), None).unwrap().unwrap();
+            filter(annotations.clone(), &query).into_iter().map(|a| a.annotation_id).collect::<Vec<_>>()
+        };
+
+        let viewport = SpatialQuery::from_query(Some("150,0,600,600"), None, None).unwrap().unwrap();
+        let found: Vec<_> = filter(annotations.clone(), &viewport).into_iter().map(|a| a.annotation_id).collect();
+        assert_eq!(found, vec!["wall", "light"]);
+
+        assert_eq!(ids("10,10"), vec!["room"]);
+        assert!(ids("50,50").is_empty(), "the hole is not part of the room");
+        assert_eq!(ids("202,50"), vec!["wall"]);
+        assert!(SpatialQuery::from_query(Some("1,2,3"), None, None).is_err());
+    }
+}
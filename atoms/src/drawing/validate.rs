@@ -0,0 +1,322 @@
+use super::model::{Geometry, Point, PolygonRings};
+use serde::{Deserialize, Serialize};
+
+/// Prefix of the error for a geometry that can't be stored; the issues
+/// follow as JSON, see `issues_from_error`
+pub const INVALID_GEOMETRY: &str = "Invalid geometry";
+
+/// Smallest area, in square pixels, an area shape may cover
+pub const MIN_AREA: f64 = 1.0;
+
+/// Vertices closer than this, in pixels, count as one
+const SAME_POINT: f64 = 1e-6;
+
+/// Pixel size of the image a geometry is drawn on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Bounds {
+    fn contains(&self, p: &Point) -> bool {
+        (0.0..=self.width).contains(&p.x) && (0.0..=self.height).contains(&p.y)
+    }
+
+    fn clamp(&self, p: &Point) -> Point {
+        Point { x: p.x.clamp(0.0, self.width), y: p.y.clamp(0.0, self.height) }
+    }
+}
+
+/// One reason a geometry was rejected. `polygon` indexes a multipolygon's
+/// polygons; `ring` is 0 for the outline and `n` for hole `n - 1`;
+/// `vertex` indexes the ring's points as sent.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct GeometryIssue {
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub polygon: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ring: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vertex: Option<usize>,
+}
+
+impl GeometryIssue {
+    fn new(message: impl Into<String>) -> Self {
+        GeometryIssue { message: message.into(), polygon: None, ring: None, vertex: None }
+    }
+
+    fn at(mut self, vertex: usize) -> Self {
+        self.vertex = Some(vertex);
+        self
+    }
+}
+
+/// The error string carrying `issues`
+pub fn to_error(issues: &[GeometryIssue]) -> String {
+    format!("{}: {}", INVALID_GEOMETRY, serde_json::to_string(issues).unwrap_or_default())
+}
+
+/// The issues inside an INVALID_GEOMETRY error; None for other errors
+pub fn issues_from_error(error: &str) -> Option<Vec<GeometryIssue>> {
+    let json = error.strip_prefix(INVALID_GEOMETRY)?.strip_prefix(": ")?;
+    serde_json::from_str(json).ok()
+}
+
+/// Check a geometry and return it in stored form, or every issue found.
+///
+/// Rings are stored open (no repeated closing vertex), without repeated
+/// vertices, with outlines positive by the shoelace formula in image
+/// coordinates (clockwise on screen) and holes the other way. With
+/// `bounds`, vertices are clamped into the image and points and centres
+/// must lie inside it. Bboxes are stored start top left, end bottom right.
+pub fn normalize(geometry: Geometry, bounds: Option<Bounds>) -> Result<Geometry, Vec<GeometryIssue>> {
+    let mut issues = Vec::new();
+    let geometry = match geometry {
+        Geometry::Polygon { points, holes } => {
+            let rings = polygon(&PolygonRings { points, holes }, bounds, &mut issues);
+            Geometry::Polygon { points: rings.points, holes: rings.holes }
+        }
+        Geometry::MultiPolygon { polygons } => {
+            if polygons.is_empty() {
+                issues.push(GeometryIssue::new("multipolygon has no polygons"));
+            }
+            let mut normalized = Vec::new();
+            for (index, rings) in polygons.iter().enumerate() {
+                let mut found = Vec::new();
+                normalized.push(polygon(rings, bounds, &mut found));
+                issues.extend(found.into_iter().map(|issue| GeometryIssue { polygon: Some(index), ..issue }));
+            }
+            Geometry::MultiPolygon { polygons: normalized }
+        }
+        Geometry::Polyline { points } => {
+            let points = clean(&points, bounds, false, &mut issues);
+            if issues.is_empty() && points.len() < 2 {
+                issues.push(GeometryIssue::new("polyline needs at least 2 distinct vertices"));
+            }
+            Geometry::Polyline { points: points.into_iter().map(|(_, p)| p).collect() }
+        }
+        Geometry::BBox { start, end } => {
+            let (start, end) = match (finite(&start, 0, &mut issues), finite(&end, 1, &mut issues)) {
+                (true, true) => {
+                    let (a, b) = match bounds {
+                        Some(bounds) => (bounds.clamp(&start), bounds.clamp(&end)),
+                        None => (start, end),
+                    };
+                    let start = Point { x: a.x.min(b.x), y: a.y.min(b.y) };
+                    let end = Point { x: a.x.max(b.x), y: a.y.max(b.y) };
+                    min_area((end.x - start.x) * (end.y - start.y), &mut issues);
+                    (start, end)
+                }
+                _ => (start, end),
+            };
+            Geometry::BBox { start, end }
+        }
+        Geometry::Point { at } => {
+            if finite(&at, 0, &mut issues) {
+                inside(&at, bounds, &mut issues);
+            }
+            Geometry::Point { at }
+        }
+        Geometry::RotatedBox { center, width, height, angle } => {
+            if finite(&center, 0, &mut issues) {
+                inside(&center, bounds, &mut issues);
+            }
+            sizes(&[("width", width), ("height", height), ("angle", angle)], &mut issues);
+            if issues.is_empty() {
+                min_area(width * height, &mut issues);
+            }
+            Geometry::RotatedBox { center, width, height, angle: angle.rem_euclid(360.0) }
+        }
+        Geometry::Ellipse { center, rx, ry, angle } => {
+            if finite(&center, 0, &mut issues) {
+                inside(&center, bounds, &mut issues);
+            }
+            sizes(&[("rx", rx), ("ry", ry), ("angle", angle)], &mut issues);
+            if issues.is_empty() {
+                min_area(std::f64::consts::PI * rx * ry, &mut issues);
+            }
+            Geometry::Ellipse { center, rx, ry, angle: angle.rem_euclid(360.0) }
+        }
+    };
+
+    if issues.is_empty() {
+        Ok(geometry)
+    } else {
+        Err(issues)
+    }
+}
+
+/// Shoelace area of a ring; positive for outlines in stored orientation
+pub fn signed_area(ring: &[Point]) -> f64 {
+    let n = ring.len();
+    (0..n).map(|i| ring[i].x * ring[(i + 1) % n].y - ring[(i + 1) % n].x * ring[i].y).sum::<f64>() / 2.0
+}
+
+/// Normalize an outline and its holes, pushing issues tagged with their ring
+fn polygon(rings: &PolygonRings, bounds: Option<Bounds>, issues: &mut Vec<GeometryIssue>) -> PolygonRings {
+    let mut normalized = PolygonRings { points: Vec::new(), holes: Vec::new() };
+    let mut area = 0.0;
+    for (index, ring) in std::iter::once(&rings.points).chain(&rings.holes).enumerate() {
+        let mut found = Vec::new();
+        let kept = clean(ring, bounds, true, &mut found);
+        if found.is_empty() && kept.len() < 3 {
+            found.push(GeometryIssue::new("ring needs at least 3 distinct vertices"));
+        }
+        if found.is_empty() {
+            if let Some(vertex) = self_intersection(&kept) {
+                found.push(GeometryIssue::new("ring crosses itself").at(vertex));
+            }
+        }
+        let mut points: Vec<Point> = kept.into_iter().map(|(_, p)| p).collect();
+        if found.is_empty() {
+            // Outline positive, holes negative
+            let signed = signed_area(&points);
+            if (index == 0) != (signed > 0.0) {
+                points.reverse();
+            }
+            area += if index == 0 { signed.abs() } else { -signed.abs() };
+        }
+
+        issues.extend(found.into_iter().map(|issue| GeometryIssue { ring: Some(index), ..issue }));
+        if index == 0 {
+            normalized.points = points;
+        } else {
+            normalized.holes.push(points);
+        }
+    }
+    if issues.is_empty() {
+        min_area(area, issues);
+    }
+    normalized
+}
+
+/// Finite check, clamping and dedup of a vertex list, keeping each kept
+/// vertex's index as sent. For rings a closing copy of the first vertex is
+/// dropped.
+fn clean(points: &[Point], bounds: Option<Bounds>, ring: bool, issues: &mut Vec<GeometryIssue>) -> Vec<(usize, Point)> {
+    let mut kept: Vec<(usize, Point)> = Vec::with_capacity(points.len());
+    for (index, point) in points.iter().enumerate() {
+        if !finite(point, index, issues) {
+            continue;
+        }
+        let point = match bounds {
+            Some(bounds) => bounds.clamp(point),
+            None => point.clone(),
+        };
+        if kept.last().is_none_or(|(_, last)| !same(last, &point)) {
+            kept.push((index, point));
+        }
+    }
+    if ring && kept.len() > 1 && same(&kept[0].1, &kept[kept.len() - 1].1) {
+        kept.pop();
+    }
+    kept
+}
+
+/// Index of the first vertex whose outgoing edge crosses a later edge that
+/// it doesn't share a vertex with
+fn self_intersection(ring: &[(usize, Point)]) -> Option<usize> {
+    let n = ring.len();
+    let edge = |i: usize| (&ring[i].1, &ring[(i + 1) % n].1);
+    (0..n).find_map(|i| {
+        // The last edge shares the first vertex with edge 0
+        let last = if i == 0 { n - 1 } else { n };
+        let (a, b) = edge(i);
+        (i + 2..last).any(|j| {
+            let (c, d) = edge(j);
+            segments_cross(a, b, c, d)
+        })
+        .then_some(ring[i].0)
+    })
+}
+
+fn segments_cross(a: &Point, b: &Point, c: &Point, d: &Point) -> bool {
+    let cross = |o: &Point, p: &Point, q: &Point| (p.x - o.x) * (q.y - o.y) - (p.y - o.y) * (q.x - o.x);
+    let on_segment = |p: &Point, q: &Point, r: &Point| {
+        q.x >= p.x.min(r.x) && q.x <= p.x.max(r.x) && q.y >= p.y.min(r.y) && q.y <= p.y.max(r.y)
+    };
+    let (d1, d2) = (cross(c, d, a), cross(c, d, b));
+    let (d3, d4) = (cross(a, b, c), cross(a, b, d));
+    if ((d1 > 0.0 && d2 < 0.0) || (d1 < 0.0 && d2 > 0.0)) && ((d3 > 0.0 && d4 < 0.0) || (d3 < 0.0 && d4 > 0.0)) {
+        return true;
+    }
+    // Touching or overlapping counts as crossing
+    (d1 == 0.0 && on_segment(c, a, d))
+        || (d2 == 0.0 && on_segment(c, b, d))
+        || (d3 == 0.0 && on_segment(a, c, b))
+        || (d4 == 0.0 && on_segment(a, d, b))
+}
+
+fn same(a: &Point, b: &Point) -> bool {
+    (a.x - b.x).abs() < SAME_POINT && (a.y - b.y).abs() < SAME_POINT
+}
+
+fn finite(point: &Point, vertex: usize, issues: &mut Vec<GeometryIssue>) -> bool {
+    let ok = point.x.is_finite() && point.y.is_finite();
+    if !ok {
+        issues.push(GeometryIssue::new("coordinates must be finite numbers").at(vertex));
+    }
+    ok
+}
+
+fn inside(point: &Point, bounds: Option<Bounds>, issues: &mut Vec<GeometryIssue>) {
+    if bounds.is_some_and(|b| !b.contains(point)) {
+        issues.push(GeometryIssue::new("point lies outside the image").at(0));
+    }
+}
+
+fn sizes(values: &[(&str, f64)], issues: &mut Vec<GeometryIssue>) {
+    for (name, value) in values {
+        if !value.is_finite() {
+            issues.push(GeometryIssue::new(format!("{} must be a finite number", name)));
+        } else if *name != "angle" && *value <= 0.0 {
+            issues.push(GeometryIssue::new(format!("{} must be positive", name)));
+        }
+    }
+}
+
+fn min_area(area: f64, issues: &mut Vec<GeometryIssue>) {
+    if area < MIN_AREA {
+        issues.push(GeometryIssue::new(format!("area must be at least {} square pixels", MIN_AREA)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn p(x: f64, y: f64) -> Point {
+        Point { x, y }
+    }
+
+    #[test]
+    fn test_normalize_fixes_what_it_can_and_locates_the_rest() {
+        // Closed, anticlockwise on screen, with a repeated vertex and one past the edge
+        let polygon = Geometry::Polygon {
+            points: vec![p(0.0, 0.0), p(0.0, 10.0), p(0.0, 10.0), p(12.0, 10.0), p(10.0, 0.0), p(0.0, 0.0)],
+            holes: vec![],
+        };
+        let bounds = Some(Bounds { width: 10.0, height: 10.0 });
+        let Geometry::Polygon { points, .. } = normalize(polygon, bounds).unwrap() else { unreachable!() };
+        assert_eq!(points, [p(10.0, 0.0), p(10.0, 10.0), p(0.0, 10.0), p(0.0, 0.0)]);
+        assert!(signed_area(&points) > 0.0);
+
+        let bbox = normalize(Geometry::BBox { start: p(5.0, 5.0), end: p(1.0, 2.0) }, None).unwrap();
+        assert_eq!(bbox, Geometry::BBox { start: p(1.0, 2.0), end: p(5.0, 5.0) });
+
+        let bowtie = Geometry::Polygon { points: vec![p(0.0, 0.0), p(4.0, 4.0), p(4.0, 0.0), p(0.0, 4.0)], holes: vec![] };
+        let issues = normalize(bowtie, None).unwrap_err();
+        assert_eq!(issues[0].message, "ring crosses itself");
+        assert_eq!((issues[0].ring, issues[0].vertex), (Some(0), Some(0)));
+
+        let nan = Geometry::Polyline { points: vec![p(0.0, 0.0), p(f64::NAN, 1.0)] };
+        assert_eq!(normalize(nan, None).unwrap_err()[0].vertex, Some(1));
+        assert!(normalize(Geometry::Polygon { points: vec![], holes: vec![] }, None).is_err());
+        assert!(normalize(Geometry::Point { at: p(11.0, 1.0) }, bounds).is_err());
+
+        let issues = vec![GeometryIssue::new("area must be at least 1 square pixels")];
+        assert_eq!(issues_from_error(&to_error(&issues)), Some(issues));
+    }
+}
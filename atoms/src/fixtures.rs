@@ -0,0 +1,53 @@
+//! Rows for tests, filled with plain defaults. Tests that care about a
+//! field set it with struct update syntax, e.g.
+//! `Block { block_type: "Site Plan".to_string(), ..test_block("b1") }`.
+
+use crate::blocks::model::Block;
+use crate::drawing::model::{Annotation, Geometry};
+use crate::labels::model::Label;
+
+pub fn test_block(block_id: &str) -> Block {
+    Block {
+        block_id: block_id.to_string(),
+        block_name: "Block".to_string(),
+        block_type: "Floor Plan".to_string(),
+        block_company: None,
+        block_state: "draft".to_string(),
+        block_locked: false,
+        image_count: 0,
+        approved_image_count: 0,
+        annotation_count: 0,
+        block_created_at: "2024-01-01T00:00:00Z".to_string(),
+        deleted_at: None,
+        trash_id: None,
+    }
+}
+
+pub fn test_label(block_id: &str, label_id: &str, label_name: &str) -> Label {
+    Label {
+        label_id: label_id.to_string(),
+        block_id: block_id.to_string(),
+        label_name: label_name.to_string(),
+        label_color: "#0000ff".to_string(),
+        label_properties: None,
+        label_count: 0,
+        label_order: None,
+        version: 1,
+    }
+}
+
+pub fn test_annotation(image_id: &str, annotation_id: &str, label_id: &str, geometry: Geometry) -> Annotation {
+    Annotation {
+        annotation_id: annotation_id.to_string(),
+        image_id: image_id.to_string(),
+        label_id: label_id.to_string(),
+        geometry,
+        attributes: Default::default(),
+        created_by: "u1".to_string(),
+        created_at: "2024-01-01T00:00:00Z".to_string(),
+        updated_at: None,
+        version: 1,
+        deleted_at: None,
+        trash_id: None,
+    }
+}
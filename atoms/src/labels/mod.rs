@@ -0,0 +1,8 @@
+pub mod model;
+pub mod schema;
+
+pub use model::{
+    Label, CreateLabelPayload, UpdateLabelPayload, LabelTemplate, TemplateLabel, PutLabelTemplatePayload,
+    MergeLabelPayload, DeletePolicy,
+};
+pub use schema::{AttributeKind, AttributeSchema, AttributeSpec, Attributes, INVALID_ATTRIBUTES, INVALID_SCHEMA};
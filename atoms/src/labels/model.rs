@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+
+/// Label domain model - a class annotators can draw within a block
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Label {
+    pub label_id: String,
+    pub block_id: String,
+    pub label_name: String,
+    pub label_color: String,
+    pub label_properties: Option<serde_json::Value>,
+    pub label_count: u32,
+    /// Position in the block's label list; labels without one sort after
+    #[serde(default)]
+    pub label_order: Option<u32>,
+    /// Bumped on every update; sent as the `ETag`
+    #[serde(default)]
+    pub version: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateLabelPayload {
+    pub label_name: String,
+    pub label_color: String,
+    pub label_properties: Option<serde_json::Value>,
+    #[serde(default)]
+    pub label_order: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateLabelPayload {
+    pub label_name: Option<String>,
+    pub label_color: Option<String>,
+    pub label_properties: Option<serde_json::Value>,
+    #[serde(default)]
+    pub label_order: Option<u32>,
+}
+
+/// One label new blocks of a template's type start with
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TemplateLabel {
+    pub label_name: String,
+    pub label_color: String,
+    pub label_order: u32,
+    #[serde(default)]
+    pub label_properties: Option<serde_json::Value>,
+}
+
+/// Admin-managed label set for one block type: what a new block starts
+/// with and the order its labels are listed in
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LabelTemplate {
+    pub block_type: String,
+    pub labels: Vec<TemplateLabel>,
+    pub updated_by: String,
+    pub updated_at: String,
+}
+
+impl LabelTemplate {
+    /// Order of the template label called `label_name`
+    pub fn order_of(&self, label_name: &str) -> Option<u32> {
+        self.labels.iter().find(|l| l.label_name == label_name).map(|l| l.label_order)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PutLabelTemplatePayload {
+    pub labels: Vec<TemplateLabel>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MergeLabelPayload {
+    /// Label that takes over the merged label's annotations
+    pub into: String,
+}
+
+/// What deleting a label does to the annotations that use it
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeletePolicy {
+    /// Refuse while any live annotation uses the label
+    Refuse,
+    /// Move the label's annotations to the trash
+    Cascade,
+    /// Re-point the label's annotations to label `into`
+    Reassign { into: String },
+}
+
+impl DeletePolicy {
+    /// Parse `?policy=refuse|cascade|reassign&into=`; refuse when no policy
+    /// is given
+    pub fn from_query(policy: Option<&str>, into: Option<&str>) -> Result<Self, String> {
+        match (policy.unwrap_or("refuse"), into) {
+            ("refuse", _) => Ok(DeletePolicy::Refuse),
+            ("cascade", _) => Ok(DeletePolicy::Cascade),
+            ("reassign", Some(into)) => Ok(DeletePolicy::Reassign { into: into.to_string() }),
+            ("reassign", None) => Err("reassign needs the label to reassign to in into".to_string()),
+            (other, _) => Err(format!("Unknown delete policy: {}", other)),
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Attribute values stored on an annotation, by attribute name
+pub type Attributes = BTreeMap<String, Value>;
+
+/// Prefix of the error for attributes that don't fit the label's schema
+pub const INVALID_ATTRIBUTES: &str = "Invalid attributes";
+
+/// Prefix of the error for a `label_properties` schema that can't be parsed
+pub const INVALID_SCHEMA: &str = "Invalid attribute schema";
+
+/// Kind of value an attribute holds
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AttributeKind {
+    /// One of `values`
+    Enum { values: Vec<String> },
+    /// A number in `unit`, within `min..=max` when given
+    Number {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        unit: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        min: Option<f64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max: Option<f64>,
+    },
+    Boolean,
+    Text,
+}
+
+/// One attribute a label declares
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AttributeSpec {
+    #[serde(flatten)]
+    pub kind: AttributeKind,
+    #[serde(default)]
+    pub required: bool,
+}
+
+/// The attributes a label's annotations may carry, declared under
+/// `label_properties.attributes`:
+/// `{"attributes": {"swing": {"type": "enum", "values": ["left", "right"], "required": true}}}`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AttributeSchema {
+    pub attributes: BTreeMap<String, AttributeSpec>,
+}
+
+impl AttributeSchema {
+    /// Read the schema out of a label's properties; no `attributes` key
+    /// means the label declares none
+    pub fn from_properties(properties: Option<&Value>) -> Result<Self, String> {
+        let Some(declared) = properties.and_then(|p| p.get("attributes")) else {
+            return Ok(Self::default());
+        };
+        let attributes: BTreeMap<String, AttributeSpec> =
+            serde_json::from_value(declared.clone()).map_err(|e| format!("{}: {}", INVALID_SCHEMA, e))?;
+        for (name, spec) in &attributes {
+            match &spec.kind {
+                AttributeKind::Enum { values } if values.is_empty() => {
+                    return Err(format!("{}: {} has no values", INVALID_SCHEMA, name));
+                }
+                AttributeKind::Number { min: Some(min), max: Some(max), .. } if min > max => {
+                    return Err(format!("{}: {} has min above max", INVALID_SCHEMA, name));
+                }
+                _ => {}
+            }
+        }
+        Ok(Self { attributes })
+    }
+
+    /// Everything wrong with `values`, one message per problem; empty when
+    /// they fit. Null counts as missing.
+    pub fn problems(&self, values: &Attributes) -> Vec<String> {
+        let mut problems = Vec::new();
+        for name in values.keys().filter(|name| !self.attributes.contains_key(*name)) {
+            problems.push(format!("{} is not an attribute of this label", name));
+        }
+        for (name, spec) in &self.attributes {
+            match values.get(name).filter(|v| !v.is_null()) {
+                None if spec.required => problems.push(format!("{} is required", name)),
+                None => {}
+                Some(value) => problems.extend(spec.kind.problem(name, value)),
+            }
+        }
+        problems
+    }
+
+    /// `problems` as one error, prefixed with INVALID_ATTRIBUTES
+    pub fn validate(&self, values: &Attributes) -> Result<(), String> {
+        let problems = self.problems(values);
+        if problems.is_empty() {
+            return Ok(());
+        }
+        Err(format!("{}: {}", INVALID_ATTRIBUTES, problems.join("; ")))
+    }
+}
+
+impl AttributeKind {
+    fn problem(&self, name: &str, value: &Value) -> Option<String> {
+        match self {
+            AttributeKind::Enum { values } => match value.as_str() {
+                Some(v) if values.iter().any(|allowed| allowed == v) => None,
+                _ => Some(format!("{} must be one of {}", name, values.join(", "))),
+            },
+            AttributeKind::Number { unit, min, max } => {
+                let Some(n) = value.as_f64() else {
+                    return Some(format!("{} must be a number", name));
+                };
+                let unit = unit.as_deref().map(|u| format!(" {}", u)).unwrap_or_default();
+                match (min, max) {
+                    (Some(min), _) if n < *min => Some(format!("{} must be at least {}{}", name, min, unit)),
+                    (_, Some(max)) if n > *max => Some(format!("{} must be at most {}{}", name, max, unit)),
+                    _ => None,
+                }
+            }
+            AttributeKind::Boolean => (!value.is_boolean()).then(|| format!("{} must be true or false", name)),
+            AttributeKind::Text => (!value.is_string()).then(|| format!("{} must be text", name)),
+        }
+    }
+}
@@ -1,6 +1,12 @@
 pub mod media;
 pub mod tasks;
 pub mod users;
-// pub mod labels;
+pub mod labels;
 pub mod drawing;
 pub mod blocks;
+pub mod store;
+pub mod trash;
+pub mod undo;
+pub mod snapshots;
+#[cfg(any(test, feature = "test-fixtures"))]
+pub mod fixtures;
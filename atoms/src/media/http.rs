@@ -1,7 +1,9 @@
 use aws_sdk_dynamodb::Client as DynamoClient;
 use lambda_http::{Body, Error as LambdaError, Response, http::StatusCode};
 use super::model::UpdateImagePayload;
-use super::service::{delete_image, get_image, update_image};
+use super::scale::{Calibration, INVALID_SCALE};
+use super::service::{calibrate_image, delete_image, get_image, update_image};
+use crate::store::DynamoStore;
 
 /// HTTP Handler: GET /images/{id}
 pub async fn get_image_handler(
@@ -10,7 +12,8 @@ pub async fn get_image_handler(
     block_id: &str,
     image_id: &str,
 ) -> Result<Response<Body>, LambdaError> {
-    match get_image(client, table_name, block_id, image_id).await {
+    let store = DynamoStore::new(client, table_name);
+    match get_image(&store, block_id, image_id).await {
         Ok(image) => Ok(Response::builder()
             .status(StatusCode::OK)
             .header("Content-Type", "application/json")
@@ -42,7 +45,8 @@ pub async fn update_image_handler(
 ) -> Result<Response<Body>, LambdaError> {
     let payload: UpdateImagePayload = serde_json::from_slice(body)?;
     
-    match update_image(client, table_name, block_id, image_id, payload).await {
+    let store = DynamoStore::new(client, table_name);
+    match update_image(&store, block_id, image_id, payload).await {
         Ok(image) => Ok(Response::builder()
             .status(StatusCode::OK)
             .header("Content-Type", "application/json")
@@ -58,19 +62,67 @@ pub async fn update_image_handler(
     }
 }
 
-/// HTTP Handler: DELETE /images/{id}
+/// HTTP Handler: PUT /images/{id}/scale - calibrate from a reference line or
+/// a drawing ratio
+pub async fn calibrate_image_handler(
+    client: &DynamoClient,
+    table_name: &str,
+    block_id: &str,
+    image_id: &str,
+    body: &[u8],
+) -> Result<Response<Body>, LambdaError> {
+    let calibration: Calibration = serde_json::from_slice(body)?;
+
+    let store = DynamoStore::new(client, table_name);
+    match calibrate_image(&store, block_id, image_id, calibration).await {
+        Ok(image) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::to_string(&image)?.into())
+            .map_err(Box::new)?),
+        Err(e) if e == "Image not found" => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": e}).to_string().into())
+            .map_err(Box::new)?),
+        Err(e) if e.starts_with(INVALID_SCALE) => Ok(Response::builder()
+            .status(StatusCode::UNPROCESSABLE_ENTITY)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": e}).to_string().into())
+            .map_err(Box::new)?),
+        Err(e) => Ok(Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": e}).to_string().into())
+            .map_err(Box::new)?),
+    }
+}
+
+/// HTTP Handler: DELETE /images/{id} - moves the image to the trash
 pub async fn delete_image_handler(
     client: &DynamoClient,
     table_name: &str,
+    user_id: &str,
     block_id: &str,
     image_id: &str,
 ) -> Result<Response<Body>, LambdaError> {
-    match delete_image(client, table_name, block_id, image_id).await {
+    let store = DynamoStore::new(client, table_name);
+    match delete_image(&store, block_id, image_id, user_id).await {
         Ok(_) => Ok(Response::builder()
             .status(StatusCode::NO_CONTENT)
             .header("Access-Control-Allow-Origin", "*")
             .body(Body::Empty)
             .map_err(Box::new)?),
+        Err(e) if e == "Image not found" => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": e}).to_string().into())
+            .map_err(Box::new)?),
         Err(e) => Ok(Response::builder()
             .status(StatusCode::INTERNAL_SERVER_ERROR)
             .header("Content-Type", "application/json")
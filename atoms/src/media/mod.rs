@@ -2,8 +2,10 @@
 pub mod model;
 pub mod service;
 pub mod http;
+pub mod scale;
 
 pub use model::{Image, CreateImagePayload, UpdateImagePayload};
+pub use scale::{Calibration, DrawingScale, RealMeasurements};
 pub use service::*;
 pub use http::*;
 
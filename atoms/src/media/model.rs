@@ -1,4 +1,5 @@
 
+use super::scale::DrawingScale;
 use serde::{Deserialize, Serialize};
 
 /// Image domain model - represents a file/media asset
@@ -10,8 +11,22 @@ pub struct Image {
     pub url: String,
     pub locked: bool,
     pub order: Option<i32>,
+    /// Pixel size, once known; annotations are clamped to it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    /// Real-world size of a pixel, once calibrated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<DrawingScale>,
     pub annotation_count:u32,
     pub uploaded_at: String,
+    /// Set while the row sits in the trash
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<String>,
+    /// Trash entry this row was trashed under; restore clears it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trash_id: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -19,10 +34,21 @@ pub struct CreateImagePayload {
     pub url: String,
     pub task_id: Option<String>,
     pub order: Option<i32>,
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct UpdateImagePayload {
     pub locked: Option<bool>,
     pub order: Option<i32>,
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
+    /// Set through calibration only
+    #[serde(skip)]
+    pub scale: Option<DrawingScale>,
 }
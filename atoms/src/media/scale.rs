@@ -0,0 +1,144 @@
+use crate::drawing::geometry::Measurements;
+use crate::drawing::model::Point;
+use serde::{Deserialize, Serialize};
+
+/// Prefix of the error for a calibration that can't give a scale
+pub const INVALID_SCALE: &str = "Invalid scale";
+
+const MM_PER_INCH: f64 = 25.4;
+
+/// How an image's scale was calibrated
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Calibration {
+    /// A line drawn over a dimension of known length
+    Reference { start: Point, end: Point, length_mm: f64 },
+    /// A drawing ratio such as "1:100", scanned or rendered at `dpi`
+    Ratio { ratio: String, dpi: f64 },
+    /// A known resolution of the drawing itself
+    PixelsPerMetre { pixels_per_metre: f64 },
+}
+
+impl Calibration {
+    /// Read a calibration from query parameters: `pixels_per_metre`, or
+    /// `ratio` with `dpi`. None when neither is given.
+    pub fn from_query(
+        pixels_per_metre: Option<&str>,
+        ratio: Option<&str>,
+        dpi: Option<&str>,
+    ) -> Result<Option<Self>, String> {
+        let number = |name: &str, value: &str| value.parse::<f64>().map_err(|_| format!("{} must be a number", name));
+        match (pixels_per_metre, ratio, dpi) {
+            (Some(ppm), None, None) => {
+                Ok(Some(Calibration::PixelsPerMetre { pixels_per_metre: number("pixels_per_metre", ppm)? }))
+            }
+            (None, Some(ratio), Some(dpi)) => {
+                Ok(Some(Calibration::Ratio { ratio: ratio.to_string(), dpi: number("dpi", dpi)? }))
+            }
+            (None, None, None) => Ok(None),
+            (Some(_), _, _) => Err("Give either pixels_per_metre or ratio with dpi, not both".to_string()),
+            (None, _, _) => Err("ratio and dpi go together".to_string()),
+        }
+    }
+}
+
+/// Real-world size of one pixel on a calibrated image
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DrawingScale {
+    pub mm_per_pixel: f64,
+    /// What the scale was worked out from, kept so it can be re-checked
+    pub calibration: Calibration,
+}
+
+impl DrawingScale {
+    pub fn calibrate(calibration: Calibration) -> Result<Self, String> {
+        let mm_per_pixel = match &calibration {
+            Calibration::Reference { start, end, length_mm } => {
+                let pixels = (end.x - start.x).hypot(end.y - start.y);
+                if !(pixels > 0.0 && pixels.is_finite()) {
+                    return Err(format!("{}: reference line has no length", INVALID_SCALE));
+                }
+                if !(*length_mm > 0.0 && length_mm.is_finite()) {
+                    return Err(format!("{}: length_mm must be positive", INVALID_SCALE));
+                }
+                length_mm / pixels
+            }
+            Calibration::Ratio { ratio, dpi } => {
+                if !(*dpi > 0.0 && dpi.is_finite()) {
+                    return Err(format!("{}: dpi must be positive", INVALID_SCALE));
+                }
+                MM_PER_INCH / dpi * parse_ratio(ratio)?
+            }
+            Calibration::PixelsPerMetre { pixels_per_metre } => {
+                if !(*pixels_per_metre > 0.0 && pixels_per_metre.is_finite()) {
+                    return Err(format!("{}: pixels_per_metre must be positive", INVALID_SCALE));
+                }
+                1000.0 / pixels_per_metre
+            }
+        };
+        Ok(Self { mm_per_pixel, calibration })
+    }
+
+    /// Pixels to metres
+    pub fn metres(&self, pixels: f64) -> f64 {
+        pixels * self.mm_per_pixel / 1000.0
+    }
+
+    /// Square pixels to square metres
+    pub fn square_metres(&self, square_pixels: f64) -> f64 {
+        square_pixels * (self.mm_per_pixel / 1000.0).powi(2)
+    }
+}
+
+/// Measurements converted to metres with an image's scale
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RealMeasurements {
+    pub area_m2: f64,
+    pub perimeter_m: f64,
+}
+
+impl RealMeasurements {
+    pub fn new(measurements: &Measurements, scale: &DrawingScale) -> Self {
+        Self {
+            area_m2: scale.square_metres(measurements.area),
+            perimeter_m: scale.metres(measurements.perimeter),
+        }
+    }
+}
+
+/// Real units per drawing unit: "1:100" gives 100
+fn parse_ratio(ratio: &str) -> Result<f64, String> {
+    let invalid = || format!("{}: ratio must look like 1:100", INVALID_SCALE);
+    let (drawing, real) = ratio.split_once(':').ok_or_else(invalid)?;
+    let drawing: f64 = drawing.trim().parse().map_err(|_| invalid())?;
+    let real: f64 = real.trim().parse().map_err(|_| invalid())?;
+    if !(drawing > 0.0 && real > 0.0 && drawing.is_finite() && real.is_finite()) {
+        return Err(invalid());
+    }
+    Ok(real / drawing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calibrate_from_reference_and_ratio() {
+        let reference = Calibration::Reference {
+            start: Point { x: 0.0, y: 0.0 },
+            end: Point { x: 300.0, y: 400.0 },
+            length_mm: 5000.0,
+        };
+        let scale = DrawingScale::calibrate(reference).unwrap();
+        assert_eq!(scale.mm_per_pixel, 10.0);
+        assert_eq!(scale.metres(500.0), 5.0);
+        assert_eq!(scale.square_metres(10_000.0), 1.0);
+
+        // 1:100 at 254 dpi: a pixel is 0.1mm on paper, 10mm built
+        let ratio = Calibration::Ratio { ratio: "1:100".to_string(), dpi: 254.0 };
+        assert!((DrawingScale::calibrate(ratio).unwrap().mm_per_pixel - 10.0).abs() < 1e-9);
+
+        let bad = Calibration::Ratio { ratio: "100".to_string(), dpi: 254.0 };
+        assert!(DrawingScale::calibrate(bad).unwrap_err().starts_with(INVALID_SCALE));
+    }
+}
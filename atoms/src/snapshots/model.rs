@@ -0,0 +1,34 @@
+use serde::{Deserialize, Serialize};
+
+use crate::blocks::model::Block;
+use crate::drawing::model::Annotation;
+use crate::labels::model::Label;
+use crate::media::model::Image;
+use crate::tasks::model::Task;
+
+/// Index row for one frozen copy of a block. The archive itself lives in S3
+/// under `s3_key` and is never rewritten.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Snapshot {
+    pub block_id: String,
+    /// 1 for a block's first snapshot, then one more for each
+    pub version: u32,
+    pub s3_key: String,
+    pub created_by: String,
+    pub created_at: String,
+    pub label_count: u32,
+    pub task_count: u32,
+    pub image_count: u32,
+    pub annotation_count: u32,
+}
+
+/// Everything a snapshot froze, as downloaded. Trashed rows are left out.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SnapshotArchive {
+    pub snapshot: Snapshot,
+    pub block: Block,
+    pub labels: Vec<Label>,
+    pub tasks: Vec<Task>,
+    pub images: Vec<Image>,
+    pub annotations: Vec<Annotation>,
+}
@@ -0,0 +1,1570 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use aws_sdk_dynamodb::operation::transact_write_items::TransactWriteItemsError;
+use aws_sdk_dynamodb::operation::update_item::UpdateItemError;
+use aws_sdk_dynamodb::types::{
+    AttributeValue, ConditionCheck, Delete, DeleteRequest, KeysAndAttributes, Put, TransactWriteItem, Update,
+    WriteRequest,
+};
+use aws_sdk_dynamodb::Client as DynamoClient;
+use tokio::time::{sleep, Duration};
+
+use super::{
+    AnnotationRepo, BatchRepo, BlockCounter, BlockRepo, ConnectionRepo, Cursor, EntityKey, ImageRepo,
+    InviteRepo, LabelRepo, LabelTemplateRepo, Page, PageRequest, SnapshotRepo, StoreResult, TaskRepo,
+    TransactRepo, TrashRepo, UndoRepo, UserRepo, WriteOp, INVALID_CURSOR, MAX_TRANSACT_OPS, VERSION_CONFLICT,
+};
+use crate::blocks::model::{Block, UpdateBlockPayload};
+use crate::drawing::model::{Annotation, AnnotationChange, Geometry, UpdateAnnotationPayload};
+use crate::labels::model::{Label, LabelTemplate, UpdateLabelPayload};
+use crate::media::model::{Image, UpdateImagePayload};
+use crate::tasks::model::{Task, UpdateTaskPayload};
+use crate::snapshots::model::Snapshot;
+use crate::trash::model::TrashEntry;
+use crate::undo::model::{ChangeRef, OpLog};
+use crate::users::model::{Connection, Invite, UpdateUserPayload, User};
+
+type Item = HashMap<String, AttributeValue>;
+
+/// The row exists and is not in the trash
+const LIVE_CONDITION: &str = "attribute_exists(PK) AND attribute_not_exists(deleted_at)";
+
+/// DynamoDB single-table backend
+///
+/// Layout:
+/// - Block:      PK = "BLOCK",                SK = "BLOCK#{block_id}"
+/// - Label:      PK = "BLOCK#{block_id}",     SK = "LABEL#{label_id}"
+/// - Task:       PK = "BLOCK#{block_id}",     SK = "TASK#{task_id}"
+/// - Image:      PK = "BLOCK#{block_id}",     SK = "IMAGE#{image_id}"
+/// - Annotation: PK = "IMAGE#{image_id}",     SK = "ANNOTATION#{annotation_id}"
+/// - History:    PK = "HISTORY#{image_id}",   SK = "ANNOTATION#{annotation_id}#{at}#{change_id}"
+/// - Task image: PK = "TASK#{task_id}",       SK = "IMAGE#{image_id}" (index row)
+/// - Snapshot:   PK = "BLOCK#{block_id}",     SK = "SNAPSHOT#{version:010}"
+/// - Template:   PK = "TEMPLATE",             SK = "TEMPLATE#{block_type}"
+/// - User:       PK = SK = "USER#{user_id}"
+/// - Invite:     PK = "INVITE#{code}",        SK = "METADATA"
+/// - Connection: PK = SK = "CONNECTION#{connection_id}"
+/// - Trash:      PK = "TRASH",                SK = "TRASH#{trash_id}"
+/// - Undo log:   PK = "USER#{user_id}",       SK = "UNDO#{image_id}" (references history rows)
+#[derive(Clone)]
+pub struct DynamoStore {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoStore {
+    pub fn new(client: &DynamoClient, table_name: &str) -> Self {
+        Self {
+            client: client.clone(),
+            table_name: table_name.to_string(),
+        }
+    }
+
+    async fn get(&self, pk: String, sk: String) -> StoreResult<Option<Item>> {
+        let result = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("PK", AttributeValue::S(pk))
+            .key("SK", AttributeValue::S(sk))
+            .send()
+            .await
+            .map_err(|e| format!("DynamoDB get_item error: {}", e))?;
+
+        Ok(result.item().cloned())
+    }
+
+    async fn put(&self, item: Item) -> StoreResult<()> {
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(item))
+            .send()
+            .await
+            .map_err(|e| format!("DynamoDB put_item error: {}", e))?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, pk: String, sk: String) -> StoreResult<()> {
+        self.client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key("PK", AttributeValue::S(pk))
+            .key("SK", AttributeValue::S(sk))
+            .send()
+            .await
+            .map_err(|e| format!("DynamoDB delete_item error: {}", e))?;
+
+        Ok(())
+    }
+
+    /// Query one partition for all live rows whose SK starts with `sk_prefix`,
+    /// following `LastEvaluatedKey` until the partition is drained
+    async fn query_prefix(&self, pk: String, sk_prefix: &str) -> StoreResult<Vec<Item>> {
+        let mut items = Vec::new();
+        let mut start_key: Option<Item> = None;
+
+        loop {
+            let result = self
+                .client
+                .query()
+                .table_name(&self.table_name)
+                .key_condition_expression("PK = :pk AND begins_with(SK, :sk_prefix)")
+                .expression_attribute_values(":pk", AttributeValue::S(pk.clone()))
+                .expression_attribute_values(":sk_prefix", AttributeValue::S(sk_prefix.to_string()))
+                .filter_expression("attribute_not_exists(deleted_at)")
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await
+                .map_err(|e| format!("DynamoDB query error: {}", e))?;
+
+            items.extend(result.items().iter().cloned());
+
+            match result.last_evaluated_key() {
+                Some(key) => start_key = Some(key.clone()),
+                None => break,
+            }
+        }
+
+        Ok(items)
+    }
+
+    /// Query one page of a partition. Trashed rows count towards the limit
+    /// but are filtered out, so a page may be short.
+    async fn query_page(
+        &self,
+        pk: String,
+        sk_prefix: &str,
+        page: &PageRequest,
+    ) -> StoreResult<(Vec<Item>, Option<String>)> {
+        let cursor = page.cursor_in(&pk)?;
+        if cursor.is_some_and(|c| !c.sk.starts_with(sk_prefix)) {
+            return Err(INVALID_CURSOR.to_string());
+        }
+
+        let result = self
+            .client
+            .query()
+            .table_name(&self.table_name)
+            .key_condition_expression("PK = :pk AND begins_with(SK, :sk_prefix)")
+            .expression_attribute_values(":pk", AttributeValue::S(pk))
+            .expression_attribute_values(":sk_prefix", AttributeValue::S(sk_prefix.to_string()))
+            .filter_expression("attribute_not_exists(deleted_at)")
+            .limit(page.page_size())
+            .set_exclusive_start_key(cursor.map(cursor_to_key))
+            .send()
+            .await
+            .map_err(|e| format!("DynamoDB query error: {}", e))?;
+
+        let next_cursor = result
+            .last_evaluated_key()
+            .and_then(key_to_cursor)
+            .map(|cursor| cursor.encode());
+
+        Ok((result.items().to_vec(), next_cursor))
+    }
+
+    /// Fetch many rows by key (100 per request, retrying unprocessed keys).
+    /// Keys with no row are left out of the result.
+    async fn batch_get(&self, keys: Vec<Item>) -> StoreResult<Vec<Item>> {
+        let mut items = Vec::new();
+
+        for chunk in keys.chunks(100) {
+            let request = KeysAndAttributes::builder()
+                .set_keys(Some(chunk.to_vec()))
+                .build()
+                .map_err(|e| format!("Failed to build batch get request: {}", e))?;
+
+            let mut unprocessed = Some(request);
+            let mut attempts = 0;
+            while let Some(request) = unprocessed {
+                attempts += 1;
+                let result = self
+                    .client
+                    .batch_get_item()
+                    .request_items(&self.table_name, request)
+                    .send()
+                    .await
+                    .map_err(|e| format!("DynamoDB batch_get_item error: {}", e))?;
+
+                if let Some(found) = result.responses().and_then(|m| m.get(&self.table_name)) {
+                    items.extend(found.iter().cloned());
+                }
+
+                unprocessed = result
+                    .unprocessed_keys()
+                    .and_then(|m| m.get(&self.table_name))
+                    .filter(|request| !request.keys().is_empty())
+                    .cloned();
+
+                if unprocessed.is_some() {
+                    if attempts >= 5 {
+                        return Err("DynamoDB batch_get_item left keys unprocessed".to_string());
+                    }
+                    sleep(Duration::from_millis(100 * attempts)).await;
+                }
+            }
+        }
+
+        Ok(items)
+    }
+
+    /// Load the given live images of a block, in the order of `image_ids`
+    async fn images_by_id(&self, block_id: &str, image_ids: &[String]) -> StoreResult<Vec<Image>> {
+        let keys = image_ids
+            .iter()
+            .map(|image_id| new_item(block_child_key(block_id, "IMAGE#", image_id)))
+            .collect();
+        let items = self.batch_get(keys).await?;
+
+        let mut found: HashMap<String, Image> = images_from_items(block_id, &items)
+            .into_iter()
+            .filter(|image| image.deleted_at.is_none())
+            .map(|image| (image.image_id.clone(), image))
+            .collect();
+        Ok(image_ids.iter().filter_map(|image_id| found.remove(image_id)).collect())
+    }
+
+    /// SET the given attributes on one row; no-op when there is nothing to set
+    async fn set_fields(&self, pk: String, sk: String, fields: Vec<(&str, AttributeValue)>) -> StoreResult<()> {
+        if fields.is_empty() {
+            return Ok(());
+        }
+
+        let mut update_expr = vec![];
+        let mut builder = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("PK", AttributeValue::S(pk))
+            .key("SK", AttributeValue::S(sk));
+
+        for (attribute, value) in fields {
+            update_expr.push(format!("#{0} = :{0}", attribute));
+            builder = builder
+                .expression_attribute_names(format!("#{}", attribute), attribute)
+                .expression_attribute_values(format!(":{}", attribute), value);
+        }
+
+        builder
+            .update_expression(format!("SET {}", update_expr.join(", ")))
+            .send()
+            .await
+            .map_err(|e| format!("DynamoDB update_item error: {}", e))?;
+
+        Ok(())
+    }
+
+    /// SET the given attributes and bump `version`; see `versioned_update`
+    async fn set_versioned(
+        &self,
+        pk: String,
+        sk: String,
+        fields: Vec<(&str, AttributeValue)>,
+        expected: Option<u64>,
+    ) -> StoreResult<()> {
+        let update = versioned_update(fields, expected);
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("PK", AttributeValue::S(pk))
+            .key("SK", AttributeValue::S(sk))
+            .update_expression(update.expression)
+            .condition_expression(update.condition)
+            .set_expression_attribute_names(Some(update.names))
+            .set_expression_attribute_values(Some(update.values))
+            .send()
+            .await
+            .map_err(|e| match e.into_service_error() {
+                UpdateItemError::ConditionalCheckFailedException(_) => VERSION_CONFLICT.to_string(),
+                other => format!("DynamoDB update_item error: {}", other),
+            })?;
+
+        Ok(())
+    }
+
+    /// Add `delta` (may be negative) to a numeric attribute
+    async fn add_to(&self, pk: String, sk: String, attribute: &str, delta: i64) -> StoreResult<()> {
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("PK", AttributeValue::S(pk))
+            .key("SK", AttributeValue::S(sk))
+            .update_expression(format!("SET {0} = {0} + :delta", attribute))
+            .expression_attribute_values(":delta", AttributeValue::N(delta.to_string()))
+            .send()
+            .await
+            .map_err(|e| format!("DynamoDB update_item error: {}", e))?;
+
+        Ok(())
+    }
+}
+
+// ========== KEYS ==========
+
+fn cursor_to_key(cursor: &Cursor) -> Item {
+    new_item((cursor.pk.clone(), cursor.sk.clone()))
+}
+
+fn key_to_cursor(key: &Item) -> Option<Cursor> {
+    Some(Cursor {
+        pk: string_attr(key, "PK")?,
+        sk: string_attr(key, "SK")?,
+    })
+}
+
+fn block_key(block_id: &str) -> (String, String) {
+    ("BLOCK".to_string(), format!("BLOCK#{}", block_id))
+}
+
+fn block_child_key(block_id: &str, prefix: &str, id: &str) -> (String, String) {
+    (format!("BLOCK#{}", block_id), format!("{}{}", prefix, id))
+}
+
+fn annotation_key(image_id: &str, annotation_id: &str) -> (String, String) {
+    (format!("IMAGE#{}", image_id), format!("ANNOTATION#{}", annotation_id))
+}
+
+fn annotation_change_key(image_id: &str, change: &ChangeRef) -> (String, String) {
+    (
+        format!("HISTORY#{}", image_id),
+        format!("ANNOTATION#{}#{}#{}", change.annotation_id, change.at, change.change_id),
+    )
+}
+
+fn task_image_key(task_id: &str, image_id: &str) -> (String, String) {
+    (format!("TASK#{}", task_id), format!("IMAGE#{}", image_id))
+}
+
+fn label_template_key(block_type: &str) -> (String, String) {
+    ("TEMPLATE".to_string(), format!("TEMPLATE#{}", block_type))
+}
+
+/// Zero-padded so versions sort in order
+fn snapshot_key(block_id: &str, version: u32) -> (String, String) {
+    block_child_key(block_id, "SNAPSHOT#", &format!("{:010}", version))
+}
+
+fn trash_key(trash_id: &str) -> (String, String) {
+    ("TRASH".to_string(), format!("TRASH#{}", trash_id))
+}
+
+fn op_log_key(user_id: &str, image_id: &str) -> (String, String) {
+    (format!("USER#{}", user_id), format!("UNDO#{}", image_id))
+}
+
+fn user_key(user_id: &str) -> (String, String) {
+    let pk = format!("USER#{}", user_id);
+    (pk.clone(), pk)
+}
+
+fn invite_key(invite_code: &str) -> (String, String) {
+    (format!("INVITE#{}", invite_code), "METADATA".to_string())
+}
+
+fn connection_key(connection_id: &str) -> (String, String) {
+    let pk = format!("CONNECTION#{}", connection_id);
+    (pk.clone(), pk)
+}
+
+fn entity_key(key: &EntityKey) -> (String, String) {
+    match key {
+        EntityKey::Block { block_id } => block_key(block_id),
+        EntityKey::Label { block_id, label_id } => block_child_key(block_id, "LABEL#", label_id),
+        EntityKey::Task { block_id, task_id } => block_child_key(block_id, "TASK#", task_id),
+        EntityKey::Image { block_id, image_id } => block_child_key(block_id, "IMAGE#", image_id),
+        EntityKey::Annotation { image_id, annotation_id } => annotation_key(image_id, annotation_id),
+        EntityKey::TaskImage { task_id, image_id } => task_image_key(task_id, image_id),
+        EntityKey::Trash { trash_id } => trash_key(trash_id),
+        EntityKey::Snapshot { block_id, version } => snapshot_key(block_id, *version),
+    }
+}
+
+/// Parts of an update that SETs `fields` and bumps `version`
+struct VersionedUpdate {
+    expression: String,
+    condition: String,
+    names: HashMap<String, String>,
+    values: Item,
+}
+
+/// Build a versioned update. The row must exist and, with `expected`, still
+/// be at that version; rows written before versioning count as version 0.
+fn versioned_update(fields: Vec<(&str, AttributeValue)>, expected: Option<u64>) -> VersionedUpdate {
+    let mut names = HashMap::new();
+    let mut values = HashMap::new();
+    let mut update_expr = vec![];
+
+    for (attribute, value) in fields {
+        update_expr.push(format!("#{0} = :{0}", attribute));
+        names.insert(format!("#{}", attribute), attribute.to_string());
+        values.insert(format!(":{}", attribute), value);
+    }
+    update_expr.push("#version = if_not_exists(#version, :zero) + :one".to_string());
+    values.insert(":zero".to_string(), AttributeValue::N("0".to_string()));
+    values.insert(":one".to_string(), AttributeValue::N("1".to_string()));
+
+    let condition = version_condition(expected, &mut names, &mut values);
+    VersionedUpdate {
+        expression: format!("SET {}", update_expr.join(", ")),
+        condition,
+        names,
+        values,
+    }
+}
+
+/// Condition that the row exists outside the trash and, with `expected`, is
+/// at that version
+fn version_condition(expected: Option<u64>, names: &mut HashMap<String, String>, values: &mut Item) -> String {
+    match expected {
+        None => LIVE_CONDITION.to_string(),
+        Some(version) => {
+            names.insert("#version".to_string(), "version".to_string());
+            if version == 0 {
+                format!("{} AND attribute_not_exists(#version)", LIVE_CONDITION)
+            } else {
+                values.insert(":expected_version".to_string(), AttributeValue::N(version.to_string()));
+                format!("{} AND #version = :expected_version", LIVE_CONDITION)
+            }
+        }
+    }
+}
+
+fn new_item((pk, sk): (String, String)) -> Item {
+    let mut item = HashMap::new();
+    item.insert("PK".to_string(), AttributeValue::S(pk));
+    item.insert("SK".to_string(), AttributeValue::S(sk));
+    item
+}
+
+// ========== ATTRIBUTE HELPERS ==========
+
+fn string_attr(item: &Item, name: &str) -> Option<String> {
+    item.get(name).and_then(|v| v.as_s().ok()).map(|s| s.to_string())
+}
+
+fn number_attr<T: FromStr>(item: &Item, name: &str) -> Option<T> {
+    item.get(name).and_then(|v| v.as_n().ok()).and_then(|n| n.parse().ok())
+}
+
+fn bool_attr(item: &Item, name: &str) -> Option<bool> {
+    item.get(name).and_then(|v| v.as_bool().ok()).copied()
+}
+
+fn sk_id<'a>(item: &'a Item, prefix: &str) -> Option<&'a str> {
+    item.get("SK")
+        .and_then(|v| v.as_s().ok())
+        .and_then(|sk| sk.strip_prefix(prefix))
+}
+
+fn to_json(value: &impl serde::Serialize) -> StoreResult<String> {
+    serde_json::to_string(value).map_err(|e| format!("Failed to serialize: {}", e))
+}
+
+// ========== ITEM <-> MODEL ==========
+
+fn block_from_item(block_id: &str, item: &Item) -> Block {
+    Block {
+        block_id: block_id.to_string(),
+        block_name: string_attr(item, "block_name").unwrap_or_default(),
+        block_type: string_attr(item, "block_type").unwrap_or_else(|| "annotation".to_string()),
+        block_company: string_attr(item, "block_company"),
+        block_state: string_attr(item, "block_state").unwrap_or_default(),
+        block_locked: bool_attr(item, "block_locked").unwrap_or(false),
+        image_count: number_attr(item, "image_count").unwrap_or(0),
+        approved_image_count: number_attr(item, "approved_image_count").unwrap_or(0),
+        annotation_count: number_attr(item, "annotation_count").unwrap_or(0),
+        block_created_at: string_attr(item, "block_created_at").unwrap_or_default(),
+        deleted_at: string_attr(item, "deleted_at"),
+        trash_id: string_attr(item, "trash_id"),
+    }
+}
+
+fn block_to_item(block: &Block) -> Item {
+    let mut item = new_item(block_key(&block.block_id));
+    item.insert("block_name".to_string(), AttributeValue::S(block.block_name.clone()));
+    item.insert("block_type".to_string(), AttributeValue::S(block.block_type.clone()));
+    item.insert("block_state".to_string(), AttributeValue::S(block.block_state.clone()));
+    item.insert("block_locked".to_string(), AttributeValue::Bool(block.block_locked));
+    item.insert("image_count".to_string(), AttributeValue::N(block.image_count.to_string()));
+    item.insert("approved_image_count".to_string(), AttributeValue::N(block.approved_image_count.to_string()));
+    item.insert("annotation_count".to_string(), AttributeValue::N(block.annotation_count.to_string()));
+    item.insert("block_created_at".to_string(), AttributeValue::S(block.block_created_at.clone()));
+    if let Some(company) = &block.block_company {
+        item.insert("block_company".to_string(), AttributeValue::S(company.clone()));
+    }
+    insert_trash_attrs(&mut item, &block.deleted_at, &block.trash_id);
+    item
+}
+
+fn label_from_item(block_id: &str, label_id: &str, item: &Item) -> Label {
+    Label {
+        label_id: label_id.to_string(),
+        block_id: block_id.to_string(),
+        label_name: string_attr(item, "label_name").unwrap_or_default(),
+        label_color: string_attr(item, "label_color").unwrap_or_default(),
+        label_properties: string_attr(item, "label_properties").and_then(|s| serde_json::from_str(&s).ok()),
+        label_count: number_attr(item, "label_count").unwrap_or(0),
+        label_order: number_attr(item, "label_order"),
+        version: number_attr(item, "version").unwrap_or(0),
+    }
+}
+
+fn label_to_item(label: &Label) -> StoreResult<Item> {
+    let mut item = new_item(block_child_key(&label.block_id, "LABEL#", &label.label_id));
+    item.insert("label_name".to_string(), AttributeValue::S(label.label_name.clone()));
+    item.insert("label_count".to_string(), AttributeValue::N(label.label_count.to_string()));
+    item.insert("label_color".to_string(), AttributeValue::S(label.label_color.clone()));
+    item.insert("version".to_string(), AttributeValue::N(label.version.to_string()));
+    if let Some(properties) = &label.label_properties {
+        item.insert("label_properties".to_string(), AttributeValue::S(to_json(properties)?));
+    }
+    if let Some(order) = label.label_order {
+        item.insert("label_order".to_string(), AttributeValue::N(order.to_string()));
+    }
+    Ok(item)
+}
+
+fn label_template_from_item(block_type: &str, item: &Item) -> StoreResult<LabelTemplate> {
+    let labels = string_attr(item, "labels").ok_or("Missing template labels")?;
+    Ok(LabelTemplate {
+        block_type: block_type.to_string(),
+        labels: serde_json::from_str(&labels).map_err(|e| format!("Failed to parse template labels: {}", e))?,
+        updated_by: string_attr(item, "updated_by").unwrap_or_default(),
+        updated_at: string_attr(item, "updated_at").unwrap_or_default(),
+    })
+}
+
+/// The label list is stored whole as JSON
+fn label_template_to_item(template: &LabelTemplate) -> StoreResult<Item> {
+    let mut item = new_item(label_template_key(&template.block_type));
+    item.insert("labels".to_string(), AttributeValue::S(to_json(&template.labels)?));
+    item.insert("updated_by".to_string(), AttributeValue::S(template.updated_by.clone()));
+    item.insert("updated_at".to_string(), AttributeValue::S(template.updated_at.clone()));
+    Ok(item)
+}
+
+fn task_from_item(block_id: &str, task_id: &str, item: &Item) -> Task {
+    Task {
+        task_id: task_id.to_string(),
+        block_id: block_id.to_string(),
+        task_name: string_attr(item, "task_name").unwrap_or_default(),
+        task_state: string_attr(item, "task_state").unwrap_or_default(),
+        assignee: string_attr(item, "assignee").unwrap_or_default(),
+        checked_by: string_attr(item, "checked_by").unwrap_or_default(),
+        locked: bool_attr(item, "locked").unwrap_or(false),
+        image_count: number_attr(item, "image_count").unwrap_or(0),
+        created_at: string_attr(item, "created_at").unwrap_or_default(),
+        version: number_attr(item, "version").unwrap_or(0),
+        deleted_at: string_attr(item, "deleted_at"),
+        trash_id: string_attr(item, "trash_id"),
+        images: vec![], // Filled in later by be/blocks/* when joining with media
+    }
+}
+
+fn task_to_item(task: &Task) -> Item {
+    let mut item = new_item(block_child_key(&task.block_id, "TASK#", &task.task_id));
+    item.insert("task_name".to_string(), AttributeValue::S(task.task_name.clone()));
+    item.insert("task_state".to_string(), AttributeValue::S(task.task_state.clone()));
+    item.insert("image_count".to_string(), AttributeValue::N(task.image_count.to_string()));
+    item.insert("created_at".to_string(), AttributeValue::S(task.created_at.clone()));
+    item.insert("locked".to_string(), AttributeValue::Bool(task.locked));
+    item.insert("version".to_string(), AttributeValue::N(task.version.to_string()));
+    if !task.assignee.is_empty() {
+        item.insert("assignee".to_string(), AttributeValue::S(task.assignee.clone()));
+    }
+    if !task.checked_by.is_empty() {
+        item.insert("checked_by".to_string(), AttributeValue::S(task.checked_by.clone()));
+    }
+    insert_trash_attrs(&mut item, &task.deleted_at, &task.trash_id);
+    item
+}
+
+fn image_from_item(block_id: &str, image_id: &str, item: &Item) -> Image {
+    Image {
+        image_id: image_id.to_string(),
+        block_id: block_id.to_string(),
+        task_id: string_attr(item, "task_id"),
+        url: string_attr(item, "url").unwrap_or_default(),
+        locked: bool_attr(item, "locked").unwrap_or(false),
+        order: number_attr(item, "order"),
+        width: number_attr(item, "width"),
+        height: number_attr(item, "height"),
+        scale: string_attr(item, "scale").and_then(|s| serde_json::from_str(&s).ok()),
+        annotation_count: number_attr(item, "annotation_count").unwrap_or(0),
+        uploaded_at: string_attr(item, "uploaded_at").unwrap_or_default(),
+        deleted_at: string_attr(item, "deleted_at"),
+        trash_id: string_attr(item, "trash_id"),
+    }
+}
+
+fn image_to_item(image: &Image) -> StoreResult<Item> {
+    let mut item = new_item(block_child_key(&image.block_id, "IMAGE#", &image.image_id));
+    item.insert("url".to_string(), AttributeValue::S(image.url.clone()));
+    item.insert("locked".to_string(), AttributeValue::Bool(image.locked));
+    item.insert("annotation_count".to_string(), AttributeValue::N(image.annotation_count.to_string()));
+    item.insert("uploaded_at".to_string(), AttributeValue::S(image.uploaded_at.clone()));
+    if let Some(task_id) = &image.task_id {
+        item.insert("task_id".to_string(), AttributeValue::S(task_id.clone()));
+    }
+    if let Some(order) = image.order {
+        item.insert("order".to_string(), AttributeValue::N(order.to_string()));
+    }
+    if let Some(width) = image.width {
+        item.insert("width".to_string(), AttributeValue::N(width.to_string()));
+    }
+    if let Some(height) = image.height {
+        item.insert("height".to_string(), AttributeValue::N(height.to_string()));
+    }
+    if let Some(scale) = &image.scale {
+        item.insert("scale".to_string(), AttributeValue::S(to_json(scale)?));
+    }
+    insert_trash_attrs(&mut item, &image.deleted_at, &image.trash_id);
+    Ok(item)
+}
+
+fn task_image_to_item(block_id: &str, task_id: &str, image_id: &str) -> Item {
+    let mut item = new_item(task_image_key(task_id, image_id));
+    item.insert("block_id".to_string(), AttributeValue::S(block_id.to_string()));
+    item.insert("image_id".to_string(), AttributeValue::S(image_id.to_string()));
+    item
+}
+
+fn annotation_from_item(image_id: &str, annotation_id: &str, item: &Item) -> StoreResult<Annotation> {
+    let geometry_str = string_attr(item, "geometry").ok_or("Missing geometry")?;
+    let geometry: Geometry = serde_json::from_str(&geometry_str)
+        .map_err(|e| format!("Failed to parse geometry: {}", e))?;
+    let attributes = match string_attr(item, "attributes") {
+        Some(json) => serde_json::from_str(&json).map_err(|e| format!("Failed to parse attributes: {}", e))?,
+        None => Default::default(),
+    };
+
+    Ok(Annotation {
+        annotation_id: annotation_id.to_string(),
+        image_id: image_id.to_string(),
+        label_id: string_attr(item, "label_id").unwrap_or_else(|| "default".to_string()),
+        geometry,
+        attributes,
+        created_by: string_attr(item, "created_by").unwrap_or_default(),
+        created_at: string_attr(item, "created_at").unwrap_or_default(),
+        updated_at: string_attr(item, "updated_at"),
+        version: number_attr(item, "version").unwrap_or(0),
+        deleted_at: string_attr(item, "deleted_at"),
+        trash_id: string_attr(item, "trash_id"),
+    })
+}
+
+fn annotation_to_item(annotation: &Annotation) -> StoreResult<Item> {
+    let mut item = new_item(annotation_key(&annotation.image_id, &annotation.annotation_id));
+    item.insert("label_id".to_string(), AttributeValue::S(annotation.label_id.clone()));
+    item.insert("geometry".to_string(), AttributeValue::S(to_json(&annotation.geometry)?));
+    if !annotation.attributes.is_empty() {
+        item.insert("attributes".to_string(), AttributeValue::S(to_json(&annotation.attributes)?));
+    }
+    item.insert("created_by".to_string(), AttributeValue::S(annotation.created_by.clone()));
+    item.insert("created_at".to_string(), AttributeValue::S(annotation.created_at.clone()));
+    item.insert("version".to_string(), AttributeValue::N(annotation.version.to_string()));
+    if let Some(updated_at) = &annotation.updated_at {
+        item.insert("updated_at".to_string(), AttributeValue::S(updated_at.clone()));
+    }
+    insert_trash_attrs(&mut item, &annotation.deleted_at, &annotation.trash_id);
+    Ok(item)
+}
+
+fn annotation_change_from_item(item: &Item) -> StoreResult<AnnotationChange> {
+    let change = string_attr(item, "change").ok_or("Missing annotation change")?;
+    serde_json::from_str(&change).map_err(|e| format!("Failed to parse annotation change: {}", e))
+}
+
+/// History entries are stored whole as JSON; only the key is queried
+fn annotation_change_to_item(change: &AnnotationChange) -> StoreResult<Item> {
+    let mut item = new_item(annotation_change_key(&change.image_id, &ChangeRef::from(change)));
+    item.insert("change".to_string(), AttributeValue::S(to_json(change)?));
+    Ok(item)
+}
+
+fn insert_trash_attrs(item: &mut Item, deleted_at: &Option<String>, trash_id: &Option<String>) {
+    if let Some(deleted_at) = deleted_at {
+        item.insert("deleted_at".to_string(), AttributeValue::S(deleted_at.clone()));
+    }
+    if let Some(trash_id) = trash_id {
+        item.insert("trash_id".to_string(), AttributeValue::S(trash_id.clone()));
+    }
+}
+
+fn trash_from_item(trash_id: &str, item: &Item) -> StoreResult<TrashEntry> {
+    let entity = string_attr(item, "entity").ok_or("Missing trashed entity")?;
+    Ok(TrashEntry {
+        trash_id: trash_id.to_string(),
+        entity: serde_json::from_str(&entity).map_err(|e| format!("Failed to parse trashed entity: {}", e))?,
+        deleted_by: string_attr(item, "deleted_by").unwrap_or_default(),
+        deleted_at: string_attr(item, "trashed_at").unwrap_or_default(),
+        expires_at: string_attr(item, "expires_at").unwrap_or_default(),
+    })
+}
+
+fn trash_to_item(entry: &TrashEntry) -> StoreResult<Item> {
+    let mut item = new_item(trash_key(&entry.trash_id));
+    item.insert("entity".to_string(), AttributeValue::S(to_json(&entry.entity)?));
+    item.insert("deleted_by".to_string(), AttributeValue::S(entry.deleted_by.clone()));
+    // Not `deleted_at`, which would hide the entry from list queries
+    item.insert("trashed_at".to_string(), AttributeValue::S(entry.deleted_at.clone()));
+    item.insert("expires_at".to_string(), AttributeValue::S(entry.expires_at.clone()));
+    Ok(item)
+}
+
+fn snapshot_from_item(block_id: &str, item: &Item) -> Snapshot {
+    Snapshot {
+        block_id: block_id.to_string(),
+        version: number_attr(item, "version").unwrap_or(0),
+        s3_key: string_attr(item, "s3_key").unwrap_or_default(),
+        created_by: string_attr(item, "created_by").unwrap_or_default(),
+        created_at: string_attr(item, "created_at").unwrap_or_default(),
+        label_count: number_attr(item, "label_count").unwrap_or(0),
+        task_count: number_attr(item, "task_count").unwrap_or(0),
+        image_count: number_attr(item, "image_count").unwrap_or(0),
+        annotation_count: number_attr(item, "annotation_count").unwrap_or(0),
+    }
+}
+
+fn snapshot_to_item(snapshot: &Snapshot) -> Item {
+    let mut item = new_item(snapshot_key(&snapshot.block_id, snapshot.version));
+    item.insert("version".to_string(), AttributeValue::N(snapshot.version.to_string()));
+    item.insert("s3_key".to_string(), AttributeValue::S(snapshot.s3_key.clone()));
+    item.insert("created_by".to_string(), AttributeValue::S(snapshot.created_by.clone()));
+    item.insert("created_at".to_string(), AttributeValue::S(snapshot.created_at.clone()));
+    item.insert("label_count".to_string(), AttributeValue::N(snapshot.label_count.to_string()));
+    item.insert("task_count".to_string(), AttributeValue::N(snapshot.task_count.to_string()));
+    item.insert("image_count".to_string(), AttributeValue::N(snapshot.image_count.to_string()));
+    item.insert("annotation_count".to_string(), AttributeValue::N(snapshot.annotation_count.to_string()));
+    item
+}
+
+fn op_log_from_item(user_id: &str, image_id: &str, item: &Item) -> StoreResult<OpLog> {
+    let stack = |name: &str| -> StoreResult<Vec<ChangeRef>> {
+        match string_attr(item, name) {
+            Some(json) => serde_json::from_str(&json).map_err(|e| format!("Failed to parse {} stack: {}", name, e)),
+            None => Ok(vec![]),
+        }
+    };
+    Ok(OpLog {
+        user_id: user_id.to_string(),
+        image_id: image_id.to_string(),
+        undo: stack("undo")?,
+        redo: stack("redo")?,
+        version: number_attr(item, "version").unwrap_or(0),
+    })
+}
+
+/// Both stacks are stored as JSON lists of history references
+fn op_log_to_item(log: &OpLog) -> StoreResult<Item> {
+    let mut item = new_item(op_log_key(&log.user_id, &log.image_id));
+    item.insert("undo".to_string(), AttributeValue::S(to_json(&log.undo)?));
+    item.insert("redo".to_string(), AttributeValue::S(to_json(&log.redo)?));
+    item.insert("version".to_string(), AttributeValue::N(log.version.to_string()));
+    Ok(item)
+}
+
+fn user_from_item(user_id: &str, item: &Item) -> User {
+    User {
+        user_id: user_id.to_string(),
+        user_name: string_attr(item, "user_name").unwrap_or_default(),
+        user_email: string_attr(item, "user_email").unwrap_or_default(),
+        user_company: string_attr(item, "user_company"),
+        user_role: string_attr(item, "user_role").unwrap_or_default(),
+        user_created_at: string_attr(item, "user_created_at").unwrap_or_default(),
+        user_last_login: string_attr(item, "user_last_login"),
+    }
+}
+
+fn user_to_item(user: &User) -> Item {
+    let mut item = new_item(user_key(&user.user_id));
+    item.insert("user_name".to_string(), AttributeValue::S(user.user_name.clone()));
+    item.insert("user_email".to_string(), AttributeValue::S(user.user_email.clone()));
+    item.insert("user_role".to_string(), AttributeValue::S(user.user_role.clone()));
+    item.insert("user_created_at".to_string(), AttributeValue::S(user.user_created_at.clone()));
+    if let Some(company) = &user.user_company {
+        item.insert("user_company".to_string(), AttributeValue::S(company.clone()));
+    }
+    item
+}
+
+fn invite_from_item(invite_code: &str, item: &Item) -> Invite {
+    Invite {
+        invite_code: invite_code.to_string(),
+        email: string_attr(item, "email").unwrap_or_default(),
+        status: string_attr(item, "status").unwrap_or_default(),
+        created_by: string_attr(item, "created_by").unwrap_or_default(),
+        created_at: string_attr(item, "created_at").unwrap_or_default(),
+        expires_at: string_attr(item, "expires_at").unwrap_or_default(),
+        used_at: string_attr(item, "used_at"),
+    }
+}
+
+fn invite_to_item(invite: &Invite) -> Item {
+    let mut item = new_item(invite_key(&invite.invite_code));
+    item.insert("invite_code".to_string(), AttributeValue::S(invite.invite_code.clone()));
+    item.insert("email".to_string(), AttributeValue::S(invite.email.clone()));
+    item.insert("status".to_string(), AttributeValue::S(invite.status.clone()));
+    item.insert("created_by".to_string(), AttributeValue::S(invite.created_by.clone()));
+    item.insert("created_at".to_string(), AttributeValue::S(invite.created_at.clone()));
+    item.insert("expires_at".to_string(), AttributeValue::S(invite.expires_at.clone()));
+    if let Some(used_at) = &invite.used_at {
+        item.insert("used_at".to_string(), AttributeValue::S(used_at.clone()));
+    }
+    item
+}
+
+fn connection_to_item(connection: &Connection) -> Item {
+    let mut item = new_item(connection_key(&connection.connection_id));
+    item.insert("connection_id".to_string(), AttributeValue::S(connection.connection_id.clone()));
+    item.insert("user_id".to_string(), AttributeValue::S(connection.user_id.clone()));
+    item.insert("connected_at".to_string(), AttributeValue::S(connection.connected_at.clone()));
+    item.insert("entity_type".to_string(), AttributeValue::S("connection".to_string()));
+    item
+}
+
+fn annotation_fields(
+    changes: &UpdateAnnotationPayload,
+    updated_at: &str,
+) -> StoreResult<Vec<(&'static str, AttributeValue)>> {
+    let mut fields = vec![("updated_at", AttributeValue::S(updated_at.to_string()))];
+    if let Some(label_id) = &changes.label_id {
+        fields.push(("label_id", AttributeValue::S(label_id.clone())));
+    }
+    if let Some(geometry) = &changes.geometry {
+        fields.push(("geometry", AttributeValue::S(to_json(geometry)?)));
+    }
+    if let Some(attributes) = &changes.attributes {
+        fields.push(("attributes", AttributeValue::S(to_json(attributes)?)));
+    }
+    Ok(fields)
+}
+
+fn task_fields(changes: &UpdateTaskPayload) -> Vec<(&'static str, AttributeValue)> {
+    let mut fields = vec![];
+    if let Some(name) = &changes.task_name {
+        fields.push(("task_name", AttributeValue::S(name.clone())));
+    }
+    if let Some(state) = &changes.task_state {
+        fields.push(("task_state", AttributeValue::S(state.clone())));
+    }
+    if let Some(assignee) = &changes.assignee {
+        fields.push(("assignee", AttributeValue::S(assignee.clone())));
+    }
+    if let Some(checked_by) = &changes.checked_by {
+        fields.push(("checked_by", AttributeValue::S(checked_by.clone())));
+    }
+    fields
+}
+
+fn blocks_from_items(items: &[Item]) -> Vec<Block> {
+    items
+        .iter()
+        .filter_map(|item| sk_id(item, "BLOCK#").map(|block_id| block_from_item(block_id, item)))
+        .collect()
+}
+
+fn tasks_from_items(block_id: &str, items: &[Item]) -> Vec<Task> {
+    items
+        .iter()
+        .filter_map(|item| sk_id(item, "TASK#").map(|task_id| task_from_item(block_id, task_id, item)))
+        .collect()
+}
+
+fn images_from_items(block_id: &str, items: &[Item]) -> Vec<Image> {
+    items
+        .iter()
+        .filter_map(|item| sk_id(item, "IMAGE#").map(|image_id| image_from_item(block_id, image_id, item)))
+        .collect()
+}
+
+fn image_ids_from_items(items: &[Item]) -> Vec<String> {
+    items
+        .iter()
+        .filter_map(|item| sk_id(item, "IMAGE#").map(|image_id| image_id.to_string()))
+        .collect()
+}
+
+fn annotations_from_items(image_id: &str, items: &[Item]) -> StoreResult<Vec<Annotation>> {
+    let mut annotations = Vec::new();
+    for item in items {
+        if let Some(annotation_id) = sk_id(item, "ANNOTATION#") {
+            annotations.push(annotation_from_item(image_id, annotation_id, item)?);
+        }
+    }
+    Ok(annotations)
+}
+
+// ========== REPOSITORIES ==========
+
+impl BlockRepo for DynamoStore {
+    async fn put_block(&self, block: &Block) -> StoreResult<()> {
+        self.put(block_to_item(block)).await
+    }
+
+    async fn get_block(&self, block_id: &str) -> StoreResult<Option<Block>> {
+        let (pk, sk) = block_key(block_id);
+        Ok(self.get(pk, sk).await?.map(|item| block_from_item(block_id, &item)))
+    }
+
+    async fn list_blocks(&self) -> StoreResult<Vec<Block>> {
+        let items = self.query_prefix("BLOCK".to_string(), "BLOCK#").await?;
+        Ok(blocks_from_items(&items))
+    }
+
+    async fn list_blocks_page(&self, page: &PageRequest) -> StoreResult<Page<Block>> {
+        let (items, next_cursor) = self.query_page("BLOCK".to_string(), "BLOCK#", page).await?;
+        Ok(Page { items: blocks_from_items(&items), next_cursor })
+    }
+
+    async fn update_block(&self, block_id: &str, changes: &UpdateBlockPayload) -> StoreResult<()> {
+        let mut fields = vec![];
+        if let Some(block_name) = &changes.block_name {
+            fields.push(("block_name", AttributeValue::S(block_name.clone())));
+        }
+        if let Some(block_state) = &changes.block_state {
+            fields.push(("block_state", AttributeValue::S(block_state.clone())));
+        }
+        if let Some(block_locked) = changes.block_locked {
+            fields.push(("block_locked", AttributeValue::Bool(block_locked)));
+        }
+
+        let (pk, sk) = block_key(block_id);
+        self.set_fields(pk, sk, fields).await
+    }
+
+    async fn increment_block_counter(&self, block_id: &str, counter: BlockCounter, delta: i64) -> StoreResult<()> {
+        let (pk, sk) = block_key(block_id);
+        self.add_to(pk, sk, counter.attribute(), delta).await
+    }
+}
+
+impl LabelRepo for DynamoStore {
+    async fn put_label(&self, label: &Label) -> StoreResult<()> {
+        self.put(label_to_item(label)?).await
+    }
+
+    async fn get_label(&self, block_id: &str, label_id: &str) -> StoreResult<Option<Label>> {
+        let (pk, sk) = block_child_key(block_id, "LABEL#", label_id);
+        Ok(self.get(pk, sk).await?.map(|item| label_from_item(block_id, label_id, &item)))
+    }
+
+    async fn list_labels(&self, block_id: &str) -> StoreResult<Vec<Label>> {
+        let items = self.query_prefix(format!("BLOCK#{}", block_id), "LABEL#").await?;
+        Ok(items
+            .iter()
+            .filter_map(|item| sk_id(item, "LABEL#").map(|label_id| label_from_item(block_id, label_id, item)))
+            .collect())
+    }
+
+    async fn update_label(
+        &self,
+        block_id: &str,
+        label_id: &str,
+        changes: &UpdateLabelPayload,
+        expected_version: Option<u64>,
+    ) -> StoreResult<()> {
+        let mut fields = vec![];
+        if let Some(name) = &changes.label_name {
+            fields.push(("label_name", AttributeValue::S(name.clone())));
+        }
+        if let Some(color) = &changes.label_color {
+            fields.push(("label_color", AttributeValue::S(color.clone())));
+        }
+        if let Some(properties) = &changes.label_properties {
+            fields.push(("label_properties", AttributeValue::S(to_json(properties)?)));
+        }
+        if let Some(order) = changes.label_order {
+            fields.push(("label_order", AttributeValue::N(order.to_string())));
+        }
+
+        let (pk, sk) = block_child_key(block_id, "LABEL#", label_id);
+        self.set_versioned(pk, sk, fields, expected_version).await
+    }
+
+    async fn delete_label(&self, block_id: &str, label_id: &str) -> StoreResult<()> {
+        let (pk, sk) = block_child_key(block_id, "LABEL#", label_id);
+        self.delete(pk, sk).await
+    }
+
+    async fn increment_label_count(&self, block_id: &str, label_id: &str, delta: i64) -> StoreResult<()> {
+        let (pk, sk) = block_child_key(block_id, "LABEL#", label_id);
+        self.add_to(pk, sk, "label_count", delta).await
+    }
+}
+
+impl LabelTemplateRepo for DynamoStore {
+    async fn put_label_template(&self, template: &LabelTemplate) -> StoreResult<()> {
+        self.put(label_template_to_item(template)?).await
+    }
+
+    async fn get_label_template(&self, block_type: &str) -> StoreResult<Option<LabelTemplate>> {
+        let (pk, sk) = label_template_key(block_type);
+        self.get(pk, sk).await?.map(|item| label_template_from_item(block_type, &item)).transpose()
+    }
+
+    async fn list_label_templates(&self) -> StoreResult<Vec<LabelTemplate>> {
+        let items = self.query_prefix("TEMPLATE".to_string(), "TEMPLATE#").await?;
+        items
+            .iter()
+            .filter_map(|item| sk_id(item, "TEMPLATE#").map(|block_type| label_template_from_item(block_type, item)))
+            .collect()
+    }
+
+    async fn delete_label_template(&self, block_type: &str) -> StoreResult<()> {
+        let (pk, sk) = label_template_key(block_type);
+        self.delete(pk, sk).await
+    }
+}
+
+impl TaskRepo for DynamoStore {
+    async fn put_task(&self, task: &Task) -> StoreResult<()> {
+        self.put(task_to_item(task)).await
+    }
+
+    async fn get_task(&self, block_id: &str, task_id: &str) -> StoreResult<Option<Task>> {
+        let (pk, sk) = block_child_key(block_id, "TASK#", task_id);
+        Ok(self.get(pk, sk).await?.map(|item| task_from_item(block_id, task_id, &item)))
+    }
+
+    async fn list_tasks(&self, block_id: &str) -> StoreResult<Vec<Task>> {
+        let items = self.query_prefix(format!("BLOCK#{}", block_id), "TASK#").await?;
+        Ok(tasks_from_items(block_id, &items))
+    }
+
+    async fn list_tasks_page(&self, block_id: &str, page: &PageRequest) -> StoreResult<Page<Task>> {
+        let (items, next_cursor) = self
+            .query_page(format!("BLOCK#{}", block_id), "TASK#", page)
+            .await?;
+        Ok(Page { items: tasks_from_items(block_id, &items), next_cursor })
+    }
+
+    async fn update_task(&self, block_id: &str, task_id: &str, changes: &UpdateTaskPayload) -> StoreResult<()> {
+        let (pk, sk) = block_child_key(block_id, "TASK#", task_id);
+        self.set_versioned(pk, sk, task_fields(changes), None).await
+    }
+
+    async fn delete_task(&self, block_id: &str, task_id: &str) -> StoreResult<()> {
+        let (pk, sk) = block_child_key(block_id, "TASK#", task_id);
+        self.delete(pk, sk).await
+    }
+
+    async fn increment_task_image_count(&self, block_id: &str, task_id: &str, delta: i64) -> StoreResult<()> {
+        let (pk, sk) = block_child_key(block_id, "TASK#", task_id);
+        self.add_to(pk, sk, "image_count", delta).await
+    }
+}
+
+impl ImageRepo for DynamoStore {
+    async fn put_image(&self, image: &Image) -> StoreResult<()> {
+        self.put(image_to_item(image)?).await
+    }
+
+    async fn get_image(&self, block_id: &str, image_id: &str) -> StoreResult<Option<Image>> {
+        let (pk, sk) = block_child_key(block_id, "IMAGE#", image_id);
+        Ok(self.get(pk, sk).await?.map(|item| image_from_item(block_id, image_id, &item)))
+    }
+
+    async fn list_images(&self, block_id: &str) -> StoreResult<Vec<Image>> {
+        let items = self.query_prefix(format!("BLOCK#{}", block_id), "IMAGE#").await?;
+        Ok(images_from_items(block_id, &items))
+    }
+
+    async fn list_task_image_ids(&self, task_id: &str) -> StoreResult<Vec<String>> {
+        let items = self.query_prefix(format!("TASK#{}", task_id), "IMAGE#").await?;
+        Ok(image_ids_from_items(&items))
+    }
+
+    async fn list_task_images(&self, block_id: &str, task_id: &str) -> StoreResult<Vec<Image>> {
+        let image_ids = self.list_task_image_ids(task_id).await?;
+        self.images_by_id(block_id, &image_ids).await
+    }
+
+    async fn list_task_images_page(
+        &self,
+        block_id: &str,
+        task_id: &str,
+        page: &PageRequest,
+    ) -> StoreResult<Page<Image>> {
+        let (items, next_cursor) = self.query_page(format!("TASK#{}", task_id), "IMAGE#", page).await?;
+        let image_ids = image_ids_from_items(&items);
+        Ok(Page { items: self.images_by_id(block_id, &image_ids).await?, next_cursor })
+    }
+
+    async fn update_image(&self, block_id: &str, image_id: &str, changes: &UpdateImagePayload) -> StoreResult<()> {
+        let mut fields = vec![];
+        if let Some(locked) = changes.locked {
+            fields.push(("locked", AttributeValue::Bool(locked)));
+        }
+        if let Some(order) = changes.order {
+            fields.push(("order", AttributeValue::N(order.to_string())));
+        }
+        if let Some(width) = changes.width {
+            fields.push(("width", AttributeValue::N(width.to_string())));
+        }
+        if let Some(height) = changes.height {
+            fields.push(("height", AttributeValue::N(height.to_string())));
+        }
+        if let Some(scale) = &changes.scale {
+            fields.push(("scale", AttributeValue::S(to_json(scale)?)));
+        }
+
+        let (pk, sk) = block_child_key(block_id, "IMAGE#", image_id);
+        self.set_fields(pk, sk, fields).await
+    }
+
+    async fn delete_image(&self, block_id: &str, image_id: &str) -> StoreResult<()> {
+        let (pk, sk) = block_child_key(block_id, "IMAGE#", image_id);
+        self.delete(pk, sk).await
+    }
+
+    async fn increment_image_annotation_count(&self, block_id: &str, image_id: &str, delta: i64) -> StoreResult<()> {
+        let (pk, sk) = block_child_key(block_id, "IMAGE#", image_id);
+        self.add_to(pk, sk, "annotation_count", delta).await
+    }
+}
+
+impl AnnotationRepo for DynamoStore {
+    async fn put_annotation(&self, annotation: &Annotation) -> StoreResult<()> {
+        self.put(annotation_to_item(annotation)?).await
+    }
+
+    async fn get_annotation(&self, image_id: &str, annotation_id: &str) -> StoreResult<Option<Annotation>> {
+        let (pk, sk) = annotation_key(image_id, annotation_id);
+        match self.get(pk, sk).await? {
+            Some(item) => Ok(Some(annotation_from_item(image_id, annotation_id, &item)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn list_annotations(&self, image_id: &str) -> StoreResult<Vec<Annotation>> {
+        let items = self.query_prefix(format!("IMAGE#{}", image_id), "ANNOTATION#").await?;
+        annotations_from_items(image_id, &items)
+    }
+
+    async fn list_annotations_page(&self, image_id: &str, page: &PageRequest) -> StoreResult<Page<Annotation>> {
+        let (items, next_cursor) = self
+            .query_page(format!("IMAGE#{}", image_id), "ANNOTATION#", page)
+            .await?;
+        Ok(Page { items: annotations_from_items(image_id, &items)?, next_cursor })
+    }
+
+    async fn update_annotation(
+        &self,
+        image_id: &str,
+        annotation_id: &str,
+        changes: &UpdateAnnotationPayload,
+        updated_at: &str,
+        expected_version: Option<u64>,
+    ) -> StoreResult<()> {
+        let fields = annotation_fields(changes, updated_at)?;
+        let (pk, sk) = annotation_key(image_id, annotation_id);
+        self.set_versioned(pk, sk, fields, expected_version).await
+    }
+
+    async fn delete_annotation(&self, image_id: &str, annotation_id: &str) -> StoreResult<()> {
+        let (pk, sk) = annotation_key(image_id, annotation_id);
+        self.delete(pk, sk).await
+    }
+
+    async fn list_annotation_history(&self, image_id: &str, annotation_id: &str) -> StoreResult<Vec<AnnotationChange>> {
+        let prefix = format!("ANNOTATION#{}#", annotation_id);
+        let items = self.query_prefix(format!("HISTORY#{}", image_id), &prefix).await?;
+        items.iter().map(annotation_change_from_item).collect()
+    }
+
+    async fn list_image_history(&self, image_id: &str) -> StoreResult<Vec<AnnotationChange>> {
+        let items = self.query_prefix(format!("HISTORY#{}", image_id), "ANNOTATION#").await?;
+        items.iter().map(annotation_change_from_item).collect()
+    }
+
+    async fn get_annotation_change(&self, image_id: &str, change: &ChangeRef) -> StoreResult<Option<AnnotationChange>> {
+        let (pk, sk) = annotation_change_key(image_id, change);
+        self.get(pk, sk).await?.as_ref().map(annotation_change_from_item).transpose()
+    }
+}
+
+impl UserRepo for DynamoStore {
+    async fn put_user(&self, user: &User) -> StoreResult<()> {
+        self.put(user_to_item(user)).await
+    }
+
+    async fn get_user(&self, user_id: &str) -> StoreResult<Option<User>> {
+        let (pk, sk) = user_key(user_id);
+        Ok(self.get(pk, sk).await?.map(|item| user_from_item(user_id, &item)))
+    }
+
+    async fn update_user(&self, user_id: &str, changes: &UpdateUserPayload) -> StoreResult<()> {
+        let mut fields = vec![];
+        if let Some(name) = &changes.user_name {
+            fields.push(("user_name", AttributeValue::S(name.clone())));
+        }
+        if let Some(company) = &changes.user_company {
+            fields.push(("user_company", AttributeValue::S(company.clone())));
+        }
+        if let Some(role) = &changes.user_role {
+            fields.push(("user_role", AttributeValue::S(role.clone())));
+        }
+
+        let (pk, sk) = user_key(user_id);
+        self.set_fields(pk, sk, fields).await
+    }
+
+    async fn touch_user_login(&self, user_id: &str, at: &str) -> StoreResult<()> {
+        let (pk, sk) = user_key(user_id);
+        self.set_fields(pk, sk, vec![("last_login", AttributeValue::S(at.to_string()))]).await
+    }
+}
+
+impl InviteRepo for DynamoStore {
+    async fn put_invite(&self, invite: &Invite) -> StoreResult<()> {
+        self.put(invite_to_item(invite)).await
+    }
+
+    async fn get_invite(&self, invite_code: &str) -> StoreResult<Option<Invite>> {
+        let (pk, sk) = invite_key(invite_code);
+        Ok(self.get(pk, sk).await?.map(|item| invite_from_item(invite_code, &item)))
+    }
+
+    async fn mark_invite_used(&self, invite_code: &str, at: &str) -> StoreResult<()> {
+        let (pk, sk) = invite_key(invite_code);
+        self.set_fields(
+            pk,
+            sk,
+            vec![
+                ("status", AttributeValue::S("used".to_string())),
+                ("used_at", AttributeValue::S(at.to_string())),
+            ],
+        )
+        .await
+    }
+}
+
+impl ConnectionRepo for DynamoStore {
+    async fn put_connection(&self, connection: &Connection) -> StoreResult<()> {
+        self.put(connection_to_item(connection)).await
+    }
+
+    async fn delete_connection(&self, connection_id: &str) -> StoreResult<()> {
+        let (pk, sk) = connection_key(connection_id);
+        self.delete(pk, sk).await
+    }
+
+    async fn list_connections(&self) -> StoreResult<Vec<Connection>> {
+        let mut connections = Vec::new();
+        let mut start_key: Option<Item> = None;
+
+        loop {
+            let result = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .filter_expression("entity_type = :type")
+                .expression_attribute_values(":type", AttributeValue::S("connection".to_string()))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await
+                .map_err(|e| format!("DynamoDB scan error: {}", e))?;
+
+            for item in result.items() {
+                if let (Some(connection_id), Some(user_id), Some(connected_at)) = (
+                    string_attr(item, "connection_id"),
+                    string_attr(item, "user_id"),
+                    string_attr(item, "connected_at"),
+                ) {
+                    connections.push(Connection {
+                        connection_id,
+                        user_id,
+                        connected_at,
+                    });
+                }
+            }
+
+            match result.last_evaluated_key() {
+                Some(key) => start_key = Some(key.clone()),
+                None => break,
+            }
+        }
+
+        Ok(connections)
+    }
+}
+
+impl TrashRepo for DynamoStore {
+    async fn get_trash(&self, trash_id: &str) -> StoreResult<Option<TrashEntry>> {
+        let (pk, sk) = trash_key(trash_id);
+        self.get(pk, sk).await?.map(|item| trash_from_item(trash_id, &item)).transpose()
+    }
+
+    async fn list_trash(&self) -> StoreResult<Vec<TrashEntry>> {
+        let items = self.query_prefix("TRASH".to_string(), "TRASH#").await?;
+        items
+            .iter()
+            .filter_map(|item| sk_id(item, "TRASH#").map(|trash_id| trash_from_item(trash_id, item)))
+            .collect()
+    }
+}
+
+impl SnapshotRepo for DynamoStore {
+    async fn get_snapshot(&self, block_id: &str, version: u32) -> StoreResult<Option<Snapshot>> {
+        let (pk, sk) = snapshot_key(block_id, version);
+        Ok(self.get(pk, sk).await?.map(|item| snapshot_from_item(block_id, &item)))
+    }
+
+    async fn list_snapshots(&self, block_id: &str) -> StoreResult<Vec<Snapshot>> {
+        let items = self.query_prefix(format!("BLOCK#{}", block_id), "SNAPSHOT#").await?;
+        Ok(items.iter().map(|item| snapshot_from_item(block_id, item)).collect())
+    }
+}
+
+impl UndoRepo for DynamoStore {
+    async fn get_op_log(&self, user_id: &str, image_id: &str) -> StoreResult<Option<OpLog>> {
+        let (pk, sk) = op_log_key(user_id, image_id);
+        self.get(pk, sk).await?.map(|item| op_log_from_item(user_id, image_id, &item)).transpose()
+    }
+}
+
+impl DynamoStore {
+    fn transact_item(&self, op: WriteOp) -> StoreResult<TransactWriteItem> {
+        let item = match op {
+            WriteOp::PutAnnotation(annotation) => {
+                TransactWriteItem::builder().put(self.transact_put(annotation_to_item(&annotation)?)?)
+            }
+            WriteOp::UpdateAnnotation { image_id, annotation_id, changes, updated_at, expected_version } => {
+                let update = versioned_update(annotation_fields(&changes, &updated_at)?, expected_version);
+                let update = Update::builder()
+                    .table_name(&self.table_name)
+                    .set_key(Some(new_item(annotation_key(&image_id, &annotation_id))))
+                    .update_expression(update.expression)
+                    .condition_expression(update.condition)
+                    .set_expression_attribute_names(Some(update.names))
+                    .set_expression_attribute_values(Some(update.values))
+                    .build()
+                    .map_err(|e| format!("Failed to build transact update: {}", e))?;
+                TransactWriteItem::builder().update(update)
+            }
+            WriteOp::PutAnnotationChange(change) => {
+                TransactWriteItem::builder().put(self.transact_put(annotation_change_to_item(&change)?)?)
+            }
+            WriteOp::PutBlock(block) => TransactWriteItem::builder().put(self.transact_put(block_to_item(&block))?),
+            WriteOp::PutImage(image) => TransactWriteItem::builder().put(self.transact_put(image_to_item(&image)?)?),
+            WriteOp::PutLabel(label) => TransactWriteItem::builder().put(self.transact_put(label_to_item(&label)?)?),
+            WriteOp::PutTask(task) => TransactWriteItem::builder().put(self.transact_put(task_to_item(&task))?),
+            WriteOp::PutTaskImage { block_id, task_id, image_id } => TransactWriteItem::builder()
+                .put(self.transact_put(task_image_to_item(&block_id, &task_id, &image_id))?),
+            WriteOp::UpdateTask { block_id, task_id, changes, expected_version } => {
+                let update = versioned_update(task_fields(&changes), expected_version);
+                let update = Update::builder()
+                    .table_name(&self.table_name)
+                    .set_key(Some(new_item(block_child_key(&block_id, "TASK#", &task_id))))
+                    .update_expression(update.expression)
+                    .condition_expression(update.condition)
+                    .set_expression_attribute_names(Some(update.names))
+                    .set_expression_attribute_values(Some(update.values))
+                    .build()
+                    .map_err(|e| format!("Failed to build transact update: {}", e))?;
+                TransactWriteItem::builder().update(update)
+            }
+            WriteOp::Delete(key) => {
+                let condition = match key {
+                    EntityKey::TaskImage { .. } => None,
+                    _ => Some("attribute_exists(PK)".to_string()),
+                };
+                let delete = Delete::builder()
+                    .table_name(&self.table_name)
+                    .set_key(Some(new_item(entity_key(&key))))
+                    .set_condition_expression(condition)
+                    .build()
+                    .map_err(|e| format!("Failed to build transact delete: {}", e))?;
+                TransactWriteItem::builder().delete(delete)
+            }
+            WriteOp::DeleteAtVersion { key, version } => {
+                let mut names = HashMap::new();
+                let mut values = HashMap::new();
+                let condition = version_condition(Some(version), &mut names, &mut values);
+                let delete = Delete::builder()
+                    .table_name(&self.table_name)
+                    .set_key(Some(new_item(entity_key(&key))))
+                    .condition_expression(condition)
+                    .set_expression_attribute_names(Some(names))
+                    .set_expression_attribute_values((!values.is_empty()).then_some(values))
+                    .build()
+                    .map_err(|e| format!("Failed to build transact delete: {}", e))?;
+                TransactWriteItem::builder().delete(delete)
+            }
+            WriteOp::PutTrash(entry) => TransactWriteItem::builder().put(self.transact_put(trash_to_item(&entry)?)?),
+            WriteOp::Trash { key, trash_id, deleted_at, expected_version } => {
+                let mut names = HashMap::new();
+                let mut values = HashMap::new();
+                let condition = version_condition(expected_version, &mut names, &mut values);
+                values.insert(":deleted_at".to_string(), AttributeValue::S(deleted_at));
+                values.insert(":trash_id".to_string(), AttributeValue::S(trash_id));
+                let update = Update::builder()
+                    .table_name(&self.table_name)
+                    .set_key(Some(new_item(entity_key(&key))))
+                    .update_expression("SET deleted_at = :deleted_at, trash_id = :trash_id")
+                    .condition_expression(condition)
+                    .set_expression_attribute_names((!names.is_empty()).then_some(names))
+                    .set_expression_attribute_values(Some(values))
+                    .build()
+                    .map_err(|e| format!("Failed to build transact update: {}", e))?;
+                TransactWriteItem::builder().update(update)
+            }
+            WriteOp::Restore { key, trash_id } => {
+                let update = Update::builder()
+                    .table_name(&self.table_name)
+                    .set_key(Some(new_item(entity_key(&key))))
+                    .update_expression("REMOVE deleted_at, trash_id")
+                    .condition_expression("trash_id = :trash_id")
+                    .expression_attribute_values(":trash_id", AttributeValue::S(trash_id))
+                    .build()
+                    .map_err(|e| format!("Failed to build transact update: {}", e))?;
+                TransactWriteItem::builder().update(update)
+            }
+            WriteOp::Increment { key, deltas } => {
+                let key = new_item(entity_key(&key));
+                if deltas.is_empty() {
+                    TransactWriteItem::builder().condition_check(self.transact_check(key)?)
+                } else {
+                    let mut update_expr = vec![];
+                    let mut builder = Update::builder()
+                        .table_name(&self.table_name)
+                        .set_key(Some(key))
+                        .condition_expression(LIVE_CONDITION);
+                    for (i, (counter, delta)) in deltas.iter().enumerate() {
+                        update_expr.push(format!("{0} = {0} + :d{1}", counter.attribute(), i));
+                        builder = builder.expression_attribute_values(format!(":d{}", i), AttributeValue::N(delta.to_string()));
+                    }
+                    let update = builder
+                        .update_expression(format!("SET {}", update_expr.join(", ")))
+                        .build()
+                        .map_err(|e| format!("Failed to build transact update: {}", e))?;
+                    TransactWriteItem::builder().update(update)
+                }
+            }
+            WriteOp::PutSnapshot(snapshot) => {
+                TransactWriteItem::builder().put(self.transact_put(snapshot_to_item(&snapshot))?)
+            }
+            WriteOp::PutOpLog { log, expected_version } => {
+                let mut put = Put::builder().table_name(&self.table_name).set_item(Some(op_log_to_item(&log)?));
+                put = if expected_version == 0 {
+                    put.condition_expression("attribute_not_exists(PK)")
+                } else {
+                    put.condition_expression("#version = :expected_version")
+                        .expression_attribute_names("#version", "version")
+                        .expression_attribute_values(":expected_version", AttributeValue::N(expected_version.to_string()))
+                };
+                let put = put.build().map_err(|e| format!("Failed to build transact put: {}", e))?;
+                TransactWriteItem::builder().put(put)
+            }
+        };
+
+        Ok(item.build())
+    }
+
+    fn transact_put(&self, item: Item) -> StoreResult<Put> {
+        Put::builder()
+            .table_name(&self.table_name)
+            .set_item(Some(item))
+            .condition_expression("attribute_not_exists(PK)")
+            .build()
+            .map_err(|e| format!("Failed to build transact put: {}", e))
+    }
+
+    fn transact_check(&self, key: Item) -> StoreResult<ConditionCheck> {
+        ConditionCheck::builder()
+            .table_name(&self.table_name)
+            .set_key(Some(key))
+            .condition_expression(LIVE_CONDITION)
+            .build()
+            .map_err(|e| format!("Failed to build transact condition check: {}", e))
+    }
+}
+
+impl TransactRepo for DynamoStore {
+    async fn transact(&self, ops: Vec<WriteOp>) -> StoreResult<()> {
+        if ops.is_empty() {
+            return Ok(());
+        }
+        if ops.len() > MAX_TRANSACT_OPS {
+            return Err(format!("Transaction has {} ops, limit is {}", ops.len(), MAX_TRANSACT_OPS));
+        }
+
+        let items = ops
+            .into_iter()
+            .map(|op| self.transact_item(op))
+            .collect::<StoreResult<Vec<_>>>()?;
+
+        self.client
+            .transact_write_items()
+            .set_transact_items(Some(items))
+            .send()
+            .await
+            .map_err(|e| match e.into_service_error() {
+                TransactWriteItemsError::TransactionCanceledException(cancelled) => {
+                    // One reason per op, in order; "None" marks ops that were fine
+                    let reasons: Vec<&str> = cancelled
+                        .cancellation_reasons()
+                        .iter()
+                        .map(|r| r.code().unwrap_or("None"))
+                        .collect();
+                    format!("Transaction cancelled: [{}]", reasons.join(", "))
+                }
+                other => format!("DynamoDB transact_write_items error: {}", other),
+            })?;
+
+        Ok(())
+    }
+}
+
+impl BatchRepo for DynamoStore {
+    /// Batch delete (25 items per request with retry logic)
+    async fn delete_batch(&self, keys: &[EntityKey]) -> StoreResult<()> {
+        for chunk in keys.chunks(25) {
+            let mut write_reqs = Vec::new();
+            for key in chunk {
+                let delete = DeleteRequest::builder()
+                    .set_key(Some(new_item(entity_key(key))))
+                    .build()
+                    .map_err(|e| format!("Failed to build delete request: {}", e))?;
+                write_reqs.push(WriteRequest::builder().delete_request(delete).build());
+            }
+
+            let mut reqs = write_reqs;
+            let mut attempts = 0;
+            loop {
+                attempts += 1;
+                let result = self
+                    .client
+                    .batch_write_item()
+                    .request_items(&self.table_name, reqs)
+                    .send()
+                    .await
+                    .map_err(|e| format!("DynamoDB batch_write_item error: {}", e))?;
+
+                let unprocessed = result
+                    .unprocessed_items()
+                    .and_then(|m| m.get(&self.table_name))
+                    .filter(|reqs| !reqs.is_empty())
+                    .cloned();
+                match unprocessed {
+                    None => break,
+                    // Callers drop their record of these rows once this
+                    // succeeds, so leftovers must fail the call
+                    Some(left) if attempts >= 5 => {
+                        return Err(format!("DynamoDB batch_write_item left {} deletes unprocessed", left.len()));
+                    }
+                    Some(left) => {
+                        sleep(Duration::from_millis(100 * attempts)).await;
+                        reqs = left;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
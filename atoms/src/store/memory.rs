@@ -0,0 +1,1251 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::{Mutex, MutexGuard};
+
+use super::{
+    AnnotationRepo, BatchRepo, BlockCounter, BlockRepo, ConnectionRepo, Counter, Cursor, EntityKey,
+    ImageRepo, InviteRepo, LabelRepo, LabelTemplateRepo, Page, PageRequest, SnapshotRepo, StoreResult, TaskRepo, TransactRepo,
+    TrashRepo, UndoRepo, UserRepo, WriteOp, VERSION_CONFLICT,
+};
+use crate::blocks::model::{Block, UpdateBlockPayload};
+use crate::drawing::model::{Annotation, AnnotationChange, UpdateAnnotationPayload};
+use crate::labels::model::{Label, LabelTemplate, UpdateLabelPayload};
+use crate::media::model::{Image, UpdateImagePayload};
+use crate::tasks::model::{Task, UpdateTaskPayload};
+use crate::snapshots::model::Snapshot;
+use crate::trash::model::TrashEntry;
+use crate::undo::model::{ChangeRef, OpLog};
+use crate::users::model::{Connection, Invite, UpdateUserPayload, User};
+
+/// Parent id + child id, ordered the same way DynamoDB orders sort keys
+type ChildKey = (String, String);
+
+#[derive(Default)]
+struct Tables {
+    blocks: BTreeMap<String, Block>,
+    labels: BTreeMap<ChildKey, Label>,
+    /// block_type -> template
+    label_templates: BTreeMap<String, LabelTemplate>,
+    tasks: BTreeMap<ChildKey, Task>,
+    images: BTreeMap<ChildKey, Image>,
+    /// (task_id, image_id) -> image_id, mirroring DynamoStore's index rows
+    task_images: BTreeMap<ChildKey, String>,
+    annotations: BTreeMap<ChildKey, Annotation>,
+    /// (image_id, "{annotation_id}#{at}#{change_id}") -> change
+    annotation_changes: BTreeMap<ChildKey, AnnotationChange>,
+    users: BTreeMap<String, User>,
+    invites: BTreeMap<String, Invite>,
+    connections: BTreeMap<String, Connection>,
+    trash: BTreeMap<String, TrashEntry>,
+    /// (block_id, version) -> snapshot index row
+    snapshots: BTreeMap<(String, u32), Snapshot>,
+    /// (user_id, image_id) -> undo log
+    op_logs: BTreeMap<ChildKey, OpLog>,
+}
+
+/// In-process backend for tests and local tooling
+///
+/// Mirrors the DynamoDB semantics the services rely on: puts overwrite,
+/// deletes of missing rows succeed, counter updates on missing or trashed rows
+/// fail, and list reads skip trashed rows.
+#[derive(Default)]
+pub struct MemoryStore {
+    tables: Mutex<Tables>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn tables(&self) -> MutexGuard<'_, Tables> {
+        // A poisoned lock only means another test thread panicked mid-write
+        self.tables.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn key(parent: &str, id: &str) -> ChildKey {
+    (parent.to_string(), id.to_string())
+}
+
+/// A stored row; list reads skip rows in the trash, like DynamoStore's filter
+trait Row: Clone {
+    fn trashed(&self) -> bool {
+        false
+    }
+}
+
+impl Row for Label {}
+impl Row for AnnotationChange {}
+impl Row for String {}
+
+impl Row for Block {
+    fn trashed(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+}
+
+impl Row for Task {
+    fn trashed(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+}
+
+impl Row for Image {
+    fn trashed(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+}
+
+impl Row for Annotation {
+    fn trashed(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+}
+
+fn children<T: Row>(table: &BTreeMap<ChildKey, T>, parent: &str) -> Vec<T> {
+    table
+        .range(key(parent, "")..)
+        .take_while(|((p, _), _)| p == parent)
+        .filter(|(_, v)| !v.trashed())
+        .map(|(_, v)| v.clone())
+        .collect()
+}
+
+/// One page of `(id, row)` pairs read in key order from `rows`. Trashed
+/// rows use up the page size but are left out, as with DynamoDB filters.
+fn take_page<'a, T: Row + 'a>(
+    rows: impl Iterator<Item = (&'a String, &'a T)>,
+    pk: &str,
+    page: &PageRequest,
+) -> Page<T> {
+    let mut rows = rows.peekable();
+    let mut items = Vec::new();
+    let mut last_id = None;
+    for (id, row) in rows.by_ref().take(page.page_size() as usize) {
+        if !row.trashed() {
+            items.push(row.clone());
+        }
+        last_id = Some(id.clone());
+    }
+
+    let next_cursor = match (rows.peek(), last_id) {
+        (Some(_), Some(sk)) => Some(Cursor { pk: pk.to_string(), sk }.encode()),
+        _ => None,
+    };
+
+    Page { items, next_cursor }
+}
+
+/// Page through `parent`'s rows, resuming after the cursor
+fn children_page<T: Row>(table: &BTreeMap<ChildKey, T>, parent: &str, page: &PageRequest) -> StoreResult<Page<T>> {
+    let start = match page.cursor_in(parent)? {
+        Some(cursor) => Bound::Excluded(key(parent, &cursor.sk)),
+        None => Bound::Included(key(parent, "")),
+    };
+    let rows = table
+        .range((start, Bound::Unbounded))
+        .take_while(|((p, _), _)| p == parent)
+        .map(|((_, id), row)| (id, row));
+
+    Ok(take_page(rows, parent, page))
+}
+
+/// Same check as DynamoStore's version condition
+fn check_version(version: u64, expected: Option<u64>) -> StoreResult<()> {
+    match expected {
+        Some(expected) if expected != version => Err(VERSION_CONFLICT.to_string()),
+        _ => Ok(()),
+    }
+}
+
+fn add_u32(value: &mut u32, delta: i64) {
+    *value = (*value as i64 + delta).max(0) as u32;
+}
+
+fn annotation_change_key(change: &AnnotationChange) -> ChildKey {
+    change_ref_key(&change.image_id, &ChangeRef::from(change))
+}
+
+fn change_ref_key(image_id: &str, change: &ChangeRef) -> ChildKey {
+    key(image_id, &format!("{}#{}#{}", change.annotation_id, change.at, change.change_id))
+}
+
+fn apply_annotation_changes(annotation: &mut Annotation, changes: &UpdateAnnotationPayload, updated_at: &str) {
+    annotation.version += 1;
+    annotation.updated_at = Some(updated_at.to_string());
+    if let Some(label_id) = &changes.label_id {
+        annotation.label_id = label_id.clone();
+    }
+    if let Some(geometry) = &changes.geometry {
+        annotation.geometry = geometry.clone();
+    }
+    if let Some(attributes) = &changes.attributes {
+        annotation.attributes = attributes.clone();
+    }
+}
+
+fn apply_task_changes(task: &mut Task, changes: &UpdateTaskPayload) {
+    task.version += 1;
+    if let Some(name) = &changes.task_name {
+        task.task_name = name.clone();
+    }
+    if let Some(state) = &changes.task_state {
+        task.task_state = state.clone();
+    }
+    if let Some(assignee) = &changes.assignee {
+        task.assignee = assignee.clone();
+    }
+    if let Some(checked_by) = &changes.checked_by {
+        task.checked_by = checked_by.clone();
+    }
+}
+
+impl Tables {
+    fn exists(&self, entity: &EntityKey) -> bool {
+        match entity {
+            EntityKey::Block { block_id } => self.blocks.contains_key(block_id),
+            EntityKey::Label { block_id, label_id } => self.labels.contains_key(&key(block_id, label_id)),
+            EntityKey::Task { block_id, task_id } => self.tasks.contains_key(&key(block_id, task_id)),
+            EntityKey::Image { block_id, image_id } => self.images.contains_key(&key(block_id, image_id)),
+            EntityKey::Annotation { image_id, annotation_id } => {
+                self.annotations.contains_key(&key(image_id, annotation_id))
+            }
+            EntityKey::TaskImage { task_id, image_id } => self.task_images.contains_key(&key(task_id, image_id)),
+            EntityKey::Trash { trash_id } => self.trash.contains_key(trash_id),
+            EntityKey::Snapshot { block_id, version } => self.snapshots.contains_key(&(block_id.clone(), *version)),
+        }
+    }
+
+    /// The row exists and is not in the trash
+    fn live(&self, entity: &EntityKey) -> bool {
+        match entity {
+            EntityKey::Block { block_id } => self.blocks.get(block_id).is_some_and(|b| !b.trashed()),
+            EntityKey::Task { block_id, task_id } => self.tasks.get(&key(block_id, task_id)).is_some_and(|t| !t.trashed()),
+            EntityKey::Image { block_id, image_id } => {
+                self.images.get(&key(block_id, image_id)).is_some_and(|i| !i.trashed())
+            }
+            EntityKey::Annotation { image_id, annotation_id } => self
+                .annotations
+                .get(&key(image_id, annotation_id))
+                .is_some_and(|a| !a.trashed()),
+            _ => self.exists(entity),
+        }
+    }
+
+    /// `deleted_at` and `trash_id` of a row that can be trashed
+    fn trash_marks(&mut self, entity: &EntityKey) -> Option<(&mut Option<String>, &mut Option<String>)> {
+        match entity {
+            EntityKey::Block { block_id } => {
+                let block = self.blocks.get_mut(block_id)?;
+                Some((&mut block.deleted_at, &mut block.trash_id))
+            }
+            EntityKey::Task { block_id, task_id } => {
+                let task = self.tasks.get_mut(&key(block_id, task_id))?;
+                Some((&mut task.deleted_at, &mut task.trash_id))
+            }
+            EntityKey::Image { block_id, image_id } => {
+                let image = self.images.get_mut(&key(block_id, image_id))?;
+                Some((&mut image.deleted_at, &mut image.trash_id))
+            }
+            EntityKey::Annotation { image_id, annotation_id } => {
+                let annotation = self.annotations.get_mut(&key(image_id, annotation_id))?;
+                Some((&mut annotation.deleted_at, &mut annotation.trash_id))
+            }
+            _ => None,
+        }
+    }
+
+    fn remove(&mut self, entity: &EntityKey) {
+        match entity {
+            EntityKey::Block { block_id } => {
+                self.blocks.remove(block_id);
+            }
+            EntityKey::Label { block_id, label_id } => {
+                self.labels.remove(&key(block_id, label_id));
+            }
+            EntityKey::Task { block_id, task_id } => {
+                self.tasks.remove(&key(block_id, task_id));
+            }
+            EntityKey::Image { block_id, image_id } => {
+                self.images.remove(&key(block_id, image_id));
+            }
+            EntityKey::Annotation { image_id, annotation_id } => {
+                self.annotations.remove(&key(image_id, annotation_id));
+            }
+            EntityKey::TaskImage { task_id, image_id } => {
+                self.task_images.remove(&key(task_id, image_id));
+            }
+            EntityKey::Trash { trash_id } => {
+                self.trash.remove(trash_id);
+            }
+            EntityKey::Snapshot { block_id, version } => {
+                self.snapshots.remove(&(block_id.clone(), *version));
+            }
+        }
+    }
+
+    /// Version of a versioned row, if the row exists
+    fn version(&self, entity: &EntityKey) -> Option<u64> {
+        match entity {
+            EntityKey::Label { block_id, label_id } => Some(self.labels.get(&key(block_id, label_id))?.version),
+            EntityKey::Task { block_id, task_id } => Some(self.tasks.get(&key(block_id, task_id))?.version),
+            EntityKey::Annotation { image_id, annotation_id } => {
+                Some(self.annotations.get(&key(image_id, annotation_id))?.version)
+            }
+            _ => None,
+        }
+    }
+
+    /// The counter field on a stored row, if the row exists and has it
+    fn counter(&mut self, entity: &EntityKey, counter: Counter) -> Option<&mut u32> {
+        match (entity, counter) {
+            (EntityKey::Block { block_id }, Counter::Block(counter)) => {
+                let block = self.blocks.get_mut(block_id)?;
+                Some(match counter {
+                    BlockCounter::ImageCount => &mut block.image_count,
+                    BlockCounter::ApprovedImageCount => &mut block.approved_image_count,
+                    BlockCounter::AnnotationCount => &mut block.annotation_count,
+                })
+            }
+            (EntityKey::Task { block_id, task_id }, Counter::TaskImageCount) => {
+                Some(&mut self.tasks.get_mut(&key(block_id, task_id))?.image_count)
+            }
+            (EntityKey::Image { block_id, image_id }, Counter::ImageAnnotationCount) => {
+                Some(&mut self.images.get_mut(&key(block_id, image_id))?.annotation_count)
+            }
+            (EntityKey::Label { block_id, label_id }, Counter::LabelCount) => {
+                Some(&mut self.labels.get_mut(&key(block_id, label_id))?.label_count)
+            }
+            _ => None,
+        }
+    }
+
+    /// Same conditions DynamoStore attaches to each transaction item
+    fn check(&mut self, op: &WriteOp) -> StoreResult<()> {
+        let ok = match op {
+            WriteOp::PutAnnotation(a) => !self.annotations.contains_key(&key(&a.image_id, &a.annotation_id)),
+            WriteOp::UpdateAnnotation { image_id, annotation_id, expected_version, .. } => self
+                .annotations
+                .get(&key(image_id, annotation_id))
+                .is_some_and(|a| !a.trashed() && check_version(a.version, *expected_version).is_ok()),
+            WriteOp::PutAnnotationChange(change) => !self.annotation_changes.contains_key(&annotation_change_key(change)),
+            WriteOp::PutBlock(b) => !self.blocks.contains_key(&b.block_id),
+            WriteOp::PutImage(i) => !self.images.contains_key(&key(&i.block_id, &i.image_id)),
+            WriteOp::PutLabel(l) => !self.labels.contains_key(&key(&l.block_id, &l.label_id)),
+            WriteOp::PutTask(t) => !self.tasks.contains_key(&key(&t.block_id, &t.task_id)),
+            WriteOp::PutTaskImage { task_id, image_id, .. } => !self.task_images.contains_key(&key(task_id, image_id)),
+            WriteOp::UpdateTask { block_id, task_id, expected_version, .. } => self
+                .tasks
+                .get(&key(block_id, task_id))
+                .is_some_and(|task| !task.trashed() && check_version(task.version, *expected_version).is_ok()),
+            WriteOp::Delete(EntityKey::TaskImage { .. }) => true,
+            WriteOp::Delete(entity) => self.exists(entity),
+            WriteOp::DeleteAtVersion { key, version } => self.live(key) && self.version(key) == Some(*version),
+            WriteOp::PutTrash(entry) => !self.trash.contains_key(&entry.trash_id),
+            WriteOp::Trash { key, expected_version, .. } => {
+                self.live(key) && check_version(self.version(key).unwrap_or(0), *expected_version).is_ok()
+            }
+            WriteOp::Restore { key, trash_id } => self
+                .trash_marks(key)
+                .is_some_and(|(_, current)| current.as_deref() == Some(trash_id.as_str())),
+            WriteOp::Increment { key, deltas } => {
+                self.live(key) && deltas.iter().all(|(counter, _)| self.counter(key, *counter).is_some())
+            }
+            WriteOp::PutSnapshot(s) => !self.snapshots.contains_key(&(s.block_id.clone(), s.version)),
+            WriteOp::PutOpLog { log, expected_version } => {
+                let current = self.op_logs.get(&key(&log.user_id, &log.image_id));
+                current.map_or(0, |log| log.version) == *expected_version
+            }
+        };
+
+        if ok {
+            Ok(())
+        } else {
+            Err(format!("Transaction cancelled: condition failed for {:?}", op))
+        }
+    }
+
+    fn apply(&mut self, op: WriteOp) {
+        match op {
+            WriteOp::PutAnnotation(a) => {
+                self.annotations.insert(key(&a.image_id, &a.annotation_id), a);
+            }
+            WriteOp::UpdateAnnotation { image_id, annotation_id, changes, updated_at, .. } => {
+                if let Some(annotation) = self.annotations.get_mut(&key(&image_id, &annotation_id)) {
+                    apply_annotation_changes(annotation, &changes, &updated_at);
+                }
+            }
+            WriteOp::PutAnnotationChange(change) => {
+                self.annotation_changes.insert(annotation_change_key(&change), change);
+            }
+            WriteOp::PutBlock(b) => {
+                self.blocks.insert(b.block_id.clone(), b);
+            }
+            WriteOp::PutImage(i) => {
+                self.images.insert(key(&i.block_id, &i.image_id), i);
+            }
+            WriteOp::PutLabel(l) => {
+                self.labels.insert(key(&l.block_id, &l.label_id), l);
+            }
+            WriteOp::PutTask(mut t) => {
+                t.images = vec![];
+                self.tasks.insert(key(&t.block_id, &t.task_id), t);
+            }
+            WriteOp::PutTaskImage { task_id, image_id, .. } => {
+                self.task_images.insert(key(&task_id, &image_id), image_id);
+            }
+            WriteOp::UpdateTask { block_id, task_id, changes, .. } => {
+                if let Some(task) = self.tasks.get_mut(&key(&block_id, &task_id)) {
+                    apply_task_changes(task, &changes);
+                }
+            }
+            WriteOp::Delete(entity) | WriteOp::DeleteAtVersion { key: entity, .. } => self.remove(&entity),
+            WriteOp::PutTrash(entry) => {
+                self.trash.insert(entry.trash_id.clone(), entry);
+            }
+            WriteOp::Trash { key, trash_id, deleted_at, .. } => {
+                if let Some((row_deleted_at, row_trash_id)) = self.trash_marks(&key) {
+                    *row_deleted_at = Some(deleted_at);
+                    *row_trash_id = Some(trash_id);
+                }
+            }
+            WriteOp::Restore { key, .. } => {
+                if let Some((row_deleted_at, row_trash_id)) = self.trash_marks(&key) {
+                    *row_deleted_at = None;
+                    *row_trash_id = None;
+                }
+            }
+            WriteOp::Increment { key, deltas } => {
+                for (counter, delta) in deltas {
+                    if let Some(value) = self.counter(&key, counter) {
+                        add_u32(value, delta);
+                    }
+                }
+            }
+            WriteOp::PutSnapshot(s) => {
+                self.snapshots.insert((s.block_id.clone(), s.version), s);
+            }
+            WriteOp::PutOpLog { log, .. } => {
+                self.op_logs.insert(key(&log.user_id, &log.image_id), log);
+            }
+        }
+    }
+}
+
+impl BlockRepo for MemoryStore {
+    async fn put_block(&self, block: &Block) -> StoreResult<()> {
+        self.tables().blocks.insert(block.block_id.clone(), block.clone());
+        Ok(())
+    }
+
+    async fn get_block(&self, block_id: &str) -> StoreResult<Option<Block>> {
+        Ok(self.tables().blocks.get(block_id).cloned())
+    }
+
+    async fn list_blocks(&self) -> StoreResult<Vec<Block>> {
+        Ok(self.tables().blocks.values().filter(|b| !b.trashed()).cloned().collect())
+    }
+
+    async fn list_blocks_page(&self, page: &PageRequest) -> StoreResult<Page<Block>> {
+        let tables = self.tables();
+        let start = match page.cursor_in("BLOCK")? {
+            Some(cursor) => Bound::Excluded(cursor.sk.clone()),
+            None => Bound::Unbounded,
+        };
+        Ok(take_page(tables.blocks.range((start, Bound::Unbounded)), "BLOCK", page))
+    }
+
+    async fn update_block(&self, block_id: &str, changes: &UpdateBlockPayload) -> StoreResult<()> {
+        let mut tables = self.tables();
+        let block = tables.blocks.get_mut(block_id).ok_or("Block not found")?;
+        if let Some(block_name) = &changes.block_name {
+            block.block_name = block_name.clone();
+        }
+        if let Some(block_state) = &changes.block_state {
+            block.block_state = block_state.clone();
+        }
+        if let Some(block_locked) = changes.block_locked {
+            block.block_locked = block_locked;
+        }
+        Ok(())
+    }
+
+    async fn increment_block_counter(&self, block_id: &str, counter: BlockCounter, delta: i64) -> StoreResult<()> {
+        let mut tables = self.tables();
+        let block = tables.blocks.get_mut(block_id).ok_or("Block not found")?;
+        let value = match counter {
+            BlockCounter::ImageCount => &mut block.image_count,
+            BlockCounter::ApprovedImageCount => &mut block.approved_image_count,
+            BlockCounter::AnnotationCount => &mut block.annotation_count,
+        };
+        add_u32(value, delta);
+        Ok(())
+    }
+}
+
+impl LabelRepo for MemoryStore {
+    async fn put_label(&self, label: &Label) -> StoreResult<()> {
+        self.tables()
+            .labels
+            .insert(key(&label.block_id, &label.label_id), label.clone());
+        Ok(())
+    }
+
+    async fn get_label(&self, block_id: &str, label_id: &str) -> StoreResult<Option<Label>> {
+        Ok(self.tables().labels.get(&key(block_id, label_id)).cloned())
+    }
+
+    async fn list_labels(&self, block_id: &str) -> StoreResult<Vec<Label>> {
+        Ok(children(&self.tables().labels, block_id))
+    }
+
+    async fn update_label(
+        &self,
+        block_id: &str,
+        label_id: &str,
+        changes: &UpdateLabelPayload,
+        expected_version: Option<u64>,
+    ) -> StoreResult<()> {
+        let mut tables = self.tables();
+        let label = tables.labels.get_mut(&key(block_id, label_id)).ok_or(VERSION_CONFLICT)?;
+        check_version(label.version, expected_version)?;
+        label.version += 1;
+        if let Some(name) = &changes.label_name {
+            label.label_name = name.clone();
+        }
+        if let Some(color) = &changes.label_color {
+            label.label_color = color.clone();
+        }
+        if let Some(properties) = &changes.label_properties {
+            label.label_properties = Some(properties.clone());
+        }
+        if let Some(order) = changes.label_order {
+            label.label_order = Some(order);
+        }
+        Ok(())
+    }
+
+    async fn delete_label(&self, block_id: &str, label_id: &str) -> StoreResult<()> {
+        self.tables().labels.remove(&key(block_id, label_id));
+        Ok(())
+    }
+
+    async fn increment_label_count(&self, block_id: &str, label_id: &str, delta: i64) -> StoreResult<()> {
+        let mut tables = self.tables();
+        let label = tables.labels.get_mut(&key(block_id, label_id)).ok_or("Label not found")?;
+        add_u32(&mut label.label_count, delta);
+        Ok(())
+    }
+}
+
+impl LabelTemplateRepo for MemoryStore {
+    async fn put_label_template(&self, template: &LabelTemplate) -> StoreResult<()> {
+        self.tables().label_templates.insert(template.block_type.clone(), template.clone());
+        Ok(())
+    }
+
+    async fn get_label_template(&self, block_type: &str) -> StoreResult<Option<LabelTemplate>> {
+        Ok(self.tables().label_templates.get(block_type).cloned())
+    }
+
+    async fn list_label_templates(&self) -> StoreResult<Vec<LabelTemplate>> {
+        Ok(self.tables().label_templates.values().cloned().collect())
+    }
+
+    async fn delete_label_template(&self, block_type: &str) -> StoreResult<()> {
+        self.tables().label_templates.remove(block_type);
+        Ok(())
+    }
+}
+
+impl TaskRepo for MemoryStore {
+    async fn put_task(&self, task: &Task) -> StoreResult<()> {
+        let mut task = task.clone();
+        task.images = vec![];
+        self.tables().tasks.insert(key(&task.block_id, &task.task_id), task);
+        Ok(())
+    }
+
+    async fn get_task(&self, block_id: &str, task_id: &str) -> StoreResult<Option<Task>> {
+        Ok(self.tables().tasks.get(&key(block_id, task_id)).cloned())
+    }
+
+    async fn list_tasks(&self, block_id: &str) -> StoreResult<Vec<Task>> {
+        Ok(children(&self.tables().tasks, block_id))
+    }
+
+    async fn list_tasks_page(&self, block_id: &str, page: &PageRequest) -> StoreResult<Page<Task>> {
+        children_page(&self.tables().tasks, block_id, page)
+    }
+
+    async fn update_task(&self, block_id: &str, task_id: &str, changes: &UpdateTaskPayload) -> StoreResult<()> {
+        let mut tables = self.tables();
+        let task = tables
+            .tasks
+            .get_mut(&key(block_id, task_id))
+            .filter(|t| !t.trashed())
+            .ok_or(VERSION_CONFLICT)?;
+        apply_task_changes(task, changes);
+        Ok(())
+    }
+
+    async fn delete_task(&self, block_id: &str, task_id: &str) -> StoreResult<()> {
+        self.tables().tasks.remove(&key(block_id, task_id));
+        Ok(())
+    }
+
+    async fn increment_task_image_count(&self, block_id: &str, task_id: &str, delta: i64) -> StoreResult<()> {
+        let mut tables = self.tables();
+        let task = tables.tasks.get_mut(&key(block_id, task_id)).ok_or("Task not found")?;
+        add_u32(&mut task.image_count, delta);
+        Ok(())
+    }
+}
+
+impl ImageRepo for MemoryStore {
+    async fn put_image(&self, image: &Image) -> StoreResult<()> {
+        self.tables()
+            .images
+            .insert(key(&image.block_id, &image.image_id), image.clone());
+        Ok(())
+    }
+
+    async fn get_image(&self, block_id: &str, image_id: &str) -> StoreResult<Option<Image>> {
+        Ok(self.tables().images.get(&key(block_id, image_id)).cloned())
+    }
+
+    async fn list_images(&self, block_id: &str) -> StoreResult<Vec<Image>> {
+        Ok(children(&self.tables().images, block_id))
+    }
+
+    async fn list_task_image_ids(&self, task_id: &str) -> StoreResult<Vec<String>> {
+        Ok(children(&self.tables().task_images, task_id))
+    }
+
+    async fn list_task_images(&self, block_id: &str, task_id: &str) -> StoreResult<Vec<Image>> {
+        let tables = self.tables();
+        Ok(children(&tables.task_images, task_id)
+            .iter()
+            .filter_map(|image_id| tables.images.get(&key(block_id, image_id)).filter(|i| !i.trashed()).cloned())
+            .collect())
+    }
+
+    async fn list_task_images_page(
+        &self,
+        block_id: &str,
+        task_id: &str,
+        page: &PageRequest,
+    ) -> StoreResult<Page<Image>> {
+        let tables = self.tables();
+        let ids = children_page(&tables.task_images, task_id, page)?;
+        Ok(Page {
+            items: ids
+                .items
+                .iter()
+                .filter_map(|image_id| tables.images.get(&key(block_id, image_id)).filter(|i| !i.trashed()).cloned())
+                .collect(),
+            next_cursor: ids.next_cursor,
+        })
+    }
+
+    async fn update_image(&self, block_id: &str, image_id: &str, changes: &UpdateImagePayload) -> StoreResult<()> {
+        let mut tables = self.tables();
+        let image = tables.images.get_mut(&key(block_id, image_id)).ok_or("Image not found")?;
+        if let Some(locked) = changes.locked {
+            image.locked = locked;
+        }
+        if let Some(order) = changes.order {
+            image.order = Some(order);
+        }
+        if let Some(width) = changes.width {
+            image.width = Some(width);
+        }
+        if let Some(height) = changes.height {
+            image.height = Some(height);
+        }
+        if let Some(scale) = &changes.scale {
+            image.scale = Some(scale.clone());
+        }
+        Ok(())
+    }
+
+    async fn delete_image(&self, block_id: &str, image_id: &str) -> StoreResult<()> {
+        self.tables().images.remove(&key(block_id, image_id));
+        Ok(())
+    }
+
+    async fn increment_image_annotation_count(&self, block_id: &str, image_id: &str, delta: i64) -> StoreResult<()> {
+        let mut tables = self.tables();
+        let image = tables.images.get_mut(&key(block_id, image_id)).ok_or("Image not found")?;
+        add_u32(&mut image.annotation_count, delta);
+        Ok(())
+    }
+}
+
+impl AnnotationRepo for MemoryStore {
+    async fn put_annotation(&self, annotation: &Annotation) -> StoreResult<()> {
+        self.tables().annotations.insert(
+            key(&annotation.image_id, &annotation.annotation_id),
+            annotation.clone(),
+        );
+        Ok(())
+    }
+
+    async fn get_annotation(&self, image_id: &str, annotation_id: &str) -> StoreResult<Option<Annotation>> {
+        Ok(self.tables().annotations.get(&key(image_id, annotation_id)).cloned())
+    }
+
+    async fn list_annotations(&self, image_id: &str) -> StoreResult<Vec<Annotation>> {
+        Ok(children(&self.tables().annotations, image_id))
+    }
+
+    async fn list_annotations_page(&self, image_id: &str, page: &PageRequest) -> StoreResult<Page<Annotation>> {
+        children_page(&self.tables().annotations, image_id, page)
+    }
+
+    async fn update_annotation(
+        &self,
+        image_id: &str,
+        annotation_id: &str,
+        changes: &UpdateAnnotationPayload,
+        updated_at: &str,
+        expected_version: Option<u64>,
+    ) -> StoreResult<()> {
+        let mut tables = self.tables();
+        let annotation = tables
+            .annotations
+            .get_mut(&key(image_id, annotation_id))
+            .filter(|a| !a.trashed())
+            .ok_or(VERSION_CONFLICT)?;
+        check_version(annotation.version, expected_version)?;
+        apply_annotation_changes(annotation, changes, updated_at);
+        Ok(())
+    }
+
+    async fn delete_annotation(&self, image_id: &str, annotation_id: &str) -> StoreResult<()> {
+        self.tables().annotations.remove(&key(image_id, annotation_id));
+        Ok(())
+    }
+
+    async fn list_annotation_history(&self, image_id: &str, annotation_id: &str) -> StoreResult<Vec<AnnotationChange>> {
+        let mut changes = children(&self.tables().annotation_changes, image_id);
+        changes.retain(|change| change.annotation_id == annotation_id);
+        Ok(changes)
+    }
+
+    async fn list_image_history(&self, image_id: &str) -> StoreResult<Vec<AnnotationChange>> {
+        Ok(children(&self.tables().annotation_changes, image_id))
+    }
+
+    async fn get_annotation_change(&self, image_id: &str, change: &ChangeRef) -> StoreResult<Option<AnnotationChange>> {
+        Ok(self.tables().annotation_changes.get(&change_ref_key(image_id, change)).cloned())
+    }
+}
+
+impl UserRepo for MemoryStore {
+    async fn put_user(&self, user: &User) -> StoreResult<()> {
+        self.tables().users.insert(user.user_id.clone(), user.clone());
+        Ok(())
+    }
+
+    async fn get_user(&self, user_id: &str) -> StoreResult<Option<User>> {
+        Ok(self.tables().users.get(user_id).cloned())
+    }
+
+    async fn update_user(&self, user_id: &str, changes: &UpdateUserPayload) -> StoreResult<()> {
+        let mut tables = self.tables();
+        let user = tables.users.get_mut(user_id).ok_or("User not found")?;
+        if let Some(name) = &changes.user_name {
+            user.user_name = name.clone();
+        }
+        if let Some(company) = &changes.user_company {
+            user.user_company = Some(company.clone());
+        }
+        if let Some(role) = &changes.user_role {
+            user.user_role = role.clone();
+        }
+        Ok(())
+    }
+
+    async fn touch_user_login(&self, user_id: &str, at: &str) -> StoreResult<()> {
+        let mut tables = self.tables();
+        let user = tables.users.get_mut(user_id).ok_or("User not found")?;
+        user.user_last_login = Some(at.to_string());
+        Ok(())
+    }
+}
+
+impl InviteRepo for MemoryStore {
+    async fn put_invite(&self, invite: &Invite) -> StoreResult<()> {
+        self.tables().invites.insert(invite.invite_code.clone(), invite.clone());
+        Ok(())
+    }
+
+    async fn get_invite(&self, invite_code: &str) -> StoreResult<Option<Invite>> {
+        Ok(self.tables().invites.get(invite_code).cloned())
+    }
+
+    async fn mark_invite_used(&self, invite_code: &str, at: &str) -> StoreResult<()> {
+        let mut tables = self.tables();
+        let invite = tables.invites.get_mut(invite_code).ok_or("Invite code not found")?;
+        invite.status = "used".to_string();
+        invite.used_at = Some(at.to_string());
+        Ok(())
+    }
+}
+
+impl ConnectionRepo for MemoryStore {
+    async fn put_connection(&self, connection: &Connection) -> StoreResult<()> {
+        self.tables()
+            .connections
+            .insert(connection.connection_id.clone(), connection.clone());
+        Ok(())
+    }
+
+    async fn delete_connection(&self, connection_id: &str) -> StoreResult<()> {
+        self.tables().connections.remove(connection_id);
+        Ok(())
+    }
+
+    async fn list_connections(&self) -> StoreResult<Vec<Connection>> {
+        Ok(self.tables().connections.values().cloned().collect())
+    }
+}
+
+impl TrashRepo for MemoryStore {
+    async fn get_trash(&self, trash_id: &str) -> StoreResult<Option<TrashEntry>> {
+        Ok(self.tables().trash.get(trash_id).cloned())
+    }
+
+    async fn list_trash(&self) -> StoreResult<Vec<TrashEntry>> {
+        Ok(self.tables().trash.values().cloned().collect())
+    }
+}
+
+impl SnapshotRepo for MemoryStore {
+    async fn get_snapshot(&self, block_id: &str, version: u32) -> StoreResult<Option<Snapshot>> {
+        Ok(self.tables().snapshots.get(&(block_id.to_string(), version)).cloned())
+    }
+
+    async fn list_snapshots(&self, block_id: &str) -> StoreResult<Vec<Snapshot>> {
+        let tables = self.tables();
+        let rows = tables.snapshots.range((block_id.to_string(), 0)..=(block_id.to_string(), u32::MAX));
+        Ok(rows.map(|(_, snapshot)| snapshot.clone()).collect())
+    }
+}
+
+impl UndoRepo for MemoryStore {
+    async fn get_op_log(&self, user_id: &str, image_id: &str) -> StoreResult<Option<OpLog>> {
+        Ok(self.tables().op_logs.get(&key(user_id, image_id)).cloned())
+    }
+}
+
+impl BatchRepo for MemoryStore {
+    async fn delete_batch(&self, keys: &[EntityKey]) -> StoreResult<()> {
+        let mut tables = self.tables();
+        for entity in keys {
+            tables.remove(entity);
+        }
+        Ok(())
+    }
+}
+
+impl TransactRepo for MemoryStore {
+    async fn transact(&self, ops: Vec<WriteOp>) -> StoreResult<()> {
+        let mut tables = self.tables();
+        for op in &ops {
+            tables.check(op)?;
+        }
+        for op in ops {
+            tables.apply(op);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drawing::model::{CreateAnnotationPayload, Geometry, Point};
+    use crate::fixtures::{test_block, test_label};
+    use crate::media::model::CreateImagePayload;
+    use crate::tasks::model::CreateTaskPayload;
+
+    async fn seed_block(store: &MemoryStore) -> String {
+        let block = test_block("b1");
+        store.put_block(&block).await.unwrap();
+        block.block_id
+    }
+
+    fn bbox() -> CreateAnnotationPayload {
+        CreateAnnotationPayload {
+            label_id: "l1".to_string(),
+            geometry: Geometry::BBox {
+                start: Point { x: 0.0, y: 0.0 },
+                end: Point { x: 1.0, y: 1.0 },
+            },
+            attributes: Default::default(),
+            cleanup: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_annotation_counters() {
+        let store = MemoryStore::new();
+        let block_id = seed_block(&store).await;
+        let payload = CreateImagePayload { url: "a.jpg".to_string(), task_id: None, order: None, width: None, height: None };
+        let image = crate::media::service::create_image(&store, &block_id, payload).await.unwrap();
+
+        let annotation = crate::drawing::service::create_annotation(&store, &block_id, &image.image_id, "u1", bbox())
+            .await
+            .unwrap();
+        crate::drawing::service::create_annotation(&store, &block_id, &image.image_id, "u1", bbox())
+            .await
+            .unwrap();
+        crate::drawing::service::delete_annotation(&store, &block_id, &image.image_id, &annotation.annotation_id, None, "u1")
+            .await
+            .unwrap();
+
+        let block = store.get_block(&block_id).await.unwrap().unwrap();
+        assert_eq!(block.image_count, 1);
+        assert_eq!(block.annotation_count, 1);
+        let image = store.get_image(&block_id, &image.image_id).await.unwrap().unwrap();
+        assert_eq!(image.annotation_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_trash_and_restore_task() {
+        let store = MemoryStore::new();
+        let block_id = seed_block(&store).await;
+        let payload = CreateTaskPayload { task_name: "t".to_string(), assignee: None, checked_by: None };
+        let task = crate::tasks::service::create_task(&store, &block_id, payload).await.unwrap();
+        let image = crate::media::service::create_image_for_task(&store, &block_id, &task.task_id, "a.jpg".to_string(), None)
+            .await
+            .unwrap();
+        crate::drawing::service::create_annotation(&store, &block_id, &image.image_id, "u1", bbox())
+            .await
+            .unwrap();
+
+        let images = crate::media::service::load_images_for_task(&store, &block_id, &task.task_id).await.unwrap();
+        assert_eq!(images.len(), 1);
+
+        let entry = crate::tasks::service::delete_task(&store, &block_id, &task.task_id, None, "u1").await.unwrap();
+
+        assert!(crate::tasks::service::get_task(&store, &block_id, &task.task_id).await.is_err());
+        assert!(store.list_tasks(&block_id).await.unwrap().is_empty());
+        assert!(store.list_images(&block_id).await.unwrap().is_empty());
+        let block = store.get_block(&block_id).await.unwrap().unwrap();
+        assert_eq!(block.image_count, 0);
+        assert_eq!(block.annotation_count, 0);
+
+        // The task was finished while in the trash; its images come back approved
+        let trashed = store.get_task(&block_id, &task.task_id).await.unwrap().unwrap();
+        let mut done = trashed.clone();
+        done.task_state = "done".to_string();
+        store.put_task(&done).await.unwrap();
+
+        crate::trash::service::restore(&store, &entry.trash_id, "u1").await.unwrap();
+        let images = crate::media::service::load_images_for_task(&store, &block_id, &task.task_id).await.unwrap();
+        assert_eq!(images.len(), 1);
+        assert_eq!(store.get_task(&block_id, &task.task_id).await.unwrap().unwrap().image_count, 1);
+        let block = store.get_block(&block_id).await.unwrap().unwrap();
+        assert_eq!(block.image_count, 1);
+        assert_eq!(block.approved_image_count, 1);
+        assert_eq!(block.annotation_count, 1);
+        assert!(store.get_trash(&entry.trash_id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_restore_needs_live_parent() {
+        let store = MemoryStore::new();
+        let block_id = seed_block(&store).await;
+        let payload = CreateImagePayload { url: "a.jpg".to_string(), task_id: None, order: None, width: None, height: None };
+        let image = crate::media::service::create_image(&store, &block_id, payload).await.unwrap();
+        let annotation = crate::drawing::service::create_annotation(&store, &block_id, &image.image_id, "u1", bbox())
+            .await
+            .unwrap();
+
+        let annotation_entry =
+            crate::drawing::service::delete_annotation(&store, &block_id, &image.image_id, &annotation.annotation_id, None, "u1")
+                .await
+                .unwrap();
+        let image_entry = crate::media::service::delete_image(&store, &block_id, &image.image_id, "u1").await.unwrap();
+        let block = store.get_block(&block_id).await.unwrap().unwrap();
+        assert_eq!((block.image_count, block.annotation_count), (0, 0));
+
+        let blocked = crate::trash::service::restore(&store, &annotation_entry.trash_id, "u1").await;
+        assert_eq!(blocked.unwrap_err(), crate::trash::service::PARENT_IN_TRASH);
+        let orphan = crate::drawing::service::create_annotation(&store, &block_id, &image.image_id, "u1", bbox()).await;
+        assert_eq!(orphan.unwrap_err(), "Image not found");
+
+        crate::trash::service::restore(&store, &image_entry.trash_id, "u1").await.unwrap();
+        crate::trash::service::restore(&store, &annotation_entry.trash_id, "u1").await.unwrap();
+        let block = store.get_block(&block_id).await.unwrap().unwrap();
+        assert_eq!((block.image_count, block.annotation_count), (1, 1));
+        let image = store.get_image(&block_id, &image.image_id).await.unwrap().unwrap();
+        assert_eq!(image.annotation_count, 1);
+
+        // A second restore of the same entry finds nothing to restore
+        assert!(crate::trash::service::restore(&store, &image_entry.trash_id, "u1").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_task_pages_cover_every_row() {
+        let store = MemoryStore::new();
+        let block_id = seed_block(&store).await;
+        for i in 0..5 {
+            let payload = CreateTaskPayload { task_name: format!("t{}", i), assignee: None, checked_by: None };
+            crate::tasks::service::create_task(&store, &block_id, payload).await.unwrap();
+        }
+
+        let mut page = PageRequest { limit: Some(2), cursor: None };
+        let mut seen = Vec::new();
+        loop {
+            let result = store.list_tasks_page(&block_id, &page).await.unwrap();
+            assert!(result.items.len() <= 2);
+            seen.extend(result.items.into_iter().map(|t| t.task_id));
+            match result.next_cursor {
+                Some(raw) => page.cursor = Some(Cursor::decode(&raw).unwrap()),
+                None => break,
+            }
+        }
+
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_cursor_from_another_block_is_rejected() {
+        use crate::store::INVALID_CURSOR;
+
+        let store = MemoryStore::new();
+        let block_id = seed_block(&store).await;
+        for i in 0..3 {
+            let payload = CreateTaskPayload { task_name: format!("t{}", i), assignee: None, checked_by: None };
+            crate::tasks::service::create_task(&store, &block_id, payload).await.unwrap();
+        }
+        let first = store.list_tasks_page(&block_id, &PageRequest { limit: Some(1), cursor: None }).await.unwrap();
+        let cursor = Cursor::decode(&first.next_cursor.unwrap()).unwrap();
+
+        let page = PageRequest { limit: Some(1), cursor: Some(cursor) };
+        let err = store.list_tasks_page("other-block", &page).await.unwrap_err();
+        assert_eq!(err, INVALID_CURSOR);
+        let err = store.list_blocks_page(&page).await.unwrap_err();
+        assert_eq!(err, INVALID_CURSOR);
+    }
+
+    #[tokio::test]
+    async fn test_stale_version_conflicts() {
+        let store = MemoryStore::new();
+        let block_id = seed_block(&store).await;
+        let payload = CreateImagePayload { url: "a.jpg".to_string(), task_id: None, order: None, width: None, height: None };
+        let image = crate::media::service::create_image(&store, &block_id, payload).await.unwrap();
+        let annotation = crate::drawing::service::create_annotation(&store, &block_id, &image.image_id, "u1", bbox())
+            .await
+            .unwrap();
+        assert_eq!(annotation.version, 1);
+
+        let edit = || UpdateAnnotationPayload { label_id: Some("l2".to_string()), geometry: None, attributes: None, cleanup: None };
+        let updated =
+            crate::drawing::service::update_annotation(&store, &block_id, &image.image_id, &annotation.annotation_id, edit(), Some(1), "u1")
+                .await
+                .unwrap();
+        assert_eq!(updated.version, 2);
+
+        // A second writer still holding version 1 must not clobber the edit
+        let stale =
+            crate::drawing::service::update_annotation(&store, &block_id, &image.image_id, &annotation.annotation_id, edit(), Some(1), "u2")
+                .await;
+        assert_eq!(stale.unwrap_err(), VERSION_CONFLICT);
+        let stale =
+            crate::drawing::service::delete_annotation(&store, &block_id, &image.image_id, &annotation.annotation_id, Some(1), "u1")
+                .await;
+        assert_eq!(stale.unwrap_err(), VERSION_CONFLICT);
+
+        crate::drawing::service::delete_annotation(&store, &block_id, &image.image_id, &annotation.annotation_id, Some(2), "u1")
+            .await
+            .unwrap();
+        let block = store.get_block(&block_id).await.unwrap().unwrap();
+        assert_eq!(block.annotation_count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_history_records_every_change() {
+        use crate::drawing::model::ChangeAction;
+
+        let store = MemoryStore::new();
+        let block_id = seed_block(&store).await;
+        let payload = CreateImagePayload { url: "a.jpg".to_string(), task_id: None, order: None, width: None, height: None };
+        let image = crate::media::service::create_image(&store, &block_id, payload).await.unwrap();
+        let annotation = crate::drawing::service::create_annotation(&store, &block_id, &image.image_id, "u1", bbox())
+            .await
+            .unwrap();
+        let edit = UpdateAnnotationPayload { label_id: Some("l2".to_string()), geometry: None, attributes: None, cleanup: None };
+        crate::drawing::service::update_annotation(&store, &block_id, &image.image_id, &annotation.annotation_id, edit, None, "u2")
+            .await
+            .unwrap();
+        crate::drawing::service::delete_annotation(&store, &block_id, &image.image_id, &annotation.annotation_id, None, "u3")
+            .await
+            .unwrap();
+
+        let history = crate::drawing::service::annotation_history(&store, &image.image_id, &annotation.annotation_id)
+            .await
+            .unwrap();
+        let actions: Vec<_> = history.iter().map(|c| (c.action, c.actor.as_str())).collect();
+        assert_eq!(
+            actions,
+            [(ChangeAction::Create, "u1"), (ChangeAction::Update, "u2"), (ChangeAction::Delete, "u3")]
+        );
+        assert_eq!(history[1].before.as_ref().unwrap().label_id, "l1");
+        assert_eq!(history[1].after.as_ref().unwrap().label_id, "l2");
+        assert!(history[2].after.is_none());
+        // The delete's undo entry is the change in the history
+        let log = store.get_op_log("u3", &image.image_id).await.unwrap().unwrap();
+        assert_eq!(log.undo[0].change_id, history[2].change_id);
+
+        let image_history = crate::drawing::service::image_history(&store, &image.image_id).await.unwrap();
+        assert_eq!(image_history.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_undo_and_redo() {
+        use crate::undo::service::{redo, undo, NOTHING_TO_UNDO, STALE_CHANGE};
+
+        let store = MemoryStore::new();
+        let block_id = seed_block(&store).await;
+        // No "l3": annotations can outlive their label
+        for label_id in ["l1", "l2"] {
+            store.put_label(&test_label(&block_id, label_id, label_id)).await.unwrap();
+        }
+        let payload = CreateImagePayload { url: "a.jpg".to_string(), task_id: None, order: None, width: None, height: None };
+        let image = crate::media::service::create_image(&store, &block_id, payload).await.unwrap();
+        let image_id = image.image_id.as_str();
+        let annotation = crate::drawing::service::create_annotation(&store, &block_id, image_id, "u1", bbox())
+            .await
+            .unwrap();
+        let annotation_id = annotation.annotation_id.as_str();
+        let edit = UpdateAnnotationPayload { label_id: Some("l2".to_string()), geometry: None, attributes: None, cleanup: None };
+        crate::drawing::service::update_annotation(&store, &block_id, image_id, annotation_id, edit, None, "u1")
+            .await
+            .unwrap();
+        crate::drawing::service::delete_annotation(&store, &block_id, image_id, annotation_id, None, "u1")
+            .await
+            .unwrap();
+        let counts = |store: &MemoryStore| {
+            let tables = store.tables();
+            (tables.blocks[&block_id].annotation_count, tables.images[&key(&block_id, image_id)].annotation_count)
+        };
+        let label_counts = |store: &MemoryStore| {
+            let tables = store.tables();
+            (tables.labels[&key(&block_id, "l1")].label_count, tables.labels[&key(&block_id, "l2")].label_count)
+        };
+        assert_eq!(label_counts(&store), (0, 0));
+
+        // Undo the delete, then the label change, then the create; the log
+        // refers to the history, so the change made is the one recorded
+        let restored = undo(&store, &block_id, image_id, "u1").await.unwrap();
+        assert_eq!(counts(&store), (1, 1));
+        let history = crate::drawing::service::image_history(&store, image_id).await.unwrap();
+        assert_eq!(history.last().unwrap().change_id, restored.change_id);
+        assert_eq!(label_counts(&store), (0, 1));
+        undo(&store, &block_id, image_id, "u1").await.unwrap();
+        let current = crate::drawing::service::get_annotation(&store, image_id, annotation_id).await.unwrap();
+        assert_eq!(current.label_id, "l1");
+        assert_eq!(label_counts(&store), (1, 0));
+        undo(&store, &block_id, image_id, "u1").await.unwrap();
+        assert_eq!(counts(&store), (0, 0));
+
+        redo(&store, &block_id, image_id, "u1").await.unwrap();
+        assert_eq!(counts(&store), (1, 1));
+        redo(&store, &block_id, image_id, "u1").await.unwrap();
+        let current = crate::drawing::service::get_annotation(&store, image_id, annotation_id).await.unwrap();
+        assert_eq!(current.label_id, "l2");
+
+        // Someone else's edit leaves both changes stale; each is dropped
+        let edit = UpdateAnnotationPayload { label_id: Some("l3".to_string()), geometry: None, attributes: None, cleanup: None };
+        crate::drawing::service::update_annotation(&store, &block_id, image_id, annotation_id, edit, None, "u2")
+            .await
+            .unwrap();
+        assert_eq!(undo(&store, &block_id, image_id, "u1").await.unwrap_err(), STALE_CHANGE);
+        assert_eq!(undo(&store, &block_id, image_id, "u1").await.unwrap_err(), STALE_CHANGE);
+        assert_eq!(undo(&store, &block_id, image_id, "u1").await.unwrap_err(), NOTHING_TO_UNDO);
+        assert_eq!(counts(&store), (1, 1));
+        assert_eq!(label_counts(&store), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_attributes_follow_label_schema() {
+        use crate::labels::schema::INVALID_ATTRIBUTES;
+        use serde_json::json;
+
+        let store = MemoryStore::new();
+        let block_id = seed_block(&store).await;
+        let door = Label {
+            label_properties: Some(json!({"attributes": {
+                "swing": {"type": "enum", "values": ["left", "right"], "required": true},
+                "height": {"type": "number", "unit": "mm", "min": 0, "max": 3000},
+            }})),
+            ..test_label(&block_id, "l1", "Door")
+        };
+        store.put_label(&door).await.unwrap();
+        let payload = CreateImagePayload { url: "a.jpg".to_string(), task_id: None, order: None, width: None, height: None };
+        let image = crate::media::service::create_image(&store, &block_id, payload).await.unwrap();
+        let with = |attributes: serde_json::Value| CreateAnnotationPayload {
+            attributes: serde_json::from_value(attributes).unwrap(),
+            ..bbox()
+        };
+
+        let missing = crate::drawing::service::create_annotation(&store, &block_id, &image.image_id, "u1", with(json!({})))
+            .await
+            .unwrap_err();
+        assert_eq!(missing, format!("{}: swing is required", INVALID_ATTRIBUTES));
+        let wrong = with(json!({"swing": "up", "height": 4000, "colour": "red"}));
+        let wrong = crate::drawing::service::create_annotation(&store, &block_id, &image.image_id, "u1", wrong)
+            .await
+            .unwrap_err();
+        assert!(wrong.contains("colour is not an attribute"));
+        assert!(wrong.contains("swing must be one of left, right"));
+        assert!(wrong.contains("height must be at most 3000 mm"));
+
+        let good = with(json!({"swing": "left", "height": 2040}));
+        let annotation = crate::drawing::service::create_annotation(&store, &block_id, &image.image_id, "u1", good)
+            .await
+            .unwrap();
+        assert_eq!(annotation.attributes["swing"], "left");
+
+        // Moving to a label without a schema leaves attributes it doesn't take
+        let relabel = UpdateAnnotationPayload { label_id: Some("l2".to_string()), geometry: None, attributes: None, cleanup: None };
+        let err = crate::drawing::service::update_annotation(&store, &block_id, &image.image_id, &annotation.annotation_id, relabel, None, "u1")
+            .await
+            .unwrap_err();
+        assert!(err.starts_with(INVALID_ATTRIBUTES));
+        let relabel = UpdateAnnotationPayload {
+            label_id: Some("l2".to_string()),
+            geometry: None,
+            attributes: Some(Default::default()),
+            cleanup: None,
+        };
+        let updated = crate::drawing::service::update_annotation(&store, &block_id, &image.image_id, &annotation.annotation_id, relabel, None, "u1")
+            .await
+            .unwrap();
+        assert!(updated.attributes.is_empty());
+        let stored = store.get_annotation(&image.image_id, &annotation.annotation_id).await.unwrap().unwrap();
+        assert!(stored.attributes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_failed_transaction_writes_nothing() {
+        let store = MemoryStore::new();
+        let block_id = seed_block(&store).await;
+
+        // The image was never created, so the image counter bump fails
+        let result = crate::drawing::service::create_annotation(&store, &block_id, "missing", "u1", bbox()).await;
+        assert!(result.is_err());
+
+        let block = store.get_block(&block_id).await.unwrap().unwrap();
+        assert_eq!(block.annotation_count, 0);
+        assert!(store.list_annotations("missing").await.unwrap().is_empty());
+    }
+}
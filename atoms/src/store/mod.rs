@@ -0,0 +1,445 @@
+//! Storage layer for the atoms services.
+//!
+//! Services are written against the repository traits below instead of a
+//! concrete DynamoDB client, so the same domain logic runs on `DynamoStore`
+//! in the lambdas and on `MemoryStore` in tests.
+//!
+//! `list_*` methods always return every row; `list_*_page` methods return one
+//! page and a cursor for the next. Rows in the trash are left out of both,
+//! so pages can come back short; `get_*` still returns them.
+
+use std::future::Future;
+
+use crate::blocks::model::{Block, UpdateBlockPayload};
+use crate::drawing::model::{Annotation, AnnotationChange, UpdateAnnotationPayload};
+use crate::labels::model::{Label, LabelTemplate, UpdateLabelPayload};
+use crate::media::model::{Image, UpdateImagePayload};
+use crate::snapshots::model::Snapshot;
+use crate::tasks::model::{Task, UpdateTaskPayload};
+use crate::trash::model::TrashEntry;
+use crate::undo::model::{ChangeRef, OpLog};
+use crate::users::model::{Connection, Invite, UpdateUserPayload, User};
+
+pub mod dynamo;
+pub mod memory;
+pub mod page;
+pub mod version;
+
+pub use dynamo::DynamoStore;
+pub use memory::MemoryStore;
+pub use page::{Cursor, Page, PageRequest, INVALID_CURSOR};
+pub use version::VERSION_CONFLICT;
+
+pub type StoreResult<T> = Result<T, String>;
+
+/// Counters kept on the block record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockCounter {
+    ImageCount,
+    ApprovedImageCount,
+    AnnotationCount,
+}
+
+impl BlockCounter {
+    /// Attribute name of the counter on the stored block
+    pub fn attribute(&self) -> &'static str {
+        match self {
+            BlockCounter::ImageCount => "image_count",
+            BlockCounter::ApprovedImageCount => "approved_image_count",
+            BlockCounter::AnnotationCount => "annotation_count",
+        }
+    }
+}
+
+/// Counters kept on child rows and the block record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Counter {
+    Block(BlockCounter),
+    TaskImageCount,
+    ImageAnnotationCount,
+    LabelCount,
+}
+
+impl Counter {
+    /// Attribute name of the counter on the stored row
+    pub fn attribute(&self) -> &'static str {
+        match self {
+            Counter::Block(counter) => counter.attribute(),
+            Counter::TaskImageCount => "image_count",
+            Counter::ImageAnnotationCount => "annotation_count",
+            Counter::LabelCount => "label_count",
+        }
+    }
+}
+
+/// Identifies one stored row, used for batch deletes and transactions
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EntityKey {
+    Block { block_id: String },
+    Label { block_id: String, label_id: String },
+    Task { block_id: String, task_id: String },
+    Image { block_id: String, image_id: String },
+    Annotation { image_id: String, annotation_id: String },
+    /// Index row linking a task to one of its images
+    TaskImage { task_id: String, image_id: String },
+    Trash { trash_id: String },
+    Snapshot { block_id: String, version: u32 },
+}
+
+impl EntityKey {
+    pub fn block(block_id: &str) -> Self {
+        EntityKey::Block { block_id: block_id.to_string() }
+    }
+
+    pub fn label(block_id: &str, label_id: &str) -> Self {
+        EntityKey::Label { block_id: block_id.to_string(), label_id: label_id.to_string() }
+    }
+
+    pub fn task(block_id: &str, task_id: &str) -> Self {
+        EntityKey::Task { block_id: block_id.to_string(), task_id: task_id.to_string() }
+    }
+
+    pub fn image(block_id: &str, image_id: &str) -> Self {
+        EntityKey::Image { block_id: block_id.to_string(), image_id: image_id.to_string() }
+    }
+
+    pub fn annotation(image_id: &str, annotation_id: &str) -> Self {
+        EntityKey::Annotation { image_id: image_id.to_string(), annotation_id: annotation_id.to_string() }
+    }
+
+    pub fn task_image(task_id: &str, image_id: &str) -> Self {
+        EntityKey::TaskImage { task_id: task_id.to_string(), image_id: image_id.to_string() }
+    }
+
+    pub fn trash(trash_id: &str) -> Self {
+        EntityKey::Trash { trash_id: trash_id.to_string() }
+    }
+
+    pub fn snapshot(block_id: &str, version: u32) -> Self {
+        EntityKey::Snapshot { block_id: block_id.to_string(), version }
+    }
+}
+
+/// One write inside an all-or-nothing transaction
+///
+/// Every op carries a condition: puts require the row to be new, the rest
+/// require it to exist. Counter bumps and updates also require the row to be
+/// out of the trash, so a bump on a parent doubles as the check that the
+/// parent is still live. Deleting a `TaskImage` index row is the exception,
+/// so images written before the index can still be deleted. DynamoDB
+/// rejects two ops on the same row in one transaction, so combine deltas for
+/// a row into one op.
+#[derive(Debug)]
+pub enum WriteOp {
+    PutAnnotation(Annotation),
+    /// Bumps the annotation's version; with `expected_version` it must
+    /// still be at that version
+    UpdateAnnotation {
+        image_id: String,
+        annotation_id: String,
+        changes: UpdateAnnotationPayload,
+        updated_at: String,
+        expected_version: Option<u64>,
+    },
+    /// Append-only history entry; write it with the change it records
+    PutAnnotationChange(AnnotationChange),
+    PutBlock(Block),
+    PutImage(Image),
+    PutLabel(Label),
+    PutTask(Task),
+    /// Index row so a task's images can be read without scanning the block;
+    /// write it in the same transaction as the image
+    PutTaskImage {
+        block_id: String,
+        task_id: String,
+        image_id: String,
+    },
+    /// Bumps the task's version; with `expected_version` the task must
+    /// still be at that version
+    UpdateTask {
+        block_id: String,
+        task_id: String,
+        changes: UpdateTaskPayload,
+        expected_version: Option<u64>,
+    },
+    Delete(EntityKey),
+    /// Delete a versioned row only if it is still at `version`
+    DeleteAtVersion {
+        key: EntityKey,
+        version: u64,
+    },
+    PutTrash(TrashEntry),
+    /// Mark a live row as trashed under `trash_id`
+    Trash {
+        key: EntityKey,
+        trash_id: String,
+        deleted_at: String,
+        expected_version: Option<u64>,
+    },
+    /// Bring back a row trashed under `trash_id`
+    Restore {
+        key: EntityKey,
+        trash_id: String,
+    },
+    Increment {
+        key: EntityKey,
+        deltas: Vec<(Counter, i64)>,
+    },
+    /// Index row of a new snapshot; fails if the version is taken
+    PutSnapshot(Snapshot),
+    /// Save an undo log that is still at `expected_version` (0: not saved yet)
+    PutOpLog {
+        log: OpLog,
+        expected_version: u64,
+    },
+}
+
+/// DynamoDB's cap on items per `TransactWriteItems` call
+pub const MAX_TRANSACT_OPS: usize = 100;
+
+pub trait BlockRepo: Send + Sync {
+    fn put_block(&self, block: &Block) -> impl Future<Output = StoreResult<()>> + Send;
+    fn get_block(&self, block_id: &str) -> impl Future<Output = StoreResult<Option<Block>>> + Send;
+    fn list_blocks(&self) -> impl Future<Output = StoreResult<Vec<Block>>> + Send;
+    fn list_blocks_page(&self, page: &PageRequest) -> impl Future<Output = StoreResult<Page<Block>>> + Send;
+    fn update_block(
+        &self,
+        block_id: &str,
+        changes: &UpdateBlockPayload,
+    ) -> impl Future<Output = StoreResult<()>> + Send;
+    fn increment_block_counter(
+        &self,
+        block_id: &str,
+        counter: BlockCounter,
+        delta: i64,
+    ) -> impl Future<Output = StoreResult<()>> + Send;
+}
+
+pub trait LabelRepo: Send + Sync {
+    fn put_label(&self, label: &Label) -> impl Future<Output = StoreResult<()>> + Send;
+    fn get_label(
+        &self,
+        block_id: &str,
+        label_id: &str,
+    ) -> impl Future<Output = StoreResult<Option<Label>>> + Send;
+    fn list_labels(&self, block_id: &str) -> impl Future<Output = StoreResult<Vec<Label>>> + Send;
+    /// Fails with `VERSION_CONFLICT` if the label is missing or, when
+    /// `expected_version` is given, at another version
+    fn update_label(
+        &self,
+        block_id: &str,
+        label_id: &str,
+        changes: &UpdateLabelPayload,
+        expected_version: Option<u64>,
+    ) -> impl Future<Output = StoreResult<()>> + Send;
+    fn delete_label(&self, block_id: &str, label_id: &str) -> impl Future<Output = StoreResult<()>> + Send;
+    fn increment_label_count(
+        &self,
+        block_id: &str,
+        label_id: &str,
+        delta: i64,
+    ) -> impl Future<Output = StoreResult<()>> + Send;
+}
+
+pub trait LabelTemplateRepo: Send + Sync {
+    /// Create or replace the template for its block type
+    fn put_label_template(&self, template: &LabelTemplate) -> impl Future<Output = StoreResult<()>> + Send;
+    fn get_label_template(&self, block_type: &str) -> impl Future<Output = StoreResult<Option<LabelTemplate>>> + Send;
+    fn list_label_templates(&self) -> impl Future<Output = StoreResult<Vec<LabelTemplate>>> + Send;
+    fn delete_label_template(&self, block_type: &str) -> impl Future<Output = StoreResult<()>> + Send;
+}
+
+pub trait TaskRepo: Send + Sync {
+    fn put_task(&self, task: &Task) -> impl Future<Output = StoreResult<()>> + Send;
+    fn get_task(
+        &self,
+        block_id: &str,
+        task_id: &str,
+    ) -> impl Future<Output = StoreResult<Option<Task>>> + Send;
+    fn list_tasks(&self, block_id: &str) -> impl Future<Output = StoreResult<Vec<Task>>> + Send;
+    fn list_tasks_page(
+        &self,
+        block_id: &str,
+        page: &PageRequest,
+    ) -> impl Future<Output = StoreResult<Page<Task>>> + Send;
+    fn update_task(
+        &self,
+        block_id: &str,
+        task_id: &str,
+        changes: &UpdateTaskPayload,
+    ) -> impl Future<Output = StoreResult<()>> + Send;
+    fn delete_task(&self, block_id: &str, task_id: &str) -> impl Future<Output = StoreResult<()>> + Send;
+    fn increment_task_image_count(
+        &self,
+        block_id: &str,
+        task_id: &str,
+        delta: i64,
+    ) -> impl Future<Output = StoreResult<()>> + Send;
+}
+
+pub trait ImageRepo: Send + Sync {
+    fn put_image(&self, image: &Image) -> impl Future<Output = StoreResult<()>> + Send;
+    fn get_image(
+        &self,
+        block_id: &str,
+        image_id: &str,
+    ) -> impl Future<Output = StoreResult<Option<Image>>> + Send;
+    fn list_images(&self, block_id: &str) -> impl Future<Output = StoreResult<Vec<Image>>> + Send;
+    /// Ids of the images linked to `task_id`, read from the task index
+    fn list_task_image_ids(&self, task_id: &str) -> impl Future<Output = StoreResult<Vec<String>>> + Send;
+    /// Every image linked to `task_id`
+    fn list_task_images(
+        &self,
+        block_id: &str,
+        task_id: &str,
+    ) -> impl Future<Output = StoreResult<Vec<Image>>> + Send;
+    /// One page of the images linked to `task_id`
+    fn list_task_images_page(
+        &self,
+        block_id: &str,
+        task_id: &str,
+        page: &PageRequest,
+    ) -> impl Future<Output = StoreResult<Page<Image>>> + Send;
+    fn update_image(
+        &self,
+        block_id: &str,
+        image_id: &str,
+        changes: &UpdateImagePayload,
+    ) -> impl Future<Output = StoreResult<()>> + Send;
+    fn delete_image(&self, block_id: &str, image_id: &str) -> impl Future<Output = StoreResult<()>> + Send;
+    fn increment_image_annotation_count(
+        &self,
+        block_id: &str,
+        image_id: &str,
+        delta: i64,
+    ) -> impl Future<Output = StoreResult<()>> + Send;
+}
+
+pub trait AnnotationRepo: Send + Sync {
+    fn put_annotation(&self, annotation: &Annotation) -> impl Future<Output = StoreResult<()>> + Send;
+    fn get_annotation(
+        &self,
+        image_id: &str,
+        annotation_id: &str,
+    ) -> impl Future<Output = StoreResult<Option<Annotation>>> + Send;
+    fn list_annotations(&self, image_id: &str) -> impl Future<Output = StoreResult<Vec<Annotation>>> + Send;
+    fn list_annotations_page(
+        &self,
+        image_id: &str,
+        page: &PageRequest,
+    ) -> impl Future<Output = StoreResult<Page<Annotation>>> + Send;
+    /// Fails with `VERSION_CONFLICT` if the annotation is missing or, when
+    /// `expected_version` is given, at another version
+    fn update_annotation(
+        &self,
+        image_id: &str,
+        annotation_id: &str,
+        changes: &UpdateAnnotationPayload,
+        updated_at: &str,
+        expected_version: Option<u64>,
+    ) -> impl Future<Output = StoreResult<()>> + Send;
+    fn delete_annotation(
+        &self,
+        image_id: &str,
+        annotation_id: &str,
+    ) -> impl Future<Output = StoreResult<()>> + Send;
+    /// History of one annotation, oldest first
+    fn list_annotation_history(
+        &self,
+        image_id: &str,
+        annotation_id: &str,
+    ) -> impl Future<Output = StoreResult<Vec<AnnotationChange>>> + Send;
+    /// History of every annotation on an image, grouped by annotation
+    fn list_image_history(&self, image_id: &str) -> impl Future<Output = StoreResult<Vec<AnnotationChange>>> + Send;
+    /// One history entry of an image
+    fn get_annotation_change(
+        &self,
+        image_id: &str,
+        change: &ChangeRef,
+    ) -> impl Future<Output = StoreResult<Option<AnnotationChange>>> + Send;
+}
+
+pub trait UserRepo: Send + Sync {
+    fn put_user(&self, user: &User) -> impl Future<Output = StoreResult<()>> + Send;
+    fn get_user(&self, user_id: &str) -> impl Future<Output = StoreResult<Option<User>>> + Send;
+    fn update_user(
+        &self,
+        user_id: &str,
+        changes: &UpdateUserPayload,
+    ) -> impl Future<Output = StoreResult<()>> + Send;
+    fn touch_user_login(&self, user_id: &str, at: &str) -> impl Future<Output = StoreResult<()>> + Send;
+}
+
+pub trait InviteRepo: Send + Sync {
+    fn put_invite(&self, invite: &Invite) -> impl Future<Output = StoreResult<()>> + Send;
+    fn get_invite(&self, invite_code: &str) -> impl Future<Output = StoreResult<Option<Invite>>> + Send;
+    fn mark_invite_used(&self, invite_code: &str, at: &str) -> impl Future<Output = StoreResult<()>> + Send;
+}
+
+pub trait ConnectionRepo: Send + Sync {
+    fn put_connection(&self, connection: &Connection) -> impl Future<Output = StoreResult<()>> + Send;
+    fn delete_connection(&self, connection_id: &str) -> impl Future<Output = StoreResult<()>> + Send;
+    fn list_connections(&self) -> impl Future<Output = StoreResult<Vec<Connection>>> + Send;
+}
+
+pub trait TrashRepo: Send + Sync {
+    fn get_trash(&self, trash_id: &str) -> impl Future<Output = StoreResult<Option<TrashEntry>>> + Send;
+    fn list_trash(&self) -> impl Future<Output = StoreResult<Vec<TrashEntry>>> + Send;
+}
+
+pub trait UndoRepo: Send + Sync {
+    fn get_op_log(&self, user_id: &str, image_id: &str) -> impl Future<Output = StoreResult<Option<OpLog>>> + Send;
+}
+
+pub trait SnapshotRepo: Send + Sync {
+    fn get_snapshot(&self, block_id: &str, version: u32) -> impl Future<Output = StoreResult<Option<Snapshot>>> + Send;
+    /// Oldest first
+    fn list_snapshots(&self, block_id: &str) -> impl Future<Output = StoreResult<Vec<Snapshot>>> + Send;
+}
+
+pub trait TransactRepo: Send + Sync {
+    /// Apply every op or none of them
+    fn transact(&self, ops: Vec<WriteOp>) -> impl Future<Output = StoreResult<()>> + Send;
+}
+
+pub trait BatchRepo: Send + Sync {
+    /// Delete many rows at once; missing rows are ignored
+    fn delete_batch(&self, keys: &[EntityKey]) -> impl Future<Output = StoreResult<()>> + Send;
+}
+
+/// Everything the services need from a backend
+pub trait Store:
+    BlockRepo
+    + LabelRepo
+    + LabelTemplateRepo
+    + TaskRepo
+    + ImageRepo
+    + AnnotationRepo
+    + UserRepo
+    + InviteRepo
+    + ConnectionRepo
+    + TrashRepo
+    + UndoRepo
+    + SnapshotRepo
+    + BatchRepo
+    + TransactRepo
+{
+}
+
+impl<T> Store for T where
+    T: BlockRepo
+        + LabelRepo
+        + LabelTemplateRepo
+        + TaskRepo
+        + ImageRepo
+        + AnnotationRepo
+        + UserRepo
+        + InviteRepo
+        + ConnectionRepo
+        + TrashRepo
+        + UndoRepo
+        + SnapshotRepo
+        + BatchRepo
+        + TransactRepo
+{
+}
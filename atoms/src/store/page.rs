@@ -0,0 +1,110 @@
+//! Cursor pagination for list queries.
+//!
+//! Cursors are opaque to clients: the position of the last row returned,
+//! serialized and base64 encoded so it can travel in a query string.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+/// Page size used when the client sends a cursor but no limit
+pub const DEFAULT_PAGE_LIMIT: i32 = 100;
+/// Upper bound on `?limit=`
+pub const MAX_PAGE_LIMIT: i32 = 500;
+/// Cursor that does not decode, or that belongs to another partition
+pub const INVALID_CURSOR: &str = "Invalid cursor";
+
+/// Key of the last row returned; the next page starts after it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cursor {
+    pub pk: String,
+    pub sk: String,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).unwrap_or_default();
+        URL_SAFE_NO_PAD.encode(json)
+    }
+
+    pub fn decode(raw: &str) -> Result<Self, String> {
+        let bytes = URL_SAFE_NO_PAD
+            .decode(raw)
+            .map_err(|_| INVALID_CURSOR.to_string())?;
+        serde_json::from_slice(&bytes).map_err(|_| INVALID_CURSOR.to_string())
+    }
+}
+
+/// `?limit=&cursor=` as sent by the client
+#[derive(Debug, Clone, Default)]
+pub struct PageRequest {
+    pub limit: Option<i32>,
+    pub cursor: Option<Cursor>,
+}
+
+impl PageRequest {
+    /// Parse the raw query parameters; bad values are client errors
+    pub fn from_query(limit: Option<&str>, cursor: Option<&str>) -> Result<Self, String> {
+        let limit = match limit {
+            Some(raw) => Some(
+                raw.parse::<i32>()
+                    .ok()
+                    .filter(|l| *l > 0)
+                    .ok_or_else(|| "limit must be a positive integer".to_string())?,
+            ),
+            None => None,
+        };
+        let cursor = cursor.map(Cursor::decode).transpose()?;
+
+        Ok(Self { limit, cursor })
+    }
+
+    /// True when the client asked for a page instead of the full list
+    pub fn is_paged(&self) -> bool {
+        self.limit.is_some() || self.cursor.is_some()
+    }
+
+    /// The cursor, if it was issued for the `pk` partition; a cursor from
+    /// another list would otherwise start the query at a foreign key
+    pub fn cursor_in(&self, pk: &str) -> Result<Option<&Cursor>, String> {
+        match &self.cursor {
+            Some(cursor) if cursor.pk != pk => Err(INVALID_CURSOR.to_string()),
+            cursor => Ok(cursor.as_ref()),
+        }
+    }
+
+    pub fn page_size(&self) -> i32 {
+        self.limit.unwrap_or(DEFAULT_PAGE_LIMIT).min(MAX_PAGE_LIMIT)
+    }
+}
+
+/// One page of rows; `next_cursor` is `None` on the last page
+#[derive(Debug, Clone, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let cursor = Cursor { pk: "BLOCK#b1".to_string(), sk: "TASK#t1".to_string() };
+        assert_eq!(Cursor::decode(&cursor.encode()).unwrap(), cursor);
+        assert!(Cursor::decode("not a cursor").is_err());
+    }
+
+    #[test]
+    fn test_page_request_from_query() {
+        let page = PageRequest::from_query(None, None).unwrap();
+        assert!(!page.is_paged());
+
+        let page = PageRequest::from_query(Some("10000"), None).unwrap();
+        assert_eq!(page.page_size(), MAX_PAGE_LIMIT);
+
+        assert!(PageRequest::from_query(Some("0"), None).is_err());
+        assert!(PageRequest::from_query(Some("abc"), None).is_err());
+    }
+}
@@ -0,0 +1,52 @@
+//! Optimistic concurrency for annotations, tasks and labels.
+//!
+//! Each of those rows carries a `version` that every update bumps. Writers
+//! may pass the version they last read; if the row has moved on since, the
+//! write fails with `VERSION_CONFLICT` instead of overwriting. Over HTTP the
+//! version travels as a strong `ETag` and comes back in `If-Match`.
+
+/// Error returned when a versioned write finds the row at another version
+pub const VERSION_CONFLICT: &str = "Version conflict";
+
+/// `ETag` header value for a version
+pub fn etag(version: u64) -> String {
+    format!("\"{}\"", version)
+}
+
+/// Parse an `If-Match` header; `*` or no header means "any version"
+pub fn parse_if_match(header: Option<&str>) -> Result<Option<u64>, String> {
+    let raw = match header.map(str::trim) {
+        None | Some("*") => return Ok(None),
+        Some(raw) => raw,
+    };
+
+    raw.strip_prefix("W/")
+        .unwrap_or(raw)
+        .trim_matches('"')
+        .parse()
+        .map(Some)
+        .map_err(|_| "If-Match must be an ETag returned by the API".to_string())
+}
+
+/// After a failed versioned write: `VERSION_CONFLICT` if the row has moved
+/// past the version the caller expected, otherwise the original error
+pub fn conflict_or(err: String, expected: Option<u64>, current: Option<u64>) -> String {
+    match (expected, current) {
+        (Some(expected), Some(current)) if expected != current => VERSION_CONFLICT.to_string(),
+        _ => err,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_if_match_round_trip() {
+        assert_eq!(parse_if_match(Some(&etag(7))).unwrap(), Some(7));
+        assert_eq!(parse_if_match(Some("W/\"3\"")).unwrap(), Some(3));
+        assert_eq!(parse_if_match(Some("*")).unwrap(), None);
+        assert_eq!(parse_if_match(None).unwrap(), None);
+        assert!(parse_if_match(Some("\"abc\"")).is_err());
+    }
+}
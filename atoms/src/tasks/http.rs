@@ -5,6 +5,7 @@ use super::service;
 
 // Import media service for the join
 use crate::media::service::load_images_for_block;
+use crate::store::DynamoStore;
 
 /// List all tasks for a block with their images (Backend Join)
 pub async fn list_block_tasks(
@@ -13,14 +14,15 @@ pub async fn list_block_tasks(
     block_id: &str,
 ) -> Result<Response<Body>, Error> {
     // 1. Fetch Tasks and Images in parallel
+    let store = DynamoStore::new(client, table_name);
     let (tasks_result, images_result) = tokio::join!(
-        service::load_tasks_for_block(client, table_name, block_id),
-        load_images_for_block(client, table_name, block_id)
+        service::load_tasks_for_block(&store, block_id),
+        load_images_for_block(&store, block_id)
     );
     
     // Handle errors (if either fails, we return 500)
-    let mut tasks = tasks_result.map_err(|e| Box::new(std::io::Error::new(std::io::ErrorKind::Other, e)))?;
-    let images = images_result.map_err(|e| Box::new(std::io::Error::new(std::io::ErrorKind::Other, e)))?;
+    let mut tasks = tasks_result.map_err(|e| Box::new(std::io::Error::other(e)))?;
+    let images = images_result.map_err(|e| Box::new(std::io::Error::other(e)))?;
     
     // 2. Index images by task_id
     let mut task_images: HashMap<String, Vec<crate::media::Image>> = HashMap::new();
@@ -21,7 +21,18 @@ pub struct Task {
     pub image_count:u32,
 
     pub created_at: String,
-    
+
+    /// Bumped on every update; sent as the `ETag`
+    #[serde(default)]
+    pub version: u64,
+
+    /// Set while the row sits in the trash
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<String>,
+    /// Trash entry this row was trashed under; restore clears it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trash_id: Option<String>,
+
     /// Images associated with this task, filled in by be/blocks/* when joining with media
     #[serde(default)]
     pub images: Vec<crate::media::model::Image>,
@@ -34,7 +45,7 @@ pub struct CreateTaskPayload {
     pub checked_by: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct UpdateTaskPayload {
     pub task_name: Option<String>,
     pub task_state: Option<String>,
@@ -0,0 +1,65 @@
+use aws_sdk_dynamodb::Client as DynamoClient;
+use lambda_http::{Body, Error, Response, http::StatusCode};
+
+use super::service::{self, PARENT_IN_TRASH};
+use crate::store::DynamoStore;
+
+/// HTTP Handler: GET /trash[?block_id=]
+pub async fn list_trash(
+    client: &DynamoClient,
+    table_name: &str,
+    block_id: Option<&str>,
+) -> Result<Response<Body>, Error> {
+    let store = DynamoStore::new(client, table_name);
+    match service::list_trash(&store, block_id).await {
+        Ok(entries) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::to_string(&entries)?.into())
+            .map_err(Box::new)?),
+        Err(e) => Ok(Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": e}).to_string().into())
+            .map_err(Box::new)?),
+    }
+}
+
+/// HTTP Handler: POST /trash/{id}/restore
+/// 409 while the parent block, task or image is itself in the trash
+pub async fn restore_trash(
+    client: &DynamoClient,
+    table_name: &str,
+    user_id: &str,
+    trash_id: &str,
+) -> Result<Response<Body>, Error> {
+    let store = DynamoStore::new(client, table_name);
+    match service::restore(&store, trash_id, user_id).await {
+        Ok(entry) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::to_string(&entry)?.into())
+            .map_err(Box::new)?),
+        Err(e) if e == "Trash entry not found" => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": e}).to_string().into())
+            .map_err(Box::new)?),
+        Err(e) if e == PARENT_IN_TRASH => Ok(Response::builder()
+            .status(StatusCode::CONFLICT)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": e}).to_string().into())
+            .map_err(Box::new)?),
+        Err(e) => Ok(Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": e}).to_string().into())
+            .map_err(Box::new)?),
+    }
+}
@@ -0,0 +1,6 @@
+pub mod model;
+pub mod service;
+pub mod http;
+
+pub use model::{TrashEntry, TrashedEntity};
+pub use http::*;
@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+
+use crate::store::EntityKey;
+
+/// The row a delete moved to the trash. A task's images are trashed with it
+/// under the same entry.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "entity", rename_all = "snake_case")]
+pub enum TrashedEntity {
+    Block { block_id: String },
+    Task { block_id: String, task_id: String },
+    Image { block_id: String, image_id: String },
+    Annotation { block_id: String, image_id: String, annotation_id: String },
+}
+
+impl TrashedEntity {
+    pub fn block_id(&self) -> &str {
+        match self {
+            TrashedEntity::Block { block_id }
+            | TrashedEntity::Task { block_id, .. }
+            | TrashedEntity::Image { block_id, .. }
+            | TrashedEntity::Annotation { block_id, .. } => block_id,
+        }
+    }
+
+    pub fn key(&self) -> EntityKey {
+        match self {
+            TrashedEntity::Block { block_id } => EntityKey::block(block_id),
+            TrashedEntity::Task { block_id, task_id } => EntityKey::task(block_id, task_id),
+            TrashedEntity::Image { block_id, image_id } => EntityKey::image(block_id, image_id),
+            TrashedEntity::Annotation { image_id, annotation_id, .. } => EntityKey::annotation(image_id, annotation_id),
+        }
+    }
+}
+
+/// One delete that can be restored until `expires_at`, after which the
+/// purge job removes the rows for good
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TrashEntry {
+    pub trash_id: String,
+    #[serde(flatten)]
+    pub entity: TrashedEntity,
+    pub deleted_by: String,
+    pub deleted_at: String,
+    pub expires_at: String,
+}
@@ -0,0 +1,399 @@
+use std::collections::BTreeMap;
+
+use super::model::{TrashEntry, TrashedEntity};
+use crate::drawing::model::AnnotationChange;
+use crate::media::model::Image;
+use crate::store::version::conflict_or;
+use crate::store::{BlockCounter, Counter, EntityKey, Store, WriteOp, MAX_TRANSACT_OPS};
+
+/// Days a trashed entity can be restored before the purge job deletes it
+pub const DEFAULT_RETENTION_DAYS: i64 = 30;
+
+/// Error when restoring something whose parent is still in the trash
+pub const PARENT_IN_TRASH: &str = "Parent is in the trash; restore it first";
+
+/// A counter change on one row: the block's, or a label's
+type Delta = (EntityKey, Counter, i64);
+
+/// One row's ops plus the counter changes they imply
+type RowChange = (Vec<WriteOp>, Vec<Delta>);
+
+/// Retention period, overridable with `TRASH_RETENTION_DAYS`
+pub fn retention() -> chrono::Duration {
+    let days = std::env::var("TRASH_RETENTION_DAYS")
+        .ok()
+        .and_then(|days| days.parse().ok())
+        .unwrap_or(DEFAULT_RETENTION_DAYS);
+    chrono::Duration::days(days)
+}
+
+/// Move an entity to the trash and take it out of the counters.
+/// A task takes its live images with it; an image keeps its annotations,
+/// which are hidden with it. With `expected_version`, fails with
+/// VERSION_CONFLICT if someone saved first.
+pub async fn trash<S: Store>(
+    store: &S,
+    entity: TrashedEntity,
+    deleted_by: &str,
+    expected_version: Option<u64>,
+) -> Result<TrashEntry, String> {
+    trash_with(store, entity, deleted_by, expected_version, None, vec![]).await
+}
+
+/// `trash` with `extra` ops written in the same transaction as the entity.
+/// A trashed annotation's history entry is `change` when the caller has
+/// already built it, so both record the same change.
+pub(crate) async fn trash_with<S: Store>(
+    store: &S,
+    entity: TrashedEntity,
+    deleted_by: &str,
+    expected_version: Option<u64>,
+    change: Option<AnnotationChange>,
+    extra: Vec<WriteOp>,
+) -> Result<TrashEntry, String> {
+    let now = chrono::Utc::now();
+    let entry = TrashEntry {
+        trash_id: uuid::Uuid::new_v4().to_string(),
+        entity,
+        deleted_by: deleted_by.to_string(),
+        deleted_at: now.to_rfc3339(),
+        expires_at: (now + retention()).to_rfc3339(),
+    };
+    let mark = |key: EntityKey, expected_version: Option<u64>| WriteOp::Trash {
+        key,
+        trash_id: entry.trash_id.clone(),
+        deleted_at: entry.deleted_at.clone(),
+        expected_version,
+    };
+
+    let mut root = vec![mark(entry.entity.key(), expected_version)];
+    root.extend(extra);
+    let mut changes: Vec<RowChange> = Vec::new();
+    match &entry.entity {
+        TrashedEntity::Block { .. } => changes.push((root, vec![])),
+        TrashedEntity::Annotation { block_id, image_id, annotation_id } => {
+            let change = match change {
+                Some(change) => change,
+                None => {
+                    let annotation = crate::drawing::service::get_annotation(store, image_id, annotation_id).await?;
+                    AnnotationChange::deleted(&annotation, deleted_by, &entry.deleted_at)
+                }
+            };
+            if let Some(before) = &change.before {
+                root.extend(crate::drawing::service::label_count_op(store, block_id, &before.label_id, -1).await?);
+            }
+            root.push(WriteOp::PutAnnotationChange(change));
+            root.push(WriteOp::Increment {
+                key: EntityKey::image(block_id, image_id),
+                deltas: vec![(Counter::ImageAnnotationCount, -1)],
+            });
+            changes.push((root, vec![block_delta(block_id, BlockCounter::AnnotationCount, -1)]));
+        }
+        TrashedEntity::Image { block_id, image_id } => {
+            let image = crate::media::service::get_image(store, block_id, image_id).await?;
+            let mut done = false;
+            if let Some(task_id) = &image.task_id {
+                // A trashed task already left its images out of its count
+                if let Some(task) = store.get_task(block_id, task_id).await?.filter(|t| t.deleted_at.is_none()) {
+                    root.push(WriteOp::Increment {
+                        key: EntityKey::task(block_id, task_id),
+                        deltas: vec![(Counter::TaskImageCount, -1)],
+                    });
+                    done = task.task_state == "done";
+                }
+            }
+            changes.push((root, image_deltas(store, &image, done, -1).await?));
+        }
+        TrashedEntity::Task { block_id, task_id } => {
+            let task = crate::tasks::service::get_task(store, block_id, task_id).await?;
+            let done = task.task_state == "done";
+            changes.push((root, vec![]));
+            for image in store.list_task_images(block_id, task_id).await? {
+                let key = EntityKey::image(block_id, &image.image_id);
+                changes.push((vec![mark(key, None)], image_deltas(store, &image, done, -1).await?));
+            }
+        }
+    }
+
+    // The entry goes in last, so the trash never lists a half-trashed task
+    let last = vec![WriteOp::PutTrash(entry.clone())];
+    if let Err(e) = transact_changes(store, changes, last, true).await {
+        return Err(match (&entry.entity, expected_version) {
+            (_, None) => e,
+            (TrashedEntity::Task { block_id, task_id }, _) => {
+                let current = store.get_task(block_id, task_id).await?;
+                conflict_or(e, expected_version, current.map(|t| t.version))
+            }
+            (TrashedEntity::Annotation { image_id, annotation_id, .. }, _) => {
+                let current = store.get_annotation(image_id, annotation_id).await?;
+                conflict_or(e, expected_version, current.map(|a| a.version))
+            }
+            _ => e,
+        });
+    }
+
+    Ok(entry)
+}
+
+/// Bring back everything still trashed under `trash_id` and add it back to
+/// the counters, then drop the entry. Counters are worked out from the rows
+/// as they are now, so a task whose state changed meanwhile is counted right.
+pub async fn restore<S: Store>(store: &S, trash_id: &str, restored_by: &str) -> Result<TrashEntry, String> {
+    restore_with(store, trash_id, restored_by, None, vec![]).await
+}
+
+/// `restore` with `extra` ops written in the same transaction as the entry,
+/// and `change` as a restored annotation's history entry if already built
+pub(crate) async fn restore_with<S: Store>(
+    store: &S,
+    trash_id: &str,
+    restored_by: &str,
+    change: Option<AnnotationChange>,
+    extra: Vec<WriteOp>,
+) -> Result<TrashEntry, String> {
+    let entry = store.get_trash(trash_id).await?.ok_or("Trash entry not found")?;
+    let block_id = entry.entity.block_id();
+    let ours = |row_trash_id: &Option<String>| row_trash_id.as_deref() == Some(trash_id);
+    let unmark = |key: EntityKey| WriteOp::Restore { key, trash_id: trash_id.to_string() };
+
+    let block = store.get_block(block_id).await?;
+    let block_gone = block.as_ref().is_none_or(|b| b.deleted_at.is_some());
+    if block_gone && !matches!(entry.entity, TrashedEntity::Block { .. }) {
+        return Err(PARENT_IN_TRASH.to_string());
+    }
+
+    let mut last = vec![WriteOp::Delete(EntityKey::trash(trash_id))];
+    last.extend(extra);
+    let mut changes: Vec<RowChange> = Vec::new();
+    match &entry.entity {
+        TrashedEntity::Block { block_id } => {
+            if block.is_some_and(|b| ours(&b.trash_id)) {
+                changes.push((vec![unmark(EntityKey::block(block_id))], vec![]));
+            }
+        }
+        TrashedEntity::Annotation { block_id, image_id, annotation_id } => {
+            let image = store.get_image(block_id, image_id).await?;
+            if image.is_none_or(|i| i.deleted_at.is_some()) {
+                return Err(PARENT_IN_TRASH.to_string());
+            }
+            let annotation = store.get_annotation(image_id, annotation_id).await?;
+            if let Some(annotation) = annotation.filter(|a| ours(&a.trash_id)) {
+                let now = chrono::Utc::now().to_rfc3339();
+                let change = change.unwrap_or_else(|| AnnotationChange::restored(&annotation, restored_by, &now));
+                let mut ops = vec![
+                    unmark(EntityKey::annotation(image_id, annotation_id)),
+                    WriteOp::PutAnnotationChange(change),
+                    WriteOp::Increment {
+                        key: EntityKey::image(block_id, image_id),
+                        deltas: vec![(Counter::ImageAnnotationCount, 1)],
+                    },
+                ];
+                ops.extend(crate::drawing::service::label_count_op(store, block_id, &annotation.label_id, 1).await?);
+                changes.push((ops, vec![block_delta(block_id, BlockCounter::AnnotationCount, 1)]));
+            }
+        }
+        TrashedEntity::Image { block_id, image_id } => {
+            if let Some(image) = store.get_image(block_id, image_id).await?.filter(|i| ours(&i.trash_id)) {
+                let mut ops = vec![unmark(EntityKey::image(block_id, image_id))];
+                let mut done = false;
+                if let Some(task_id) = &image.task_id {
+                    let task = store.get_task(block_id, task_id).await?;
+                    let task = task.filter(|t| t.deleted_at.is_none()).ok_or(PARENT_IN_TRASH)?;
+                    ops.push(WriteOp::Increment {
+                        key: EntityKey::task(block_id, task_id),
+                        deltas: vec![(Counter::TaskImageCount, 1)],
+                    });
+                    done = task.task_state == "done";
+                }
+                changes.push((ops, image_deltas(store, &image, done, 1).await?));
+            }
+        }
+        TrashedEntity::Task { block_id, task_id } => {
+            if let Some(task) = store.get_task(block_id, task_id).await? {
+                let done = task.task_state == "done";
+                if ours(&task.trash_id) {
+                    changes.push((vec![unmark(EntityKey::task(block_id, task_id))], vec![]));
+                }
+                for image_id in store.list_task_image_ids(task_id).await? {
+                    let image = store.get_image(block_id, &image_id).await?;
+                    if let Some(image) = image.filter(|i| ours(&i.trash_id)) {
+                        let key = EntityKey::image(block_id, &image_id);
+                        changes.push((vec![unmark(key)], image_deltas(store, &image, done, 1).await?));
+                    }
+                }
+            }
+        }
+    }
+
+    // A restore that stops part way keeps its entry and can be run again
+    transact_changes(store, changes, last, false).await?;
+    Ok(entry)
+}
+
+/// Trash entries, newest first, optionally only those of one block
+pub async fn list_trash<S: Store>(store: &S, block_id: Option<&str>) -> Result<Vec<TrashEntry>, String> {
+    let mut entries: Vec<TrashEntry> = store
+        .list_trash()
+        .await?
+        .into_iter()
+        .filter(|entry| block_id.is_none_or(|block_id| entry.entity.block_id() == block_id))
+        .collect();
+    entries.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at));
+    Ok(entries)
+}
+
+/// Entries whose retention ran out at `now` (RFC 3339)
+pub async fn list_expired<S: Store>(store: &S, now: &str) -> Result<Vec<TrashEntry>, String> {
+    let mut entries = store.list_trash().await?;
+    entries.retain(|entry| entry.expires_at.as_str() <= now);
+    Ok(entries)
+}
+
+fn block_delta(block_id: &str, counter: BlockCounter, delta: i64) -> Delta {
+    (EntityKey::block(block_id), Counter::Block(counter), delta)
+}
+
+/// Counter changes for trashing (-1) or restoring (+1) one image: the
+/// block's, and the counts of the labels its annotations use
+async fn image_deltas<S: Store>(store: &S, image: &Image, task_done: bool, sign: i64) -> Result<Vec<Delta>, String> {
+    let block_id = image.block_id.as_str();
+    let mut deltas = vec![block_delta(block_id, BlockCounter::ImageCount, sign)];
+    if task_done {
+        deltas.push(block_delta(block_id, BlockCounter::ApprovedImageCount, sign));
+    }
+    if image.annotation_count > 0 {
+        deltas.push(block_delta(block_id, BlockCounter::AnnotationCount, sign * image.annotation_count as i64));
+    }
+
+    let mut per_label: BTreeMap<String, i64> = BTreeMap::new();
+    for annotation in store.list_annotations(&image.image_id).await? {
+        *per_label.entry(annotation.label_id).or_default() += sign;
+    }
+    for (label_id, delta) in per_label {
+        // Annotations can outlive their label
+        if store.get_label(block_id, &label_id).await?.is_some() {
+            deltas.push((EntityKey::label(block_id, &label_id), Counter::LabelCount, delta));
+        }
+    }
+    Ok(deltas)
+}
+
+/// Write `changes` in as few transactions as the op limit allows. Each one
+/// carries the counter changes for its own rows, merged into one increment
+/// per row, so the counters match the live rows after every commit. `last`
+/// rides in the final transaction. With `undo_on_failure`, a failed
+/// transaction takes the marks and counter changes of the ones before it
+/// back out.
+async fn transact_changes<S: Store>(
+    store: &S,
+    changes: Vec<RowChange>,
+    last: Vec<WriteOp>,
+    undo_on_failure: bool,
+) -> Result<(), String> {
+    let budget = MAX_TRANSACT_OPS - last.len();
+    let mut batches = Vec::new();
+    let mut batch = Vec::new();
+    let mut counters: Vec<(EntityKey, Vec<(Counter, i64)>)> = Vec::new();
+
+    for (ops, row_deltas) in changes {
+        let mut new_rows: Vec<&EntityKey> = row_deltas
+            .iter()
+            .map(|(key, _, _)| key)
+            .filter(|key| !counters.iter().any(|(row, _)| row == *key))
+            .collect();
+        new_rows.sort();
+        new_rows.dedup();
+        if !batch.is_empty() && batch.len() + ops.len() + counters.len() + new_rows.len() > budget {
+            batches.push(with_counters(std::mem::take(&mut batch), std::mem::take(&mut counters)));
+        }
+        batch.extend(ops);
+        for (key, counter, delta) in row_deltas {
+            let i = match counters.iter().position(|(row, _)| *row == key) {
+                Some(i) => i,
+                None => {
+                    counters.push((key, vec![]));
+                    counters.len() - 1
+                }
+            };
+            match counters[i].1.iter_mut().find(|(c, _)| *c == counter) {
+                Some((_, total)) => *total += delta,
+                None => counters[i].1.push((counter, delta)),
+            }
+        }
+    }
+    batch.extend(last);
+    batches.push(with_counters(batch, counters));
+
+    let mut written: Vec<Vec<WriteOp>> = Vec::new();
+    for ops in batches {
+        let undo: Vec<WriteOp> = if undo_on_failure { ops.iter().filter_map(undo_op).collect() } else { vec![] };
+        if let Err(e) = store.transact(ops).await {
+            for undo in written.into_iter().rev() {
+                if let Err(undo_error) = store.transact(undo).await {
+                    return Err(format!("{}; undoing the transactions before it failed: {}", e, undo_error));
+                }
+            }
+            return Err(e);
+        }
+        written.push(undo);
+    }
+    Ok(())
+}
+
+fn with_counters(mut ops: Vec<WriteOp>, counters: Vec<(EntityKey, Vec<(Counter, i64)>)>) -> Vec<WriteOp> {
+    for (key, deltas) in counters {
+        if deltas.iter().any(|(_, delta)| *delta != 0) {
+            ops.push(WriteOp::Increment { key, deltas });
+        }
+    }
+    ops
+}
+
+/// The op taking a trash mark or counter change back out
+fn undo_op(op: &WriteOp) -> Option<WriteOp> {
+    match op {
+        WriteOp::Trash { key, trash_id, .. } => Some(WriteOp::Restore { key: key.clone(), trash_id: trash_id.clone() }),
+        WriteOp::Increment { key, deltas } => Some(WriteOp::Increment {
+            key: key.clone(),
+            deltas: deltas.iter().map(|(counter, delta)| (*counter, -delta)).collect(),
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::test_block;
+    use crate::media::model::CreateImagePayload;
+    use crate::store::{BlockRepo, ImageRepo, MemoryStore};
+
+    #[tokio::test]
+    async fn test_failed_transaction_undoes_the_ones_before_it() {
+        let store = MemoryStore::new();
+        store.put_block(&test_block("b1")).await.unwrap();
+        let mut changes: Vec<RowChange> = Vec::new();
+        for _ in 0..MAX_TRANSACT_OPS {
+            let payload = CreateImagePayload { url: "a.jpg".to_string(), task_id: None, order: None, width: None, height: None };
+            let image = crate::media::service::create_image(&store, "b1", payload).await.unwrap();
+            let mark = WriteOp::Trash {
+                key: EntityKey::image("b1", &image.image_id),
+                trash_id: "t1".to_string(),
+                deleted_at: "2024-01-01T00:00:00Z".to_string(),
+                expected_version: None,
+            };
+            changes.push((vec![mark], vec![block_delta("b1", BlockCounter::ImageCount, -1)]));
+        }
+
+        // The last transaction trips on an image that isn't there
+        let missing = WriteOp::Trash {
+            key: EntityKey::image("b1", "missing"),
+            trash_id: "t1".to_string(),
+            deleted_at: "2024-01-01T00:00:00Z".to_string(),
+            expected_version: None,
+        };
+        assert!(transact_changes(&store, changes, vec![missing], true).await.is_err());
+        assert_eq!(store.list_images("b1").await.unwrap().len(), MAX_TRANSACT_OPS);
+        assert_eq!(store.get_block("b1").await.unwrap().unwrap().image_count, MAX_TRANSACT_OPS as u32);
+    }
+}
@@ -0,0 +1,58 @@
+use aws_sdk_dynamodb::Client as DynamoClient;
+use lambda_http::{Body, Error, Response, http::StatusCode};
+
+use super::service::{self, NOTHING_TO_REDO, NOTHING_TO_UNDO, STALE_CHANGE};
+use crate::drawing::model::AnnotationChange;
+use crate::store::DynamoStore;
+use crate::trash::service::PARENT_IN_TRASH;
+
+/// HTTP Handler: POST /images/{iid}/undo?block_id=
+pub async fn undo_handler(
+    client: &DynamoClient,
+    table_name: &str,
+    user_id: &str,
+    block_id: &str,
+    image_id: &str,
+) -> Result<Response<Body>, Error> {
+    let store = DynamoStore::new(client, table_name);
+    step_response(service::undo(&store, block_id, image_id, user_id).await)
+}
+
+/// HTTP Handler: POST /images/{iid}/redo?block_id=
+pub async fn redo_handler(
+    client: &DynamoClient,
+    table_name: &str,
+    user_id: &str,
+    block_id: &str,
+    image_id: &str,
+) -> Result<Response<Body>, Error> {
+    let store = DynamoStore::new(client, table_name);
+    step_response(service::redo(&store, block_id, image_id, user_id).await)
+}
+
+/// 200 with the change made; 409 when there is nothing to step through or
+/// the change can no longer be applied
+fn step_response(result: Result<AnnotationChange, String>) -> Result<Response<Body>, Error> {
+    match result {
+        Ok(change) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::to_string(&change)?.into())
+            .map_err(Box::new)?),
+        Err(e) if [NOTHING_TO_UNDO, NOTHING_TO_REDO, STALE_CHANGE, PARENT_IN_TRASH].contains(&e.as_str()) => {
+            Ok(Response::builder()
+                .status(StatusCode::CONFLICT)
+                .header("Content-Type", "application/json")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(serde_json::json!({"error": e}).to_string().into())
+                .map_err(Box::new)?)
+        }
+        Err(e) => Ok(Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": e}).to_string().into())
+            .map_err(Box::new)?),
+    }
+}
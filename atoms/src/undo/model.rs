@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+
+use crate::drawing::model::AnnotationChange;
+
+/// One user's undo and redo stacks for one image, newest change last.
+/// Saved in the same transaction as the edit it records.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct OpLog {
+    pub user_id: String,
+    pub image_id: String,
+    pub undo: Vec<ChangeRef>,
+    pub redo: Vec<ChangeRef>,
+    /// Bumped on every save; 0 until the first one
+    #[serde(default)]
+    pub version: u64,
+}
+
+impl OpLog {
+    pub fn new(user_id: &str, image_id: &str) -> Self {
+        Self { user_id: user_id.to_string(), image_id: image_id.to_string(), ..Default::default() }
+    }
+}
+
+/// Where a change sits in the image's history. The log keeps these rather
+/// than the changes, so its size doesn't grow with the geometry; logs saved
+/// with whole changes still read, as the extra fields are ignored.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ChangeRef {
+    pub annotation_id: String,
+    pub at: String,
+    pub change_id: String,
+}
+
+impl From<&AnnotationChange> for ChangeRef {
+    fn from(change: &AnnotationChange) -> Self {
+        ChangeRef {
+            annotation_id: change.annotation_id.clone(),
+            at: change.at.clone(),
+            change_id: change.change_id.clone(),
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use super::model::{ChangeRef, OpLog};
+use crate::drawing::model::{Annotation, AnnotationChange, AnnotationState, ChangeAction, UpdateAnnotationPayload};
+use crate::store::version::conflict_or;
+use crate::store::{Store, WriteOp, VERSION_CONFLICT};
+use crate::trash::model::TrashedEntity;
+
+/// Changes kept on each stack; the oldest fall off first
+pub const MAX_UNDO_DEPTH: usize = 50;
+
+/// Size a log may reach before its oldest entries are dropped, well under
+/// DynamoDB's 400 KB item limit
+pub const MAX_OP_LOG_BYTES: usize = 64 * 1024;
+
+pub const NOTHING_TO_UNDO: &str = "Nothing to undo";
+pub const NOTHING_TO_REDO: &str = "Nothing to redo";
+
+/// Error when the annotation no longer looks the way the change being undone
+/// or redone left it; the change is dropped from the stack
+pub const STALE_CHANGE: &str = "Annotation changed since; the change was dropped";
+
+/// The op that pushes `change` onto its actor's undo log and clears the
+/// redo stack. Write it in the same transaction as the change, which must
+/// also go to the history: the log only refers to it.
+pub(crate) async fn logged<S: Store>(store: &S, change: &AnnotationChange) -> Result<WriteOp, String> {
+    let mut log = load(store, &change.actor, &change.image_id).await?;
+    let expected_version = log.version;
+    log.redo.clear();
+    push(&mut log, true, change);
+    log.version += 1;
+    Ok(WriteOp::PutOpLog { log, expected_version })
+}
+
+/// Undo the user's latest change on an image; returns the change made
+pub async fn undo<S: Store>(
+    store: &S,
+    block_id: &str,
+    image_id: &str,
+    user_id: &str,
+) -> Result<AnnotationChange, String> {
+    step(store, block_id, image_id, user_id, true).await
+}
+
+/// Redo the user's latest undone change on an image; returns the change made
+pub async fn redo<S: Store>(
+    store: &S,
+    block_id: &str,
+    image_id: &str,
+    user_id: &str,
+) -> Result<AnnotationChange, String> {
+    step(store, block_id, image_id, user_id, false).await
+}
+
+/// Pop a change from one stack, apply its reverse and push that onto the
+/// other stack, all in one transaction. A change is stale once the
+/// annotation no longer looks the way it left it, or it has left the history.
+async fn step<S: Store>(
+    store: &S,
+    block_id: &str,
+    image_id: &str,
+    user_id: &str,
+    undoing: bool,
+) -> Result<AnnotationChange, String> {
+    let mut log = load(store, user_id, image_id).await?;
+    let expected_version = log.version;
+    let from = if undoing { &mut log.undo } else { &mut log.redo };
+    let entry = from.pop().ok_or(if undoing { NOTHING_TO_UNDO } else { NOTHING_TO_REDO })?;
+    // Saved instead when the change can't be applied
+    let mut dropped = log.clone();
+    dropped.version += 1;
+
+    let Some(change) = store.get_annotation_change(image_id, &entry).await? else {
+        store.transact(vec![WriteOp::PutOpLog { log: dropped, expected_version }]).await?;
+        return Err(STALE_CHANGE.to_string());
+    };
+    let deleted = change.action == ChangeAction::Delete;
+    let left = if deleted { &change.before } else { &change.after };
+    let current = store
+        .get_annotation(image_id, &change.annotation_id)
+        .await?
+        .filter(|a| a.deleted_at.is_some() == deleted && left.as_ref() == Some(&AnnotationState::from(a)));
+    let Some(current) = current else {
+        store.transact(vec![WriteOp::PutOpLog { log: dropped, expected_version }]).await?;
+        return Err(STALE_CHANGE.to_string());
+    };
+
+    let (made, ops) = reversal(store, block_id, &change, &current, user_id).await?;
+    push(&mut log, !undoing, &made);
+    log.version += 1;
+    let op = WriteOp::PutOpLog { log, expected_version };
+    match apply(store, block_id, &change, &current, made.clone(), ops, op).await {
+        Ok(()) => Ok(made),
+        Err(e) if e == VERSION_CONFLICT || e == "Annotation not found" => {
+            // The change was overtaken mid-step
+            store.transact(vec![WriteOp::PutOpLog { log: dropped, expected_version }]).await?;
+            Err(STALE_CHANGE.to_string())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// The change that reverses `change` on the `current` row, made by
+/// `user_id`, and for updates the ops that write it
+async fn reversal<S: Store>(
+    store: &S,
+    block_id: &str,
+    change: &AnnotationChange,
+    current: &Annotation,
+    user_id: &str,
+) -> Result<(AnnotationChange, Vec<WriteOp>), String> {
+    let now = chrono::Utc::now().to_rfc3339();
+    Ok(match change.action {
+        ChangeAction::Create | ChangeAction::Restore => (AnnotationChange::deleted(current, user_id, &now), vec![]),
+        ChangeAction::Delete => (AnnotationChange::restored(current, user_id, &now), vec![]),
+        ChangeAction::Update => {
+            let before = change.before.clone().ok_or("Update change without a previous state")?;
+            let payload = UpdateAnnotationPayload {
+                label_id: Some(before.label_id),
+                geometry: Some(before.geometry),
+                attributes: Some(before.attributes),
+                cleanup: None,
+            };
+            let (after, made, mut ops) = crate::drawing::service::update_ops(current, &payload, user_id);
+            ops.extend(crate::drawing::service::relabel_ops(store, block_id, &current.label_id, &after.label_id).await?);
+            (made, ops)
+        }
+    })
+}
+
+/// Write `made`, reversing `change`, through the same paths as a user edit,
+/// pinned to the `current` row and with `log` riding in the same transaction
+async fn apply<S: Store>(
+    store: &S,
+    block_id: &str,
+    change: &AnnotationChange,
+    current: &Annotation,
+    made: AnnotationChange,
+    mut ops: Vec<WriteOp>,
+    log: WriteOp,
+) -> Result<(), String> {
+    let user_id = made.actor.clone();
+    match change.action {
+        ChangeAction::Create | ChangeAction::Restore => {
+            let entity = TrashedEntity::Annotation {
+                block_id: block_id.to_string(),
+                image_id: current.image_id.clone(),
+                annotation_id: current.annotation_id.clone(),
+            };
+            let version = Some(current.version);
+            crate::trash::service::trash_with(store, entity, &user_id, version, Some(made), vec![log]).await?;
+        }
+        ChangeAction::Delete => {
+            let trash_id = current.trash_id.as_deref().ok_or(VERSION_CONFLICT)?;
+            crate::trash::service::restore_with(store, trash_id, &user_id, Some(made), vec![log]).await?;
+        }
+        ChangeAction::Update => {
+            ops.push(log);
+            if let Err(e) = store.transact(ops).await {
+                let now = store.get_annotation(&current.image_id, &current.annotation_id).await?;
+                return Err(conflict_or(e, Some(current.version), now.map(|a| a.version)));
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn load<S: Store>(store: &S, user_id: &str, image_id: &str) -> Result<OpLog, String> {
+    Ok(store.get_op_log(user_id, image_id).await?.unwrap_or_else(|| OpLog::new(user_id, image_id)))
+}
+
+/// Push onto the undo or redo stack, then drop the oldest entries, from
+/// whichever stack is longer, until the log is within its count and size
+fn push(log: &mut OpLog, undo: bool, change: &AnnotationChange) {
+    let stack = if undo { &mut log.undo } else { &mut log.redo };
+    stack.push(ChangeRef::from(change));
+    if stack.len() > MAX_UNDO_DEPTH {
+        stack.remove(0);
+    }
+    while serde_json::to_vec(&*log).map_or(0, |json| json.len()) > MAX_OP_LOG_BYTES {
+        let longer = if log.undo.len() >= log.redo.len() { &mut log.undo } else { &mut log.redo };
+        if longer.len() <= 1 {
+            break;
+        }
+        longer.remove(0);
+    }
+}
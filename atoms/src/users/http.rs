@@ -0,0 +1,74 @@
+use lambda_http::{Body, Error, Response};
+use aws_sdk_dynamodb::Client as DynamoClient;
+use super::model::{User, CreateUserPayload, UpdateUserPayload};
+use super::service;
+use crate::store::DynamoStore;
+
+/// Create user in DynamoDB after Cognito signup
+/// This is called once after user signs up in Cognito
+pub async fn create_user(
+    client: &DynamoClient,
+    table_name: &str,
+    user_id: &str,
+    body: &[u8],
+) -> Result<Response<Body>, Error> {
+    let req: CreateUserPayload = serde_json::from_slice(body)?;
+
+    let store = DynamoStore::new(client, table_name);
+    let user = service::create_user(&store, user_id, req).await?;
+
+    let resp = Response::builder()
+        .status(201)
+        .header("content-type", "application/json")
+        .header("Access-Control-Allow-Origin", "*")
+        .body(serde_json::to_string(&user)?.into())
+        .map_err(Box::new)?;
+    Ok(resp)
+}
+
+/// Get current user from DynamoDB
+pub async fn get_user(
+    client: &DynamoClient,
+    table_name: &str,
+    user_id: &str,
+) -> Result<Response<Body>, Error> {
+    let store = DynamoStore::new(client, table_name);
+    user_response(service::get_user(&store, user_id).await)
+}
+
+/// Update user
+pub async fn update_user(
+    client: &DynamoClient,
+    table_name: &str,
+    user_id: &str,
+    body: &[u8],
+) -> Result<Response<Body>, Error> {
+    let req: UpdateUserPayload = serde_json::from_slice(body)?;
+
+    let store = DynamoStore::new(client, table_name);
+    user_response(service::update_user(&store, user_id, req).await)
+}
+
+fn user_response(result: Result<User, String>) -> Result<Response<Body>, Error> {
+    match result {
+        Ok(user) => {
+            let resp = Response::builder()
+                .status(200)
+                .header("content-type", "application/json")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(serde_json::to_string(&user)?.into())
+                .map_err(Box::new)?;
+            Ok(resp)
+        }
+        Err(e) if e == "User not found" => {
+            let resp = Response::builder()
+                .status(404)
+                .header("content-type", "application/json")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(serde_json::json!({"error": "User not found"}).to_string().into())
+                .map_err(Box::new)?;
+            Ok(resp)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
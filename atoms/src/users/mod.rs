@@ -1,5 +1,6 @@
 pub mod model;
 pub mod service;
+pub mod http;
 
-pub use model::{User, CreateUserPayload, UpdateUserPayload};
-pub use service::*;
+pub use model::{User, CreateUserPayload, UpdateUserPayload, Invite, Connection};
+pub use http::*;
@@ -25,3 +25,23 @@ pub struct UpdateUserPayload {
     pub user_company: Option<String>,
     pub user_role: Option<String>,
 }
+
+/// Signup invite, keyed by its invite code
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Invite {
+    pub invite_code: String,
+    pub email: String,
+    pub status: String, // pending | used
+    pub created_by: String,
+    pub created_at: String,
+    pub expires_at: String,
+    pub used_at: Option<String>,
+}
+
+/// WebSocket connection owned by a user
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Connection {
+    pub connection_id: String,
+    pub user_id: String,
+    pub connected_at: String,
+}
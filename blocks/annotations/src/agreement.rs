@@ -0,0 +1,424 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use aws_sdk_dynamodb::Client as DynamoClient;
+use doxle_atoms::drawing::geometry;
+use doxle_atoms::drawing::model::{Annotation, Geometry, Point};
+use doxle_atoms::store::{DynamoStore, Store};
+use lambda_http::{http::StatusCode, Body, Error, Response};
+use serde::Serialize;
+
+/// IoU at which two annotators' annotations count as the same object
+pub const DEFAULT_MATCH_IOU: f64 = 0.5;
+/// Pixels lines and points are padded by so they have an area to compare
+const LINE_PADDING: f64 = 5.0;
+
+/// Matches between annotators for one label. In each pair of annotators the
+/// one with the lower user id is the reference: `recall` is the share of
+/// their annotations the other found, `precision` the share of the other's
+/// that match theirs.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LabelAgreement {
+    pub label_id: String,
+    pub matched: u32,
+    pub reference: u32,
+    pub compared: u32,
+    pub precision: f64,
+    pub recall: f64,
+    pub f1: f64,
+}
+
+impl LabelAgreement {
+    fn add(&mut self, other: &LabelAgreement) {
+        self.matched += other.matched;
+        self.reference += other.reference;
+        self.compared += other.compared;
+    }
+
+    fn finish(mut self) -> Self {
+        let ratio = |n: u32, d: u32| if d == 0 { 0.0 } else { f64::from(n) / f64::from(d) };
+        self.precision = ratio(self.matched, self.compared);
+        self.recall = ratio(self.matched, self.reference);
+        self.f1 = ratio(2 * self.matched, self.reference + self.compared);
+        self
+    }
+}
+
+/// An object most annotators drew, with their geometries averaged
+#[derive(Debug, Clone, Serialize)]
+pub struct ConsensusAnnotation {
+    pub label_id: String,
+    pub geometry: Geometry,
+    pub votes: u32,
+    /// The annotations it was built from, one per annotator
+    pub annotation_ids: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageAgreement {
+    pub image_id: String,
+    pub annotators: Vec<String>,
+    pub labels: Vec<LabelAgreement>,
+    /// F1 over every label and pair of annotators; None with fewer than two
+    /// annotators
+    pub agreement: Option<f64>,
+    pub consensus: Vec<ConsensusAnnotation>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskAgreement {
+    pub task_id: String,
+    pub images: Vec<ImageAgreement>,
+    /// Label counts summed over the task's images
+    pub labels: Vec<LabelAgreement>,
+    pub agreement: Option<f64>,
+}
+
+/// Compare what each annotator (`created_by`) drew on one image: match
+/// same-label annotations pairwise by IoU, then keep the objects a strict
+/// majority of annotators agree on as the consensus
+pub fn compare_annotations(image_id: &str, annotations: &[Annotation], match_iou: f64) -> ImageAgreement {
+    let annotators: BTreeSet<String> = annotations.iter().map(|a| a.created_by.clone()).collect();
+    let annotators: Vec<String> = annotators.into_iter().collect();
+    let mut labels: BTreeMap<String, LabelAgreement> = BTreeMap::new();
+    let mut links: Vec<(usize, usize)> = Vec::new();
+
+    for (x, reference) in annotators.iter().enumerate() {
+        for compared in &annotators[x + 1..] {
+            let by = |user: &String| {
+                annotations.iter().enumerate().filter(move |(_, a)| &a.created_by == user).collect::<Vec<_>>()
+            };
+            let (ours, theirs) = (by(reference), by(compared));
+            let label_ids: BTreeSet<&str> = ours.iter().chain(&theirs).map(|(_, a)| a.label_id.as_str()).collect();
+            for label_id in label_ids {
+                let (ours, theirs) = (of_label(&ours, label_id), of_label(&theirs, label_id));
+                let matches = match_greedily(&ours, &theirs, match_iou);
+                let entry = labels.entry(label_id.to_string()).or_insert_with(|| LabelAgreement {
+                    label_id: label_id.to_string(),
+                    ..Default::default()
+                });
+                entry.add(&LabelAgreement {
+                    matched: matches.len() as u32,
+                    reference: ours.len() as u32,
+                    compared: theirs.len() as u32,
+                    ..Default::default()
+                });
+                links.extend(matches);
+            }
+        }
+    }
+
+    let labels: Vec<LabelAgreement> = labels.into_values().map(LabelAgreement::finish).collect();
+    let agreement = (annotators.len() > 1).then(|| overall(&labels));
+    let consensus = consensus(annotations, &links, annotators.len());
+    ImageAgreement { image_id: image_id.to_string(), annotators, labels, agreement, consensus }
+}
+
+fn of_label<'a>(side: &[(usize, &'a Annotation)], label_id: &str) -> Vec<(usize, &'a Annotation)> {
+    side.iter().filter(|(_, a)| a.label_id == label_id).copied().collect()
+}
+
+/// Pairs of positions, best IoU first, each annotation used once
+fn match_greedily(
+    ours: &[(usize, &Annotation)],
+    theirs: &[(usize, &Annotation)],
+    match_iou: f64,
+) -> Vec<(usize, usize)> {
+    let mut candidates = Vec::new();
+    for (i, a) in ours {
+        for (j, b) in theirs {
+            let iou = similarity(&a.geometry, &b.geometry);
+            if iou >= match_iou {
+                candidates.push((iou, *i, *j));
+            }
+        }
+    }
+    candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let (mut used_ours, mut used_theirs) = (BTreeSet::new(), BTreeSet::new());
+    let mut matches = Vec::new();
+    for (_, i, j) in candidates {
+        if !used_ours.contains(&i) && !used_theirs.contains(&j) {
+            used_ours.insert(i);
+            used_theirs.insert(j);
+            matches.push((i, j));
+        }
+    }
+    matches
+}
+
+/// IoU, with lines and points compared as their padded bounding boxes
+fn similarity(a: &Geometry, b: &Geometry) -> f64 {
+    geometry::iou(a, b).unwrap_or_else(|| {
+        let padded = |g: &Geometry| {
+            let bbox = geometry::bounding_box(g);
+            Geometry::BBox {
+                start: Point { x: bbox.min.x - LINE_PADDING, y: bbox.min.y - LINE_PADDING },
+                end: Point { x: bbox.max.x + LINE_PADDING, y: bbox.max.y + LINE_PADDING },
+            }
+        };
+        geometry::iou(&padded(a), &padded(b)).unwrap_or(0.0)
+    })
+}
+
+fn overall(labels: &[LabelAgreement]) -> f64 {
+    let mut total = LabelAgreement::default();
+    for label in labels {
+        total.add(label);
+    }
+    let total = total.finish();
+    // Nobody drew anything: nothing to disagree on
+    if total.reference + total.compared == 0 { 1.0 } else { total.f1 }
+}
+
+/// Group linked annotations and keep the groups drawn by a strict majority
+/// of annotators. Should a chain of links pull in two annotations by one
+/// annotator, only the first counts.
+fn consensus(annotations: &[Annotation], links: &[(usize, usize)], annotators: usize) -> Vec<ConsensusAnnotation> {
+    let mut parent: Vec<usize> = (0..annotations.len()).collect();
+    fn root(parent: &mut [usize], i: usize) -> usize {
+        let mut r = i;
+        while parent[r] != r {
+            r = parent[r];
+        }
+        parent[i] = r;
+        r
+    }
+    for &(a, b) in links {
+        let (ra, rb) = (root(&mut parent, a), root(&mut parent, b));
+        parent[ra.max(rb)] = ra.min(rb);
+    }
+    let mut groups: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for i in 0..annotations.len() {
+        groups.entry(root(&mut parent, i)).or_default().push(i);
+    }
+
+    groups
+        .into_values()
+        .filter_map(|members| {
+            let mut seen = HashSet::new();
+            let members: Vec<&Annotation> = members
+                .into_iter()
+                .map(|i| &annotations[i])
+                .filter(|a| seen.insert(a.created_by.as_str()))
+                .collect();
+            if members.len() * 2 <= annotators {
+                return None;
+            }
+            let geometries: Vec<&Geometry> = members.iter().map(|a| &a.geometry).collect();
+            Some(ConsensusAnnotation {
+                label_id: members[0].label_id.clone(),
+                geometry: average(&geometries).unwrap_or_else(|| medoid(&geometries).clone()),
+                votes: members.len() as u32,
+                annotation_ids: members.iter().map(|a| a.annotation_id.clone()).collect(),
+            })
+        })
+        .collect()
+}
+
+/// Vertex-by-vertex mean of geometries of one kind and shape. Rings are
+/// lined up on the vertex nearest the first ring's start. None when the
+/// kinds or vertex counts differ.
+fn average(geometries: &[&Geometry]) -> Option<Geometry> {
+    let n = geometries.len() as f64;
+    let mean = |values: &mut dyn Iterator<Item = f64>| values.sum::<f64>() / n;
+    let mean_point = |points: &[&Point]| Point {
+        x: mean(&mut points.iter().map(|p| p.x)),
+        y: mean(&mut points.iter().map(|p| p.y)),
+    };
+    let mean_path = |paths: Vec<&Vec<Point>>, closed: bool| -> Option<Vec<Point>> {
+        let first = paths[0];
+        if paths.iter().any(|p| p.len() != first.len()) {
+            return None;
+        }
+        let aligned: Vec<Vec<&Point>> = paths
+            .iter()
+            .map(|path| {
+                let nearest = |a: &usize, b: &usize| dist(&path[*a], &first[0]).total_cmp(&dist(&path[*b], &first[0]));
+                let shift = if closed { (0..path.len()).min_by(nearest).unwrap_or(0) } else { 0 };
+                (0..path.len()).map(|k| &path[(k + shift) % path.len()]).collect()
+            })
+            .collect();
+        Some((0..first.len()).map(|k| mean_point(&aligned.iter().map(|p| p[k]).collect::<Vec<_>>())).collect())
+    };
+
+    match geometries[0] {
+        Geometry::BBox { .. } => {
+            let (mut starts, mut ends) = (vec![], vec![]);
+            for g in geometries {
+                let Geometry::BBox { start, end } = g else { return None };
+                starts.push(start);
+                ends.push(end);
+            }
+            Some(Geometry::BBox { start: mean_point(&starts), end: mean_point(&ends) })
+        }
+        Geometry::Point { .. } => {
+            let mut points = vec![];
+            for g in geometries {
+                let Geometry::Point { at } = g else { return None };
+                points.push(at);
+            }
+            Some(Geometry::Point { at: mean_point(&points) })
+        }
+        Geometry::Polyline { .. } => {
+            let mut paths = vec![];
+            for g in geometries {
+                let Geometry::Polyline { points } = g else { return None };
+                paths.push(points);
+            }
+            Some(Geometry::Polyline { points: mean_path(paths, false)? })
+        }
+        Geometry::Polygon { .. } => {
+            let mut rings = vec![];
+            for g in geometries {
+                let Geometry::Polygon { points, holes } = g else { return None };
+                if !holes.is_empty() {
+                    return None;
+                }
+                rings.push(points);
+            }
+            Some(Geometry::Polygon { points: mean_path(rings, true)?, holes: vec![] })
+        }
+        _ => None,
+    }
+}
+
+/// The geometry with the highest total IoU against the rest
+fn medoid<'a>(geometries: &[&'a Geometry]) -> &'a Geometry {
+    geometries
+        .iter()
+        .map(|g| (*g, geometries.iter().map(|other| similarity(g, other)).sum::<f64>()))
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(g, _)| g)
+        .unwrap_or(geometries[0])
+}
+
+fn dist(a: &Point, b: &Point) -> f64 {
+    (b.x - a.x).hypot(b.y - a.y)
+}
+
+/// Agreement on one image
+pub async fn image_agreement<S: Store>(store: &S, image_id: &str, match_iou: f64) -> Result<ImageAgreement, String> {
+    let annotations = store.list_annotations(image_id).await?;
+    Ok(compare_annotations(image_id, &annotations, match_iou))
+}
+
+/// Agreement on each of a task's images, with label counts summed over them
+pub async fn task_agreement<S: Store>(
+    store: &S,
+    block_id: &str,
+    task_id: &str,
+    match_iou: f64,
+) -> Result<TaskAgreement, String> {
+    doxle_atoms::tasks::service::get_task(store, block_id, task_id).await?;
+    let mut images = Vec::new();
+    for image in doxle_atoms::media::service::load_images_for_task(store, block_id, task_id).await? {
+        images.push(image_agreement(store, &image.image_id, match_iou).await?);
+    }
+
+    let mut labels: BTreeMap<String, LabelAgreement> = BTreeMap::new();
+    for label in images.iter().flat_map(|image| &image.labels) {
+        labels
+            .entry(label.label_id.clone())
+            .or_insert_with(|| LabelAgreement { label_id: label.label_id.clone(), ..Default::default() })
+            .add(label);
+    }
+    let labels: Vec<LabelAgreement> = labels.into_values().map(LabelAgreement::finish).collect();
+    let compared = images.iter().any(|image| image.agreement.is_some());
+    let agreement = compared.then(|| overall(&labels));
+    Ok(TaskAgreement { task_id: task_id.to_string(), images, labels, agreement })
+}
+
+/// Read `?iou=`, the IoU two annotations need to count as a match
+pub fn match_iou_from_query(iou: Option<&str>) -> Result<f64, String> {
+    match iou {
+        None => Ok(DEFAULT_MATCH_IOU),
+        Some(v) => v
+            .parse::<f64>()
+            .ok()
+            .filter(|v| *v > 0.0 && *v <= 1.0)
+            .ok_or_else(|| "iou must be above 0 and at most 1".to_string()),
+    }
+}
+
+/// GET /images/{id}/agreement[?iou=]
+pub async fn image_agreement_handler(
+    client: &DynamoClient,
+    table_name: &str,
+    image_id: &str,
+    match_iou: f64,
+) -> Result<Response<Body>, Error> {
+    let store = DynamoStore::new(client, table_name);
+    agreement_response(image_agreement(&store, image_id, match_iou).await)
+}
+
+/// GET /blocks/{bid}/tasks/{tid}/agreement[?iou=]
+pub async fn task_agreement_handler(
+    client: &DynamoClient,
+    table_name: &str,
+    block_id: &str,
+    task_id: &str,
+    match_iou: f64,
+) -> Result<Response<Body>, Error> {
+    let store = DynamoStore::new(client, table_name);
+    agreement_response(task_agreement(&store, block_id, task_id, match_iou).await)
+}
+
+fn agreement_response<T: Serialize>(result: Result<T, String>) -> Result<Response<Body>, Error> {
+    match result {
+        Ok(report) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::to_string(&report)?.into())
+            .map_err(Box::new)?),
+        Err(e) if e.ends_with("not found") => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": e}).to_string().into())
+            .map_err(Box::new)?),
+        Err(e) => Ok(Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": e}).to_string().into())
+            .map_err(Box::new)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use doxle_atoms::fixtures::test_annotation;
+
+    fn drawn(id: &str, by: &str, label_id: &str, x0: f64) -> Annotation {
+        let geometry = Geometry::BBox { start: Point { x: x0, y: 0.0 }, end: Point { x: x0 + 10.0, y: 10.0 } };
+        Annotation { created_by: by.to_string(), ..test_annotation("i1", id, label_id, geometry) }
+    }
+
+    #[test]
+    fn test_agreement_and_majority_consensus() {
+        // Three annotators agree on one door, two of them on a window, and
+        // only one saw a second door
+        let annotations = vec![
+            drawn("a1", "alex", "door", 0.0),
+            drawn("b1", "blair", "door", 1.0),
+            drawn("c1", "casey", "door", 2.0),
+            drawn("a2", "alex", "window", 50.0),
+            drawn("b2", "blair", "window", 51.0),
+            drawn("c2", "casey", "door", 100.0),
+        ];
+        let report = compare_annotations("i1", &annotations, DEFAULT_MATCH_IOU);
+        assert_eq!(report.annotators, vec!["alex", "blair", "casey"]);
+
+        let door = report.labels.iter().find(|l| l.label_id == "door").unwrap();
+        // Pairs: alex-blair 1 of 1/1, alex-casey 1 of 1/2, blair-casey 1 of 1/2
+        assert_eq!((door.matched, door.reference, door.compared), (3, 3, 5));
+        assert_eq!(door.recall, 1.0);
+
+        assert_eq!(report.consensus.len(), 2);
+        let door = report.consensus.iter().find(|c| c.label_id == "door").unwrap();
+        assert_eq!(door.votes, 3);
+        assert_eq!(door.geometry, Geometry::BBox { start: Point { x: 1.0, y: 0.0 }, end: Point { x: 11.0, y: 10.0 } });
+        assert_eq!(report.consensus.iter().find(|c| c.label_id == "window").unwrap().votes, 2);
+    }
+}
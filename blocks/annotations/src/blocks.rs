@@ -1,14 +1,16 @@
-use doxle_atoms::blocks::model::{Block, CreateBlockPayload, UpdateBlockPayload};
+use std::collections::HashMap;
+
+use doxle_atoms::blocks::model::{Block, CloneBlockPayload, CloneScope, CreateBlockPayload, UpdateBlockPayload};
+use doxle_atoms::store::{BlockRepo, DynamoStore, EntityKey, Page, PageRequest, Store, WriteOp, INVALID_CURSOR, MAX_TRANSACT_OPS};
+use doxle_atoms::trash::{service as trash, TrashedEntity};
 use aws_sdk_dynamodb::Client as DynamoClient;
 use aws_sdk_s3::Client as S3Client;
 use lambda_http::{http::StatusCode, Body, Error, Response};
-use aws_sdk_dynamodb::types::{AttributeValue, WriteRequest, DeleteRequest};
-use std::collections::HashMap;
-use tokio::time::{sleep, Duration};
 use crate::types::AnnotationBlock;
 use crate::labels::fetch_labels_for_block;
+use crate::templates::{seed_labels, sort_labels};
 
-/// Create a new block:
+/// Create a new block, starting with its block type's template labels:
 /// PK = "BLOCK"
 /// SK = "BLOCK#{block_id}"
 pub async fn create_block(
@@ -18,33 +20,8 @@ pub async fn create_block(
 ) -> Result<Response<Body>, Error> {
     let req: CreateBlockPayload = serde_json::from_slice(body)?;
 
-    let block_id = uuid::Uuid::new_v4().to_string();
-    let now = chrono::Utc::now().to_rfc3339();
-    let pk = "BLOCK".to_string();
-    let sk = format!("BLOCK#{}", block_id);
-    
-    let mut builder = client
-        .put_item()
-        .table_name(table_name)
-        .item("PK", AttributeValue::S(pk.clone()))
-        .item("SK", AttributeValue::S(sk.clone()))
-        .item("block_name", AttributeValue::S(req.block_name.clone()))
-        .item("block_type", AttributeValue::S(req.block_type.clone()))
-        .item("block_state", AttributeValue::S("draft".to_string()))
-        .item("block_locked", AttributeValue::Bool(false))
-        .item("image_count", AttributeValue::N(0.to_string()))
-        .item("approved_image_count", AttributeValue::N(0.to_string()))
-        .item("annotation_count", AttributeValue::N(0.to_string()))
-        .item("block_created_at", AttributeValue::S(now.clone()));
-
-    if let Some(comp) = &req.block_company {
-        builder = builder.item("block_company", AttributeValue::S(comp.clone()));
-    }
-        
-    builder.send().await?;
-
     let block = Block {
-        block_id,
+        block_id: uuid::Uuid::new_v4().to_string(),
         block_name: req.block_name,
         block_type: req.block_type,
         block_company: req.block_company,
@@ -53,13 +30,16 @@ pub async fn create_block(
         image_count: 0,
         approved_image_count: 0,
         annotation_count: 0,
-        block_created_at: now,
+        block_created_at: chrono::Utc::now().to_rfc3339(),
+        deleted_at: None,
+        trash_id: None,
     };
 
-    let response = AnnotationBlock {
-        block,
-        labels: Vec::new(),
-    };
+    let store = DynamoStore::new(client, table_name);
+    let mut labels = seed_labels(&store, &block).await?;
+    sort_labels(&mut labels, &block.block_type, None);
+
+    let response = AnnotationBlock { block, labels };
 
     Ok(Response::builder()
         .status(StatusCode::CREATED)
@@ -75,67 +55,10 @@ pub async fn get_block(
     table_name: &str,
     block_id: &str,
 ) -> Result<Response<Body>, Error> {
-    let pk = "BLOCK".to_string();
-    let sk = format!("BLOCK#{}", block_id);
-
-    let result = client
-        .get_item()
-        .table_name(table_name)
-        .key("PK", AttributeValue::S(pk))
-        .key("SK", AttributeValue::S(sk))
-        .send()
-        .await?;
+    let store = DynamoStore::new(client, table_name);
 
-    if let Some(item) = result.item() {
-        let labels = fetch_labels_for_block(client, table_name, block_id).await?;
-        
-        let block = Block {
-            block_id: block_id.to_string(),
-            block_name: item
-                .get("block_name")
-                .and_then(|v| v.as_s().ok())
-                .map(|s| s.to_string())
-                .unwrap_or_default(),
-            block_type: item
-                .get("block_type")
-                .and_then(|v| v.as_s().ok())
-                .map(|s| s.to_string())
-                .unwrap_or_else(|| "annotation".to_string()),
-            block_company: item
-                .get("block_company")
-                .and_then(|v| v.as_s().ok())
-                .map(|s| s.to_string()),
-            block_state: item
-                .get("block_state")
-                .and_then(|v| v.as_s().ok())
-                .map(|s| s.to_string())
-                .unwrap_or_default(),
-            block_locked: item
-                .get("block_locked")
-                .and_then(|v| v.as_bool().ok())
-                .copied()
-                .unwrap_or(false),
-            image_count: item
-                .get("image_count")
-                .and_then(|v| v.as_n().ok())
-                .and_then(|n| n.parse().ok())
-                .unwrap_or(0),
-            approved_image_count: item
-                .get("approved_image_count")
-                .and_then(|v| v.as_n().ok())
-                .and_then(|n| n.parse().ok())
-                .unwrap_or(0),
-            annotation_count: item
-               .get("annotation_count")
-                .and_then(|v| v.as_n().ok())
-                .and_then(|n| n.parse().ok())
-                .unwrap_or(0),
-            block_created_at: item
-                .get("block_created_at")
-                .and_then(|v| v.as_s().ok())
-                .map(|s| s.to_string())
-                .unwrap_or_default(),
-        };
+    if let Some(block) = store.get_block(block_id).await?.filter(|b| b.deleted_at.is_none()) {
+        let labels = fetch_labels_for_block(&store, block_id).await?;
 
         let response = AnnotationBlock { block, labels };
 
@@ -160,105 +83,64 @@ pub async fn get_block(
 }
 
 /// List all blocks (annotation blocks)
+/// Returns a bare array, or a `Page` when `?limit=`/`?cursor=` is given
 pub async fn list_blocks(
     client: &DynamoClient,
     table_name: &str,
+    page: &PageRequest,
 ) -> Result<Response<Body>, Error> {
-    let pk = "BLOCK".to_string();
-
-    let result = match client
-        .query()
-        .table_name(table_name)
-        .key_condition_expression("PK = :pk AND begins_with(SK, :sk_prefix)")
-        .expression_attribute_values(":pk", AttributeValue::S(pk))
-        .expression_attribute_values(
-            ":sk_prefix",
-            AttributeValue::S("BLOCK#".to_string()),
-        )
-        .send()
-        .await
-    {
+    let store = DynamoStore::new(client, table_name);
+
+    let listed = if page.is_paged() {
+        store.list_blocks_page(page).await
+    } else {
+        store.list_blocks().await.map(|items| Page { items, next_cursor: None })
+    };
+
+    let stored = match listed {
         Ok(res) => res,
+        Err(e) if e == INVALID_CURSOR => {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .header("Content-Type", "application/json")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(serde_json::json!({"error": e}).to_string().into())
+                .map_err(Box::new)?);
+        }
         Err(e) => {
             tracing::error!(
                 "DynamoDB list_blocks query failed for table {}: {:?}",
                 table_name,
                 e
             );
-            return Err(Box::new(e));
+            return Err(e.into());
         }
     };
 
     let mut blocks = Vec::new();
 
-    for item in result.items() {
-        if let Some(sk) = item.get("SK").and_then(|v| v.as_s().ok()) {
-            if let Some(block_id) = sk.strip_prefix("BLOCK#") {
-                let labels = match fetch_labels_for_block(client, table_name, block_id).await {
-                    Ok(l) => l,
-                    Err(e) => {
-                        tracing::error!("Failed to load labels for block {}: {:?}", block_id, e);
-                        vec![]
-                    }
-                };
-                
-                let block = Block {
-                    block_id: block_id.to_string(),
-                    block_name: item
-                        .get("block_name")
-                        .and_then(|v| v.as_s().ok())
-                        .map(|s| s.to_string())
-                        .unwrap_or_default(),
-                    block_type: item
-                        .get("block_type")
-                        .and_then(|v| v.as_s().ok())
-                        .map(|s| s.to_string())
-                        .unwrap_or_else(|| "annotation".to_string()),
-                    block_company: item
-                        .get("block_company")
-                        .and_then(|v| v.as_s().ok())
-                        .map(|s| s.to_string()),
-                    block_state: item
-                        .get("block_state")
-                        .and_then(|v| v.as_s().ok())
-                        .map(|s| s.to_string())
-                        .unwrap_or_default(),
-                    block_locked: item
-                        .get("block_locked")
-                        .and_then(|v| v.as_bool().ok())
-                        .copied()
-                        .unwrap_or(false),
-                    image_count: item
-                        .get("image_count")
-                        .and_then(|v| v.as_n().ok())
-                        .and_then(|n| n.parse().ok())
-                        .unwrap_or(0),
-                    approved_image_count: item
-                        .get("approved_image_count")
-                        .and_then(|v| v.as_n().ok())
-                        .and_then(|n| n.parse().ok())
-                        .unwrap_or(0),
-                    annotation_count: item
-                       .get("annotation_count")
-                        .and_then(|v| v.as_n().ok())
-                        .and_then(|n| n.parse().ok())
-                        .unwrap_or(0),
-                    block_created_at: item
-                        .get("block_created_at")
-                        .and_then(|v| v.as_s().ok())
-                        .map(|s| s.to_string())
-                        .unwrap_or_default(),
-                };
-                blocks.push(AnnotationBlock { block, labels });
+    for block in stored.items {
+        let labels = match fetch_labels_for_block(&store, &block.block_id).await {
+            Ok(l) => l,
+            Err(e) => {
+                tracing::error!("Failed to load labels for block {}: {:?}", block.block_id, e);
+                vec![]
             }
-        }
+        };
+        blocks.push(AnnotationBlock { block, labels });
     }
 
+    let body = if page.is_paged() {
+        serde_json::to_string(&Page { items: blocks, next_cursor: stored.next_cursor })?
+    } else {
+        serde_json::to_string(&blocks)?
+    };
+
     Ok(Response::builder()
         .status(StatusCode::OK)
         .header("Content-Type", "application/json")
         .header("Access-Control-Allow-Origin", "*")
-        .body(serde_json::to_string(&blocks)?.into())
+        .body(body.into())
         .map_err(Box::new)?)
 }
 
@@ -270,313 +152,385 @@ pub async fn update_block(
     body: &[u8],
 ) -> Result<Response<Body>, Error> {
     let req: UpdateBlockPayload = serde_json::from_slice(body)?;
-    let pk = "BLOCK".to_string();
-    let sk = format!("BLOCK#{}", block_id);
-
-    let mut update_expr = vec![];
-    let mut expr_names = std::collections::HashMap::new();
-    let mut expr_values = std::collections::HashMap::new();
-
-    if let Some(block_name) = req.block_name {
-        update_expr.push("#block_name = :block_name");
-        expr_names.insert("#block_name".to_string(), "block_name".to_string());
-        expr_values.insert(
-            ":block_name".to_string(),
-            aws_sdk_dynamodb::types::AttributeValue::S(block_name),
-        );
-    }
 
-    if let Some(block_state) = req.block_state {
-        update_expr.push("#block_state = :block_state");
-        expr_names.insert("#block_state".to_string(), "block_state".to_string());
-        expr_values.insert(
-            ":block_state".to_string(),
-            aws_sdk_dynamodb::types::AttributeValue::S(block_state),
-        );
-    }
-
-    if let Some(block_locked) = req.block_locked {
-        update_expr.push("#block_locked = :block_locked");
-        expr_names.insert("#block_locked".to_string(), "block_locked".to_string());
-        expr_values.insert(
-            ":block_locked".to_string(),
-            aws_sdk_dynamodb::types::AttributeValue::Bool(block_locked),
-        );
-    }
-
-    if !update_expr.is_empty() {
-        let mut builder = client
-            .update_item()
-            .table_name(table_name)
-            .key("PK", aws_sdk_dynamodb::types::AttributeValue::S(pk))
-            .key("SK", aws_sdk_dynamodb::types::AttributeValue::S(sk))
-            .update_expression(format!("SET {}", update_expr.join(", ")));
-
-        for (k, v) in expr_names {
-            builder = builder.expression_attribute_names(k, v);
-        }
-
-        for (k, v) in expr_values {
-            builder = builder.expression_attribute_values(k, v);
-        }
-
-        builder.send().await?;
-    }
+    DynamoStore::new(client, table_name)
+        .update_block(block_id, &req)
+        .await?;
 
     get_block(client, table_name, block_id).await
 }
 
-/// Delete a block and associated records (images, annotations, links)
+/// Move a block to the trash; its rows and S3 files stay until the purge
 pub async fn delete_block(
     client: &DynamoClient,
-    s3_client: &S3Client,
     table_name: &str,
+    user_id: &str,
     block_id: &str,
 ) -> Result<Response<Body>, Error> {
-   let block_pk = format!("BLOCK#{}", block_id);
-   let mut delete_keys:Vec<HashMap<String, AttributeValue>> = vec![]; //collection to delete
+    let store = DynamoStore::new(client, table_name);
 
-    // STEP 1: Delete tasks and their images
-    delete_tasks(&client, table_name, &block_pk, &mut delete_keys).await?;
+    if store.get_block(block_id).await?.is_none_or(|b| b.deleted_at.is_some()) {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": "Block not found"}).to_string().into())
+            .map_err(Box::new)?);
+    }
 
-    // STEP 2: Delete labels
-    delete_labels(&client, table_name, &block_pk, &mut delete_keys).await?;
+    let entity = TrashedEntity::Block { block_id: block_id.to_string() };
+    trash::trash(&store, entity, user_id, None).await?;
 
-    // STEP 3: Delete block images and their annotations
-    delete_block_images(&client, table_name, &block_pk, &mut delete_keys).await?;
+    Ok(Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .header("Access-Control-Allow-Origin", "*")
+        .body(Body::Empty)
+        .map_err(Box::new)?)
+}
 
-    // STEP 4: Delete the block itself
-    delete_block_record(&block_pk, block_id, &mut delete_keys);
+/// Clone a block into a new one
+/// With `scope: all` the image files are copied under the new block first,
+/// and deleted again if the clone fails
+pub async fn clone_block_handler(
+    client: &DynamoClient,
+    table_name: &str,
+    s3_client: &S3Client,
+    block_id: &str,
+    body: &[u8],
+) -> Result<Response<Body>, Error> {
+    let req: CloneBlockPayload = serde_json::from_slice(body)?;
+    let store = DynamoStore::new(client, table_name);
 
-    // STEP 5: Batch delete all records
-    batch_delete_items(&client, table_name, &delete_keys).await?;
+    if store.get_block(block_id).await?.is_none_or(|b| b.deleted_at.is_some()) {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": "Block not found"}).to_string().into())
+            .map_err(Box::new)?);
+    }
 
-    // STEP 6: Delete S3 files
-    delete_s3_prefix(s3_client, block_id).await.ok();
+    let new_block_id = uuid::Uuid::new_v4().to_string();
+    let cloned = async {
+        if req.scope == CloneScope::All {
+            copy_s3_prefix(s3_client, &image_prefix(block_id), &image_prefix(&new_block_id)).await?;
+        }
+        Ok::<_, Error>(clone_block(&store, block_id, &new_block_id, &req).await?)
+    }
+    .await;
+    let response = match cloned {
+        Ok(response) => response,
+        Err(e) => {
+            // Files copied before the failure would otherwise be orphaned
+            delete_s3_prefix(s3_client, &new_block_id).await?;
+            return Err(e);
+        }
+    };
 
     Ok(Response::builder()
-        .status(StatusCode::NO_CONTENT)
+        .status(StatusCode::CREATED)
+        .header("Content-Type", "application/json")
         .header("Access-Control-Allow-Origin", "*")
-        .body(Body::Empty)
+        .body(serde_json::to_string(&response)?.into())
         .map_err(Box::new)?)
 }
 
-// PRIVATE FUNCTIONS 
+/// Copy a block's live rows into a new block `new_block_id`: its labels,
+/// with `tasks` its task breakdown, with `all` its images and annotations.
+/// Copies get new IDs and references to them are remapped; counters are
+/// counted from the copies. Tasks keep their state only when their images
+/// come along, otherwise they start over as "todo". The block row is
+/// written last, so a clone that fails part way never shows up, and the
+/// rows it did write are deleted again.
+pub async fn clone_block<S: Store>(
+    store: &S,
+    block_id: &str,
+    new_block_id: &str,
+    req: &CloneBlockPayload,
+) -> Result<AnnotationBlock, String> {
+    let source = store
+        .get_block(block_id)
+        .await?
+        .filter(|b| b.deleted_at.is_none())
+        .ok_or("Block not found")?;
+    let new_id = || uuid::Uuid::new_v4().to_string();
+
+    let mut labels = fetch_labels_for_block(store, block_id).await?;
+    let label_ids: HashMap<String, String> = labels.iter().map(|l| (l.label_id.clone(), new_id())).collect();
+
+    let mut tasks = if req.scope >= CloneScope::Tasks { store.list_tasks(block_id).await? } else { vec![] };
+    let task_ids: HashMap<String, String> = tasks.iter().map(|t| (t.task_id.clone(), new_id())).collect();
+
+    let mut images = if req.scope == CloneScope::All { store.list_images(block_id).await? } else { vec![] };
+    let mut annotations = Vec::new();
+    for image in &images {
+        annotations.extend(store.list_annotations(&image.image_id).await?);
+    }
+    let image_ids: HashMap<String, String> = images.iter().map(|i| (i.image_id.clone(), new_id())).collect();
+
+    let mut label_counts: HashMap<String, u32> = HashMap::new();
+    let mut image_counts: HashMap<String, u32> = HashMap::new();
+    for annotation in &mut annotations {
+        annotation.annotation_id = new_id();
+        annotation.image_id = image_ids[&annotation.image_id].clone();
+        // An annotation whose label was deleted keeps pointing at nothing
+        if let Some(label_id) = label_ids.get(&annotation.label_id) {
+            annotation.label_id = label_id.clone();
+            *label_counts.entry(label_id.clone()).or_default() += 1;
+        }
+        annotation.version = 1;
+        *image_counts.entry(annotation.image_id.clone()).or_default() += 1;
+    }
 
-/// Delete all tasks for a block and their associated images
-async fn delete_tasks(
-    client: &DynamoClient,
-    table_name: &str,
-    block_pk: &str,
-    delete_keys: &mut Vec<HashMap<String, AttributeValue>>,
-) -> Result<(), Error> {
-    let tasks_result = client
-        .query()
-        .table_name(table_name)
-        .key_condition_expression("PK = :pk AND begins_with(SK, :sk_prefix)")
-        .expression_attribute_values(":pk", AttributeValue::S(block_pk.to_string()))
-        .expression_attribute_values(":sk_prefix", AttributeValue::S("TASK#".to_string()))
-        .send()
-        .await?;
+    let (old_prefix, new_prefix) = (image_prefix(block_id), image_prefix(new_block_id));
+    let mut task_counts: HashMap<String, u32> = HashMap::new();
+    for image in &mut images {
+        image.image_id = image_ids[&image.image_id].clone();
+        image.block_id = new_block_id.to_string();
+        image.url = image.url.replace(&old_prefix, &new_prefix);
+        image.locked = false;
+        image.task_id = image.task_id.as_ref().and_then(|t| task_ids.get(t)).cloned();
+        image.annotation_count = image_counts.get(&image.image_id).copied().unwrap_or(0);
+        if let Some(task_id) = &image.task_id {
+            *task_counts.entry(task_id.clone()).or_default() += 1;
+        }
+    }
 
-    let mut task_pks: Vec<String> = Vec::new();
-    for item in tasks_result.items() {
-        if let Some(sk) = item.get("SK").and_then(|v| v.as_s().ok()) {
-            if sk.starts_with("TASK#") {
-                task_pks.push(sk.to_string());
-                add_delete_key(delete_keys, block_pk, sk);
-            }
+    for task in &mut tasks {
+        task.task_id = task_ids[&task.task_id].clone();
+        task.block_id = new_block_id.to_string();
+        if req.scope != CloneScope::All {
+            task.task_state = "todo".to_string();
         }
+        task.locked = false;
+        task.image_count = task_counts.get(&task.task_id).copied().unwrap_or(0);
+        task.version = 1;
+        task.images = vec![];
     }
 
-    for task_pk in &task_pks {
-        delete_task_images(client, table_name, task_pk, delete_keys).await?;
-        add_delete_key(delete_keys, task_pk, task_pk);
+    for label in &mut labels {
+        label.label_id = label_ids[&label.label_id].clone();
+        label.block_id = new_block_id.to_string();
+        label.label_count = label_counts.get(&label.label_id).copied().unwrap_or(0);
+        label.version = 1;
     }
 
-    Ok(())
+    let done: Vec<&str> = tasks.iter().filter(|t| t.task_state == "done").map(|t| t.task_id.as_str()).collect();
+    let block = Block {
+        block_id: new_block_id.to_string(),
+        block_name: req.block_name.clone().unwrap_or_else(|| format!("{} (copy)", source.block_name)),
+        block_type: source.block_type,
+        block_company: source.block_company,
+        block_state: "draft".to_string(),
+        block_locked: false,
+        image_count: images.len() as u32,
+        approved_image_count: images.iter().filter(|i| i.task_id.as_deref().is_some_and(|t| done.contains(&t))).count()
+            as u32,
+        annotation_count: annotations.len() as u32,
+        block_created_at: chrono::Utc::now().to_rfc3339(),
+        deleted_at: None,
+        trash_id: None,
+    };
+
+    let mut ops: Vec<WriteOp> = labels.iter().cloned().map(WriteOp::PutLabel).collect();
+    ops.extend(tasks.into_iter().map(WriteOp::PutTask));
+    for image in images {
+        if let Some(task_id) = &image.task_id {
+            ops.push(WriteOp::PutTaskImage {
+                block_id: new_block_id.to_string(),
+                task_id: task_id.clone(),
+                image_id: image.image_id.clone(),
+            });
+        }
+        ops.push(WriteOp::PutImage(image));
+    }
+    ops.extend(annotations.into_iter().map(WriteOp::PutAnnotation));
+    if let Err(e) = write_clone(store, ops, &block).await {
+        discard_clone(store, new_block_id).await?;
+        return Err(e);
+    }
+
+    Ok(AnnotationBlock { block, labels })
 }
 
-/// Delete all images for a task
-async fn delete_task_images(
-    client: &DynamoClient,
-    table_name: &str,
-    task_pk: &str,
-    delete_keys: &mut Vec<HashMap<String, AttributeValue>>,
+/// Write a clone's rows in chunks of one transaction each, then its block
+async fn write_clone<S: Store>(store: &S, mut ops: Vec<WriteOp>, block: &Block) -> Result<(), String> {
+    while !ops.is_empty() {
+        let rest = ops.split_off(ops.len().min(MAX_TRANSACT_OPS));
+        store.transact(std::mem::replace(&mut ops, rest)).await?;
+    }
+    store.put_block(block).await
+}
+
+/// Delete whatever a failed clone wrote under `new_block_id`
+async fn discard_clone<S: Store>(store: &S, new_block_id: &str) -> Result<(), String> {
+    let mut delete_keys = vec![];
+    delete_tasks(store, new_block_id, &mut delete_keys).await?;
+    delete_labels(store, new_block_id, &mut delete_keys).await?;
+    delete_block_images(store, new_block_id, &mut delete_keys).await?;
+    delete_keys.push(EntityKey::block(new_block_id));
+    store.delete_batch(&delete_keys).await
+}
+
+/// Hard-delete a block and associated records (images, annotations, links)
+/// and its S3 files. Children trashed on their own are purged with their
+/// own trash entries.
+pub(crate) async fn purge_block<S: Store>(
+    store: &S,
+    s3_client: &S3Client,
+    block_id: &str,
 ) -> Result<(), Error> {
-    let task_images_result = client
-        .query()
-        .table_name(table_name)
-        .key_condition_expression("PK = :pk AND begins_with(SK, :sk_prefix)")
-        .expression_attribute_values(":pk", AttributeValue::S(task_pk.to_string()))
-        .expression_attribute_values(":sk_prefix", AttributeValue::S("IMAGE#".to_string()))
-        .send()
-        .await?;
+    let mut delete_keys: Vec<EntityKey> = vec![]; //collection to delete
 
-    for item in task_images_result.items() {
-        if let Some(sk) = item.get("SK").and_then(|v| v.as_s().ok()) {
-            add_delete_key(delete_keys, task_pk, sk);
-        }
+    // STEP 1: Delete tasks
+    delete_tasks(store, block_id, &mut delete_keys).await?;
+
+    // STEP 2: Delete labels
+    delete_labels(store, block_id, &mut delete_keys).await?;
+
+    // STEP 3: Delete block images and their annotations
+    delete_block_images(store, block_id, &mut delete_keys).await?;
+
+    // STEP 4: Delete snapshot index rows; the archives go with the S3 files
+    for snapshot in store.list_snapshots(block_id).await? {
+        delete_keys.push(EntityKey::snapshot(block_id, snapshot.version));
     }
 
+    // STEP 5: Batch delete all records
+    store.delete_batch(&delete_keys).await?;
+
+    // STEP 6: Delete S3 files
+    delete_s3_prefix(s3_client, block_id).await?;
+
+    // STEP 7: Delete the block itself, only once the rest is gone, so a
+    // failed purge still finds the block and runs again
+    store.delete_batch(&[EntityKey::Block { block_id: block_id.to_string() }]).await?;
+
     Ok(())
 }
 
-/// Delete all labels for a block
-async fn delete_labels(
-    client: &DynamoClient,
-    table_name: &str,
-    block_pk: &str,
-    delete_keys: &mut Vec<HashMap<String, AttributeValue>>,
-) -> Result<(), Error> {
-    let labels_result = client
-        .query()
-        .table_name(table_name)
-        .key_condition_expression("PK = :pk AND begins_with(SK, :sk_prefix)")
-        .expression_attribute_values(":pk", AttributeValue::S(block_pk.to_string()))
-        .expression_attribute_values(":sk_prefix", AttributeValue::S("LABEL#".to_string()))
-        .send()
-        .await?;
+// PRIVATE FUNCTIONS 
 
-    for item in labels_result.items() {
-        if let Some(sk) = item.get("SK").and_then(|v| v.as_s().ok()) {
-            add_delete_key(delete_keys, block_pk, sk);
-        }
+/// Collect all tasks for a block and their image index rows
+async fn delete_tasks<S: Store>(
+    store: &S,
+    block_id: &str,
+    delete_keys: &mut Vec<EntityKey>,
+) -> Result<(), String> {
+    for task in store.list_tasks(block_id).await? {
+        delete_task_images(store, &task.task_id, delete_keys).await?;
+        delete_keys.push(EntityKey::Task {
+            block_id: block_id.to_string(),
+            task_id: task.task_id,
+        });
     }
 
     Ok(())
 }
 
-// Delete all images for a block and their annotations
-async fn delete_block_images(
-    client: &DynamoClient,
-    table_name: &str,
-    block_pk: &str,
-    delete_keys: &mut Vec<HashMap<String, AttributeValue>>,
-) -> Result<(), Error> {
-    let images_result = client
-        .query()
-        .table_name(table_name)
-        .key_condition_expression("PK = :pk AND begins_with(SK, :sk_prefix)")
-        .expression_attribute_values(":pk", AttributeValue::S(block_pk.to_string()))
-        .expression_attribute_values(":sk_prefix", AttributeValue::S("IMAGE#".to_string()))
-        .send()
-        .await?;
-
-    let mut image_pks: Vec<String> = Vec::new();
-    for item in images_result.items() {
-        if let Some(sk) = item.get("SK").and_then(|v| v.as_s().ok()) {
-            if sk.starts_with("IMAGE#") {
-                image_pks.push(sk.to_string());
-                add_delete_key(delete_keys, block_pk, sk);
-            }
-        }
+/// Collect the index rows linking a task to its images
+async fn delete_task_images<S: Store>(
+    store: &S,
+    task_id: &str,
+    delete_keys: &mut Vec<EntityKey>,
+) -> Result<(), String> {
+    for image_id in store.list_task_image_ids(task_id).await? {
+        delete_keys.push(EntityKey::task_image(task_id, &image_id));
     }
 
-    for image_pk in &image_pks {
-        delete_annotations(client, table_name, image_pk, delete_keys).await?;
-        add_delete_key(delete_keys, image_pk, image_pk);
+    Ok(())
+}
+
+/// Collect all labels for a block
+async fn delete_labels<S: Store>(
+    store: &S,
+    block_id: &str,
+    delete_keys: &mut Vec<EntityKey>,
+) -> Result<(), String> {
+    for label in store.list_labels(block_id).await? {
+        delete_keys.push(EntityKey::Label {
+            block_id: block_id.to_string(),
+            label_id: label.label_id,
+        });
     }
 
     Ok(())
 }
 
-/// Delete all annotations for an image
-async fn delete_annotations(
-    client:&DynamoClient,
-    table_name:&str,
-    image_pk:&str,
-    delete_keys: &mut Vec<HashMap<String, AttributeValue>>,
-    )->Result<(),Error>{
-    let annotations_result = client
-            .query()
-            .table_name(table_name)
-            .key_condition_expression("PK = :pk AND begins_with(SK, :sk_prefix)")
-            .expression_attribute_values(":pk", AttributeValue::S(image_pk.to_string()))
-            .expression_attribute_values(":sk_prefix", AttributeValue::S("ANNOTATION#".to_string()))
-            .send()
-            .await?;
+/// Collect all images for a block and their annotations
+async fn delete_block_images<S: Store>(
+    store: &S,
+    block_id: &str,
+    delete_keys: &mut Vec<EntityKey>,
+) -> Result<(), String> {
+    for image in store.list_images(block_id).await? {
+        delete_annotations(store, &image.image_id, delete_keys).await?;
+        delete_keys.push(EntityKey::Image {
+            block_id: block_id.to_string(),
+            image_id: image.image_id,
+        });
+    }
 
-    for item in annotations_result.items() {
-        if let Some(sk) = item.get("SK").and_then(|v| v.as_s().ok()) {
-            add_delete_key(delete_keys, image_pk, sk);
-        }
+    Ok(())
+}
+
+/// Collect all annotations for an image
+pub(crate) async fn delete_annotations<S: Store>(
+    store: &S,
+    image_id: &str,
+    delete_keys: &mut Vec<EntityKey>,
+) -> Result<(), String> {
+    for annotation in store.list_annotations(image_id).await? {
+        delete_keys.push(EntityKey::Annotation {
+            image_id: image_id.to_string(),
+            annotation_id: annotation.annotation_id,
+        });
     }
 
     Ok(())
 }
 
-/// Add the block self-record to delete keys
-fn delete_block_record(
-    _block_pk: &str,
-    block_id: &str,
-    delete_keys: &mut Vec<HashMap<String, AttributeValue>>,
-) {
-    let mut key = HashMap::new();
-    key.insert("PK".to_string(), AttributeValue::S("BLOCK".to_string()));
-    key.insert("SK".to_string(), AttributeValue::S(format!("BLOCK#{}", block_id)));
-    delete_keys.push(key);
+/// Where a block's uploaded image files live
+fn image_prefix(block_id: &str) -> String {
+    format!("annotations/blocks/{}/images/", block_id)
 }
 
-/// Batch delete items from DynamoDB (25 items per request with retry logic)
-async fn batch_delete_items(
-    client: &DynamoClient,
-    table_name: &str,
-    delete_keys: &[HashMap<String, AttributeValue>],
-) -> Result<(), Error> {
-    for chunk in delete_keys.chunks(25) {
-        let write_reqs: Vec<_> = chunk
-            .iter()
-            .map(|k| {
-                WriteRequest::builder()
-                    .delete_request(
-                        DeleteRequest::builder()
-                            .set_key(Some(k.clone()))
-                            .build()
-                            .unwrap(),
-                    )
-                    .build()
-            })
-            .collect();
+/// S3 helper: copy every object under `from` to the same path under `to`
+async fn copy_s3_prefix(s3_client: &S3Client, from: &str, to: &str) -> Result<(), Error> {
+    let bucket_name = std::env::var("S3_BUCKET_NAME").unwrap_or_else(|_| "doxle-app".to_string());
 
-        let mut unprocessed = Some(write_reqs);
-        let mut attempts = 0;
-        while let Some(reqs) = unprocessed {
-            attempts += 1;
-            let result = client
-                .batch_write_item()
-                .request_items(table_name, reqs)
-                .send()
-                .await?;
+    let mut continuation: Option<String> = None;
+    loop {
+        let mut req = s3_client.list_objects_v2().bucket(&bucket_name).prefix(from);
+        if let Some(token) = continuation.as_ref() {
+            req = req.continuation_token(token);
+        }
+        let resp = req.send().await.map_err(|e| {
+            tracing::error!("S3 list_objects_v2 failed for prefix {}: {}", from, e);
+            format!("S3 list failed: {}", e)
+        })?;
 
-            unprocessed = result
-                .unprocessed_items()
-                .and_then(|m| m.get(table_name))
-                .map(|v| v.clone());
+        for key in resp.contents().iter().filter_map(|o| o.key()) {
+            let target = format!("{}{}", to, &key[from.len()..]);
+            s3_client
+                .copy_object()
+                .bucket(&bucket_name)
+                .copy_source(format!("{}/{}", bucket_name, key))
+                .key(&target)
+                .send()
+                .await
+                .map_err(|e| format!("S3 copy of {} failed: {}", key, e))?;
+        }
 
-            if unprocessed.is_some() && attempts < 5 {
-                sleep(Duration::from_millis(100 * attempts)).await;
-            } else {
-                break;
-            }
+        if resp.is_truncated().unwrap_or(false) {
+            continuation = resp.next_continuation_token().map(|s| s.to_string());
+        } else {
+            break;
         }
     }
-
     Ok(())
 }
 
-/// Helper to add a delete key to the list
-fn add_delete_key(
-    delete_keys: &mut Vec<HashMap<String, AttributeValue>>,
-    pk: &str,
-    sk: &str,
-) {
-    let mut key = HashMap::new();
-    key.insert("PK".to_string(), AttributeValue::S(pk.to_string()));
-    key.insert("SK".to_string(), AttributeValue::S(sk.to_string()));
-    delete_keys.push(key);
-}
-
 /// S3 helper: del everything under block/{block_id}/
 async fn delete_s3_prefix(
     s3_client: &S3Client,
@@ -0,0 +1,498 @@
+use std::collections::HashSet;
+
+use aws_sdk_dynamodb::Client as DynamoClient;
+use doxle_atoms::blocks::model::Block;
+use doxle_atoms::drawing::geometry;
+use doxle_atoms::drawing::model::{Annotation, Point};
+use doxle_atoms::labels::model::Label;
+use doxle_atoms::media::model::Image;
+use doxle_atoms::store::{DynamoStore, Store};
+use lambda_http::{http::StatusCode, Body, Error, Response};
+use serde::Serialize;
+
+use crate::labels::fetch_labels_for_block;
+
+/// Prefix of the error for images that can't be exported without their size
+pub const MISSING_DIMENSIONS: &str = "Images have no width and height";
+
+/// Training formats a block can be exported to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Coco,
+    Yolo(YoloMode),
+}
+
+/// Which YOLO label rows to write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YoloMode {
+    /// `class cx cy w h`
+    Detect,
+    /// `class x1 y1 x2 y2 ...`
+    Segment,
+}
+
+impl ExportFormat {
+    /// Read `?format=coco|yolo`, and `?mode=detect|segment` for YOLO
+    pub fn from_query(format: Option<&str>, mode: Option<&str>) -> Result<Self, String> {
+        match format {
+            Some("coco") => Ok(ExportFormat::Coco),
+            Some("yolo") => match mode {
+                None | Some("detect") => Ok(ExportFormat::Yolo(YoloMode::Detect)),
+                Some("segment") => Ok(ExportFormat::Yolo(YoloMode::Segment)),
+                Some(other) => Err(format!("Unknown YOLO mode: {}", other)),
+            },
+            Some(other) => Err(format!("Unknown export format: {}", other)),
+            None => Err("Missing format query parameter".to_string()),
+        }
+    }
+}
+
+/// A block's labels, images and their annotations, as an export sees them
+#[derive(Debug)]
+pub struct ExportSource {
+    pub block: Block,
+    /// In the block's label order
+    pub labels: Vec<Label>,
+    pub images: Vec<(Image, Vec<Annotation>)>,
+}
+
+/// Read what an export needs from a live block; `approved_only` keeps
+/// images whose task is done. Every image kept must know its width and
+/// height.
+pub async fn load_export<S: Store>(store: &S, block_id: &str, approved_only: bool) -> Result<ExportSource, String> {
+    let block = store
+        .get_block(block_id)
+        .await?
+        .filter(|b| b.deleted_at.is_none())
+        .ok_or("Block not found")?;
+    let labels = fetch_labels_for_block(store, block_id).await?;
+    let mut images = doxle_atoms::media::service::load_images_for_block(store, block_id).await?;
+    if approved_only {
+        let done: HashSet<String> = store
+            .list_tasks(block_id)
+            .await?
+            .into_iter()
+            .filter(|task| task.task_state == "done")
+            .map(|task| task.task_id)
+            .collect();
+        images.retain(|image| image.task_id.as_ref().is_some_and(|task_id| done.contains(task_id)));
+    }
+
+    let missing: Vec<&str> = images
+        .iter()
+        .filter(|image| image.width.is_none() || image.height.is_none())
+        .map(|image| image.image_id.as_str())
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!("{}: {}", MISSING_DIMENSIONS, missing.join(", ")));
+    }
+
+    let mut with_annotations = Vec::new();
+    for image in images {
+        let annotations = store.list_annotations(&image.image_id).await?;
+        with_annotations.push((image, annotations));
+    }
+    Ok(ExportSource { block, labels, images: with_annotations })
+}
+
+/// Name of the image file, from the last part of its URL
+fn file_name(image: &Image) -> String {
+    let path = image.url.split(['?', '#']).next().unwrap_or_default();
+    path.rsplit('/').next().filter(|name| !name.is_empty()).unwrap_or(&image.image_id).to_string()
+}
+
+// ========== COCO ==========
+
+#[derive(Debug, Serialize)]
+pub struct CocoDataset {
+    pub info: CocoInfo,
+    pub licenses: Vec<serde_json::Value>,
+    pub images: Vec<CocoImage>,
+    pub annotations: Vec<CocoAnnotation>,
+    pub categories: Vec<CocoCategory>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CocoInfo {
+    pub description: String,
+    pub version: String,
+    pub date_created: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CocoImage {
+    pub id: u64,
+    pub file_name: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CocoAnnotation {
+    pub id: u64,
+    pub image_id: u64,
+    pub category_id: u64,
+    /// `[x, y, width, height]`
+    pub bbox: [f64; 4],
+    /// One flat `[x1, y1, x2, y2, ...]` outline per polygon
+    pub segmentation: Vec<Vec<f64>>,
+    pub area: f64,
+    pub iscrowd: u8,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CocoCategory {
+    pub id: u64,
+    pub name: String,
+    pub supercategory: String,
+}
+
+/// COCO detection and segmentation dataset. Ids are numbered from 1 in
+/// label and image order. Lines and points have no area and are left
+/// out; COCO polygons can't hold holes, so outlines are exported without
+/// them while `area` still subtracts them.
+pub fn to_coco(source: &ExportSource) -> CocoDataset {
+    let categories: Vec<CocoCategory> = source
+        .labels
+        .iter()
+        .enumerate()
+        .map(|(i, label)| CocoCategory {
+            id: i as u64 + 1,
+            name: label.label_name.clone(),
+            supercategory: source.block.block_type.clone(),
+        })
+        .collect();
+
+    let mut images = Vec::new();
+    let mut annotations = Vec::new();
+    for (n, (image, image_annotations)) in source.images.iter().enumerate() {
+        let image_id = n as u64 + 1;
+        images.push(CocoImage {
+            id: image_id,
+            file_name: file_name(image),
+            width: image.width.unwrap_or_default(),
+            height: image.height.unwrap_or_default(),
+        });
+        for annotation in image_annotations {
+            let Some(category) = source.labels.iter().position(|l| l.label_id == annotation.label_id) else {
+                continue;
+            };
+            let polygons = geometry::polygons(&annotation.geometry);
+            if polygons.is_empty() {
+                continue;
+            }
+            let bbox = geometry::bounding_box(&annotation.geometry);
+            annotations.push(CocoAnnotation {
+                id: annotations.len() as u64 + 1,
+                image_id,
+                category_id: category as u64 + 1,
+                bbox: [bbox.min.x, bbox.min.y, bbox.max.x - bbox.min.x, bbox.max.y - bbox.min.y],
+                segmentation: polygons.iter().map(|p| flatten(&p.points)).collect(),
+                area: geometry::area(&annotation.geometry),
+                iscrowd: 0,
+            });
+        }
+    }
+
+    CocoDataset {
+        info: CocoInfo {
+            description: source.block.block_name.clone(),
+            version: "1.0".to_string(),
+            date_created: chrono::Utc::now().to_rfc3339(),
+        },
+        licenses: vec![],
+        images,
+        annotations,
+        categories,
+    }
+}
+
+fn flatten(points: &[Point]) -> Vec<f64> {
+    points.iter().flat_map(|p| [p.x, p.y]).collect()
+}
+
+// ========== YOLO ==========
+
+/// Files of a YOLO dataset: `data.yaml`, one `labels/{image_id}.txt` per
+/// image and `manifest.csv` saying where to download each image to under
+/// `images/`. Classes are numbered from 0 in label order; coordinates are
+/// fractions of the image size. Lines and points are left out, as are
+/// polygon holes.
+pub fn to_yolo(source: &ExportSource, mode: YoloMode) -> Vec<(String, Vec<u8>)> {
+    let mut data = String::from("path: .\ntrain: images\nval: images\n");
+    data.push_str(&format!("nc: {}\nnames:\n", source.labels.len()));
+    for (i, label) in source.labels.iter().enumerate() {
+        // A JSON string is a valid quoted YAML scalar
+        data.push_str(&format!("  {}: {}\n", i, serde_json::Value::from(label.label_name.as_str())));
+    }
+
+    let mut manifest = String::from("file_name,url,width,height\n");
+    let mut files = vec![("data.yaml".to_string(), data.into_bytes())];
+    for (image, annotations) in &source.images {
+        let width = f64::from(image.width.unwrap_or(1));
+        let height = f64::from(image.height.unwrap_or(1));
+        let extension = file_name(image).rsplit_once('.').map(|(_, ext)| format!(".{}", ext)).unwrap_or_default();
+        manifest.push_str(&format!(
+            "images/{}{},{},{},{}\n",
+            image.image_id,
+            extension,
+            csv_field(&image.url),
+            width,
+            height
+        ));
+
+        let mut rows = String::new();
+        for annotation in annotations {
+            let Some(class) = source.labels.iter().position(|l| l.label_id == annotation.label_id) else {
+                continue;
+            };
+            let polygons = geometry::polygons(&annotation.geometry);
+            if polygons.is_empty() {
+                continue;
+            }
+            match mode {
+                YoloMode::Detect => {
+                    let bbox = geometry::bounding_box(&annotation.geometry);
+                    rows.push_str(&format!(
+                        "{} {:.6} {:.6} {:.6} {:.6}\n",
+                        class,
+                        (bbox.min.x + bbox.max.x) / 2.0 / width,
+                        (bbox.min.y + bbox.max.y) / 2.0 / height,
+                        (bbox.max.x - bbox.min.x) / width,
+                        (bbox.max.y - bbox.min.y) / height
+                    ));
+                }
+                YoloMode::Segment => {
+                    for polygon in polygons {
+                        let points = polygon.points.iter().map(|p| format!("{:.6} {:.6}", p.x / width, p.y / height));
+                        rows.push_str(&format!("{} {}\n", class, points.collect::<Vec<_>>().join(" ")));
+                    }
+                }
+            }
+        }
+        files.push((format!("labels/{}.txt", image.image_id), rows.into_bytes()));
+    }
+    files.push(("manifest.csv".to_string(), manifest.into_bytes()));
+    files
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Pack `files` into a zip archive, stored without compression
+pub fn zip(files: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let now = chrono::Utc::now();
+    let (time, date) = {
+        use chrono::{Datelike, Timelike};
+        let time = ((now.hour() << 11) | (now.minute() << 5) | (now.second() / 2)) as u16;
+        let date = (((now.year() - 1980).max(0) << 9) | (now.month() << 5) as i32 | now.day() as i32) as u16;
+        (time, date)
+    };
+
+    let mut out = Vec::new();
+    let mut directory = Vec::new();
+    for (name, data) in files {
+        let offset = out.len() as u32;
+        let crc = crc32fast::hash(data);
+        // Version 2.0, no flags, stored
+        let mut header = Vec::new();
+        for field in [20u16, 0, 0, time, date] {
+            header.extend_from_slice(&field.to_le_bytes());
+        }
+        for field in [crc, data.len() as u32, data.len() as u32] {
+            header.extend_from_slice(&field.to_le_bytes());
+        }
+        header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes());
+
+        out.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        out.extend_from_slice(&header);
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(data);
+
+        directory.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        directory.extend_from_slice(&20u16.to_le_bytes());
+        directory.extend_from_slice(&header);
+        // No comment, disk 0, no attributes
+        for field in [0u16, 0, 0] {
+            directory.extend_from_slice(&field.to_le_bytes());
+        }
+        directory.extend_from_slice(&0u32.to_le_bytes());
+        directory.extend_from_slice(&offset.to_le_bytes());
+        directory.extend_from_slice(name.as_bytes());
+    }
+
+    let directory_offset = out.len() as u32;
+    out.extend_from_slice(&directory);
+    out.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    for field in [0u16, 0, files.len() as u16, files.len() as u16] {
+        out.extend_from_slice(&field.to_le_bytes());
+    }
+    out.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+    out.extend_from_slice(&directory_offset.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes());
+    out
+}
+
+/// GET /blocks/{bid}/export?format=coco|yolo[&mode=detect|segment][&approved=true]
+pub async fn export_block_handler(
+    client: &DynamoClient,
+    table_name: &str,
+    block_id: &str,
+    format: ExportFormat,
+    approved_only: bool,
+) -> Result<Response<Body>, Error> {
+    let store = DynamoStore::new(client, table_name);
+    let source = match load_export(&store, block_id, approved_only).await {
+        Ok(source) => source,
+        Err(e) => return error_response(&e),
+    };
+
+    match format {
+        ExportFormat::Coco => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .header("Content-Disposition", format!("attachment; filename=\"{}-coco.json\"", block_id))
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::to_string(&to_coco(&source))?.into())
+            .map_err(Box::new)?),
+        ExportFormat::Yolo(mode) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/zip")
+            .header("Content-Disposition", format!("attachment; filename=\"{}-yolo.zip\"", block_id))
+            .header("Access-Control-Allow-Origin", "*")
+            .body(Body::Binary(zip(&to_yolo(&source, mode))))
+            .map_err(Box::new)?),
+    }
+}
+
+fn error_response(e: &str) -> Result<Response<Body>, Error> {
+    let status = if e == "Block not found" {
+        StatusCode::NOT_FOUND
+    } else if e.starts_with(MISSING_DIMENSIONS) {
+        StatusCode::UNPROCESSABLE_ENTITY
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    Ok(Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .header("Access-Control-Allow-Origin", "*")
+        .body(serde_json::json!({"error": e}).to_string().into())
+        .map_err(Box::new)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use doxle_atoms::drawing::model::{CreateAnnotationPayload, Geometry};
+    use doxle_atoms::media::model::CreateImagePayload;
+    use doxle_atoms::store::{BlockRepo, LabelRepo, MemoryStore};
+    use doxle_atoms::tasks::model::{CreateTaskPayload, UpdateTaskPayload};
+    use doxle_atoms::fixtures::{test_annotation, test_block, test_label};
+
+    #[tokio::test]
+    async fn test_coco_export_of_approved_images() {
+        let store = MemoryStore::new();
+        let block = Block { block_name: "Level 1".to_string(), ..test_block("b1") };
+        store.put_block(&block).await.unwrap();
+        store.put_label(&test_label("b1", "l1", "doors")).await.unwrap();
+
+        let task = CreateTaskPayload { task_name: "Plan".to_string(), assignee: None, checked_by: None };
+        let task = doxle_atoms::tasks::service::create_task(&store, "b1", task).await.unwrap();
+        // The image outside a done task has no size, but isn't exported
+        for (task_id, size) in [(Some(task.task_id.clone()), Some(200)), (None, None)] {
+            let payload = CreateImagePayload {
+                url: "https://cdn.example.com/plans/ground.png?v=2".to_string(),
+                task_id,
+                order: None,
+                width: size,
+                height: size.map(|w| w / 2),
+            };
+            let image = doxle_atoms::media::service::create_image(&store, "b1", payload).await.unwrap();
+            let annotation = CreateAnnotationPayload {
+                label_id: "l1".to_string(),
+                geometry: Geometry::BBox { start: Point { x: 10.0, y: 20.0 }, end: Point { x: 30.0, y: 60.0 } },
+                attributes: Default::default(),
+                cleanup: None,
+            };
+            doxle_atoms::drawing::service::create_annotation(&store, "b1", &image.image_id, "u1", annotation)
+                .await
+                .unwrap();
+        }
+        assert!(load_export(&store, "b1", false).await.unwrap_err().starts_with(MISSING_DIMENSIONS));
+        assert!(load_export(&store, "b1", true).await.unwrap().images.is_empty());
+
+        let done = UpdateTaskPayload {
+            task_name: None,
+            task_state: Some("done".to_string()),
+            assignee: None,
+            checked_by: None,
+        };
+        doxle_atoms::tasks::service::update_task(&store, "b1", &task.task_id, done, None).await.unwrap();
+        let coco = to_coco(&load_export(&store, "b1", true).await.unwrap());
+        assert_eq!(coco.images.len(), 1);
+        assert_eq!((coco.images[0].file_name.as_str(), coco.images[0].width), ("ground.png", 200));
+        assert_eq!(coco.categories[0].name, "doors");
+        let annotation = &coco.annotations[0];
+        assert_eq!(annotation.bbox, [10.0, 20.0, 20.0, 40.0]);
+        assert_eq!(annotation.area, 800.0);
+        assert_eq!(annotation.segmentation, vec![vec![10.0, 20.0, 30.0, 20.0, 30.0, 60.0, 10.0, 60.0]]);
+
+        let entity = doxle_atoms::trash::TrashedEntity::Block { block_id: "b1".to_string() };
+        doxle_atoms::trash::service::trash(&store, entity, "u1", None).await.unwrap();
+        assert_eq!(load_export(&store, "b1", true).await.unwrap_err(), "Block not found");
+    }
+
+    #[test]
+    fn test_yolo_rows_are_normalised_to_the_image() {
+        let p = |x, y| Point { x, y };
+        let annotation = |geometry| test_annotation("i1", "a1", "l2", geometry);
+        let label = |id: &str, name: &str| test_label("b1", id, name);
+        let image = Image {
+            image_id: "i1".to_string(),
+            block_id: "b1".to_string(),
+            task_id: None,
+            url: "https://cdn.example.com/a.jpg".to_string(),
+            locked: false,
+            order: None,
+            width: Some(200),
+            height: Some(100),
+            scale: None,
+            annotation_count: 1,
+            uploaded_at: "2024-01-01T00:00:00Z".to_string(),
+            deleted_at: None,
+            trash_id: None,
+        };
+        let source = ExportSource {
+            block: Block {
+                block_name: "Level 1".to_string(),
+                image_count: 1,
+                annotation_count: 1,
+                ..test_block("b1")
+            },
+            labels: vec![label("l1", "doors"), label("l2", "rooms: wet")],
+            images: vec![(image, vec![
+                annotation(Geometry::Polygon { points: vec![p(0.0, 0.0), p(100.0, 0.0), p(50.0, 50.0)], holes: vec![] }),
+                annotation(Geometry::Polyline { points: vec![p(0.0, 0.0), p(10.0, 10.0)] }),
+            ])],
+        };
+
+        let detect = to_yolo(&source, YoloMode::Detect);
+        let names: Vec<&str> = detect.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["data.yaml", "labels/i1.txt", "manifest.csv"]);
+        let text = |files: &[(String, Vec<u8>)], i: usize| String::from_utf8(files[i].1.clone()).unwrap();
+        assert!(text(&detect, 0).ends_with("nc: 2\nnames:\n  0: \"doors\"\n  1: \"rooms: wet\"\n"));
+        assert_eq!(text(&detect, 1), "1 0.250000 0.250000 0.500000 0.500000\n");
+        assert!(text(&detect, 2).contains("images/i1.jpg,https://cdn.example.com/a.jpg,200,100"));
+
+        let segment = to_yolo(&source, YoloMode::Segment);
+        assert_eq!(text(&segment, 1), "1 0.000000 0.000000 0.500000 0.000000 0.250000 0.500000\n");
+        assert!(zip(&segment).starts_with(b"PK\x03\x04"));
+    }
+}
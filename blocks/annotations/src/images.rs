@@ -1,5 +1,6 @@
 use aws_sdk_dynamodb::{Client as DynamoClient} ;
 use doxle_atoms::media;
+use doxle_atoms::store::{DynamoStore, PageRequest, INVALID_CURSOR};
 use lambda_http::{Body, Error, Response, http::StatusCode};
 use serde::Deserialize;
 
@@ -41,9 +42,9 @@ pub async fn create_image_for_task_handler(
     );
 
     // Step B: call shared media atom
+    let store = DynamoStore::new(client, table_name);
     let result = media::create_image_for_task(
-    		client,
-    		table_name,
+    		&store,
     		block_id,
     		task_id,
     		req.url,
@@ -91,19 +92,40 @@ pub async fn create_image_for_task_handler(
 
 
 // HTTP handler: GET /blocks/{block_id}/tasks/{task_id}/images
+// Returns a bare array, or a `Page` when `?limit=`/`?cursor=` is given
 pub async fn list_images_for_task_handler(
     client: &DynamoClient,
     table_name: &str,
     block_id: &str,
     task_id: &str,
+    page: &PageRequest,
 ) -> Result<Response<Body>, Error> {
-    match media::load_images_for_task(client, table_name, block_id, task_id).await {
-        Ok(images) => {
+    let store = DynamoStore::new(client, table_name);
+    let body = if page.is_paged() {
+        media::load_images_for_task_page(&store, block_id, task_id, page)
+            .await
+            .and_then(|p| serde_json::to_string(&p).map_err(|e| e.to_string()))
+    } else {
+        media::load_images_for_task(&store, block_id, task_id)
+            .await
+            .and_then(|images| serde_json::to_string(&images).map_err(|e| e.to_string()))
+    };
+
+    match body {
+        Ok(body) => {
             Ok(Response::builder()
                 .status(StatusCode::OK)
                 .header("Content-Type", "application/json")
                 .header("Access-Control-Allow-Origin", "*")
-                .body(serde_json::to_string(&images)?.into())
+                .body(body.into())
+                .map_err(Box::new)?)
+        }
+        Err(e) if e == INVALID_CURSOR => {
+            Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .header("Content-Type", "application/json")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(serde_json::json!({ "error": e }).to_string().into())
                 .map_err(Box::new)?)
         }
         Err(e) => {
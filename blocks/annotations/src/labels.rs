@@ -1,76 +1,37 @@
 use lambda_http::{Body, Error, Response, http::StatusCode};
 use aws_sdk_dynamodb::Client as DynamoClient;
+use crate::templates::{sort_labels, template_order};
 use crate::types::{Label, CreateLabelPayload, UpdateLabelPayload};
-use aws_sdk_dynamodb::types::AttributeValue;
-use std::collections::HashMap;
-
-const FLOOR_PLAN_ORDER: [&str; 20] = [
-    "fp-outside",
-    "fp-inside",
-    "ewalls",
-    "windows",
-    "iwalls",
-    "doors",
-    "cav-slider",
-    "stairs",
-    "robes",
-    "toilet",
-    "vanity",
-    "shower",
-    "bathtub",
-    "sink",
-    "outbuilding",
-    "scale",
-    "dims",
-    "area",
-    "title",
-    "legend",
-];
-
-const ELEVATION_ORDER: [&str; 15] = [
-    "gf-wall",
-    "gf-window",
-    "gf-roof",
-    "ff-wall",
-    "ff-window",
-    "ff-roof",
-    "sf-wall",
-    "sf-window",
-    "sf-roof",
-    "skylight",
-    "fence",
-    "dims",
-    "area",
-    "title",
-    "legend",
-];
-
-const ELECTRICAL_PLAN_ORDER: [&str; 3] = [
-    "downlight",
-    "gpo-single",
-    "gpo-double",
-];
-
-const ROOF_PLAN_ORDER: [&str; 1] = [
-    "box-gutter",
-];
-
-/// Sorts the block labels in order
-fn order_index_for(block_type:&str, label_name:&str) -> Option<u32> {
-    let list: &[&str] = match block_type {
-        "floor" => &FLOOR_PLAN_ORDER,
-        "elevation" => &ELEVATION_ORDER,
-        "electrical" => &ELECTRICAL_PLAN_ORDER,
-        "roof" => &ROOF_PLAN_ORDER,
-        _ => return None,
-    };
-    
-    list.iter()
-        .position(|&name| name == label_name)
-        .map(|pos| pos as u32)
-}
+use doxle_atoms::drawing::model::{Annotation, UpdateAnnotationPayload};
+use doxle_atoms::labels::{Attributes, AttributeSchema, DeletePolicy, MergeLabelPayload};
+use doxle_atoms::store::version::{conflict_or, etag};
+use doxle_atoms::store::{
+    Counter, DynamoStore, EntityKey, LabelRepo, Store, WriteOp, MAX_TRANSACT_OPS,
+    VERSION_CONFLICT,
+};
+
+/// Error for deleting a label live annotations still use under `DeletePolicy::Refuse`
+pub const LABEL_IN_USE: &str = "Label is in use";
+
+/// Error for merging or reassigning a label onto itself
+pub const SAME_LABEL: &str = "Cannot merge a label into itself";
+
+/// Error for merging or reassigning into a label that doesn't exist
+pub const TARGET_NOT_FOUND: &str = "Target label not found";
+
+/// Error for renaming a label to the name of another label in its block
+pub const NAME_TAKEN: &str = "Another label in the block has that name; merge into it instead";
+
+/// Error when a merge or reassign stopped after moving some annotations.
+/// Running it again moves the rest and deletes the label.
+pub const MERGE_INCOMPLETE: &str = "Merge stopped part way; retry to finish it";
+
+/// Annotations re-pointed per transaction: two ops each plus the count
+/// changes on both labels
+const REPOINT_CHUNK: usize = (MAX_TRANSACT_OPS - 2) / 2;
 
 /// Create a new label for a block
+/// An attribute schema in `label_properties` that can't be parsed gets a 422
 pub async fn create_label(
     client: &DynamoClient,
     table_name: &str,
@@ -78,38 +39,27 @@ pub async fn create_label(
     body: &[u8],
 ) -> Result<Response<Body>, Error> {
     let req: CreateLabelPayload = serde_json::from_slice(body)?;
-    
-    let label_id = uuid::Uuid::new_v4().to_string();
-    let pk = format!("BLOCK#{}", block_id);
-    let sk = format!("LABEL#{}", label_id);
-    
-    let mut builder = client
-        .put_item()
-        .table_name(table_name)
-        .item("PK", AttributeValue::S(pk))
-        .item("SK", AttributeValue::S(sk))
-        .item("label_name", AttributeValue::S(req.label_name.clone()))
-        .item("label_count", AttributeValue::N("0".to_string()))
-        .item("label_color", AttributeValue::S(req.label_color.clone()));
-    
-    if let Some(label_properties) = &req.label_properties {
-        builder = builder.item("label_properties", AttributeValue::S(serde_json::to_string(label_properties)?));
+    if let Err(e) = AttributeSchema::from_properties(req.label_properties.as_ref()) {
+        return unprocessable(&e);
     }
-    
-    builder.send().await?;
-    
+
     let label = Label {
-        label_id: label_id.clone(),
+        label_id: uuid::Uuid::new_v4().to_string(),
         block_id: block_id.to_string(),
         label_name: req.label_name,
         label_color: req.label_color,
         label_properties: req.label_properties,
         label_count: 0,
+        label_order: req.label_order,
+        version: 1,
     };
+
+    DynamoStore::new(client, table_name).put_label(&label).await?;
     
     Ok(Response::builder()
         .status(StatusCode::CREATED)
         .header("Content-Type", "application/json")
+        .header("ETag", etag(label.version))
         .header("Access-Control-Allow-Origin", "*")
         .body(serde_json::to_string(&label)?.into())
         .map_err(Box::new)?)
@@ -122,35 +72,13 @@ pub async fn get_label(
     block_id: &str,
     label_id: &str,
 ) -> Result<Response<Body>, Error> {
-     let pk = format!("BLOCK#{}", block_id);
-     let sk = format!("LABEL#{}", label_id);
-    
-    let result = client
-        .get_item()
-        .table_name(table_name)
-        .key("PK", AttributeValue::S(pk))
-        .key("SK", AttributeValue::S(sk))
-        .send()
-        .await?;
-    
-    if let Some(item) = result.item() {
-        let label = Label {
-            label_id: label_id.to_string(),
-            block_id: block_id.to_string(),
-            label_name: item.get("label_name").and_then(|v| v.as_s().ok()).map(|s| s.to_string()).unwrap_or_default(),
-            label_color: item.get("label_color")
-                .and_then(|v| v.as_s().ok())
-                .map(|s| s.to_string())
-                .expect("label_color missing"),
-            label_properties: item.get("label_properties")
-                .and_then(|v| v.as_s().ok())
-                .and_then(|s| serde_json::from_str(s).ok()),
-            label_count: item.get("label_count").and_then(|v| v.as_n().ok()).and_then(|n| n.parse().ok()).unwrap_or(0),
-        };
-        
+    let store = DynamoStore::new(client, table_name);
+
+    if let Some(label) = store.get_label(block_id, label_id).await? {
         Ok(Response::builder()
             .status(StatusCode::OK)
             .header("Content-Type", "application/json")
+            .header("ETag", etag(label.version))
             .header("Access-Control-Allow-Origin", "*")
             .body(serde_json::to_string(&label)?.into())
             .map_err(Box::new)?)
@@ -165,59 +93,20 @@ pub async fn get_label(
 }
 
 /// Load labels for a block (internal helper for block list/get)
-pub async fn fetch_labels_for_block(
-    client: &DynamoClient,
-    table_name: &str,
+pub async fn fetch_labels_for_block<S: Store>(
+    store: &S,
     block_id: &str,
-) -> Result<Vec<Label>, Error> {
-    let pk = format!("BLOCK#{}", block_id);
-    
-    let result = client
-        .query()
-        .table_name(table_name)
-        .key_condition_expression("PK = :pk AND begins_with(SK, :sk_prefix)")
-        .expression_attribute_values(":pk", AttributeValue::S(pk))
-        .expression_attribute_values(":sk_prefix", AttributeValue::S("LABEL#".to_string()))
-        .send()
-        .await?;
-    
-    let mut labels = Vec::new();
-    
-    for item in result.items() {
-            if let Some(sk) = item.get("SK").and_then(|v| v.as_s().ok()) {
-                if let Some(label_id) = sk.strip_prefix("LABEL#") {
-                    let label = Label {
-                        label_id: label_id.to_string(),
-                        block_id: block_id.to_string(),
-                        label_name: item.get("label_name").and_then(|v| v.as_s().ok()).map(|s| s.to_string()).unwrap_or_default(),
-                        label_color: item.get("label_color")
-                            .and_then(|v| v.as_s().ok())
-                            .map(|s| s.to_string())
-                            .expect("label_color missing"),
-                        label_properties: item.get("label_properties")
-                            .and_then(|v| v.as_s().ok())
-                            .and_then(|s| serde_json::from_str(s).ok()),
-                        label_count: item.get("label_count").and_then(|v| v.as_n().ok()).and_then(|n| n.parse().ok()).unwrap_or(0),
-                    };
-                    labels.push(label);
-                }
-            }
-    }
+) -> Result<Vec<Label>, String> {
+    let mut labels = store.list_labels(block_id).await?;
 
-    // Sort the labels
-    if let Some(block_type) = get_block_type(client, table_name, block_id).await? {
-        labels.sort_by(|a, b| {
-            let a_idx = order_index_for(&block_type, &a.label_name);
-            let b_idx = order_index_for(&block_type, &b.label_name);
-            match (a_idx, b_idx) {
-                (Some(a_order), Some(b_order)) => a_order.cmp(&b_order),
-                (Some(_), None) => std::cmp::Ordering::Less,
-                (None, Some(_)) => std::cmp::Ordering::Greater,
-                (None, None) => a.label_name.cmp(&b.label_name),
-            }
-        });
+    // Labels from before stored orders fall back to their block type's template
+    if let Some(block) = store.get_block(block_id).await? {
+        let template = store.get_label_template(&block.block_type).await?;
+        sort_labels(&mut labels, &block.block_type, template.as_ref());
+    } else {
+        sort_labels(&mut labels, "", None);
     }
-    
+
     Ok(labels)
 }
 
@@ -227,7 +116,8 @@ pub async fn list_block_labels(
     table_name: &str,
     block_id: &str,
 ) -> Result<Response<Body>, Error> {
-    let labels = fetch_labels_for_block(client, table_name, block_id).await?;
+    let store = DynamoStore::new(client, table_name);
+    let labels = fetch_labels_for_block(&store, block_id).await?;
     
     Ok(Response::builder()
         .status(StatusCode::OK)
@@ -237,131 +127,393 @@ pub async fn list_block_labels(
         .map_err(Box::new)?)
 }
 
-async fn get_block_type(
+/// Update a label
+/// `expected_version` comes from `If-Match`; a stale one gets a 409
+/// An attribute schema in `label_properties` that can't be parsed gets a 422
+pub async fn update_label(
+    client: &DynamoClient,
+    table_name: &str,
+    block_id: &str,
+    label_id: &str,
+    expected_version: Option<u64>,
+    body: &[u8],
+) -> Result<Response<Body>, Error> {
+    let mut req: UpdateLabelPayload = serde_json::from_slice(body)?;
+    if let Err(e) = AttributeSchema::from_properties(req.label_properties.as_ref()) {
+        return unprocessable(&e);
+    }
+
+    let store = DynamoStore::new(client, table_name);
+    if let Some(name) = &req.label_name {
+        match order_after_rename(&store, block_id, label_id, name).await {
+            Ok(order) => req.label_order = req.label_order.or(order),
+            Err(e) if e == NAME_TAKEN => return error_response(StatusCode::CONFLICT, &e),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    match store.update_label(block_id, label_id, &req, expected_version).await {
+        Ok(()) => get_label(client, table_name, block_id, label_id).await,
+        // Also raised for a missing label; conflict_response answers 404
+        Err(e) if e == VERSION_CONFLICT => conflict_response(&store, block_id, label_id).await,
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Delete a label; `policy` decides what happens to the annotations using it
+/// `expected_version` comes from `If-Match`; a stale one gets a 409
+pub async fn delete_label(
     client: &DynamoClient,
     table_name: &str,
+    user_id: &str,
     block_id: &str,
-) -> Result<Option<String>, Error> {
-    let pk = "BLOCK".to_string();
-    let sk = format!("BLOCK#{}", block_id);
-
-    let result = client
-        .get_item()
-        .table_name(table_name)
-        .key("PK", AttributeValue::S(pk))
-        .key("SK", AttributeValue::S(sk))
-        .send()
-        .await?;
-
-    Ok(result
-        .item()
-        .and_then(|item| item.get("block_type"))
-        .and_then(|v| v.as_s().ok())
-        .map(|s| s.to_string()))
+    label_id: &str,
+    expected_version: Option<u64>,
+    policy: DeletePolicy,
+) -> Result<Response<Body>, Error> {
+    let store = DynamoStore::new(client, table_name);
+    match delete_label_with(&store, block_id, label_id, expected_version, &policy, user_id).await {
+        Ok(()) => Ok(Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header("Access-Control-Allow-Origin", "*")
+            .body(Body::Empty)
+            .map_err(Box::new)?),
+        Err(e) if e == VERSION_CONFLICT || e == "Label not found" => conflict_response(&store, block_id, label_id).await,
+        Err(e) if e == LABEL_IN_USE => error_response(StatusCode::CONFLICT, &e),
+        Err(e) if e == SAME_LABEL => error_response(StatusCode::BAD_REQUEST, &e),
+        Err(e) if e == TARGET_NOT_FOUND => error_response(StatusCode::NOT_FOUND, &e),
+        Err(e) if e == MERGE_INCOMPLETE => error_response(StatusCode::SERVICE_UNAVAILABLE, &e),
+        Err(e) => Err(e.into()),
+    }
 }
 
-/// Update a label
-pub async fn update_label(
+/// POST /blocks/{bid}/labels/{lid}/merge - move the label's annotations to
+/// `into` and delete it; answers with the label merged into
+pub async fn merge_label_handler(
     client: &DynamoClient,
     table_name: &str,
+    user_id: &str,
     block_id: &str,
     label_id: &str,
     body: &[u8],
 ) -> Result<Response<Body>, Error> {
-    let req: UpdateLabelPayload = serde_json::from_slice(body)?;
-     let pk = format!("BLOCK#{}", block_id);
-     let sk = format!("LABEL#{}", label_id);
-    
-    let mut update_expr = vec![];
-    let mut expr_names = HashMap::new();
-    let mut expr_values = HashMap::new();
-    
-    if let Some(name) = req.label_name {
-        update_expr.push("#label_name = :label_name");
-        expr_names.insert("#label_name".to_string(), "name".to_string());
-        expr_values.insert(":label_name".to_string(), AttributeValue::S(name));
+    let req: MergeLabelPayload = serde_json::from_slice(body)?;
+
+    let store = DynamoStore::new(client, table_name);
+    match merge_label(&store, block_id, label_id, &req.into, user_id).await {
+        Ok(label) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .header("ETag", etag(label.version))
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::to_string(&label)?.into())
+            .map_err(Box::new)?),
+        Err(e) if e == "Label not found" || e == TARGET_NOT_FOUND => error_response(StatusCode::NOT_FOUND, &e),
+        Err(e) if e == SAME_LABEL => error_response(StatusCode::BAD_REQUEST, &e),
+        Err(e) if e == MERGE_INCOMPLETE => error_response(StatusCode::SERVICE_UNAVAILABLE, &e),
+        Err(e) => Err(e.into()),
     }
-    
-    if let Some(color) = req.label_color {
-        update_expr.push("#label_color = :label_color");
-        expr_names.insert("#label_color".to_string(), "label_color".to_string());
-        expr_values.insert(":label_color".to_string(), AttributeValue::S(color));
+}
+
+/// Merge label `from` into `into`: re-point its live annotations, then
+/// delete it. Returns `into` with its new count. A merge that fails with
+/// MERGE_INCOMPLETE can be retried; it picks up the annotations left.
+pub async fn merge_label<S: Store>(
+    store: &S,
+    block_id: &str,
+    from: &str,
+    into: &str,
+    user_id: &str,
+) -> Result<Label, String> {
+    let policy = DeletePolicy::Reassign { into: into.to_string() };
+    delete_label_with(store, block_id, from, None, &policy, user_id).await?;
+    store.get_label(block_id, into).await?.ok_or_else(|| TARGET_NOT_FOUND.to_string())
+}
+
+/// Delete a label once `policy` has dealt with the live annotations using
+/// it. Trashed annotations keep the old label id.
+pub async fn delete_label_with<S: Store>(
+    store: &S,
+    block_id: &str,
+    label_id: &str,
+    expected_version: Option<u64>,
+    policy: &DeletePolicy,
+    user_id: &str,
+) -> Result<(), String> {
+    let label = store.get_label(block_id, label_id).await?.ok_or("Label not found")?;
+    if expected_version.is_some_and(|v| v != label.version) {
+        return Err(VERSION_CONFLICT.to_string());
     }
-    
-    if let Some(properties) = req.label_properties {
-        update_expr.push("#label_properties = :label_properties");
-        expr_names.insert("#label_properties".to_string(), "label_properties".to_string());
-        expr_values.insert(":label_properties".to_string(), 
-            AttributeValue::S(serde_json::to_string(&properties)?));
+
+    let annotations = annotations_using(store, block_id, label_id).await?;
+    let delete = WriteOp::DeleteAtVersion { key: EntityKey::label(block_id, label_id), version: label.version };
+    let deleted = match policy {
+        DeletePolicy::Refuse if !annotations.is_empty() => return Err(LABEL_IN_USE.to_string()),
+        DeletePolicy::Refuse => store.transact(vec![delete]).await,
+        DeletePolicy::Cascade => {
+            for a in &annotations {
+                doxle_atoms::drawing::service::delete_annotation(store, block_id, &a.image_id, &a.annotation_id, None, user_id)
+                    .await?;
+            }
+            store.transact(vec![delete]).await
+        }
+        DeletePolicy::Reassign { into } => {
+            if into == label_id {
+                return Err(SAME_LABEL.to_string());
+            }
+            let target = store.get_label(block_id, into).await?.ok_or(TARGET_NOT_FOUND)?;
+            repoint(store, &label, annotations, &target, user_id, delete).await
+        }
+    };
+
+    match deleted {
+        Err(e) if e != MERGE_INCOMPLETE => {
+            let current = store.get_label(block_id, label_id).await?;
+            Err(conflict_or(e, Some(label.version), current.map(|l| l.version)))
+        }
+        deleted => deleted,
     }
-    
-    if !update_expr.is_empty() {
-        let mut builder = client
-            .update_item()
-            .table_name(table_name)
-            .key("PK", AttributeValue::S(pk.clone()))
-            .key("SK", AttributeValue::S(sk.clone()))
-            .update_expression(format!("SET {}", update_expr.join(", ")));
-        
-        for (k, v) in expr_names {
-            builder = builder.expression_attribute_names(k, v);
+}
+
+/// Live annotations in the block labelled `label_id`
+async fn annotations_using<S: Store>(store: &S, block_id: &str, label_id: &str) -> Result<Vec<Annotation>, String> {
+    let mut using = Vec::new();
+    for image in store.list_images(block_id).await? {
+        let annotations = store.list_annotations(&image.image_id).await?;
+        using.extend(annotations.into_iter().filter(|a| a.label_id == label_id));
+    }
+    Ok(using)
+}
+
+/// Move `annotations` from `source` to `target` and their counts with
+/// them, recording each move in the annotation's history, then run
+/// `delete` on the source in the last transaction. Attributes `target`
+/// doesn't declare are dropped; ones it requires are not filled in. Fails
+/// with MERGE_INCOMPLETE once some transactions went through, so a rerun
+/// only has the rest to move.
+async fn repoint<S: Store>(
+    store: &S,
+    source: &Label,
+    annotations: Vec<Annotation>,
+    target: &Label,
+    user_id: &str,
+    delete: WriteOp,
+) -> Result<(), String> {
+    let block_id = source.block_id.as_str();
+    let schema = AttributeSchema::from_properties(target.label_properties.as_ref())?;
+    let chunks = annotations.len().div_ceil(REPOINT_CHUNK);
+    let mut delete = Some(delete);
+    for (i, chunk) in annotations.chunks(REPOINT_CHUNK).enumerate() {
+        let mut ops = Vec::with_capacity(chunk.len() * 2 + 2);
+        for annotation in chunk {
+            let kept: Attributes = annotation
+                .attributes
+                .iter()
+                .filter(|(name, _)| schema.attributes.contains_key(*name))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect();
+            let payload = UpdateAnnotationPayload {
+                label_id: Some(target.label_id.clone()),
+                geometry: None,
+                attributes: (kept.len() != annotation.attributes.len()).then_some(kept),
+                cleanup: None,
+            };
+            ops.extend(doxle_atoms::drawing::service::update_ops(annotation, &payload, user_id).2);
+        }
+        ops.push(WriteOp::Increment {
+            key: EntityKey::label(block_id, &target.label_id),
+            deltas: vec![(Counter::LabelCount, chunk.len() as i64)],
+        });
+        // The source's count goes with the source in the last transaction
+        if i + 1 == chunks {
+            ops.extend(delete.take());
+        } else {
+            ops.push(WriteOp::Increment {
+                key: EntityKey::label(block_id, &source.label_id),
+                deltas: vec![(Counter::LabelCount, -(chunk.len() as i64))],
+            });
         }
-        
-        for (k, v) in expr_values {
-            builder = builder.expression_attribute_values(k, v);
+        if let Err(e) = store.transact(ops).await {
+            return Err(if i == 0 { e } else { MERGE_INCOMPLETE.to_string() });
         }
-        
-        builder.send().await?;
     }
-    
-    get_label(client, table_name, block_id, label_id).await
+    match delete {
+        Some(delete) => store.transact(vec![delete]).await,
+        None => Ok(()),
+    }
 }
 
-/// Delete a label
-pub async fn delete_label(
-    client: &DynamoClient,
-    table_name: &str,
+/// The order to store when renaming a label. A label without a stored order
+/// is placed by its name's position in the block type's template, so it
+/// keeps that position rather than moving with the new name.
+async fn order_after_rename<S: Store>(
+    store: &S,
     block_id: &str,
     label_id: &str,
-) -> Result<Response<Body>, Error> {
-    let pk = format!("BLOCK#{}", block_id);
-    let sk = format!("LABEL#{}", label_id);
-    
-    client
-        .delete_item()
-        .table_name(table_name)
-        .key("PK", AttributeValue::S(pk))
-        .key("SK", AttributeValue::S(sk))
-        .send()
-        .await?;
-    
+    new_name: &str,
+) -> Result<Option<u32>, String> {
+    let labels = store.list_labels(block_id).await?;
+    if labels.iter().any(|l| l.label_id != label_id && l.label_name == new_name) {
+        return Err(NAME_TAKEN.to_string());
+    }
+    let Some(label) = labels.iter().find(|l| l.label_id == label_id) else {
+        return Ok(None);
+    };
+    if label.label_order.is_some() || label.label_name == new_name {
+        return Ok(None);
+    }
+    let Some(block) = store.get_block(block_id).await? else {
+        return Ok(None);
+    };
+    let template = store.get_label_template(&block.block_type).await?;
+    Ok(template_order(template.as_ref(), &block.block_type, &label.label_name))
+}
+
+/// 409 carrying the label as the server has it; 404 if it was deleted
+async fn conflict_response(store: &DynamoStore, block_id: &str, label_id: &str) -> Result<Response<Body>, Error> {
+    match store.get_label(block_id, label_id).await? {
+        Some(current) => Ok(Response::builder()
+            .status(StatusCode::CONFLICT)
+            .header("Content-Type", "application/json")
+            .header("ETag", etag(current.version))
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": VERSION_CONFLICT, "current": current}).to_string().into())
+            .map_err(Box::new)?),
+        None => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": "Label not found"}).to_string().into())
+            .map_err(Box::new)?),
+    }
+}
+
+/// 422 for a `label_properties` attribute schema that can't be parsed
+pub(crate) fn unprocessable(error: &str) -> Result<Response<Body>, Error> {
+    error_response(StatusCode::UNPROCESSABLE_ENTITY, error)
+}
+
+fn error_response(status: StatusCode, error: &str) -> Result<Response<Body>, Error> {
     Ok(Response::builder()
-        .status(StatusCode::NO_CONTENT)
+        .status(status)
+        .header("Content-Type", "application/json")
         .header("Access-Control-Allow-Origin", "*")
-        .body(Body::Empty)
+        .body(serde_json::json!({"error": error}).to_string().into())
         .map_err(Box::new)?)
 }
 
 /// Increment label count (when annotations are added/removed)
-pub async fn increment_label_count(
-    client: &DynamoClient,
-    table_name: &str,
+pub async fn increment_label_count<S: Store>(
+    store: &S,
     block_id: &str,
     label_id: &str,
     delta: i32,
-) -> Result<(), Error> {
-    let pk = format!("BLOCK#{}", block_id);
-    let sk = format!("LABEL#{}", label_id);
-    
-    client
-        .update_item()
-        .table_name(table_name)
-        .key("PK", AttributeValue::S(pk))
-        .key("SK", AttributeValue::S(sk))
-        .update_expression("SET label_count =  label_count + :delta")
-        .expression_attribute_values(":delta", AttributeValue::N(delta.to_string()))
-        .send()
-        .await?;
-    
-    Ok(())
+) -> Result<(), String> {
+    store.increment_label_count(block_id, label_id, delta as i64).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use doxle_atoms::drawing::model::{CreateAnnotationPayload, Geometry, Point};
+    use doxle_atoms::media::model::CreateImagePayload;
+    use doxle_atoms::store::{AnnotationRepo, BlockRepo, MemoryStore};
+    use doxle_atoms::fixtures::{test_block, test_label};
+
+    fn label(label_id: &str, label_name: &str) -> Label {
+        test_label("b1", label_id, label_name)
+    }
+
+    #[tokio::test]
+    async fn test_merge_and_delete_policies_leave_no_orphans() {
+        let store = MemoryStore::new();
+        store
+            .put_block(&test_block("b1"))
+            .await
+            .unwrap();
+        for l in [label("door", "Door"), label("doors", "Doors"), label("wall", "Wall")] {
+            store.put_label(&l).await.unwrap();
+        }
+        let payload = CreateImagePayload { url: "a.jpg".to_string(), task_id: None, order: None, width: None, height: None };
+        let image = doxle_atoms::media::service::create_image(&store, "b1", payload).await.unwrap();
+        for label_id in ["door", "doors", "doors", "wall"] {
+            let annotation = CreateAnnotationPayload {
+                label_id: label_id.to_string(),
+                geometry: Geometry::BBox { start: Point { x: 0.0, y: 0.0 }, end: Point { x: 1.0, y: 1.0 } },
+                attributes: Default::default(),
+                cleanup: None,
+            };
+            doxle_atoms::drawing::service::create_annotation(&store, "b1", &image.image_id, "u1", annotation)
+                .await
+                .unwrap();
+        }
+
+        for (label_id, count) in [("door", 1), ("doors", 2), ("wall", 1)] {
+            assert_eq!(store.get_label("b1", label_id).await.unwrap().unwrap().label_count, count);
+        }
+
+        let merged = merge_label(&store, "b1", "doors", "door", "u1").await.unwrap();
+        assert_eq!(merged.label_count, 3);
+        assert!(store.get_label("b1", "doors").await.unwrap().is_none());
+        assert_eq!(annotations_using(&store, "b1", "door").await.unwrap().len(), 3);
+        assert_eq!(merge_label(&store, "b1", "door", "door", "u1").await.unwrap_err(), SAME_LABEL);
+
+        let refused = delete_label_with(&store, "b1", "wall", None, &DeletePolicy::Refuse, "u1").await;
+        assert_eq!(refused.unwrap_err(), LABEL_IN_USE);
+        delete_label_with(&store, "b1", "wall", None, &DeletePolicy::Cascade, "u1").await.unwrap();
+        let left = store.list_annotations(&image.image_id).await.unwrap();
+        assert_eq!(left.len(), 3);
+        assert_eq!(store.get_label("b1", "door").await.unwrap().unwrap().label_count, 3);
+        assert!(left.iter().all(|a| a.label_id == "door"));
+
+        assert_eq!(order_after_rename(&store, "b1", "door", "Door").await.unwrap(), None);
+        store.put_label(&label("window", "Window")).await.unwrap();
+        assert_eq!(order_after_rename(&store, "b1", "door", "Window").await.unwrap_err(), NAME_TAKEN);
+
+        let rename =
+            UpdateLabelPayload { label_name: Some("Doorway".to_string()), label_color: None, label_properties: None, label_order: None };
+        store.update_label("b1", "door", &rename, None).await.unwrap();
+        assert_eq!(store.get_label("b1", "door").await.unwrap().unwrap().label_name, "Doorway");
+    }
+
+    #[tokio::test]
+    async fn test_merge_stopped_part_way_finishes_on_retry() {
+        let store = MemoryStore::new();
+        store.put_block(&test_block("b1")).await.unwrap();
+        for l in [label("door", "Door"), label("doors", "Doors")] {
+            store.put_label(&l).await.unwrap();
+        }
+        let payload = CreateImagePayload { url: "a.jpg".to_string(), task_id: None, order: None, width: None, height: None };
+        let image = doxle_atoms::media::service::create_image(&store, "b1", payload).await.unwrap();
+        let bbox = || CreateAnnotationPayload {
+            label_id: "doors".to_string(),
+            geometry: Geometry::BBox { start: Point { x: 0.0, y: 0.0 }, end: Point { x: 1.0, y: 1.0 } },
+            attributes: Default::default(),
+            cleanup: None,
+        };
+        for _ in 0..REPOINT_CHUNK + 1 {
+            doxle_atoms::drawing::service::create_annotation(&store, "b1", &image.image_id, "u1", bbox())
+                .await
+                .unwrap();
+        }
+
+        // An edit after the annotations were read fails the second transaction
+        let source = store.get_label("b1", "doors").await.unwrap().unwrap();
+        let target = store.get_label("b1", "door").await.unwrap().unwrap();
+        let annotations = annotations_using(&store, "b1", "doors").await.unwrap();
+        let last = annotations.last().unwrap();
+        let edit = UpdateAnnotationPayload { label_id: None, geometry: None, attributes: None, cleanup: None };
+        doxle_atoms::drawing::service::update_annotation(&store, "b1", &image.image_id, &last.annotation_id, edit, None, "u2")
+            .await
+            .unwrap();
+        let delete = WriteOp::DeleteAtVersion { key: EntityKey::label("b1", "doors"), version: source.version };
+        let stopped = repoint(&store, &source, annotations, &target, "u1", delete).await;
+        assert_eq!(stopped.unwrap_err(), MERGE_INCOMPLETE);
+        assert_eq!(store.get_label("b1", "doors").await.unwrap().unwrap().label_count, 1);
+        assert_eq!(store.get_label("b1", "door").await.unwrap().unwrap().label_count, REPOINT_CHUNK as u32);
+
+        let merged = merge_label(&store, "b1", "doors", "door", "u1").await.unwrap();
+        assert_eq!(merged.label_count, REPOINT_CHUNK as u32 + 1);
+        assert!(store.get_label("b1", "doors").await.unwrap().is_none());
+        assert!(annotations_using(&store, "b1", "doors").await.unwrap().is_empty());
+    }
 }
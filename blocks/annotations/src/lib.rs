@@ -1,7 +1,15 @@
 pub mod types;
 pub mod images;
 pub mod labels;
+pub mod templates;
 pub mod blocks;
 pub mod annotations;
 pub mod handler;
 pub mod tasks;
+pub mod reconcile;
+pub mod trash;
+pub mod snapshots;
+pub mod takeoff;
+pub mod qa;
+pub mod agreement;
+pub mod export;
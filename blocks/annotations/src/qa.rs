@@ -0,0 +1,256 @@
+use std::collections::{HashMap, HashSet};
+
+use aws_sdk_dynamodb::Client as DynamoClient;
+use doxle_atoms::drawing::geometry;
+use doxle_atoms::drawing::model::Annotation;
+use doxle_atoms::drawing::spatial::{SpatialIndex, SpatialQuery};
+use doxle_atoms::labels::model::Label;
+use doxle_atoms::store::{DynamoStore, Store};
+use lambda_http::{http::StatusCode, Body, Error, Response};
+use serde::Serialize;
+
+/// IoU above which two same-label annotations count as one drawn twice
+pub const DEFAULT_DUPLICATE_IOU: f64 = 0.8;
+/// IoU above which annotations of mutually exclusive labels are flagged
+pub const DEFAULT_EXCLUSIVE_IOU: f64 = 0.2;
+
+/// Thresholds for the duplicate check, both IoU in `0..=1`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct DuplicateCheck {
+    pub threshold: f64,
+    pub overlap: f64,
+}
+
+impl Default for DuplicateCheck {
+    fn default() -> Self {
+        Self { threshold: DEFAULT_DUPLICATE_IOU, overlap: DEFAULT_EXCLUSIVE_IOU }
+    }
+}
+
+impl DuplicateCheck {
+    /// Read `?threshold=` and `?overlap=`, keeping the defaults for either
+    /// one left out
+    pub fn from_query(threshold: Option<&str>, overlap: Option<&str>) -> Result<Self, String> {
+        let ratio = |name: &str, value: Option<&str>, default: f64| match value {
+            None => Ok(default),
+            Some(v) => v
+                .parse::<f64>()
+                .ok()
+                .filter(|v| (0.0..=1.0).contains(v))
+                .ok_or_else(|| format!("{} must be between 0 and 1", name)),
+        };
+        Ok(Self {
+            threshold: ratio("threshold", threshold, DEFAULT_DUPLICATE_IOU)?,
+            overlap: ratio("overlap", overlap, DEFAULT_EXCLUSIVE_IOU)?,
+        })
+    }
+}
+
+/// Two annotations on one image a reviewer should look at
+#[derive(Debug, Clone, Serialize)]
+pub struct OverlapFinding {
+    pub image_id: String,
+    pub annotation_ids: [String; 2],
+    pub label_ids: [String; 2],
+    pub iou: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateReport {
+    pub block_id: String,
+    pub check: DuplicateCheck,
+    /// Same label, drawn over each other
+    pub duplicates: Vec<OverlapFinding>,
+    /// Labels that exclude each other, overlapping
+    pub exclusive_overlaps: Vec<OverlapFinding>,
+}
+
+/// Find double-drawn annotations and overlaps between mutually exclusive
+/// labels, on one image or across the block. Labels list what they exclude
+/// by name or id under `label_properties.exclusive_with`; either side
+/// declaring it is enough. Lines and points have no IoU and only count as
+/// duplicates when drawn identically.
+pub async fn find_duplicates<S: Store>(
+    store: &S,
+    block_id: &str,
+    image_id: Option<&str>,
+    check: DuplicateCheck,
+) -> Result<DuplicateReport, String> {
+    store
+        .get_block(block_id)
+        .await?
+        .filter(|b| b.deleted_at.is_none())
+        .ok_or("Block not found")?;
+    let image_ids: Vec<String> = match image_id {
+        Some(image_id) => {
+            let image = doxle_atoms::media::service::get_image(store, block_id, image_id).await?;
+            vec![image.image_id]
+        }
+        None => doxle_atoms::media::service::load_images_for_block(store, block_id)
+            .await?
+            .into_iter()
+            .map(|image| image.image_id)
+            .collect(),
+    };
+    let labels = store.list_labels(block_id).await?;
+    let exclusive = exclusive_pairs(&labels);
+
+    let mut report = DuplicateReport {
+        block_id: block_id.to_string(),
+        check,
+        duplicates: vec![],
+        exclusive_overlaps: vec![],
+    };
+    for image_id in image_ids {
+        let annotations = store.list_annotations(&image_id).await?;
+        let index = SpatialIndex::new(&annotations);
+        for (i, a) in annotations.iter().enumerate() {
+            let near = SpatialQuery::Within(geometry::bounding_box(&a.geometry));
+            for j in index.query(&annotations, &near).into_iter().filter(|&j| j > i) {
+                let b = &annotations[j];
+                let same_label = a.label_id == b.label_id;
+                let excluded = exclusive.contains(&(a.label_id.as_str(), b.label_id.as_str()));
+                if !same_label && !excluded {
+                    continue;
+                }
+                let Some(iou) = overlap(a, b) else { continue };
+                if same_label && iou > check.threshold {
+                    report.duplicates.push(finding(&image_id, a, b, iou));
+                } else if excluded && iou > check.overlap {
+                    report.exclusive_overlaps.push(finding(&image_id, a, b, iou));
+                }
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// IoU of two shapes; identical lines and points count as 1
+fn overlap(a: &Annotation, b: &Annotation) -> Option<f64> {
+    geometry::iou(&a.geometry, &b.geometry).or_else(|| (a.geometry == b.geometry).then_some(1.0))
+}
+
+fn finding(image_id: &str, a: &Annotation, b: &Annotation, iou: f64) -> OverlapFinding {
+    OverlapFinding {
+        image_id: image_id.to_string(),
+        annotation_ids: [a.annotation_id.clone(), b.annotation_id.clone()],
+        label_ids: [a.label_id.clone(), b.label_id.clone()],
+        iou,
+    }
+}
+
+/// Label id pairs that exclude each other, both ways round
+fn exclusive_pairs(labels: &[Label]) -> HashSet<(&str, &str)> {
+    let by_name: HashMap<&str, &str> = labels.iter().map(|l| (l.label_name.as_str(), l.label_id.as_str())).collect();
+    let mut pairs = HashSet::new();
+    for label in labels {
+        let declared = label
+            .label_properties
+            .as_ref()
+            .and_then(|p| p.get("exclusive_with"))
+            .and_then(|v| v.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|v| v.as_str());
+        for other in declared {
+            let by_id = || labels.iter().find(|l| l.label_id == other).map(|l| l.label_id.as_str());
+            let Some(other) = by_name.get(other).copied().or_else(by_id) else {
+                continue;
+            };
+            pairs.insert((label.label_id.as_str(), other));
+            pairs.insert((other, label.label_id.as_str()));
+        }
+    }
+    pairs
+}
+
+/// GET /blocks/{bid}/qa/duplicates[?image_id=][&threshold=][&overlap=]
+pub async fn find_duplicates_handler(
+    client: &DynamoClient,
+    table_name: &str,
+    block_id: &str,
+    image_id: Option<&str>,
+    check: DuplicateCheck,
+) -> Result<Response<Body>, Error> {
+    let store = DynamoStore::new(client, table_name);
+    match find_duplicates(&store, block_id, image_id, check).await {
+        Ok(report) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::to_string(&report)?.into())
+            .map_err(Box::new)?),
+        Err(e) if e == "Block not found" || e == "Image not found" => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": e}).to_string().into())
+            .map_err(Box::new)?),
+        Err(e) => Ok(Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": e}).to_string().into())
+            .map_err(Box::new)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use doxle_atoms::drawing::model::{CreateAnnotationPayload, Geometry, Point};
+    use doxle_atoms::media::model::CreateImagePayload;
+    use doxle_atoms::store::{BlockRepo, LabelRepo, MemoryStore};
+    use doxle_atoms::fixtures::{test_block, test_label};
+
+    #[tokio::test]
+    async fn test_flags_double_drawn_doors_and_door_window_overlaps() {
+        let store = MemoryStore::new();
+        store.put_block(&test_block("b1")).await.unwrap();
+        for (label_id, name, properties) in [
+            ("door", "doors", Some(serde_json::json!({"exclusive_with": ["windows"]}))),
+            ("window", "windows", None),
+        ] {
+            let label = Label { label_properties: properties, ..test_label("b1", label_id, name) };
+            store.put_label(&label).await.unwrap();
+        }
+        let payload = CreateImagePayload { url: "a.jpg".to_string(), task_id: None, order: None, width: None, height: None };
+        let image = doxle_atoms::media::service::create_image(&store, "b1", payload).await.unwrap();
+
+        let mut ids = vec![];
+        for (label_id, x0) in [("door", 0.0), ("door", 1.0), ("window", 3.0), ("door", 100.0)] {
+            let annotation = CreateAnnotationPayload {
+                label_id: label_id.to_string(),
+                geometry: Geometry::BBox { start: Point { x: x0, y: 0.0 }, end: Point { x: x0 + 10.0, y: 10.0 } },
+                attributes: Default::default(),
+                cleanup: None,
+            };
+            let created = doxle_atoms::drawing::service::create_annotation(&store, "b1", &image.image_id, "u1", annotation)
+                .await
+                .unwrap();
+            ids.push(created.annotation_id);
+        }
+
+        let report = find_duplicates(&store, "b1", None, DuplicateCheck::default()).await.unwrap();
+        let sorted = |a: &String, b: &String| {
+            let mut pair = [a.clone(), b.clone()];
+            pair.sort();
+            pair
+        };
+        let pairs = |findings: &[OverlapFinding]| {
+            findings.iter().map(|f| sorted(&f.annotation_ids[0], &f.annotation_ids[1])).collect::<Vec<_>>()
+        };
+        assert_eq!(pairs(&report.duplicates), vec![sorted(&ids[0], &ids[1])]);
+        // The window has IoU 7/13 with the first door and 8/12 with the second
+        let mut overlaps = pairs(&report.exclusive_overlaps);
+        overlaps.sort();
+        let mut expected = vec![sorted(&ids[0], &ids[2]), sorted(&ids[1], &ids[2])];
+        expected.sort();
+        assert_eq!(overlaps, expected);
+
+        let entity = doxle_atoms::trash::TrashedEntity::Block { block_id: "b1".to_string() };
+        doxle_atoms::trash::service::trash(&store, entity, "u1", None).await.unwrap();
+        let trashed = find_duplicates(&store, "b1", None, DuplicateCheck::default()).await;
+        assert_eq!(trashed.unwrap_err(), "Block not found");
+    }
+}
@@ -0,0 +1,409 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use aws_sdk_dynamodb::Client as DynamoClient;
+use doxle_atoms::store::{
+    BlockCounter, Counter, DynamoStore, EntityKey, Store, WriteOp, MAX_TRANSACT_OPS,
+};
+use lambda_http::{http::StatusCode, Body, Error, Response};
+use serde::Serialize;
+
+use crate::templates::require_admin;
+
+/// One counter whose stored value differs from the source rows
+#[derive(Debug, Clone, Serialize)]
+pub struct CounterDrift {
+    pub entity: &'static str, // block | task | image | label
+    pub id: String,
+    pub counter: &'static str,
+    pub stored: u32,
+    pub actual: u32,
+    #[serde(skip)]
+    key: EntityKey,
+    #[serde(skip)]
+    field: Counter,
+}
+
+/// A task -> image index row that is missing or points at the wrong image
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct TaskImageLink {
+    pub task_id: String,
+    pub image_id: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReconcileReport {
+    pub block_id: String,
+    pub drifts: Vec<CounterDrift>,
+    pub missing_task_links: Vec<TaskImageLink>,
+    pub stale_task_links: Vec<TaskImageLink>,
+    pub fixed: bool,
+}
+
+impl ReconcileReport {
+    pub fn is_clean(&self) -> bool {
+        self.drifts.is_empty() && self.missing_task_links.is_empty() && self.stale_task_links.is_empty()
+    }
+}
+
+/// Recompute every counter and task index row for a live block from its rows
+/// Drifts are always reported; with `fix` they are also corrected
+pub async fn reconcile_block<S: Store>(
+    store: &S,
+    block_id: &str,
+    fix: bool,
+) -> Result<ReconcileReport, String> {
+    let block = store
+        .get_block(block_id)
+        .await?
+        .filter(|b| b.deleted_at.is_none())
+        .ok_or("Block not found")?;
+    let tasks = store.list_tasks(block_id).await?;
+    let images = store.list_images(block_id).await?;
+    let labels = store.list_labels(block_id).await?;
+
+    let done_tasks: HashMap<&str, bool> = tasks
+        .iter()
+        .map(|t| (t.task_id.as_str(), t.task_state == "done"))
+        .collect();
+
+    let mut task_images: HashMap<&str, u32> = HashMap::new();
+    let mut image_annotations: HashMap<&str, u32> = HashMap::new();
+    let mut label_annotations: HashMap<String, u32> = HashMap::new();
+    let mut approved_images = 0;
+    let mut block_annotations = 0;
+
+    for image in &images {
+        if let Some(task_id) = image.task_id.as_deref() {
+            *task_images.entry(task_id).or_default() += 1;
+            if done_tasks.get(task_id).copied().unwrap_or(false) {
+                approved_images += 1;
+            }
+        }
+
+        let annotations = store.list_annotations(&image.image_id).await?;
+        image_annotations.insert(image.image_id.as_str(), annotations.len() as u32);
+        block_annotations += annotations.len() as u32;
+        for annotation in annotations {
+            *label_annotations.entry(annotation.label_id).or_default() += 1;
+        }
+    }
+
+    let mut drifts = Vec::new();
+    let mut check = |entity, id: &str, key: EntityKey, field: Counter, stored: u32, actual: u32| {
+        if stored != actual {
+            drifts.push(CounterDrift {
+                entity,
+                id: id.to_string(),
+                counter: field.attribute(),
+                stored,
+                actual,
+                key,
+                field,
+            });
+        }
+    };
+
+    let block_counters = [
+        (BlockCounter::ImageCount, block.image_count, images.len() as u32),
+        (BlockCounter::ApprovedImageCount, block.approved_image_count, approved_images),
+        (BlockCounter::AnnotationCount, block.annotation_count, block_annotations),
+    ];
+    for (counter, stored, actual) in block_counters {
+        check("block", block_id, EntityKey::block(block_id), Counter::Block(counter), stored, actual);
+    }
+
+    for task in &tasks {
+        let key = EntityKey::task(block_id, &task.task_id);
+        let actual = task_images.get(task.task_id.as_str()).copied().unwrap_or(0);
+        check("task", &task.task_id, key, Counter::TaskImageCount, task.image_count, actual);
+    }
+
+    for image in &images {
+        let key = EntityKey::image(block_id, &image.image_id);
+        let actual = image_annotations.get(image.image_id.as_str()).copied().unwrap_or(0);
+        check("image", &image.image_id, key, Counter::ImageAnnotationCount, image.annotation_count, actual);
+    }
+
+    for label in &labels {
+        let key = EntityKey::label(block_id, &label.label_id);
+        let actual = label_annotations.get(&label.label_id).copied().unwrap_or(0);
+        check("label", &label.label_id, key, Counter::LabelCount, label.label_count, actual);
+    }
+
+    // Images written before the task index existed have no index row yet
+    let expected: BTreeSet<TaskImageLink> = images
+        .iter()
+        .filter_map(|image| {
+            let task_id = image.task_id.as_deref().filter(|t| done_tasks.contains_key(t))?;
+            Some(TaskImageLink { task_id: task_id.to_string(), image_id: image.image_id.clone() })
+        })
+        .collect();
+    let mut indexed = BTreeSet::new();
+    for task in &tasks {
+        for image_id in store.list_task_image_ids(&task.task_id).await? {
+            indexed.insert(TaskImageLink { task_id: task.task_id.clone(), image_id });
+        }
+    }
+
+    let mut stale_task_links = Vec::new();
+    for link in indexed.difference(&expected) {
+        // Trashed images keep their index row so a restore finds them again
+        let image = store.get_image(block_id, &link.image_id).await?;
+        if !image.is_some_and(|i| i.deleted_at.is_some() && i.task_id.as_deref() == Some(link.task_id.as_str())) {
+            stale_task_links.push(link.clone());
+        }
+    }
+
+    let mut report = ReconcileReport {
+        block_id: block_id.to_string(),
+        drifts,
+        missing_task_links: expected.difference(&indexed).cloned().collect(),
+        stale_task_links,
+        fixed: false,
+    };
+
+    if fix && !report.is_clean() {
+        apply_fixes(store, block_id, &report).await?;
+        report.fixed = true;
+    }
+
+    Ok(report)
+}
+
+/// Reconcile every block; one failing block does not stop the rest
+pub async fn reconcile_all_blocks<S: Store>(store: &S, fix: bool) -> Result<Vec<ReconcileReport>, String> {
+    let mut reports = Vec::new();
+
+    for block in store.list_blocks().await? {
+        match reconcile_block(store, &block.block_id, fix).await {
+            Ok(report) => reports.push(report),
+            Err(e) => tracing::error!("Failed to reconcile block {}: {}", block.block_id, e),
+        }
+    }
+
+    Ok(reports)
+}
+
+/// Correct counters by adding the difference, so writes that land between
+/// the read and the fix are kept, and write or drop task index rows.
+/// One op per row, as transactions require.
+async fn apply_fixes<S: Store>(store: &S, block_id: &str, report: &ReconcileReport) -> Result<(), String> {
+    let mut by_row: BTreeMap<EntityKey, Vec<(Counter, i64)>> = BTreeMap::new();
+    for drift in &report.drifts {
+        let delta = drift.actual as i64 - drift.stored as i64;
+        by_row.entry(drift.key.clone()).or_default().push((drift.field, delta));
+    }
+
+    let mut ops: Vec<WriteOp> = by_row
+        .into_iter()
+        .map(|(key, deltas)| WriteOp::Increment { key, deltas })
+        .collect();
+    for link in &report.missing_task_links {
+        ops.push(WriteOp::PutTaskImage {
+            block_id: block_id.to_string(),
+            task_id: link.task_id.clone(),
+            image_id: link.image_id.clone(),
+        });
+    }
+    for link in &report.stale_task_links {
+        ops.push(WriteOp::Delete(EntityKey::task_image(&link.task_id, &link.image_id)));
+    }
+
+    let mut batch = Vec::new();
+    for op in ops {
+        batch.push(op);
+        if batch.len() == MAX_TRANSACT_OPS {
+            store.transact(std::mem::take(&mut batch)).await?;
+        }
+    }
+    store.transact(batch).await
+}
+
+/// POST /blocks/{bid}/reconcile[?fix=true] - admin only
+pub async fn reconcile_block_handler(
+    client: &DynamoClient,
+    table_name: &str,
+    user_id: &str,
+    block_id: &str,
+    fix: bool,
+) -> Result<Response<Body>, Error> {
+    let store = DynamoStore::new(client, table_name);
+
+    if let Some(resp) = require_admin(&store, user_id).await? {
+        return Ok(resp);
+    }
+
+    match reconcile_block(&store, block_id, fix).await {
+        Ok(report) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::to_string(&report)?.into())
+            .map_err(Box::new)?),
+        Err(e) if e == "Block not found" => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": e}).to_string().into())
+            .map_err(Box::new)?),
+        Err(e) => Ok(Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": e}).to_string().into())
+            .map_err(Box::new)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use doxle_atoms::media::model::{CreateImagePayload, Image};
+    use doxle_atoms::store::{BlockRepo, ImageRepo, MemoryStore};
+    use doxle_atoms::tasks::model::CreateTaskPayload;
+    use doxle_atoms::fixtures::{test_block, test_label};
+
+    async fn seed_block(store: &MemoryStore) {
+        store.put_block(&test_block("b1")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_reports_and_fixes_drift() {
+        let store = MemoryStore::new();
+        seed_block(&store).await;
+        let payload = CreateImagePayload { url: "a.jpg".to_string(), task_id: None, order: None, width: None, height: None };
+        doxle_atoms::media::service::create_image(&store, "b1", payload).await.unwrap();
+
+        // Simulate drift from a half-applied write
+        store.increment_block_counter("b1", BlockCounter::ImageCount, 2).await.unwrap();
+
+        let report = reconcile_block(&store, "b1", false).await.unwrap();
+        assert_eq!(report.drifts.len(), 1);
+        assert_eq!((report.drifts[0].stored, report.drifts[0].actual), (3, 1));
+        assert!(!report.fixed);
+
+        let report = reconcile_block(&store, "b1", true).await.unwrap();
+        assert!(report.fixed);
+        assert!(reconcile_block(&store, "b1", false).await.unwrap().is_clean());
+
+        let entity = doxle_atoms::trash::TrashedEntity::Block { block_id: "b1".to_string() };
+        doxle_atoms::trash::service::trash(&store, entity, "u1", None).await.unwrap();
+        assert_eq!(reconcile_block(&store, "b1", true).await.unwrap_err(), "Block not found");
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_backfills_task_index() {
+        let store = MemoryStore::new();
+        seed_block(&store).await;
+        let payload = CreateTaskPayload { task_name: "t".to_string(), assignee: None, checked_by: None };
+        let task = doxle_atoms::tasks::service::create_task(&store, "b1", payload).await.unwrap();
+
+        // Rows written before the task index existed have no index row
+        for image_id in ["i1", "i2"] {
+            let image = Image {
+                image_id: image_id.to_string(),
+                block_id: "b1".to_string(),
+                task_id: Some(task.task_id.clone()),
+                url: format!("{}.jpg", image_id),
+                locked: false,
+                order: None,
+                width: None,
+                height: None,
+                scale: None,
+                annotation_count: 0,
+                uploaded_at: "2024-01-01T00:00:00Z".to_string(),
+                deleted_at: None,
+                trash_id: None,
+            };
+            store.put_image(&image).await.unwrap();
+        }
+        let images = doxle_atoms::media::service::load_images_for_task(&store, "b1", &task.task_id).await.unwrap();
+        assert!(images.is_empty());
+
+        let report = reconcile_block(&store, "b1", true).await.unwrap();
+        assert_eq!(report.missing_task_links.len(), 2);
+        let images = doxle_atoms::media::service::load_images_for_task(&store, "b1", &task.task_id).await.unwrap();
+        assert_eq!(images.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_trash_and_restore_keep_label_counts() {
+        use doxle_atoms::drawing::model::{CreateAnnotationPayload, Geometry, Point};
+        use doxle_atoms::store::LabelRepo;
+        use doxle_atoms::trash::TrashedEntity;
+
+        let store = MemoryStore::new();
+        seed_block(&store).await;
+        for label_id in ["l1", "l2"] {
+            store.put_label(&test_label("b1", label_id, label_id)).await.unwrap();
+        }
+        let payload = CreateTaskPayload { task_name: "t".to_string(), assignee: None, checked_by: None };
+        let task = doxle_atoms::tasks::service::create_task(&store, "b1", payload).await.unwrap();
+        let mut images = vec![];
+        for task_id in [None, Some(task.task_id.clone())] {
+            let payload = CreateImagePayload { url: "a.jpg".to_string(), task_id, order: None, width: None, height: None };
+            let image = doxle_atoms::media::service::create_image(&store, "b1", payload).await.unwrap();
+            for label_id in ["l1", "l1", "l2"] {
+                let annotation = CreateAnnotationPayload {
+                    label_id: label_id.to_string(),
+                    geometry: Geometry::BBox { start: Point { x: 0.0, y: 0.0 }, end: Point { x: 1.0, y: 1.0 } },
+                    attributes: Default::default(),
+                    cleanup: None,
+                };
+                doxle_atoms::drawing::service::create_annotation(&store, "b1", &image.image_id, "u1", annotation)
+                    .await
+                    .unwrap();
+            }
+            images.push(image);
+        }
+
+        let entities = [
+            TrashedEntity::Image { block_id: "b1".to_string(), image_id: images[0].image_id.clone() },
+            TrashedEntity::Task { block_id: "b1".to_string(), task_id: task.task_id.clone() },
+        ];
+        for entity in entities {
+            let entry = doxle_atoms::trash::service::trash(&store, entity, "u1", None).await.unwrap();
+            assert!(reconcile_block(&store, "b1", false).await.unwrap().is_clean());
+            doxle_atoms::trash::service::restore(&store, &entry.trash_id, "u1").await.unwrap();
+            assert!(reconcile_block(&store, "b1", false).await.unwrap().is_clean());
+        }
+        let label = store.get_label("b1", "l1").await.unwrap().unwrap();
+        assert_eq!(label.label_count, 4);
+    }
+
+    #[tokio::test]
+    async fn test_cloned_block_counts_match_its_rows() {
+        use doxle_atoms::blocks::model::{CloneBlockPayload, CloneScope};
+        use doxle_atoms::drawing::model::{CreateAnnotationPayload, Geometry, Point};
+        use doxle_atoms::store::{AnnotationRepo, LabelRepo};
+
+        let store = MemoryStore::new();
+        seed_block(&store).await;
+        store.put_label(&test_label("b1", "l1", "Wall")).await.unwrap();
+        let payload = CreateTaskPayload { task_name: "t".to_string(), assignee: None, checked_by: None };
+        let task = doxle_atoms::tasks::service::create_task(&store, "b1", payload).await.unwrap();
+        let payload = CreateImagePayload { url: "a.jpg".to_string(), task_id: Some(task.task_id), order: None, width: None, height: None };
+        let image = doxle_atoms::media::service::create_image(&store, "b1", payload).await.unwrap();
+        let annotation = CreateAnnotationPayload {
+            label_id: "l1".to_string(),
+            geometry: Geometry::BBox { start: Point { x: 0.0, y: 0.0 }, end: Point { x: 1.0, y: 1.0 } },
+            attributes: Default::default(),
+            cleanup: None,
+        };
+        doxle_atoms::drawing::service::create_annotation(&store, "b1", &image.image_id, "u1", annotation)
+            .await
+            .unwrap();
+
+        let req = CloneBlockPayload { block_name: None, scope: CloneScope::All };
+        let clone = crate::blocks::clone_block(&store, "b1", "b2", &req).await.unwrap();
+        assert_eq!(clone.block.block_name, "Block (copy)");
+        assert_eq!((clone.block.image_count, clone.block.annotation_count), (1, 1));
+        assert_ne!(clone.labels[0].label_id, "l1");
+        assert_eq!(clone.labels[0].label_count, 1);
+
+        let copied = store.list_images("b2").await.unwrap();
+        let annotations = store.list_annotations(&copied[0].image_id).await.unwrap();
+        assert_eq!(annotations[0].label_id, clone.labels[0].label_id);
+        assert!(reconcile_block(&store, "b2", false).await.unwrap().is_clean());
+    }
+}
@@ -0,0 +1,266 @@
+use aws_sdk_dynamodb::Client as DynamoClient;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::Client as S3Client;
+use doxle_atoms::snapshots::{Snapshot, SnapshotArchive};
+use doxle_atoms::store::{DynamoStore, EntityKey, SnapshotRepo, Store, WriteOp};
+use lambda_http::{http::StatusCode, Body, Error, Response};
+
+/// Tries at claiming the next version before giving up
+const VERSION_ATTEMPTS: usize = 3;
+
+/// S3 key of a snapshot archive; under the block prefix so the block purge
+/// removes it with the block
+pub fn snapshot_s3_key(block_id: &str, version: u32) -> String {
+    format!("annotations/blocks/{}/snapshots/v{}.json", block_id, version)
+}
+
+/// Freeze the block's live labels, tasks, images and annotations under the
+/// next version and write its index row. The archive still has to be
+/// uploaded; see `create_snapshot`.
+pub async fn record_snapshot<S: Store>(
+    store: &S,
+    block_id: &str,
+    created_by: &str,
+) -> Result<SnapshotArchive, String> {
+    let block = store
+        .get_block(block_id)
+        .await?
+        .filter(|b| b.deleted_at.is_none())
+        .ok_or("Block not found")?;
+    let labels = store.list_labels(block_id).await?;
+    let tasks = store.list_tasks(block_id).await?;
+    let images = store.list_images(block_id).await?;
+    let mut annotations = Vec::new();
+    for image in &images {
+        annotations.extend(store.list_annotations(&image.image_id).await?);
+    }
+
+    let mut snapshot = Snapshot {
+        block_id: block_id.to_string(),
+        version: 0,
+        s3_key: String::new(),
+        created_by: created_by.to_string(),
+        created_at: chrono::Utc::now().to_rfc3339(),
+        label_count: labels.len() as u32,
+        task_count: tasks.len() as u32,
+        image_count: images.len() as u32,
+        annotation_count: annotations.len() as u32,
+    };
+
+    // Versions are claimed by the conditional put, so two snapshots taken
+    // at once still get different ones
+    let mut claimed = false;
+    for _ in 0..VERSION_ATTEMPTS {
+        let latest = store.list_snapshots(block_id).await?.last().map_or(0, |s| s.version);
+        snapshot.version = latest + 1;
+        snapshot.s3_key = snapshot_s3_key(block_id, snapshot.version);
+        let result = store
+            .transact(vec![
+                WriteOp::PutSnapshot(snapshot.clone()),
+                WriteOp::Increment { key: EntityKey::block(block_id), deltas: vec![] },
+            ])
+            .await;
+        match result {
+            Ok(()) => {
+                claimed = true;
+                break;
+            }
+            Err(e) if store.get_snapshot(block_id, snapshot.version).await?.is_none() => return Err(e),
+            Err(_) => {}
+        }
+    }
+    if !claimed {
+        return Err("Could not claim a snapshot version".to_string());
+    }
+
+    Ok(SnapshotArchive { snapshot, block, labels, tasks, images, annotations })
+}
+
+/// Take a snapshot and upload its archive. If the upload fails the index
+/// row is removed again, so every listed snapshot can be downloaded.
+pub async fn create_snapshot<S: Store>(
+    store: &S,
+    s3_client: &S3Client,
+    block_id: &str,
+    created_by: &str,
+) -> Result<Snapshot, String> {
+    let archive = record_snapshot(store, block_id, created_by).await?;
+    let snapshot = archive.snapshot.clone();
+    let body = serde_json::to_vec(&archive).map_err(|e| format!("Failed to serialize snapshot: {}", e))?;
+
+    let uploaded = s3_client
+        .put_object()
+        .bucket(bucket_name())
+        .key(&snapshot.s3_key)
+        .content_type("application/json")
+        // Never overwrite an archive
+        .if_none_match("*")
+        .body(ByteStream::from(body))
+        .send()
+        .await;
+    if let Err(e) = uploaded {
+        tracing::error!("Failed to upload snapshot {}: {}", snapshot.s3_key, e);
+        store.delete_batch(&[EntityKey::snapshot(block_id, snapshot.version)]).await?;
+        return Err(format!("Failed to upload snapshot: {}", e));
+    }
+
+    Ok(snapshot)
+}
+
+/// Read a snapshot's archive back from S3
+pub async fn load_archive(s3_client: &S3Client, snapshot: &Snapshot) -> Result<SnapshotArchive, String> {
+    let object = s3_client
+        .get_object()
+        .bucket(bucket_name())
+        .key(&snapshot.s3_key)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to read snapshot {}: {}", snapshot.s3_key, e))?;
+    let bytes = object
+        .body
+        .collect()
+        .await
+        .map_err(|e| format!("Failed to read snapshot {}: {}", snapshot.s3_key, e))?
+        .into_bytes();
+    serde_json::from_slice(&bytes).map_err(|e| format!("Failed to parse snapshot {}: {}", snapshot.s3_key, e))
+}
+
+fn bucket_name() -> String {
+    std::env::var("S3_BUCKET_NAME").unwrap_or_else(|_| "doxle-app".to_string())
+}
+
+/// POST /blocks/{bid}/snapshots
+pub async fn create_snapshot_handler(
+    client: &DynamoClient,
+    table_name: &str,
+    s3_client: &S3Client,
+    user_id: &str,
+    block_id: &str,
+) -> Result<Response<Body>, Error> {
+    let store = DynamoStore::new(client, table_name);
+    match create_snapshot(&store, s3_client, block_id, user_id).await {
+        Ok(snapshot) => Ok(Response::builder()
+            .status(StatusCode::CREATED)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::to_string(&snapshot)?.into())
+            .map_err(Box::new)?),
+        Err(e) if e == "Block not found" => not_found(&e),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// GET /blocks/{bid}/snapshots - oldest first
+pub async fn list_snapshots_handler(
+    client: &DynamoClient,
+    table_name: &str,
+    block_id: &str,
+) -> Result<Response<Body>, Error> {
+    let snapshots = DynamoStore::new(client, table_name).list_snapshots(block_id).await?;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .header("Access-Control-Allow-Origin", "*")
+        .body(serde_json::to_string(&snapshots)?.into())
+        .map_err(Box::new)?)
+}
+
+/// GET /blocks/{bid}/snapshots/{version}
+pub async fn get_snapshot_handler(
+    client: &DynamoClient,
+    table_name: &str,
+    block_id: &str,
+    version: u32,
+) -> Result<Response<Body>, Error> {
+    match DynamoStore::new(client, table_name).get_snapshot(block_id, version).await? {
+        Some(snapshot) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::to_string(&snapshot)?.into())
+            .map_err(Box::new)?),
+        None => not_found("Snapshot not found"),
+    }
+}
+
+/// GET /blocks/{bid}/snapshots/{version}/archive - the whole snapshot as one
+/// JSON download
+pub async fn download_snapshot_handler(
+    client: &DynamoClient,
+    table_name: &str,
+    s3_client: &S3Client,
+    block_id: &str,
+    version: u32,
+) -> Result<Response<Body>, Error> {
+    let Some(snapshot) = DynamoStore::new(client, table_name).get_snapshot(block_id, version).await? else {
+        return not_found("Snapshot not found");
+    };
+    let archive = load_archive(s3_client, &snapshot).await?;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}-v{}.json\"", block_id, version),
+        )
+        .header("Access-Control-Allow-Origin", "*")
+        .body(serde_json::to_string(&archive)?.into())
+        .map_err(Box::new)?)
+}
+
+fn not_found(error: &str) -> Result<Response<Body>, Error> {
+    Ok(Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .header("Content-Type", "application/json")
+        .header("Access-Control-Allow-Origin", "*")
+        .body(serde_json::json!({ "error": error }).to_string().into())
+        .map_err(Box::new)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use doxle_atoms::drawing::model::{CreateAnnotationPayload, Geometry, Point};
+    use doxle_atoms::media::model::CreateImagePayload;
+    use doxle_atoms::store::{BlockRepo, MemoryStore};
+    use doxle_atoms::fixtures::test_block;
+
+    #[tokio::test]
+    async fn test_snapshots_freeze_live_rows_under_new_versions() {
+        let store = MemoryStore::new();
+        store.put_block(&test_block("b1")).await.unwrap();
+        let payload = CreateImagePayload { url: "a.jpg".to_string(), task_id: None, order: None, width: None, height: None };
+        let image = doxle_atoms::media::service::create_image(&store, "b1", payload).await.unwrap();
+        let bbox = || CreateAnnotationPayload {
+            label_id: "l1".to_string(),
+            geometry: Geometry::BBox { start: Point { x: 0.0, y: 0.0 }, end: Point { x: 1.0, y: 1.0 } },
+            attributes: Default::default(),
+            cleanup: None,
+        };
+        let kept = doxle_atoms::drawing::service::create_annotation(&store, "b1", &image.image_id, "u1", bbox())
+            .await
+            .unwrap();
+        let trashed = doxle_atoms::drawing::service::create_annotation(&store, "b1", &image.image_id, "u1", bbox())
+            .await
+            .unwrap();
+        doxle_atoms::drawing::service::delete_annotation(&store, "b1", &image.image_id, &trashed.annotation_id, None, "u1")
+            .await
+            .unwrap();
+
+        let first = record_snapshot(&store, "b1", "u1").await.unwrap();
+        assert_eq!(first.snapshot.version, 1);
+        assert_eq!(first.snapshot.s3_key, snapshot_s3_key("b1", 1));
+        assert_eq!(first.images.len(), 1);
+        let ids: Vec<_> = first.annotations.iter().map(|a| a.annotation_id.as_str()).collect();
+        assert_eq!(ids, [kept.annotation_id.as_str()]);
+
+        let second = record_snapshot(&store, "b1", "u2").await.unwrap();
+        assert_eq!(second.snapshot.version, 2);
+        let versions: Vec<_> = store.list_snapshots("b1").await.unwrap().iter().map(|s| s.version).collect();
+        assert_eq!(versions, [1, 2]);
+
+        assert_eq!(record_snapshot(&store, "missing", "u1").await.unwrap_err(), "Block not found");
+    }
+}
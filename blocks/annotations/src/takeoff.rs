@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+
+use aws_sdk_dynamodb::Client as DynamoClient;
+use doxle_atoms::drawing::geometry;
+use doxle_atoms::media::scale::{Calibration, DrawingScale, INVALID_SCALE};
+use doxle_atoms::store::{DynamoStore, Store};
+use lambda_http::{http::StatusCode, Body, Error, Response};
+use serde::Serialize;
+
+use crate::labels::fetch_labels_for_block;
+
+/// Where an image's scale came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScaleSource {
+    /// Calibrated and stored on the image
+    Image,
+    /// The fallback given with the request
+    Request,
+}
+
+/// Quantities of one label. Lengths are polyline lengths and shape
+/// perimeters; metres are missing when no scale is known.
+#[derive(Debug, Clone, Serialize)]
+pub struct TakeoffLine {
+    pub label_id: String,
+    pub label_name: String,
+    pub count: u32,
+    pub length_px: f64,
+    pub area_px: f64,
+    pub length_m: Option<f64>,
+    pub area_m2: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageTakeoff {
+    pub image_id: String,
+    pub scale_source: Option<ScaleSource>,
+    pub mm_per_pixel: Option<f64>,
+    pub lines: Vec<TakeoffLine>,
+}
+
+/// Block totals per label. Metres only add up scaled images; `unscaled`
+/// counts the annotations left out of them.
+#[derive(Debug, Clone, Serialize)]
+pub struct TakeoffTotal {
+    pub label_id: String,
+    pub label_name: String,
+    pub count: u32,
+    pub length_m: f64,
+    pub area_m2: f64,
+    pub unscaled: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Takeoff {
+    pub block_id: String,
+    pub images: Vec<ImageTakeoff>,
+    pub totals: Vec<TakeoffTotal>,
+    /// Images with no stored scale and no fallback
+    pub unscaled_images: Vec<String>,
+}
+
+/// Count, length and area of every label's annotations, per image and per
+/// block. `fallback` scales images that have no calibrated scale.
+pub async fn block_takeoff<S: Store>(
+    store: &S,
+    block_id: &str,
+    fallback: Option<Calibration>,
+) -> Result<Takeoff, String> {
+    store
+        .get_block(block_id)
+        .await?
+        .filter(|b| b.deleted_at.is_none())
+        .ok_or("Block not found")?;
+    let fallback = fallback.map(DrawingScale::calibrate).transpose()?;
+    let labels = fetch_labels_for_block(store, block_id).await?;
+    let images = doxle_atoms::media::service::load_images_for_block(store, block_id).await?;
+
+    let mut totals: Vec<TakeoffTotal> = labels
+        .iter()
+        .map(|label| TakeoffTotal {
+            label_id: label.label_id.clone(),
+            label_name: label.label_name.clone(),
+            count: 0,
+            length_m: 0.0,
+            area_m2: 0.0,
+            unscaled: 0,
+        })
+        .collect();
+    let rank: HashMap<&str, usize> = labels.iter().enumerate().map(|(i, l)| (l.label_id.as_str(), i)).collect();
+    let mut takeoff = Takeoff { block_id: block_id.to_string(), images: vec![], totals: vec![], unscaled_images: vec![] };
+
+    for image in images {
+        let (scale, scale_source) = match (image.scale, &fallback) {
+            (Some(scale), _) => (Some(scale), Some(ScaleSource::Image)),
+            (None, Some(scale)) => (Some(scale.clone()), Some(ScaleSource::Request)),
+            (None, None) => (None, None),
+        };
+        if scale.is_none() {
+            takeoff.unscaled_images.push(image.image_id.clone());
+        }
+
+        // Lines follow the block's label order
+        let mut lines: Vec<TakeoffLine> = Vec::new();
+        let mut line_of: HashMap<String, usize> = HashMap::new();
+        let mut annotations = store.list_annotations(&image.image_id).await?;
+        annotations.sort_by_key(|a| rank.get(a.label_id.as_str()).copied().unwrap_or(usize::MAX));
+        for annotation in annotations {
+            let i = *line_of.entry(annotation.label_id.clone()).or_insert_with(|| {
+                let label_name = labels
+                    .iter()
+                    .find(|l| l.label_id == annotation.label_id)
+                    .map(|l| l.label_name.clone())
+                    .unwrap_or_else(|| annotation.label_id.clone());
+                lines.push(TakeoffLine {
+                    label_id: annotation.label_id.clone(),
+                    label_name,
+                    count: 0,
+                    length_px: 0.0,
+                    area_px: 0.0,
+                    length_m: None,
+                    area_m2: None,
+                });
+                lines.len() - 1
+            });
+            lines[i].count += 1;
+            lines[i].length_px += geometry::perimeter(&annotation.geometry);
+            lines[i].area_px += geometry::area(&annotation.geometry);
+        }
+
+        for line in &mut lines {
+            if let Some(scale) = &scale {
+                line.length_m = Some(scale.metres(line.length_px));
+                line.area_m2 = Some(scale.square_metres(line.area_px));
+            }
+            let total = match totals.iter_mut().find(|t| t.label_id == line.label_id) {
+                Some(total) => total,
+                None => {
+                    totals.push(TakeoffTotal {
+                        label_id: line.label_id.clone(),
+                        label_name: line.label_name.clone(),
+                        count: 0,
+                        length_m: 0.0,
+                        area_m2: 0.0,
+                        unscaled: 0,
+                    });
+                    totals.last_mut().unwrap()
+                }
+            };
+            total.count += line.count;
+            total.length_m += line.length_m.unwrap_or(0.0);
+            total.area_m2 += line.area_m2.unwrap_or(0.0);
+            if scale.is_none() {
+                total.unscaled += line.count;
+            }
+        }
+
+        takeoff.images.push(ImageTakeoff {
+            image_id: image.image_id,
+            scale_source,
+            mm_per_pixel: scale.map(|s| s.mm_per_pixel),
+            lines,
+        });
+    }
+
+    takeoff.totals = totals;
+    Ok(takeoff)
+}
+
+/// One row per image and label, then one `total` row per label; a total is
+/// `partial` when some of its annotations had no scale
+pub fn takeoff_csv(takeoff: &Takeoff) -> String {
+    let number = |value: Option<f64>| value.map(|v| format!("{:.3}", v)).unwrap_or_default();
+    let mut csv = String::from("image_id,label_id,label_name,count,length_m,area_m2,length_px,area_px,scale\n");
+    for image in &takeoff.images {
+        let scale = match image.scale_source {
+            Some(ScaleSource::Image) => "image",
+            Some(ScaleSource::Request) => "request",
+            None => "none",
+        };
+        for line in &image.lines {
+            let row = [
+                csv_field(&image.image_id),
+                csv_field(&line.label_id),
+                csv_field(&line.label_name),
+                line.count.to_string(),
+                number(line.length_m),
+                number(line.area_m2),
+                number(Some(line.length_px)),
+                number(Some(line.area_px)),
+                scale.to_string(),
+            ];
+            csv.push_str(&row.join(","));
+            csv.push('\n');
+        }
+    }
+    for total in &takeoff.totals {
+        let scale = if total.unscaled > 0 { "partial" } else { "complete" };
+        let row = [
+            "total".to_string(),
+            csv_field(&total.label_id),
+            csv_field(&total.label_name),
+            total.count.to_string(),
+            number(Some(total.length_m)),
+            number(Some(total.area_m2)),
+            String::new(),
+            String::new(),
+            scale.to_string(),
+        ];
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Quote a field that holds a comma, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// GET /blocks/{bid}/takeoff[?format=csv][&pixels_per_metre= | &ratio=&dpi=]
+pub async fn block_takeoff_handler(
+    client: &DynamoClient,
+    table_name: &str,
+    block_id: &str,
+    fallback: Option<Calibration>,
+    csv: bool,
+) -> Result<Response<Body>, Error> {
+    let store = DynamoStore::new(client, table_name);
+    match block_takeoff(&store, block_id, fallback).await {
+        Ok(takeoff) if csv => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "text/csv")
+            .header("Content-Disposition", format!("attachment; filename=\"{}-takeoff.csv\"", block_id))
+            .header("Access-Control-Allow-Origin", "*")
+            .body(takeoff_csv(&takeoff).into())
+            .map_err(Box::new)?),
+        Ok(takeoff) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::to_string(&takeoff)?.into())
+            .map_err(Box::new)?),
+        Err(e) if e == "Block not found" => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": e}).to_string().into())
+            .map_err(Box::new)?),
+        Err(e) if e.starts_with(INVALID_SCALE) => Ok(Response::builder()
+            .status(StatusCode::UNPROCESSABLE_ENTITY)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": e}).to_string().into())
+            .map_err(Box::new)?),
+        Err(e) => Ok(Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": e}).to_string().into())
+            .map_err(Box::new)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use doxle_atoms::drawing::model::{CreateAnnotationPayload, Geometry, Point};
+    use doxle_atoms::media::model::CreateImagePayload;
+    use doxle_atoms::store::{BlockRepo, LabelRepo, MemoryStore};
+    use doxle_atoms::fixtures::{test_block, test_label};
+
+    #[tokio::test]
+    async fn test_takeoff_totals_scaled_images_and_flags_the_rest() {
+        let store = MemoryStore::new();
+        store.put_block(&test_block("b1")).await.unwrap();
+        let wall = test_label("b1", "l1", "iwalls, ground");
+        store.put_label(&wall).await.unwrap();
+
+        let p = |x, y| Point { x, y };
+        let mut image_ids = vec![];
+        for _ in 0..2 {
+            let payload = CreateImagePayload { url: "a.jpg".to_string(), task_id: None, order: None, width: None, height: None };
+            let image = doxle_atoms::media::service::create_image(&store, "b1", payload).await.unwrap();
+            let annotation = CreateAnnotationPayload {
+                label_id: wall.label_id.clone(),
+                geometry: Geometry::Polyline { points: vec![p(0.0, 0.0), p(300.0, 400.0)] },
+                attributes: Default::default(),
+                cleanup: None,
+            };
+            doxle_atoms::drawing::service::create_annotation(&store, "b1", &image.image_id, "u1", annotation)
+                .await
+                .unwrap();
+            image_ids.push(image.image_id);
+        }
+        let calibration = Calibration::PixelsPerMetre { pixels_per_metre: 100.0 };
+        doxle_atoms::media::service::calibrate_image(&store, "b1", &image_ids[0], calibration).await.unwrap();
+
+        let takeoff = block_takeoff(&store, "b1", None).await.unwrap();
+        assert_eq!(takeoff.unscaled_images, vec![image_ids[1].clone()]);
+        let total = &takeoff.totals[0];
+        assert_eq!((total.count, total.length_m, total.unscaled), (2, 5.0, 1));
+
+        // A request fallback fills in the uncalibrated image only
+        let fallback = Calibration::PixelsPerMetre { pixels_per_metre: 50.0 };
+        let takeoff = block_takeoff(&store, "b1", Some(fallback)).await.unwrap();
+        assert!(takeoff.unscaled_images.is_empty());
+        assert_eq!(takeoff.totals[0].length_m, 15.0);
+        assert!(takeoff_csv(&takeoff).contains("total,") && takeoff_csv(&takeoff).contains("\"iwalls, ground\""));
+
+        let entity = doxle_atoms::trash::TrashedEntity::Block { block_id: "b1".to_string() };
+        doxle_atoms::trash::service::trash(&store, entity, "u1", None).await.unwrap();
+        assert_eq!(block_takeoff(&store, "b1", None).await.unwrap_err(), "Block not found");
+    }
+}
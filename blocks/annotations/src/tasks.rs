@@ -1,6 +1,8 @@
 use lambda_http::{Body, Error, Response, http::StatusCode};
 use aws_sdk_dynamodb::Client as DynamoClient;
 use doxle_atoms::{tasks, media};
+use doxle_atoms::store::version::etag;
+use doxle_atoms::store::{DynamoStore, Page, PageRequest, TaskRepo, INVALID_CURSOR, VERSION_CONFLICT};
 use std::collections::HashMap;
 
 /// Create a new task
@@ -10,35 +12,56 @@ pub async fn create_task(
     block_id: &str,
     body: &[u8],
 ) -> Result<Response<Body>, Error> {
+    let store = DynamoStore::new(client, table_name);
     let payload: tasks::model::CreateTaskPayload = serde_json::from_slice(body)?;
     
-    let task = tasks::service::create_task(client, table_name, block_id, payload)
+    let task = tasks::service::create_task(&store, block_id, payload)
         .await
-        .map_err(|e| Box::new(std::io::Error::new(std::io::ErrorKind::Other, e)) as Box<dyn std::error::Error + Send + Sync>)?;
+        .map_err(|e| Box::new(std::io::Error::other(e)) as Box<dyn std::error::Error + Send + Sync>)?;
     
     Ok(Response::builder()
         .status(StatusCode::CREATED)
         .header("Content-Type", "application/json")
+        .header("ETag", etag(task.version))
         .header("Access-Control-Allow-Origin", "*")
         .body(serde_json::to_string(&task)?.into())
         .map_err(Box::new)?)
 }
 
 /// List all tasks for a block (returns tasks WITH images)
+/// Returns a bare array, or a `Page` when `?limit=`/`?cursor=` is given
 pub async fn list_block_tasks(
     client: &DynamoClient,
     table_name: &str,
     block_id: &str,
+    page: &PageRequest,
 ) -> Result<Response<Body>, Error> {
+    let store = DynamoStore::new(client, table_name);
     // 1) Load tasks via domain service (images empty)
-    let mut task_rows = tasks::service::load_tasks_for_block(client, table_name, block_id)
-        .await
-        .map_err(|e| Box::new(std::io::Error::new(std::io::ErrorKind::Other, e)) as Box<dyn std::error::Error + Send + Sync>)?;
+    let loaded = if page.is_paged() {
+        tasks::service::load_tasks_page(&store, block_id, page).await
+    } else {
+        tasks::service::load_tasks_for_block(&store, block_id)
+            .await
+            .map(|items| Page { items, next_cursor: None })
+    };
+    let Page { items: mut task_rows, next_cursor } = match loaded {
+        Ok(page) => page,
+        Err(e) if e == INVALID_CURSOR => {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .header("Content-Type", "application/json")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(serde_json::json!({"error": e}).to_string().into())
+                .map_err(Box::new)?);
+        }
+        Err(e) => return Err(Box::new(std::io::Error::other(e))),
+    };
 
     // 2) Load ALL images for block
-    let image_rows = media::service::load_images_for_block(client, table_name, block_id)
+    let image_rows = media::service::load_images_for_block(&store, block_id)
         .await
-        .map_err(|e| Box::new(std::io::Error::new(std::io::ErrorKind::Other, e)) as Box<dyn std::error::Error + Send + Sync>)?;
+        .map_err(|e| Box::new(std::io::Error::other(e)) as Box<dyn std::error::Error + Send + Sync>)?;
 
     // 3) Group images by task_id
     let mut images_by_task: HashMap<String, Vec<media::model::Image>> = HashMap::new();
@@ -56,46 +79,64 @@ pub async fn list_block_tasks(
     // Sort by created_at desc (newest first)
     task_rows.sort_by(|a, b| b.created_at.cmp(&a.created_at));
 
+    let body = if page.is_paged() {
+        serde_json::to_string(&Page { items: task_rows, next_cursor })?
+    } else {
+        serde_json::to_string(&task_rows)?
+    };
+
     Ok(Response::builder()
         .status(StatusCode::OK)
         .header("Content-Type", "application/json")
         .header("Access-Control-Allow-Origin", "*")
-        .body(serde_json::to_string(&task_rows)?.into())
+        .body(body.into())
         .map_err(Box::new)?)
 }
 
 /// Update a task
+/// `expected_version` comes from `If-Match`; a stale one gets a 409
 pub async fn update_task(
     client: &DynamoClient,
     table_name: &str,
     block_id: &str,
     task_id: &str,
+    expected_version: Option<u64>,
     body: &[u8],
 ) -> Result<Response<Body>, Error> {
+    let store = DynamoStore::new(client, table_name);
     let payload: tasks::model::UpdateTaskPayload = serde_json::from_slice(body)?;
     
-    let task = tasks::service::update_task(client, table_name, block_id, task_id, payload)
-        .await
-        .map_err(|e| Box::new(std::io::Error::new(std::io::ErrorKind::Other, e)) as Box<dyn std::error::Error + Send + Sync>)?;
+    let task = match tasks::service::update_task(&store, block_id, task_id, payload, expected_version).await {
+        Ok(task) => task,
+        Err(e) if e == VERSION_CONFLICT => return conflict_response(&store, block_id, task_id).await,
+        Err(e) => return Err(Box::new(std::io::Error::other(e))),
+    };
     
     Ok(Response::builder()
         .status(StatusCode::OK)
         .header("Content-Type", "application/json")
+        .header("ETag", etag(task.version))
         .header("Access-Control-Allow-Origin", "*")
         .body(serde_json::to_string(&task)?.into())
         .map_err(Box::new)?)
 }
 
-/// Delete a task
+/// Move a task and its images to the trash
+/// `expected_version` comes from `If-Match`; a stale one gets a 409
 pub async fn delete_task(
     client: &DynamoClient,
     table_name: &str,
+    user_id: &str,
     block_id: &str,
     task_id: &str,
+    expected_version: Option<u64>,
 ) -> Result<Response<Body>, Error> {
-    tasks::service::delete_task(client, table_name, block_id, task_id)
-        .await
-        .map_err(|e| Box::new(std::io::Error::new(std::io::ErrorKind::Other, e)) as Box<dyn std::error::Error + Send + Sync>)?;
+    let store = DynamoStore::new(client, table_name);
+    match tasks::service::delete_task(&store, block_id, task_id, expected_version, user_id).await {
+        Ok(_) => {}
+        Err(e) if e == VERSION_CONFLICT => return conflict_response(&store, block_id, task_id).await,
+        Err(e) => return Err(Box::new(std::io::Error::other(e))),
+    }
     
     Ok(Response::builder()
         .status(StatusCode::NO_CONTENT)
@@ -111,19 +152,40 @@ pub async fn get_task(
     block_id: &str,
     task_id: &str,
 ) -> Result<Response<Body>, Error> {
-    let task = tasks::service::get_task(client, table_name, block_id, task_id)
+    let store = DynamoStore::new(client, table_name);
+    let task = tasks::service::get_task(&store, block_id, task_id)
         .await
         .map_err(|e| {
             if e == "Task not found" {
                 return Box::new(std::io::Error::new(std::io::ErrorKind::NotFound, e)) as Box<dyn std::error::Error + Send + Sync>;
             }
-            Box::new(std::io::Error::new(std::io::ErrorKind::Other, e)) as Box<dyn std::error::Error + Send + Sync>
+            Box::new(std::io::Error::other(e)) as Box<dyn std::error::Error + Send + Sync>
         })?;
     
     Ok(Response::builder()
         .status(StatusCode::OK)
         .header("Content-Type", "application/json")
+        .header("ETag", etag(task.version))
         .header("Access-Control-Allow-Origin", "*")
         .body(serde_json::to_string(&task)?.into())
         .map_err(Box::new)?)
 }
+
+/// 409 carrying the task as the server has it; 404 if it was deleted
+async fn conflict_response(store: &DynamoStore, block_id: &str, task_id: &str) -> Result<Response<Body>, Error> {
+    match store.get_task(block_id, task_id).await?.filter(|t| t.deleted_at.is_none()) {
+        Some(current) => Ok(Response::builder()
+            .status(StatusCode::CONFLICT)
+            .header("Content-Type", "application/json")
+            .header("ETag", etag(current.version))
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": VERSION_CONFLICT, "current": current}).to_string().into())
+            .map_err(Box::new)?),
+        None => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": "Task not found"}).to_string().into())
+            .map_err(Box::new)?),
+    }
+}
@@ -0,0 +1,329 @@
+use aws_sdk_dynamodb::Client as DynamoClient;
+use crate::labels::unprocessable;
+use doxle_atoms::labels::model::{Label, LabelTemplate, PutLabelTemplatePayload};
+use doxle_atoms::labels::AttributeSchema;
+use doxle_atoms::blocks::model::Block;
+use doxle_atoms::store::{DynamoStore, LabelTemplateRepo, Store, UserRepo, WriteOp, MAX_TRANSACT_OPS};
+use lambda_http::{http::StatusCode, Body, Error, Response};
+
+/// Labels a template may hold, so a new block and all of them fit in one
+/// transaction
+pub const MAX_TEMPLATE_LABELS: usize = MAX_TRANSACT_OPS - 1;
+
+/// Write a new block together with its template's labels, in one
+/// transaction so a failure leaves neither; a block type without a template
+/// starts with none
+pub async fn seed_labels<S: Store>(store: &S, block: &Block) -> Result<Vec<Label>, String> {
+    let template = store.get_label_template(&block.block_type).await?;
+
+    let mut labels = Vec::new();
+    for entry in template.map(|t| t.labels).unwrap_or_default() {
+        let label = Label {
+            label_id: uuid::Uuid::new_v4().to_string(),
+            block_id: block.block_id.clone(),
+            label_name: entry.label_name,
+            label_color: entry.label_color,
+            label_properties: entry.label_properties,
+            label_count: 0,
+            label_order: Some(entry.label_order),
+            version: 1,
+        };
+        labels.push(label);
+    }
+
+    let mut ops = vec![WriteOp::PutBlock(block.clone())];
+    ops.extend(labels.iter().cloned().map(WriteOp::PutLabel));
+    store.transact(ops).await?;
+    Ok(labels)
+}
+
+/// Stored order first, then the template's order for the label's name,
+/// then name; labels with neither order go last
+pub fn sort_labels(labels: &mut [Label], block_type: &str, template: Option<&LabelTemplate>) {
+    labels.sort_by_cached_key(|label| {
+        let order = label.label_order.or_else(|| template_order(template, block_type, &label.label_name));
+        (order.is_none(), order, label.label_name.clone())
+    });
+}
+
+/// Order of `label_name` in the stored template for `block_type`, or in
+/// the built-in order for block types that have no stored template
+pub fn template_order(template: Option<&LabelTemplate>, block_type: &str, label_name: &str) -> Option<u32> {
+    match template {
+        Some(template) => template.order_of(label_name),
+        None => order_index_for(block_type, label_name),
+    }
+}
+
+// Orders from before templates were stored
+const FLOOR_PLAN_ORDER: [&str; 20] = [
+    "fp-outside",
+    "fp-inside",
+    "ewalls",
+    "windows",
+    "iwalls",
+    "doors",
+    "cav-slider",
+    "stairs",
+    "robes",
+    "toilet",
+    "vanity",
+    "shower",
+    "bathtub",
+    "sink",
+    "outbuilding",
+    "scale",
+    "dims",
+    "area",
+    "title",
+    "legend",
+];
+
+const ELEVATION_ORDER: [&str; 15] = [
+    "gf-wall",
+    "gf-window",
+    "gf-roof",
+    "ff-wall",
+    "ff-window",
+    "ff-roof",
+    "sf-wall",
+    "sf-window",
+    "sf-roof",
+    "skylight",
+    "fence",
+    "dims",
+    "area",
+    "title",
+    "legend",
+];
+
+const ELECTRICAL_PLAN_ORDER: [&str; 3] = [
+    "downlight",
+    "gpo-single",
+    "gpo-double",
+];
+
+const ROOF_PLAN_ORDER: [&str; 1] = [
+    "box-gutter",
+];
+
+/// Position of `label_name` in the built-in order for `block_type`
+fn order_index_for(block_type:&str, label_name:&str) -> Option<u32> {
+    let list: &[&str] = match block_type {
+        "floor" => &FLOOR_PLAN_ORDER,
+        "elevation" => &ELEVATION_ORDER,
+        "electrical" => &ELECTRICAL_PLAN_ORDER,
+        "roof" => &ROOF_PLAN_ORDER,
+        _ => return None,
+    };
+    
+    list.iter()
+        .position(|&name| name == label_name)
+        .map(|pos| pos as u32)
+}
+
+/// GET /label-templates
+pub async fn list_label_templates(client: &DynamoClient, table_name: &str) -> Result<Response<Body>, Error> {
+    let templates = DynamoStore::new(client, table_name).list_label_templates().await?;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .header("Access-Control-Allow-Origin", "*")
+        .body(serde_json::to_string(&templates)?.into())
+        .map_err(Box::new)?)
+}
+
+/// GET /label-templates/{block_type}
+pub async fn get_label_template(
+    client: &DynamoClient,
+    table_name: &str,
+    block_type: &str,
+) -> Result<Response<Body>, Error> {
+    match DynamoStore::new(client, table_name).get_label_template(block_type).await? {
+        Some(template) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::to_string(&template)?.into())
+            .map_err(Box::new)?),
+        None => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": "Label template not found"}).to_string().into())
+            .map_err(Box::new)?),
+    }
+}
+
+/// PUT /label-templates/{block_type} - admin only; creates or replaces.
+/// Blocks created before the change keep their labels.
+pub async fn put_label_template(
+    client: &DynamoClient,
+    table_name: &str,
+    user_id: &str,
+    block_type: &str,
+    body: &[u8],
+) -> Result<Response<Body>, Error> {
+    let store = DynamoStore::new(client, table_name);
+    if let Some(resp) = require_admin(&store, user_id).await? {
+        return Ok(resp);
+    }
+
+    let req: PutLabelTemplatePayload = serde_json::from_slice(body)?;
+    if req.labels.len() > MAX_TEMPLATE_LABELS {
+        let error = format!("A template holds at most {} labels", MAX_TEMPLATE_LABELS);
+        return Ok(Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": error}).to_string().into())
+            .map_err(Box::new)?);
+    }
+    let mut names: Vec<&str> = req.labels.iter().map(|l| l.label_name.as_str()).collect();
+    names.sort_unstable();
+    if let Some(name) = names.windows(2).find(|pair| pair[0] == pair[1]).map(|pair| pair[0]) {
+        return Ok(Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": format!("Label {} appears twice", name)}).to_string().into())
+            .map_err(Box::new)?);
+    }
+    for label in &req.labels {
+        if let Err(e) = AttributeSchema::from_properties(label.label_properties.as_ref()) {
+            return unprocessable(&format!("{} ({})", e, label.label_name));
+        }
+    }
+
+    let template = LabelTemplate {
+        block_type: block_type.to_string(),
+        labels: req.labels,
+        updated_by: user_id.to_string(),
+        updated_at: chrono::Utc::now().to_rfc3339(),
+    };
+    store.put_label_template(&template).await?;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .header("Access-Control-Allow-Origin", "*")
+        .body(serde_json::to_string(&template)?.into())
+        .map_err(Box::new)?)
+}
+
+/// DELETE /label-templates/{block_type} - admin only
+pub async fn delete_label_template(
+    client: &DynamoClient,
+    table_name: &str,
+    user_id: &str,
+    block_type: &str,
+) -> Result<Response<Body>, Error> {
+    let store = DynamoStore::new(client, table_name);
+    if let Some(resp) = require_admin(&store, user_id).await? {
+        return Ok(resp);
+    }
+
+    store.delete_label_template(block_type).await?;
+
+    Ok(Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .header("Access-Control-Allow-Origin", "*")
+        .body(Body::Empty)
+        .map_err(Box::new)?)
+}
+
+/// 403 unless `user_id` is an admin
+pub(crate) async fn require_admin(store: &DynamoStore, user_id: &str) -> Result<Option<Response<Body>>, Error> {
+    let is_admin = store
+        .get_user(user_id)
+        .await?
+        .map(|u| u.user_role == "admin")
+        .unwrap_or(false);
+    if is_admin {
+        return Ok(None);
+    }
+
+    Ok(Some(
+        Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(serde_json::json!({"error": "Admin access required"}).to_string().into())
+            .map_err(Box::new)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::labels::fetch_labels_for_block;
+    use doxle_atoms::labels::model::TemplateLabel;
+    use doxle_atoms::store::{BlockRepo, LabelRepo, MemoryStore};
+    use doxle_atoms::fixtures::test_block;
+
+    fn entry(label_name: &str, label_order: u32) -> TemplateLabel {
+        TemplateLabel {
+            label_name: label_name.to_string(),
+            label_color: "#ff0000".to_string(),
+            label_order,
+            label_properties: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_new_blocks_get_template_labels_in_template_order() {
+        let store = MemoryStore::new();
+        let template = LabelTemplate {
+            block_type: "Site Plan".to_string(),
+            labels: vec![entry("Fence", 2), entry("Driveway", 1)],
+            updated_by: "admin".to_string(),
+            updated_at: "2024-01-01T00:00:00Z".to_string(),
+        };
+        store.put_label_template(&template).await.unwrap();
+
+        let block = Block { block_type: "Site Plan".to_string(), ..test_block("b1") };
+        let seeded = seed_labels(&store, &block).await.unwrap();
+        assert_eq!(seeded.len(), 2);
+        let unknown = Block { block_type: "Unknown".to_string(), ..test_block("b2") };
+        assert!(seed_labels(&store, &unknown).await.unwrap().is_empty());
+        assert!(store.get_block("b2").await.unwrap().is_some());
+        // A failed write leaves no labels behind
+        assert!(seed_labels(&store, &block).await.is_err());
+        assert_eq!(store.list_labels("b1").await.unwrap().len(), 2);
+
+        // A label added later without an order goes after the template's
+        let mut extra = seeded[0].clone();
+        extra.label_id = "extra".to_string();
+        extra.label_name = "Bin".to_string();
+        extra.label_order = None;
+        store.put_label(&extra).await.unwrap();
+
+        let names: Vec<_> = fetch_labels_for_block(&store, "b1")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|l| l.label_name)
+            .collect();
+        assert_eq!(names, ["Driveway", "Fence", "Bin"]);
+    }
+
+    #[tokio::test]
+    async fn test_block_types_without_a_template_keep_the_built_in_order() {
+        use doxle_atoms::fixtures::test_label;
+
+        let store = MemoryStore::new();
+        store.put_block(&Block { block_type: "floor".to_string(), ..test_block("b1") }).await.unwrap();
+        for (label_id, name) in [("l1", "doors"), ("l2", "bin"), ("l3", "ewalls")] {
+            store.put_label(&test_label("b1", label_id, name)).await.unwrap();
+        }
+
+        let names: Vec<_> = fetch_labels_for_block(&store, "b1")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|l| l.label_name)
+            .collect();
+        assert_eq!(names, ["ewalls", "doors", "bin"]);
+    }
+}
@@ -0,0 +1,79 @@
+use aws_sdk_s3::Client as S3Client;
+use doxle_atoms::store::{EntityKey, Store};
+use doxle_atoms::trash::{service as trash, TrashEntry, TrashedEntity};
+use lambda_http::Error;
+
+use crate::blocks::{delete_annotations, purge_block};
+
+/// Hard-delete everything whose retention ran out at `now` (RFC 3339).
+/// One failing entry does not stop the rest; returns the purged entries.
+pub async fn purge_expired<S: Store>(store: &S, s3_client: &S3Client, now: &str) -> Result<Vec<TrashEntry>, String> {
+    let mut purged = Vec::new();
+
+    for entry in trash::list_expired(store, now).await? {
+        match purge_entry(store, s3_client, &entry).await {
+            Ok(()) => purged.push(entry),
+            Err(e) => tracing::error!("Failed to purge trash entry {}: {}", entry.trash_id, e),
+        }
+    }
+
+    Ok(purged)
+}
+
+/// Delete the rows still trashed under `entry`, then the entry itself.
+/// Counters were already adjusted when the rows went to the trash.
+async fn purge_entry<S: Store>(store: &S, s3_client: &S3Client, entry: &TrashEntry) -> Result<(), Error> {
+    let ours = |row_trash_id: &Option<String>| row_trash_id.as_deref() == Some(entry.trash_id.as_str());
+    let mut delete_keys: Vec<EntityKey> = vec![];
+
+    match &entry.entity {
+        TrashedEntity::Block { block_id } => {
+            if store.get_block(block_id).await?.is_some_and(|b| ours(&b.trash_id)) {
+                purge_block(store, s3_client, block_id).await?;
+            }
+        }
+        TrashedEntity::Task { block_id, task_id } => {
+            for image_id in store.list_task_image_ids(task_id).await? {
+                if store.get_image(block_id, &image_id).await?.is_some_and(|i| ours(&i.trash_id)) {
+                    delete_image_keys(store, block_id, &image_id, Some(task_id), &mut delete_keys).await?;
+                }
+            }
+            if store.get_task(block_id, task_id).await?.is_some_and(|t| ours(&t.trash_id)) {
+                delete_keys.push(EntityKey::task(block_id, task_id));
+            }
+        }
+        TrashedEntity::Image { block_id, image_id } => {
+            if let Some(image) = store.get_image(block_id, image_id).await?.filter(|i| ours(&i.trash_id)) {
+                delete_image_keys(store, block_id, image_id, image.task_id.as_deref(), &mut delete_keys).await?;
+            }
+        }
+        TrashedEntity::Annotation { image_id, annotation_id, .. } => {
+            if store.get_annotation(image_id, annotation_id).await?.is_some_and(|a| ours(&a.trash_id)) {
+                delete_keys.push(EntityKey::annotation(image_id, annotation_id));
+            }
+        }
+    }
+
+    // The entry goes only once its rows have, so a failed purge is retried
+    store.delete_batch(&delete_keys).await?;
+    store.delete_batch(&[EntityKey::trash(&entry.trash_id)]).await?;
+    Ok(())
+}
+
+/// Collect an image, its annotations and its task index row, children
+/// first: rows are deleted in order, so whatever a failed purge leaves can
+/// still be found from its parent
+async fn delete_image_keys<S: Store>(
+    store: &S,
+    block_id: &str,
+    image_id: &str,
+    task_id: Option<&str>,
+    delete_keys: &mut Vec<EntityKey>,
+) -> Result<(), String> {
+    delete_annotations(store, image_id, delete_keys).await?;
+    delete_keys.push(EntityKey::image(block_id, image_id));
+    if let Some(task_id) = task_id {
+        delete_keys.push(EntityKey::task_image(task_id, image_id));
+    }
+    Ok(())
+}
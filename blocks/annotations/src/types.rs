@@ -27,29 +27,8 @@ pub struct UpdateImagePayload {
 
 
 // ========== LABELS ==========
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct Label {
-    pub label_id: String,
-    pub block_id: String,
-    pub label_name: String,
-    pub label_color: String,
-    pub label_properties: Option<serde_json::Value>,
-    pub label_count: u32,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct CreateLabelPayload {
-    pub label_name: String,
-    pub label_color: String,
-    pub label_properties: Option<serde_json::Value>,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct UpdateLabelPayload {
-    pub label_name: Option<String>,
-    pub label_color: Option<String>,
-    pub label_properties: Option<serde_json::Value>,
-}
+// Re-export from shared atoms
+pub use doxle_atoms::labels::model::{Label, CreateLabelPayload, UpdateLabelPayload};
 
 // ========== BLOCK RESPONSE (annotation) ==========
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -1,6 +1,7 @@
 use aws_sdk_dynamodb::{types::AttributeValue, Client as DynamoClient};
 use aws_sdk_s3::Client as S3Client;
 use doxle_atoms as atoms;
+use doxle_atoms::store::{version, PageRequest};
 use doxle_shared::{
     auth, cloudfront, contact, image_proxy, invites,
     s3_multipart, users, AppState,
@@ -41,8 +42,9 @@ fn with_cors_headers(mut resp: Response<Body>, request_origin: Option<&str>) ->
     );
     headers.insert(
         "Access-Control-Allow-Headers",
-        HeaderValue::from_static("Content-Type,Authorization,X-User-Id,Cookie"),
+        HeaderValue::from_static("Content-Type,Authorization,X-User-Id,Cookie,If-Match"),
     );
+    headers.insert("Access-Control-Expose-Headers", HeaderValue::from_static("ETag"));
     headers.append(VARY, HeaderValue::from_static("Origin"));
 
     resp
@@ -410,22 +412,25 @@ pub(crate) async fn function_handler(
         let resp = match (method, parts.as_slice()) {
             // --- BLOCKS ---
             // GET /blocks - list all blocks
-            (&Method::GET, ["blocks"]) => match blocks::list_blocks(&state.dynamo_client, &table_name).await {
-                Ok(resp) => Ok(resp),
-                Err(e) => {
-                    tracing::error!("Failed to list blocks: {}", e);
-                    Ok(Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .header("Content-Type", "application/json")
-                        .body(
-                            serde_json::json!({
-                                "error": e.to_string()
-                            })
-                            .to_string()
-                            .into(),
-                        )
-                        .map_err(Box::new)?)
-                }
+            (&Method::GET, ["blocks"]) => match page_request(&event) {
+                Err(e) => bad_request(&e),
+                Ok(page) => match blocks::list_blocks(&state.dynamo_client, &table_name, &page).await {
+                    Ok(resp) => Ok(resp),
+                    Err(e) => {
+                        tracing::error!("Failed to list blocks: {}", e);
+                        Ok(Response::builder()
+                            .status(StatusCode::INTERNAL_SERVER_ERROR)
+                            .header("Content-Type", "application/json")
+                            .body(
+                                serde_json::json!({
+                                    "error": e.to_string()
+                                })
+                                .to_string()
+                                .into(),
+                            )
+                            .map_err(Box::new)?)
+                    }
+                },
             },
             // POST /blocks - create block
             (&Method::POST, ["blocks"]) => blocks::create_block(&state.dynamo_client, &table_name, body).await,
@@ -437,17 +442,148 @@ pub(crate) async fn function_handler(
             (&Method::PATCH, ["blocks", block_id]) => {
                 blocks::update_block(&state.dynamo_client, &table_name, block_id, body).await
             }
-            // DELETE /blocks/{id} - delete block
+            // DELETE /blocks/{id} - move block to the trash
             (&Method::DELETE, ["blocks", block_id]) => {
                 blocks::delete_block(
                     &state.dynamo_client,
-                    &state.s3_client,
                     &table_name,
+                    &user_id,
                     &block_id,
                 )
                 .await
             }
 
+            // POST /blocks/{bid}/clone - copy labels, optionally tasks, images and annotations into a new block
+            (&Method::POST, ["blocks", block_id, "clone"]) => {
+                blocks::clone_block_handler(&state.dynamo_client, &table_name, &state.s3_client, block_id, body).await
+            }
+
+            // GET /blocks/{bid}/takeoff - quantities per label, per image and block (?format=csv;
+            // ?pixels_per_metre= or ?ratio=1:100&dpi= scales images with no calibrated scale)
+            (&Method::GET, ["blocks", block_id, "takeoff"]) => match takeoff_fallback(&event) {
+                Ok(fallback) => {
+                    let csv = event
+                        .query_string_parameters_ref()
+                        .and_then(|params| params.first("format"))
+                        .is_some_and(|format| format == "csv");
+                    annotations_block::takeoff::block_takeoff_handler(
+                        &state.dynamo_client,
+                        &table_name,
+                        block_id,
+                        fallback,
+                        csv,
+                    )
+                    .await
+                }
+                Err(e) => bad_request(&e),
+            },
+
+            // GET /blocks/{bid}/export - training dataset download (?format=coco, or ?format=yolo&mode=detect|segment
+            // as a zip; ?approved=true for done tasks only)
+            (&Method::GET, ["blocks", block_id, "export"]) => {
+                let params = event.query_string_parameters_ref();
+                match annotations_block::export::ExportFormat::from_query(
+                    params.and_then(|p| p.first("format")),
+                    params.and_then(|p| p.first("mode")),
+                ) {
+                    Ok(format) => {
+                        let approved = params.and_then(|p| p.first("approved")).is_some_and(|v| v == "true");
+                        annotations_block::export::export_block_handler(
+                            &state.dynamo_client,
+                            &table_name,
+                            block_id,
+                            format,
+                            approved,
+                        )
+                        .await
+                    }
+                    Err(e) => bad_request(&e),
+                }
+            }
+
+            // GET /blocks/{bid}/qa/duplicates - double-drawn annotations and overlaps of exclusive labels
+            // (?image_id= for one image, ?threshold= and ?overlap= as IoU)
+            (&Method::GET, ["blocks", block_id, "qa", "duplicates"]) => {
+                let params = event.query_string_parameters_ref();
+                match annotations_block::qa::DuplicateCheck::from_query(
+                    params.and_then(|p| p.first("threshold")),
+                    params.and_then(|p| p.first("overlap")),
+                ) {
+                    Ok(check) => {
+                        annotations_block::qa::find_duplicates_handler(
+                            &state.dynamo_client,
+                            &table_name,
+                            block_id,
+                            params.and_then(|p| p.first("image_id")),
+                            check,
+                        )
+                        .await
+                    }
+                    Err(e) => bad_request(&e),
+                }
+            }
+
+            // POST /blocks/{bid}/reconcile - recompute counters (admin, ?fix=true to write)
+            (&Method::POST, ["blocks", block_id, "reconcile"]) => {
+                let fix = event
+                    .query_string_parameters_ref()
+                    .and_then(|params| params.first("fix"))
+                    .map(|v| v == "true")
+                    .unwrap_or(false);
+                annotations_block::reconcile::reconcile_block_handler(
+                    &state.dynamo_client,
+                    &table_name,
+                    &user_id,
+                    block_id,
+                    fix,
+                )
+                .await
+            }
+
+            // --- SNAPSHOTS ---
+            // POST /blocks/{bid}/snapshots - freeze the block into a new snapshot version
+            (&Method::POST, ["blocks", block_id, "snapshots"]) => {
+                annotations_block::snapshots::create_snapshot_handler(
+                    &state.dynamo_client,
+                    &table_name,
+                    &state.s3_client,
+                    &user_id,
+                    block_id,
+                )
+                .await
+            }
+            // GET /blocks/{bid}/snapshots - list snapshots, oldest first
+            (&Method::GET, ["blocks", block_id, "snapshots"]) => {
+                annotations_block::snapshots::list_snapshots_handler(&state.dynamo_client, &table_name, block_id).await
+            }
+            // GET /blocks/{bid}/snapshots/{version} - get one snapshot
+            (&Method::GET, ["blocks", block_id, "snapshots", version]) => match version.parse() {
+                Ok(version) => {
+                    annotations_block::snapshots::get_snapshot_handler(
+                        &state.dynamo_client,
+                        &table_name,
+                        block_id,
+                        version,
+                    )
+                    .await
+                }
+                Err(_) => bad_request("Snapshot version must be a number"),
+            },
+            // GET /blocks/{bid}/snapshots/{version}/archive - download a snapshot as one JSON file
+            (&Method::GET, ["blocks", block_id, "snapshots", version, "archive"]) => match version.parse() {
+                Ok(version) => {
+                    annotations_block::snapshots::download_snapshot_handler(
+                        &state.dynamo_client,
+                        &table_name,
+                        &state.s3_client,
+                        block_id,
+                        version,
+                    )
+                    .await
+                }
+                Err(_) => bad_request("Snapshot version must be a number"),
+            },
+
             // --- LABELS ---
             // GET /blocks/{bid}/labels - list block labels
             (&Method::GET, ["blocks", block_id, "labels"]) => {
@@ -462,26 +598,50 @@ pub(crate) async fn function_handler(
                 labels::get_label(&state.dynamo_client, &table_name, block_id, label_id).await
             }
             // PATCH /blocks/{bid}/labels/{lid} - update label
-            (&Method::PATCH, ["blocks", block_id, "labels", label_id]) => {
-                labels::update_label(
-                    &state.dynamo_client,
-                    &table_name,
-                    &block_id,
-                    &label_id,
-                    body,
-                )
-                .await
-            }
-            // DELETE /blocks/{bid}/labels/{lid} - delete label
-            (&Method::DELETE, ["blocks", block_id, "labels", label_id]) => {
-                labels::delete_label(&state.dynamo_client, &table_name, block_id, label_id).await
+            (&Method::PATCH, ["blocks", block_id, "labels", label_id]) => match if_match(&event) {
+                Ok(expected_version) => {
+                    labels::update_label(
+                        &state.dynamo_client,
+                        &table_name,
+                        &block_id,
+                        &label_id,
+                        expected_version,
+                        body,
+                    )
+                    .await
+                }
+                Err(e) => bad_request(&e),
+            },
+            // DELETE /blocks/{bid}/labels/{lid}[?policy=refuse|cascade|reassign&into=] - delete label
+            (&Method::DELETE, ["blocks", block_id, "labels", label_id]) => match (if_match(&event), delete_policy(&event)) {
+                (Ok(expected_version), Ok(policy)) => {
+                    labels::delete_label(
+                        &state.dynamo_client,
+                        &table_name,
+                        &user_id,
+                        block_id,
+                        label_id,
+                        expected_version,
+                        policy,
+                    )
+                    .await
+                }
+                (Err(e), _) | (_, Err(e)) => bad_request(&e),
+            },
+            // POST /blocks/{bid}/labels/{lid}/merge - move the label's annotations to another label and delete it
+            (&Method::POST, ["blocks", block_id, "labels", label_id, "merge"]) => {
+                labels::merge_label_handler(&state.dynamo_client, &table_name, &user_id, block_id, label_id, body).await
             }
 
             // --- TASKS ---
             // GET /blocks/{bid}/tasks - list tasks (WITH IMAGES - JOIN LOGIC)
-            (&Method::GET, ["blocks", block_id, "tasks"]) => {
-                annotations_block::tasks::list_block_tasks(&state.dynamo_client, &table_name, block_id).await
-            }
+            (&Method::GET, ["blocks", block_id, "tasks"]) => match page_request(&event) {
+                Ok(page) => {
+                    annotations_block::tasks::list_block_tasks(&state.dynamo_client, &table_name, block_id, &page)
+                        .await
+                }
+                Err(e) => bad_request(&e),
+            },
             // POST /blocks/{bid}/tasks - create task
             (&Method::POST, ["blocks", block_id, "tasks"]) => {
                 annotations_block::tasks::create_task(&state.dynamo_client, &table_name, block_id, body).await
@@ -491,14 +651,53 @@ pub(crate) async fn function_handler(
                 annotations_block::tasks::get_task(&state.dynamo_client, &table_name, block_id, task_id).await
             }
             // PATCH /blocks/{bid}/tasks/{tid} - update task
-            (&Method::PATCH, ["blocks", block_id, "tasks", task_id]) => {
-                annotations_block::tasks::update_task(&state.dynamo_client, &table_name, block_id, task_id, body).await
-            }
-            // DELETE /blocks/{bid}/tasks/{tid} - delete task
-            (&Method::DELETE, ["blocks", block_id, "tasks", task_id]) => {
-                annotations_block::tasks::delete_task(&state.dynamo_client, &table_name, block_id, task_id).await
-            }
+            (&Method::PATCH, ["blocks", block_id, "tasks", task_id]) => match if_match(&event) {
+                Ok(expected_version) => {
+                    annotations_block::tasks::update_task(
+                        &state.dynamo_client,
+                        &table_name,
+                        block_id,
+                        task_id,
+                        expected_version,
+                        body,
+                    )
+                    .await
+                }
+                Err(e) => bad_request(&e),
+            },
+            // DELETE /blocks/{bid}/tasks/{tid} - move task and its images to the trash
+            (&Method::DELETE, ["blocks", block_id, "tasks", task_id]) => match if_match(&event) {
+                Ok(expected_version) => {
+                    annotations_block::tasks::delete_task(
+                        &state.dynamo_client,
+                        &table_name,
+                        &user_id,
+                        block_id,
+                        task_id,
+                        expected_version,
+                    )
+                    .await
+                }
+                Err(e) => bad_request(&e),
+            },
             // --- TASK IMAGES ---
+            // GET /blocks/{bid}/tasks/{tid}/agreement - annotator agreement and consensus over the task's images (?iou=)
+            (&Method::GET, ["blocks", block_id, "tasks", task_id, "agreement"]) => {
+                let iou = event.query_string_parameters_ref().and_then(|params| params.first("iou"));
+                match annotations_block::agreement::match_iou_from_query(iou) {
+                    Ok(iou) => {
+                        annotations_block::agreement::task_agreement_handler(
+                            &state.dynamo_client,
+                            &table_name,
+                            block_id,
+                            task_id,
+                            iou,
+                        )
+                        .await
+                    }
+                    Err(e) => bad_request(&e),
+                }
+            }
             // POST /blocks/{bid}/tasks/{tid}/images - create image for task
             (&Method::POST, ["blocks", block_id, "tasks", task_id, "images"]) => {
                 annotations_block::images::create_image_for_task_handler(
@@ -511,15 +710,19 @@ pub(crate) async fn function_handler(
                 .await
             }
             // GET /blocks/{bid}/tasks/{tid}/images - list images for task
-            (&Method::GET, ["blocks", block_id, "tasks", task_id, "images"]) => {
-                annotations_block::images::list_images_for_task_handler(
-                    &state.dynamo_client,
-                    &table_name,
-                    &block_id,
-                    &task_id,
-                )
-                .await
-            }
+            (&Method::GET, ["blocks", block_id, "tasks", task_id, "images"]) => match page_request(&event) {
+                Ok(page) => {
+                    annotations_block::images::list_images_for_task_handler(
+                        &state.dynamo_client,
+                        &table_name,
+                        &block_id,
+                        &task_id,
+                        &page,
+                    )
+                    .await
+                }
+                Err(e) => bad_request(&e),
+            },
 
             _ => not_found(),
         };
@@ -561,6 +764,57 @@ pub(crate) async fn function_handler(
         return finalize_response(resp, request_origin, &auth_ctx.set_cookies);
     }
 
+    // Trash routes
+    if path.starts_with("/trash") {
+        let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+        let resp = match (method, parts.as_slice()) {
+            // GET /trash[?block_id=] - list trashed entities, newest first
+            (&Method::GET, ["trash"]) => {
+                let block_id = event
+                    .query_string_parameters_ref()
+                    .and_then(|params| params.first("block_id"));
+                atoms::trash::list_trash(&state.dynamo_client, &table_name, block_id).await
+            }
+            // POST /trash/{id}/restore - restore a trashed entity
+            (&Method::POST, ["trash", trash_id, "restore"]) => {
+                atoms::trash::restore_trash(&state.dynamo_client, &table_name, &user_id, trash_id).await
+            }
+            _ => not_found(),
+        };
+
+        return finalize_response(resp, request_origin, &auth_ctx.set_cookies);
+    }
+
+    // Label template routes
+    if path.starts_with("/label-templates") {
+        let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+        let resp = match (method, parts.as_slice()) {
+            // GET /label-templates - list templates for every block type
+            (&Method::GET, ["label-templates"]) => {
+                annotations_block::templates::list_label_templates(&state.dynamo_client, &table_name).await
+            }
+            // GET /label-templates/{block_type} - get one block type's template
+            (&Method::GET, ["label-templates", block_type]) => {
+                annotations_block::templates::get_label_template(&state.dynamo_client, &table_name, block_type).await
+            }
+            // PUT /label-templates/{block_type} - create or replace a template (admin)
+            (&Method::PUT, ["label-templates", block_type]) => {
+                annotations_block::templates::put_label_template(&state.dynamo_client, &table_name, &user_id, block_type, body)
+                    .await
+            }
+            // DELETE /label-templates/{block_type} - delete a template (admin)
+            (&Method::DELETE, ["label-templates", block_type]) => {
+                annotations_block::templates::delete_label_template(&state.dynamo_client, &table_name, &user_id, block_type)
+                    .await
+            }
+            _ => not_found(),
+        };
+
+        return finalize_response(resp, request_origin, &auth_ctx.set_cookies);
+    }
+
     // Images routes
     if path.starts_with("/images") {
         let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
@@ -583,19 +837,79 @@ pub(crate) async fn function_handler(
                 atoms::media::update_image_handler(&state.dynamo_client, &table_name, block_id, image_id, body)
                     .await
             }
-            // DELETE /images/{id} - delete image
-            (&Method::DELETE, ["images", image_id]) => {
+            // PUT /images/{id}/scale - calibrate the image's real-world scale
+            (&Method::PUT, ["images", image_id, "scale"]) => {
                 let block_id = event
                     .query_string_parameters_ref()
                     .and_then(|params| params.first("block_id"))
                     .ok_or("Missing block id query parameter")?;
-                atoms::media::delete_image_handler(&state.dynamo_client, &table_name, block_id, image_id).await
+                atoms::media::calibrate_image_handler(&state.dynamo_client, &table_name, block_id, image_id, body)
+                    .await
             }
-            // GET /images/{id}/annotations - list image annotations
-            (&Method::GET, ["images", image_id, "annotations"]) => {
-                atoms::drawing::list_image_annotations(&state.dynamo_client, &table_name, image_id)
+            // DELETE /images/{id} - move image to the trash
+            (&Method::DELETE, ["images", image_id]) => {
+                let block_id = event
+                    .query_string_parameters_ref()
+                    .and_then(|params| params.first("block_id"))
+                    .ok_or("Missing block id query parameter")?;
+                atoms::media::delete_image_handler(&state.dynamo_client, &table_name, &user_id, block_id, image_id)
                     .await
             }
+            // POST /images/{id}/undo - undo the caller's latest annotation change on the image
+            (&Method::POST, ["images", image_id, "undo"]) => {
+                let block_id = event
+                    .query_string_parameters_ref()
+                    .and_then(|params| params.first("block_id"))
+                    .ok_or("Missing block id query parameter")?;
+                atoms::undo::undo_handler(&state.dynamo_client, &table_name, &user_id, block_id, image_id).await
+            }
+            // POST /images/{id}/redo - redo the caller's latest undone change on the image
+            (&Method::POST, ["images", image_id, "redo"]) => {
+                let block_id = event
+                    .query_string_parameters_ref()
+                    .and_then(|params| params.first("block_id"))
+                    .ok_or("Missing block id query parameter")?;
+                atoms::undo::redo_handler(&state.dynamo_client, &table_name, &user_id, block_id, image_id).await
+            }
+            // GET /images/{id}/agreement - annotator agreement and consensus on the image (?iou=)
+            (&Method::GET, ["images", image_id, "agreement"]) => {
+                let iou = event.query_string_parameters_ref().and_then(|params| params.first("iou"));
+                match annotations_block::agreement::match_iou_from_query(iou) {
+                    Ok(iou) => {
+                        annotations_block::agreement::image_agreement_handler(&state.dynamo_client, &table_name, image_id, iou)
+                            .await
+                    }
+                    Err(e) => bad_request(&e),
+                }
+            }
+            // GET /images/{id}/history - change history of every annotation on the image
+            (&Method::GET, ["images", image_id, "history"]) => {
+                atoms::drawing::get_image_history(&state.dynamo_client, &table_name, image_id).await
+            }
+            // GET /images/{id}/annotations - list image annotations (?measure=true adds area, perimeter, centroid,
+            // bbox, and metres too when ?block_id= names the block of a calibrated image; ?bbox=x0,y0,x1,y1 or
+            // ?point=x,y[&tolerance=] return only the annotations in a viewport or under a point, unpaged)
+            (&Method::GET, ["images", image_id, "annotations"]) => match (page_request(&event), spatial_query(&event)) {
+                (Ok(page), Ok(spatial)) if spatial.is_some() && page.is_paged() => {
+                    bad_request("bbox and point queries are not paged")
+                }
+                (Ok(page), Ok(spatial)) => {
+                    let params = event.query_string_parameters_ref();
+                    let measure = params.and_then(|params| params.first("measure")).map(|v| v == "true").unwrap_or(false);
+                    let block_id = params.and_then(|params| params.first("block_id"));
+                    atoms::drawing::list_image_annotations(
+                        &state.dynamo_client,
+                        &table_name,
+                        image_id,
+                        &page,
+                        spatial.as_ref(),
+                        measure,
+                        block_id,
+                    )
+                    .await
+                }
+                (Err(e), _) | (_, Err(e)) => bad_request(&e),
+            },
             // POST /images/{id}/annotations - create annotation
             (&Method::POST, ["images", image_id, "annotations"]) => {
                 let block_id = event
@@ -613,48 +927,71 @@ pub(crate) async fn function_handler(
                 )
                 .await
             }
-            // GET /images/{iid}/annotations/{aid} - get annotation
-            (&Method::GET, ["images", image_id, "annotations", annotation_id]) => {
-                atoms::drawing::get_annotation(
-                    &state.dynamo_client,
-                    &table_name,
-                    &image_id,
-                    &annotation_id,
-                )
-                .await
-            }
-            // PATCH /images/{iid}/annotations/{aid} - update annotation
-            (&Method::PATCH, ["images", image_id, "annotations", annotation_id]) => {
+            // POST /images/{id}/annotations/normalize - simplify, square up and snap a geometry without saving it
+            (&Method::POST, ["images", image_id, "annotations", "normalize"]) => {
                 let block_id = event
                     .query_string_parameters_ref()
                     .and_then(|params| params.first("block_id"))
                     .ok_or("Missing block id query parameter")?;
-
-                atoms::drawing::update_annotation(
+                atoms::drawing::normalize_geometry(&state.dynamo_client, &table_name, block_id, image_id, body).await
+            }
+            // GET /images/{iid}/annotations/{aid} - get annotation
+            (&Method::GET, ["images", image_id, "annotations", annotation_id]) => {
+                atoms::drawing::get_annotation(
                     &state.dynamo_client,
                     &table_name,
-                    &block_id,
                     &image_id,
                     &annotation_id,
-                    body,
                 )
                 .await
             }
-            // DELETE /images/{iid}/annotations/{aid} - delete annotation
+            // GET /images/{iid}/annotations/{aid}/history - annotation change history
+            (&Method::GET, ["images", image_id, "annotations", annotation_id, "history"]) => {
+                atoms::drawing::get_annotation_history(&state.dynamo_client, &table_name, image_id, annotation_id).await
+            }
+            // PATCH /images/{iid}/annotations/{aid} - update annotation
+            (&Method::PATCH, ["images", image_id, "annotations", annotation_id]) => match if_match(&event) {
+                Ok(expected_version) => {
+                    let block_id = event
+                        .query_string_parameters_ref()
+                        .and_then(|params| params.first("block_id"))
+                        .ok_or("Missing block id query parameter")?;
+                    atoms::drawing::update_annotation(
+                        &state.dynamo_client,
+                        &table_name,
+                        &user_id,
+                        block_id,
+                        &image_id,
+                        &annotation_id,
+                        expected_version,
+                        body,
+                    )
+                    .await
+                }
+                Err(e) => bad_request(&e),
+            },
+            // DELETE /images/{iid}/annotations/{aid} - move annotation to the trash
             (&Method::DELETE, ["images", image_id, "annotations", annotation_id]) => {
                 let block_id = event
                     .query_string_parameters_ref()
                     .and_then(|params| params.first("block_id"))
                     .ok_or("Missing block id query parameter")?;
 
-                atoms::drawing::delete_annotation(
-                    &state.dynamo_client,
-                    &table_name,
-                    &block_id,
-                    &image_id,
-                    &annotation_id,
-                )
-                .await
+                match if_match(&event) {
+                    Ok(expected_version) => {
+                        atoms::drawing::delete_annotation(
+                            &state.dynamo_client,
+                            &table_name,
+                            &user_id,
+                            &block_id,
+                            &image_id,
+                            &annotation_id,
+                            expected_version,
+                        )
+                        .await
+                    }
+                    Err(e) => bad_request(&e),
+                }
             }
             _ => not_found(),
         };
@@ -777,6 +1114,58 @@ async fn _list_block_images_signed(
         .map_err(Box::new)?)
 }
 
+fn bad_request(message: &str) -> Result<Response<Body>, Error> {
+    Ok(Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .header("Content-Type", "application/json")
+        .header("Access-Control-Allow-Origin", "*")
+        .body(serde_json::json!({"error": message}).to_string().into())
+        .map_err(Box::new)?)
+}
+
+/// Read `?limit=&cursor=` for list endpoints
+fn page_request(event: &Request) -> Result<PageRequest, String> {
+    let params = event.query_string_parameters_ref();
+    PageRequest::from_query(
+        params.and_then(|p| p.first("limit")),
+        params.and_then(|p| p.first("cursor")),
+    )
+}
+
+/// Read `?policy=&into=` for label deletes
+fn delete_policy(event: &Request) -> Result<atoms::labels::DeletePolicy, String> {
+    let params = event.query_string_parameters_ref();
+    atoms::labels::DeletePolicy::from_query(
+        params.and_then(|p| p.first("policy")),
+        params.and_then(|p| p.first("into")),
+    )
+}
+
+/// Read the takeoff's fallback scale from `?pixels_per_metre=` or `?ratio=&dpi=`
+fn takeoff_fallback(event: &Request) -> Result<Option<atoms::media::Calibration>, String> {
+    let params = event.query_string_parameters_ref();
+    atoms::media::Calibration::from_query(
+        params.and_then(|p| p.first("pixels_per_metre")),
+        params.and_then(|p| p.first("ratio")),
+        params.and_then(|p| p.first("dpi")),
+    )
+}
+
+/// Read a viewport `?bbox=` or hit-test `?point=` for annotation lists
+fn spatial_query(event: &Request) -> Result<Option<atoms::drawing::spatial::SpatialQuery>, String> {
+    let params = event.query_string_parameters_ref();
+    atoms::drawing::spatial::SpatialQuery::from_query(
+        params.and_then(|p| p.first("bbox")),
+        params.and_then(|p| p.first("point")),
+        params.and_then(|p| p.first("tolerance")),
+    )
+}
+
+/// Read `If-Match` for versioned PATCH/DELETE routes
+fn if_match(event: &Request) -> Result<Option<u64>, String> {
+    version::parse_if_match(event.headers().get("If-Match").and_then(|v| v.to_str().ok()))
+}
+
 fn not_found() -> Result<Response<Body>, Error> {
     Ok(Response::builder()
         .status(StatusCode::NOT_FOUND)
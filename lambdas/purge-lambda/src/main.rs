@@ -0,0 +1,35 @@
+use annotations_block::trash::purge_expired;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use aws_sdk_s3::Client as S3Client;
+use doxle_atoms::store::DynamoStore;
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use serde_json::Value;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .with_target(false)
+        .without_time()
+        .init();
+
+    run(service_fn(function_handler)).await
+}
+
+/// Scheduled hard delete of trash entries past their retention period
+async fn function_handler(_event: LambdaEvent<Value>) -> Result<Value, Error> {
+    let config = aws_config::load_from_env().await;
+    let dynamo_client = DynamoClient::new(&config);
+    let s3_client = S3Client::new(&config);
+    let table_name = std::env::var("TABLE_NAME").unwrap_or_else(|_| "doxle".to_string());
+    let store = DynamoStore::new(&dynamo_client, &table_name);
+
+    let now = chrono::Utc::now().to_rfc3339();
+    let purged = purge_expired(&store, &s3_client, &now).await?;
+    for entry in &purged {
+        tracing::info!("Purged trash entry {} ({:?})", entry.trash_id, entry.entity);
+    }
+    tracing::info!("Purged {} expired trash entries", purged.len());
+
+    Ok(serde_json::to_value(&purged)?)
+}
@@ -0,0 +1,51 @@
+use annotations_block::reconcile::reconcile_all_blocks;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use doxle_atoms::store::DynamoStore;
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Input set on the EventBridge schedule rule, e.g. `{"fix": true}`
+#[derive(Debug, Default, Deserialize)]
+struct ReconcileInput {
+    #[serde(default)]
+    fix: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .with_target(false)
+        .without_time()
+        .init();
+
+    run(service_fn(function_handler)).await
+}
+
+/// Scheduled counter reconciliation over every block
+async fn function_handler(event: LambdaEvent<Value>) -> Result<Value, Error> {
+    let input: ReconcileInput = serde_json::from_value(event.payload).unwrap_or_default();
+
+    let config = aws_config::load_from_env().await;
+    let dynamo_client = DynamoClient::new(&config);
+    let table_name = std::env::var("TABLE_NAME").unwrap_or_else(|_| "doxle".to_string());
+    let store = DynamoStore::new(&dynamo_client, &table_name);
+
+    let reports = reconcile_all_blocks(&store, input.fix).await?;
+
+    let drifted: Vec<_> = reports.iter().filter(|r| !r.is_clean()).collect();
+    for report in &drifted {
+        tracing::warn!(
+            "Block {} had {} drifted counters, {} missing and {} stale task index rows (fixed: {})",
+            report.block_id,
+            report.drifts.len(),
+            report.missing_task_links.len(),
+            report.stale_task_links.len(),
+            report.fixed
+        );
+    }
+    tracing::info!("Reconciled {} blocks, {} drifted", reports.len(), drifted.len());
+
+    Ok(serde_json::to_value(&drifted)?)
+}
@@ -1,4 +1,3 @@
-use aws_config;
 use aws_lambda_events::event::dynamodb::{Event, EventRecord};
 use aws_sdk_apigatewaymanagement::Client as ApiGatewayManagementClient;
 use aws_sdk_dynamodb::Client as DynamoClient;
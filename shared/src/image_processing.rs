@@ -48,15 +48,15 @@ mod tests {
     #[test]
     fn test_needs_half_width() {
         // Small file, small dimensions → No
-        assert_eq!(needs_half_width(2_000_000, 2048, 1536), false);
+        assert!(!needs_half_width(2_000_000, 2048, 1536));
         
         // Large file, small dimensions → Yes
-        assert_eq!(needs_half_width(4_000_000, 2048, 1536), true);
+        assert!(needs_half_width(4_000_000, 2048, 1536));
         
         // Small file, large dimensions → Yes
-        assert_eq!(needs_half_width(2_000_000, 4000, 3000), true);
+        assert!(needs_half_width(2_000_000, 4000, 3000));
         
         // Large file, large dimensions → Yes
-        assert_eq!(needs_half_width(4_000_000, 4000, 3000), true);
+        assert!(needs_half_width(4_000_000, 4000, 3000));
     }
 }
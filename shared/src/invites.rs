@@ -5,6 +5,8 @@ use serde::{Deserialize, Serialize};
 use chrono::Utc;
 use uuid::Uuid;
 use std::env;
+use doxle_atoms::store::{DynamoStore, InviteRepo};
+use doxle_atoms::users::Invite;
 
 #[derive(Debug, Deserialize)]
 pub struct CreateInviteRequest {
@@ -66,19 +68,16 @@ pub async fn create_invite(
     let expires_at = now + chrono::Duration::days(request.expires_days);
 
     // Store invite in DynamoDB
-    let result = dynamo_client
-        .put_item()
-        .table_name(table_name)
-        .item("PK", aws_sdk_dynamodb::types::AttributeValue::S(format!("INVITE#{}", invite_code)))
-        .item("SK", aws_sdk_dynamodb::types::AttributeValue::S("METADATA".to_string()))
-        .item("invite_code", aws_sdk_dynamodb::types::AttributeValue::S(invite_code.clone()))
-        .item("email", aws_sdk_dynamodb::types::AttributeValue::S(request.email.clone()))
-        .item("status", aws_sdk_dynamodb::types::AttributeValue::S("pending".to_string()))
-        .item("created_by", aws_sdk_dynamodb::types::AttributeValue::S(admin_user_id.to_string()))
-        .item("created_at", aws_sdk_dynamodb::types::AttributeValue::S(now.to_rfc3339()))
-        .item("expires_at", aws_sdk_dynamodb::types::AttributeValue::S(expires_at.to_rfc3339()))
-        .send()
-        .await;
+    let invite = Invite {
+        invite_code: invite_code.clone(),
+        email: request.email.clone(),
+        status: "pending".to_string(),
+        created_by: admin_user_id.to_string(),
+        created_at: now.to_rfc3339(),
+        expires_at: expires_at.to_rfc3339(),
+        used_at: None,
+    };
+    let result = DynamoStore::new(dynamo_client, table_name).put_invite(&invite).await;
 
     match result {
         Ok(_) => {
@@ -137,44 +136,24 @@ pub async fn validate_invite(
     invite_code: &str,
     email: &str,
 ) -> Result<bool, String> {
-    let result = client
-        .get_item()
-        .table_name(table_name)
-        .key("PK", aws_sdk_dynamodb::types::AttributeValue::S(format!("INVITE#{}", invite_code)))
-        .key("SK", aws_sdk_dynamodb::types::AttributeValue::S("METADATA".to_string()))
-        .send()
+    let invite = DynamoStore::new(client, table_name)
+        .get_invite(invite_code)
         .await
-        .map_err(|e| format!("Failed to fetch invite: {:?}", e))?;
-
-    let item = result.item().ok_or("Invite code not found")?;
+        .map_err(|e| format!("Failed to fetch invite: {}", e))?
+        .ok_or("Invite code not found")?;
 
     // Check status
-    let status = item
-        .get("status")
-        .and_then(|v| v.as_s().ok())
-        .ok_or("Invalid invite status")?;
-
-    if status != "pending" {
+    if invite.status != "pending" {
         return Err("Invite code has already been used".to_string());
     }
 
     // Check email match
-    let invite_email = item
-        .get("email")
-        .and_then(|v| v.as_s().ok())
-        .ok_or("Invalid invite email")?;
-
-    if invite_email != email {
+    if invite.email != email {
         return Err("Email does not match invite".to_string());
     }
 
     // Check expiry
-    let expires_at = item
-        .get("expires_at")
-        .and_then(|v| v.as_s().ok())
-        .ok_or("Invalid invite expiry")?;
-
-    let expiry_time = chrono::DateTime::parse_from_rfc3339(expires_at)
+    let expiry_time = chrono::DateTime::parse_from_rfc3339(&invite.expires_at)
         .map_err(|_| "Invalid expiry format")?;
 
     if expiry_time < Utc::now() {
@@ -190,20 +169,10 @@ pub async fn mark_invite_used(
     table_name: &str,
     invite_code: &str,
 ) -> Result<(), String> {
-    client
-        .update_item()
-        .table_name(table_name)
-        .key("PK", aws_sdk_dynamodb::types::AttributeValue::S(format!("INVITE#{}", invite_code)))
-        .key("SK", aws_sdk_dynamodb::types::AttributeValue::S("METADATA".to_string()))
-        .update_expression("SET #status = :used, used_at = :now")
-        .expression_attribute_names("#status", "status")
-        .expression_attribute_values(":used", aws_sdk_dynamodb::types::AttributeValue::S("used".to_string()))
-        .expression_attribute_values(":now", aws_sdk_dynamodb::types::AttributeValue::S(Utc::now().to_rfc3339()))
-        .send()
+    DynamoStore::new(client, table_name)
+        .mark_invite_used(invite_code, &Utc::now().to_rfc3339())
         .await
-        .map_err(|e| format!("Failed to mark invite as used: {:?}", e))?;
-
-    Ok(())
+        .map_err(|e| format!("Failed to mark invite as used: {}", e))
 }
 
 /// Get invite details (for frontend to pre-fill email)
@@ -212,26 +181,16 @@ pub async fn get_invite(
     table_name: &str,
     invite_code: &str,
 ) -> Result<Response<Body>, Error> {
-    let result = client
-        .get_item()
-        .table_name(table_name)
-        .key("PK", aws_sdk_dynamodb::types::AttributeValue::S(format!("INVITE#{}", invite_code)))
-        .key("SK", aws_sdk_dynamodb::types::AttributeValue::S("METADATA".to_string()))
-        .send()
-        .await;
+    let result = DynamoStore::new(client, table_name).get_invite(invite_code).await;
 
     match result {
-        Ok(output) => {
-            if let Some(item) = output.item() {
-                let email = item.get("email").and_then(|v| v.as_s().ok()).unwrap_or(&String::new()).clone();
-                let status = item.get("status").and_then(|v| v.as_s().ok()).unwrap_or(&String::new()).clone();
-                let expires_at = item.get("expires_at").and_then(|v| v.as_s().ok()).unwrap_or(&String::new()).clone();
-
+        Ok(found) => {
+            if let Some(invite) = found {
                 let response = InviteResponse {
-                    invite_code: invite_code.to_string(),
-                    email: email.to_string(),
-                    expires_at: expires_at.to_string(),
-                    status: status.to_string(),
+                    invite_code: invite.invite_code,
+                    email: invite.email,
+                    expires_at: invite.expires_at,
+                    status: invite.status,
                 };
 
                 Ok(Response::builder()
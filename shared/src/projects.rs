@@ -2,9 +2,9 @@ use aws_sdk_dynamodb::Client as DynamoClient;
 use aws_sdk_s3::Client as S3Client;
 use lambda_http::{http::StatusCode, Body, Error, Response};
 
-/// Projects have been removed from the domain model.
-/// These functions are kept only to keep older routes compiling if they are still called.
-/// All of them now return 410 Gone.
+// Projects have been removed from the domain model.
+// These functions are kept only to keep older routes compiling if they are still called.
+// All of them now return 410 Gone.
 
 pub async fn create_project(
     _client: &DynamoClient,
@@ -32,7 +32,7 @@ pub async fn upload_image(
     // Get file extension from filename
     let extension = request.file_name
         .split('.')
-        .last()
+        .next_back()
         .unwrap_or("jpg");
     
     // S3 key: projects/{project_id}/blocks/{block_id}/{image_id}.{ext}
@@ -93,7 +93,7 @@ pub async fn generate_presigned_upload_url(
     
     let extension = file_name
         .split('.')
-        .last()
+        .next_back()
         .unwrap_or("jpg");
     
     let s3_key = format!(
@@ -64,7 +64,7 @@ pub async fn initiate_upload(
     
     let extension = request.file_name
         .split('.')
-        .last()
+        .next_back()
         .unwrap_or("jpg")
         .to_string();
     
@@ -85,7 +85,7 @@ pub async fn initiate_upload(
         // Initiate multipart upload
         let create_result = s3_client
             .create_multipart_upload()
-            .bucket(&get_bucket_name())
+            .bucket(get_bucket_name())
             .key(&s3_key)
             .content_type(&request.content_type)
             .send()
@@ -102,7 +102,7 @@ pub async fn initiate_upload(
         for part_number in 1..=num_parts {
             let presigned = s3_client
                 .upload_part()
-                .bucket(&get_bucket_name())
+                .bucket(get_bucket_name())
                 .key(&s3_key)
                 .upload_id(&upload_id)
                 .part_number(part_number)
@@ -139,7 +139,7 @@ pub async fn initiate_upload(
         // Single part upload for files < 5MB
         let presigned = s3_client
             .put_object()
-            .bucket(&get_bucket_name())
+            .bucket(get_bucket_name())
             .key(&s3_key)
             .content_type(&request.content_type)
             .presigned(
@@ -202,7 +202,7 @@ pub async fn complete_multipart_upload(
         // Complete the multipart upload
         s3_client
             .complete_multipart_upload()
-            .bucket(&get_bucket_name())
+            .bucket(get_bucket_name())
             .key(&s3_key)
             .upload_id(&request.upload_id)
             .multipart_upload(completed_upload)
@@ -264,7 +264,7 @@ pub async fn abort_multipart_upload(
     
     s3_client
         .abort_multipart_upload()
-        .bucket(&get_bucket_name())
+        .bucket(get_bucket_name())
         .key(&s3_key)
         .upload_id(&upload_id)
         .send()
@@ -294,7 +294,7 @@ pub async fn process_uploaded_image(
     tracing::info!("📥 Downloading image from S3: {}", original_key);
     let result = s3_client
         .get_object()
-        .bucket(&get_bucket_name())
+        .bucket(get_bucket_name())
         .key(&original_key)
         .send()
         .await
@@ -335,7 +335,7 @@ pub async fn process_uploaded_image(
         tracing::info!("📤 Uploading full resolution to: {}", full_key);
         s3_client
             .put_object()
-            .bucket(&get_bucket_name())
+            .bucket(get_bucket_name())
             .key(&full_key)
             .body(image_bytes.into())
             .send()
@@ -345,7 +345,7 @@ pub async fn process_uploaded_image(
         // Delete old flat file
         s3_client
             .delete_object()
-            .bucket(&get_bucket_name())
+            .bucket(get_bucket_name())
             .key(&original_key)
             .send()
             .await
@@ -356,7 +356,7 @@ pub async fn process_uploaded_image(
         tracing::info!("📤 Uploading half-width to: {}", half_key);
         s3_client
             .put_object()
-            .bucket(&get_bucket_name())
+            .bucket(get_bucket_name())
             .key(&half_key)
             .body(half_bytes.into())
             .content_type("image/jpeg")
@@ -397,7 +397,7 @@ pub async fn process_uploaded_image(
         tracing::info!("📤 Uploading metadata to: {}", metadata_key);
         s3_client
             .put_object()
-            .bucket(&get_bucket_name())
+            .bucket(get_bucket_name())
             .key(&metadata_key)
             .body(metadata_json.into_bytes().into())
             .content_type("application/json")
@@ -218,8 +218,8 @@ async fn handle_message(
                 .ok_or("Missing block_id")?;
             blocks::delete_block(
                 &state.dynamo_client,
-                &state.s3_client,
                 table_name,
+                &user_id,
                 block_id
             )
             .await
@@ -252,6 +252,7 @@ async fn handle_message(
                 table_name,
                 project_id,
                 class_id,
+                expected_version(&message.data),
                 &body_bytes,
             )
             .await
@@ -267,7 +268,20 @@ async fn handle_message(
                 .get("class_id")
                 .and_then(|v| v.as_str())
                 .ok_or("Missing class_id")?;
-            labels::delete_label(&state.dynamo_client, table_name, project_id, class_id).await
+            let policy = doxle_atoms::labels::DeletePolicy::from_query(
+                message.data.get("policy").and_then(|v| v.as_str()),
+                message.data.get("into").and_then(|v| v.as_str()),
+            )?;
+            labels::delete_label(
+                &state.dynamo_client,
+                table_name,
+                &user_id,
+                project_id,
+                class_id,
+                expected_version(&message.data),
+                policy,
+            )
+            .await
         }
 
         // Annotation actions
@@ -294,6 +308,11 @@ async fn handle_message(
             .await
         }
         "update_annotation" => {
+            let block_id = message
+                .data
+                .get("block_id")
+                .and_then(|v| v.as_str())
+                .ok_or("Missing block_id")?;
             let image_id = message
                 .data
                 .get("image_id")
@@ -308,8 +327,11 @@ async fn handle_message(
             doxle_atoms::drawing::update_annotation(
                 &state.dynamo_client,
                 table_name,
+                &user_id,
+                block_id,
                 image_id,
                 annotation_id,
+                expected_version(&message.data),
                 &body_bytes,
             )
             .await
@@ -333,9 +355,11 @@ async fn handle_message(
             doxle_atoms::drawing::delete_annotation(
                 &state.dynamo_client,
                 table_name,
+                &user_id,
                 block_id,
                 image_id,
                 annotation_id,
+                expected_version(&message.data),
             )
             .await
         }
@@ -352,3 +376,8 @@ async fn handle_message(
         }
     }
 }
+
+/// Version the client last saw, sent as `"version"` alongside the payload
+fn expected_version(data: &serde_json::Value) -> Option<u64> {
+    data.get("version").and_then(|v| v.as_u64())
+}